@@ -0,0 +1,125 @@
+//! Holding back the acks of received messages: for consume to ack the messages it prints only
+//! once printed, and for `consume --ack-hold` and `--ack-hold-time`, to act as a consumer that
+//! receives quickly but acks slowly, e.g. to build up unacked messages until the broker blocks
+//! the consumer
+
+use crate::{
+    connection::{self, ConsumerSet},
+    metrics::{self, STATS},
+};
+use pulsar::{consumer::Message, proto::MessageIdData};
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::Instant;
+
+/// A received message not acked yet
+pub struct Held {
+    /// Which consumer of the set received it
+    pub source: usize,
+    pub topic: String,
+    pub id: MessageIdData,
+    received: Instant,
+    /// How many messages have to be printed before it is, if it is to be
+    printing: Option<u64>,
+}
+
+impl Held {
+    pub fn of(source: usize, message: &Message<Vec<u8>>) -> Self {
+        Self {
+            source,
+            topic: message.topic.clone(),
+            id: message.message_id.id.clone(),
+            received: Instant::now(),
+            printing: None,
+        }
+    }
+
+    /// Whether it waits for more than `printed` messages to be printed
+    fn waits_for(&self, printed: u64) -> bool {
+        self.printing.map_or(false, |printing| printing > printed)
+    }
+}
+
+pub struct AckHold {
+    max: Option<usize>,
+    time: Option<Duration>,
+    /// Oldest first
+    held: VecDeque<Held>,
+}
+
+impl AckHold {
+    /// Holding up to `max` messages, each for up to `time`, or without either only until they
+    /// are printed
+    pub fn new(max: Option<usize>, time: Option<Duration>) -> Self {
+        Self {
+            max,
+            time,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Holds `held` until `printing` messages were printed, if given, and as long as the
+    /// maximum and time say
+    pub fn hold(&mut self, mut held: Held, printing: Option<u64>) {
+        held.printing = printing;
+        self.held.push_back(held);
+        metrics::set(&STATS.held_unacked, self.held.len() as u64);
+    }
+
+    /// The messages to ack now that `printed` messages were printed, oldest first: those
+    /// printed if they were to be, and beyond the maximum or held long enough
+    pub fn due(&mut self, printed: u64) -> Vec<Held> {
+        let mut due = Vec::new();
+        while let Some(oldest) = self.held.front() {
+            if oldest.waits_for(printed) {
+                break;
+            }
+            let over = self.max.map_or(false, |max| self.held.len() > max);
+            let expired = self
+                .time
+                .map_or(false, |time| oldest.received.elapsed() >= time);
+            let holding = self.max.is_some() || self.time.is_some();
+            if holding && !over && !expired {
+                break;
+            }
+            due.extend(self.held.pop_front());
+        }
+        metrics::set(&STATS.held_unacked, self.held.len() as u64);
+        due
+    }
+
+    /// Resolves once the oldest message was held for `time`, never without one or while it
+    /// waits for more than `printed` messages to be printed
+    pub async fn next_due(&self, printed: u64) {
+        match (self.held.front(), self.time) {
+            (Some(oldest), Some(time)) if !oldest.waits_for(printed) => {
+                tokio::time::sleep_until(oldest.received + time).await
+            }
+            _ => futures::future::pending().await,
+        }
+    }
+
+    /// Whether the oldest message waits for more than `printed` messages to be printed
+    pub fn waits_for_printing(&self, printed: u64) -> bool {
+        self.held
+            .front()
+            .map_or(false, |oldest| oldest.waits_for(printed))
+    }
+
+    pub fn count(&self) -> usize {
+        self.held.len()
+    }
+}
+
+/// Acks the messages `due`, failing with the consumer an ack failed with
+pub async fn ack(
+    consumers: &mut ConsumerSet,
+    due: Vec<Held>,
+    retries: usize,
+) -> Result<(), (usize, anyhow::Error)> {
+    for held in due {
+        connection::ack_id_with_retry(consumers.get(held.source), &held.topic, &held.id, retries)
+            .await
+            .map_err(|e| (held.source, e))?;
+    }
+    Ok(())
+}
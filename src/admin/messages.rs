@@ -0,0 +1,335 @@
+//! Decoding of the messages returned by the admin API endpoints that fetch message contents
+//! (peek, get by ID). The payload is the response body, and the metadata comes as headers.
+
+use crate::{
+    display::{DisplayMessage, Timestamp},
+    message_id::MessageId,
+};
+use anyhow::{bail, format_err, Result};
+use chrono::{DateTime, Utc};
+use prost::Message as _;
+use pulsar::proto::SingleMessageMetadata;
+use reqwest::header::HeaderMap;
+use std::convert::TryInto;
+
+const MESSAGE_ID_HEADER: &str = "x-pulsar-message-id";
+const PUBLISH_TIME_HEADER: &str = "x-pulsar-publish-time";
+const EVENT_TIME_HEADER: &str = "x-pulsar-event-time";
+const BATCH_SIZE_HEADER: &str = "x-pulsar-num-batch-message";
+const PROPERTY_HEADER_PREFIX: &str = "x-pulsar-property-";
+
+/// A message fetched through the admin API
+#[derive(Debug)]
+pub struct AdminMessage {
+    pub message_id: Option<MessageId>,
+    pub publish_time: Option<DateTime<Utc>>,
+    pub event_time: Option<Timestamp>,
+    pub properties: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+impl AdminMessage {
+    /// Decodes a response into the messages of the entry it contains: a single message, or all
+    /// the messages of a batch
+    pub fn from_response(headers: &HeaderMap, body: &[u8]) -> Result<Vec<AdminMessage>> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let message_id = header(MESSAGE_ID_HEADER)
+            .map(str::parse::<MessageId>)
+            .transpose()?;
+        let publish_time = header(PUBLISH_TIME_HEADER).map(parse_time).transpose()?;
+        let event_time = header(EVENT_TIME_HEADER)
+            .map(parse_time)
+            .transpose()?
+            .map(Timestamp::Time);
+
+        let batch_size = match header(BATCH_SIZE_HEADER) {
+            Some(size) => size
+                .parse::<usize>()
+                .map_err(|_| format_err!("Invalid batch size header {:?}", size))?,
+            None => 0,
+        };
+        if batch_size == 0 {
+            // Header names are case-insensitive, so the original case of property keys is lost
+            let properties = headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    let key = name.as_str().strip_prefix(PROPERTY_HEADER_PREFIX)?;
+                    Some((key.to_owned(), value.to_str().ok()?.to_owned()))
+                })
+                .collect();
+            return Ok(vec![AdminMessage {
+                message_id,
+                publish_time,
+                event_time,
+                properties,
+                payload: body.to_vec(),
+            }]);
+        }
+
+        // The body of a batch is the batch payload itself, from which each message's own
+        // metadata is taken instead of the (merged) property headers
+        split_batch(body, batch_size)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, (metadata, payload))| {
+                Ok(AdminMessage {
+                    message_id: message_id.map(|id| MessageId {
+                        batch_index: Some(index as i32),
+                        ..id
+                    }),
+                    publish_time,
+                    event_time: metadata
+                        .event_time
+                        .map(Timestamp::from_millis)
+                        .or(event_time),
+                    properties: metadata
+                        .properties
+                        .into_iter()
+                        .map(|item| (item.key, item.value))
+                        .collect(),
+                    payload,
+                })
+            })
+            .collect()
+    }
+
+    /// The message as `display` prints it, timed by its event time if it has one
+    pub fn display(&self) -> DisplayMessage<'_> {
+        DisplayMessage {
+            time: self
+                .event_time
+                .or_else(|| self.publish_time.map(Timestamp::Time))
+                .unwrap_or(Timestamp::Implausible(0)),
+            topic: None,
+            properties: self
+                .properties
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+            entry: Vec::new(),
+            payload: &self.payload,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(value)
+        .map_err(|e| format_err!("Invalid timestamp header {:?}: {}", value, e))?
+        .with_timezone(&Utc))
+}
+
+/// Splits a batch payload into its messages. Each message is framed as a big-endian 32-bit
+/// metadata size, the `SingleMessageMetadata` itself, and the payload whose size the metadata
+/// declares.
+fn split_batch(
+    mut data: &[u8],
+    batch_size: usize,
+) -> Result<Vec<(SingleMessageMetadata, Vec<u8>)>> {
+    let mut messages = Vec::with_capacity(batch_size);
+    for index in 0..batch_size {
+        if data.len() < 4 {
+            bail!("Batch truncated before message #{}", index);
+        }
+        let (size, rest) = data.split_at(4);
+        let size = u32::from_be_bytes(size.try_into()?) as usize;
+        if rest.len() < size {
+            bail!("Batch truncated in the metadata of message #{}", index);
+        }
+        let (metadata, rest) = rest.split_at(size);
+        let metadata = SingleMessageMetadata::decode(metadata)?;
+
+        let payload_size = metadata.payload_size as usize;
+        if rest.len() < payload_size {
+            bail!("Batch truncated in the payload of message #{}", index);
+        }
+        let (payload, rest) = rest.split_at(payload_size);
+        messages.push((metadata, payload.to_vec()));
+        data = rest;
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulsar::proto::KeyValue;
+    use reqwest::header::HeaderValue;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| (*name, HeaderValue::from_static(*value)))
+            .fold(HeaderMap::new(), |mut map, (name, value)| {
+                map.insert(name, value);
+                map
+            })
+    }
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().unwrap()
+    }
+
+    /// A batch payload of messages with their properties and event time
+    fn batch_body(messages: &[(&[(&str, &str)], Option<u64>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (properties, event_time, payload) in messages {
+            let metadata = SingleMessageMetadata {
+                properties: properties
+                    .iter()
+                    .map(|(key, value)| KeyValue {
+                        key: (*key).to_owned(),
+                        value: (*value).to_owned(),
+                    })
+                    .collect(),
+                event_time: *event_time,
+                payload_size: payload.len() as i32,
+                ..Default::default()
+            };
+            body.extend(&(prost::Message::encoded_len(&metadata) as u32).to_be_bytes());
+            prost::Message::encode(&metadata, &mut body).unwrap();
+            body.extend(*payload);
+        }
+        body
+    }
+
+    #[test]
+    fn single_message() {
+        let headers = headers(&[
+            (MESSAGE_ID_HEADER, "12:34"),
+            (PUBLISH_TIME_HEADER, "2021-03-04T05:06:07.089Z"),
+            (EVENT_TIME_HEADER, "2021-03-04T05:06:00Z"),
+            ("x-pulsar-property-env", "prod"),
+            ("x-pulsar-property-trace-id", "abc"),
+            ("content-type", "application/octet-stream"),
+        ]);
+        let messages = AdminMessage::from_response(&headers, b"payload").unwrap();
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.message_id, Some("12:34".parse().unwrap()));
+        assert_eq!(message.publish_time, Some(time("2021-03-04T05:06:07.089Z")));
+        assert_eq!(
+            message.event_time,
+            Some(Timestamp::Time(time("2021-03-04T05:06:00Z")))
+        );
+        let mut properties = message.properties.clone();
+        properties.sort();
+        assert_eq!(
+            properties,
+            vec![
+                ("env".to_owned(), "prod".to_owned()),
+                ("trace-id".to_owned(), "abc".to_owned())
+            ]
+        );
+        assert_eq!(message.payload, b"payload");
+    }
+
+    #[test]
+    fn without_headers() {
+        let messages = AdminMessage::from_response(&HeaderMap::new(), b"").unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message_id, None);
+        assert_eq!(messages[0].publish_time, None);
+        assert_eq!(messages[0].event_time, None);
+        assert!(messages[0].properties.is_empty());
+    }
+
+    #[test]
+    fn invalid_headers() {
+        for (name, value) in &[
+            (MESSAGE_ID_HEADER, "twelve"),
+            (PUBLISH_TIME_HEADER, "yesterday"),
+            (EVENT_TIME_HEADER, "1614834367089"),
+            (BATCH_SIZE_HEADER, "-1"),
+        ] {
+            assert!(
+                AdminMessage::from_response(&headers(&[(*name, *value)]), b"").is_err(),
+                "{}: {}",
+                name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let headers = headers(&[
+            (MESSAGE_ID_HEADER, "12:34"),
+            (PUBLISH_TIME_HEADER, "2021-03-04T05:06:07.089Z"),
+            (EVENT_TIME_HEADER, "2021-03-04T05:06:00Z"),
+            (BATCH_SIZE_HEADER, "3"),
+            // Merged from the messages of the batch, not what each has
+            ("x-pulsar-property-env", "prod"),
+        ]);
+        let body = batch_body(&[
+            (
+                &[("env", "prod"), ("Trace-Id", "a")][..],
+                Some(1_614_834_000_000),
+                &b"first"[..],
+            ),
+            (&[], None, &b""[..]),
+            (&[("env", "test")][..], None, &b"third"[..]),
+        ]);
+        let messages = AdminMessage::from_response(&headers, &body).unwrap();
+        assert_eq!(messages.len(), 3);
+        for (index, message) in messages.iter().enumerate() {
+            assert_eq!(
+                message.message_id,
+                Some(MessageId {
+                    batch_index: Some(index as i32),
+                    .."12:34".parse().unwrap()
+                })
+            );
+            assert_eq!(message.publish_time, Some(time("2021-03-04T05:06:07.089Z")));
+        }
+
+        // Each with its own properties, their case kept, and event time if it has one
+        assert_eq!(
+            messages[0].properties,
+            vec![
+                ("env".to_owned(), "prod".to_owned()),
+                ("Trace-Id".to_owned(), "a".to_owned())
+            ]
+        );
+        assert_eq!(
+            messages[0].event_time,
+            Some(Timestamp::from_millis(1_614_834_000_000))
+        );
+        assert_eq!(messages[0].payload, b"first");
+        assert!(messages[1].properties.is_empty());
+        assert_eq!(
+            messages[1].event_time,
+            Some(Timestamp::Time(time("2021-03-04T05:06:00Z")))
+        );
+        assert!(messages[1].payload.is_empty());
+        assert_eq!(
+            messages[2].properties,
+            vec![("env".to_owned(), "test".to_owned())]
+        );
+        assert_eq!(messages[2].payload, b"third");
+    }
+
+    #[test]
+    fn truncated_batch() {
+        let body = batch_body(&[
+            (&[("env", "prod")][..], None, &b"first"[..]),
+            (&[], None, &b"second"[..]),
+        ]);
+        let batch_size = |size| headers(&[(BATCH_SIZE_HEADER, size)]);
+        assert_eq!(
+            AdminMessage::from_response(&batch_size("2"), &body)
+                .unwrap()
+                .len(),
+            2
+        );
+        // More messages than the body has
+        assert!(AdminMessage::from_response(&batch_size("3"), &body).is_err());
+        // Cut in the payload, metadata and size of the last message
+        for cut in &[3, 7, 11] {
+            assert!(
+                AdminMessage::from_response(&batch_size("2"), &body[..body.len() - cut]).is_err(),
+                "{}",
+                cut
+            );
+        }
+    }
+}
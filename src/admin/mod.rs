@@ -0,0 +1,422 @@
+use crate::{
+    auth::{Auth, AuthenticationFailed},
+    connection::TimeoutOpts,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    proxy,
+    tls::{load_ca_chain, TlsConfig},
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::{bail, format_err, Result};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::PathBuf,
+    time::Duration,
+};
+use structopt::StructOpt;
+use url::Url;
+
+pub mod messages;
+pub mod stats;
+
+use messages::AdminMessage;
+use stats::{InternalStats, TopicStats};
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "The options of the admin API client")]
+#[derive(StructOpt)]
+pub struct AdminOpts {
+    /// Base URL of the Pulsar admin REST API [default: derived from --url, e.g.
+    /// https://host:8443 for pulsar+ssl://host:6651]
+    #[structopt(long, env = "PULSAR_ADMIN_URL")]
+    pub admin_url: Option<Url>,
+
+    /// Token sent as a bearer token to the admin API, instead of --auth-token
+    #[structopt(long, env = "PULSAR_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// PEM file with the CA certificate(s) to trust for the admin API, instead of --tls-ca-cert
+    #[structopt(long, env = "PULSAR_ADMIN_TLS_CA_CERT")]
+    pub admin_tls_ca_cert: Option<PathBuf>,
+
+    /// Skip verification of the admin API's TLS certificate [env: PULSAR_ADMIN_TLS_INSECURE]
+    #[structopt(long)]
+    pub admin_tls_insecure: bool,
+}
+
+/// The admin API URL of a cluster by the usual conventions, e.g. `http://host:8080` for
+/// `pulsar://host:6650` and `https://host:8443` for `pulsar+ssl://host:6651`
+fn derive_admin_url(service_url: &Url) -> Result<Url> {
+    let scheme = match service_url.scheme() {
+        "pulsar" => "http",
+        "pulsar+ssl" => "https",
+        _ => bail!(
+            "Can't derive the admin API URL from {}, pass --admin-url",
+            service_url
+        ),
+    };
+    let host = service_url
+        .host_str()
+        .ok_or_else(|| format_err!("No host in {}, pass --admin-url", service_url))?;
+    let port = if scheme == "https" { 8443 } else { 8080 };
+    Ok(Url::parse(&format!("{}://{}:{}/", scheme, host, port))?)
+}
+
+/// Client for the Pulsar admin REST API (`/admin/v2`)
+pub struct AdminClient {
+    http: reqwest::Client,
+    base_url: Url,
+    auth: Auth,
+    proxy: Option<Url>,
+    connect_timeout: Duration,
+}
+
+/// A non-successful response from the admin API
+#[derive(Debug)]
+pub struct AdminError {
+    pub status: StatusCode,
+    pub url: Url,
+    pub reason: String,
+}
+
+impl fmt::Display for AdminError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} returned {}", self.url, self.status)?;
+        if !self.reason.is_empty() {
+            write!(f, ": {}", self.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+impl AdminError {
+    /// Returns the admin error in `error`'s chain, if any
+    pub fn find(error: &anyhow::Error) -> Option<&AdminError> {
+        error.chain().find_map(|e| e.downcast_ref::<AdminError>())
+    }
+
+    /// Whether `error` is the admin API answering 404
+    pub fn is_not_found(error: &anyhow::Error) -> bool {
+        Self::find(error).map_or(false, |e| e.status == StatusCode::NOT_FOUND)
+    }
+}
+
+impl AdminClient {
+    /// Builds a client using `tls` and `auth` unless the admin-specific options override them,
+    /// for the admin API of the cluster at `service_url` unless `--admin-url` is given
+    pub fn new(
+        opts: &AdminOpts,
+        service_url: &Url,
+        tls: &TlsConfig,
+        auth: &Auth,
+        timeouts: &TimeoutOpts,
+        proxy: Option<&Url>,
+    ) -> Result<Self> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect_timeout.into())
+            .timeout(timeouts.operation_timeout.into())
+            .danger_accept_invalid_certs(opts.admin_tls_insecure || tls.insecure)
+            .danger_accept_invalid_hostnames(!tls.hostname_verification);
+        let ca_chain = match &opts.admin_tls_ca_cert {
+            Some(path) => Some(load_ca_chain(path)?),
+            None => tls.ca_chain.clone(),
+        };
+        if let Some(pem) = ca_chain {
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        if let Some((cert, key)) = &tls.identity {
+            builder = builder.identity(reqwest::Identity::from_pkcs8_pem(cert, key)?);
+        }
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+
+        Ok(Self {
+            http: builder.build()?,
+            base_url: match &opts.admin_url {
+                Some(url) => url.clone(),
+                None => {
+                    let url = derive_admin_url(service_url)?;
+                    log::info!(
+                        "Using the admin API at {}, derived from {}",
+                        url,
+                        service_url
+                    );
+                    url
+                }
+            },
+            auth: match &opts.admin_token {
+                Some(token) => Auth::Token(token.clone()),
+                None => auth.clone(),
+            },
+            proxy: proxy.cloned(),
+            connect_timeout: timeouts.connect_timeout.into(),
+        })
+    }
+
+    /// The admin API URL requests are relative to
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// `path` is relative to the admin API or, with a leading `/`, to the web service, e.g. for
+    /// the lookup service
+    fn url(&self, path: &str) -> Result<Url> {
+        match path.strip_prefix('/') {
+            Some(path) => Ok(self.base_url.join(path)?),
+            None => Ok(self.base_url.join(&format!("admin/v2/{}", path))?),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<reqwest::Response> {
+        match self.try_request(method.clone(), path, body).await {
+            Err(e)
+                if e.downcast_ref::<AuthenticationFailed>().is_some()
+                    && self.auth.forget_rejected().await =>
+            {
+                self.try_request(method, path, body).await
+            }
+            result => result,
+        }
+    }
+
+    async fn try_request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<reqwest::Response> {
+        let url = self.url(path)?;
+        log::debug!("{} {}", method, url);
+        let mut request = self
+            .auth
+            .authorize(self.http.request(method, url.clone()))
+            .await?;
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(e) if e.is_connect() || e.is_timeout() => {
+                let code = if e.is_timeout() {
+                    exit::TIMEOUT
+                } else {
+                    exit::UNREACHABLE
+                };
+                let mut message = format!("Admin API at {} is unreachable: {}", self.base_url, e);
+                if let Some(proxy) = &self.proxy {
+                    message.push_str(&format!(
+                        " ({})",
+                        proxy::failed_leg(proxy, self.connect_timeout).await
+                    ));
+                }
+                return Err(ExitError::new(code, message).into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        // Pulsar reports failures as `{"reason": "..."}`, but proxies in front of it may not
+        let body = response.text().await.unwrap_or_default();
+        let reason = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|v| v.get("reason").and_then(Value::as_str).map(str::to_owned))
+            .unwrap_or(body);
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(self.auth.rejected_by(url.as_str(), reason).into());
+        }
+        Err(AdminError {
+            status,
+            url,
+            reason,
+        }
+        .into())
+    }
+
+    /// Fetches and deserializes the JSON document at `path`
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        Ok(self.request(Method::GET, path, None).await?.json().await?)
+    }
+
+    /// Fetches an endpoint answering in plain text rather than JSON
+    pub async fn get_text(&self, path: &str) -> Result<String> {
+        Ok(self.request(Method::GET, path, None).await?.text().await?)
+    }
+
+    /// Fetches an entry's messages from one of the endpoints returning message contents
+    pub async fn get_messages(&self, path: &str) -> Result<Vec<AdminMessage>> {
+        let response = self.request(Method::GET, path, None).await?;
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        AdminMessage::from_response(&headers, &body)
+    }
+
+    /// Puts `body`, if any, as JSON, ignoring the response
+    pub async fn put(&self, path: &str, body: Option<&Value>) -> Result<()> {
+        self.request(Method::PUT, path, body).await?;
+        Ok(())
+    }
+
+    /// Posts `body`, if any, as JSON, ignoring the response
+    pub async fn post(&self, path: &str, body: Option<&Value>) -> Result<()> {
+        self.request(Method::POST, path, body).await?;
+        Ok(())
+    }
+
+    /// Posts to an endpoint that answers with a JSON document
+    pub async fn post_for<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<T> {
+        Ok(self.request(Method::POST, path, body).await?.json().await?)
+    }
+
+    /// Deletes the resource at `path`
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        self.request(Method::DELETE, path, None).await?;
+        Ok(())
+    }
+
+    /// Number of partitions of `topic`, 0 if it isn't partitioned
+    pub async fn partitions(&self, topic: &TopicName) -> Result<u32> {
+        let metadata: Value = self
+            .get(&format!("{}/partitions", topic.admin_path()))
+            .await?;
+        Ok(metadata
+            .get("partitions")
+            .and_then(Value::as_u64)
+            .unwrap_or(0) as u32)
+    }
+
+    /// Raw stats document of `topic`, aggregated over all partitions for partitioned topics
+    pub async fn topic_stats_raw(&self, topic: &TopicName) -> Result<Value> {
+        if self.partitions(topic).await? > 0 {
+            self.get(&format!(
+                "{}/partitioned-stats?perPartition=true",
+                topic.admin_path()
+            ))
+            .await
+        } else {
+            self.get(&format!("{}/stats", topic.admin_path())).await
+        }
+    }
+
+    /// Stats of `topic`, aggregated over all partitions for partitioned topics
+    pub async fn topic_stats(&self, topic: &TopicName) -> Result<TopicStats> {
+        Ok(serde_json::from_value(self.topic_stats_raw(topic).await?)?)
+    }
+
+    /// The partitions of `topic`, or the topic itself if it isn't partitioned
+    pub async fn partition_names(&self, topic: &TopicName) -> Result<Vec<TopicName>> {
+        let partitions = self.partitions(topic).await?;
+        if partitions == 0 {
+            Ok(vec![topic.clone()])
+        } else {
+            Ok((0..partitions).map(|i| topic.partition(i)).collect())
+        }
+    }
+
+    /// Internal stats of a single non-partitioned topic or partition
+    pub async fn internal_stats(&self, topic: &TopicName) -> Result<InternalStats> {
+        self.get(&format!("{}/internalStats", topic.admin_path()))
+            .await
+    }
+
+    /// ID of the last message published on a single non-partitioned topic or partition
+    pub async fn last_message_id(&self, topic: &TopicName) -> Result<MessageId> {
+        MessageId::from_admin_json(
+            self.get(&format!("{}/lastMessageId", topic.admin_path()))
+                .await?,
+        )
+    }
+
+    /// Mark-delete position of each subscription's cursor, one entry per partition
+    pub async fn mark_delete_positions(
+        &self,
+        topic: &TopicName,
+    ) -> Result<BTreeMap<String, Vec<(TopicName, String)>>> {
+        let mut positions = BTreeMap::<_, Vec<_>>::new();
+        for partition in self.partition_names(topic).await? {
+            for (subscription, cursor) in self.internal_stats(&partition).await?.cursors {
+                positions
+                    .entry(subscription)
+                    .or_default()
+                    .push((partition.clone(), cursor.mark_delete_position));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// The range of the bundle of its namespace a single non-partitioned topic or partition
+    /// belongs to, e.g. 0x40000000_0x80000000
+    pub async fn topic_bundle(&self, topic: &TopicName) -> Result<String> {
+        let body = self
+            .get_text(&format!("/lookup/v2/topic/{}/bundle", topic.admin_path()))
+            .await?;
+        Ok(body.trim().trim_matches('"').to_owned())
+    }
+
+    /// Topics of `namespace`, including the individual partitions of partitioned topics
+    pub async fn topics(&self, namespace: &NamespaceName, persistent: bool) -> Result<Vec<String>> {
+        self.get(&namespace.topics_admin_path(persistent)).await
+    }
+
+    /// Partitioned topics of `namespace`
+    pub async fn partitioned_topics(
+        &self,
+        namespace: &NamespaceName,
+        persistent: bool,
+    ) -> Result<Vec<String>> {
+        self.get(&format!(
+            "{}/partitioned",
+            namespace.topics_admin_path(persistent)
+        ))
+        .await
+    }
+
+    /// Topics of `namespace`, partitioned topics by their own name rather than their partitions'
+    pub async fn top_level_topics(
+        &self,
+        namespace: &NamespaceName,
+        persistent: bool,
+    ) -> Result<BTreeSet<String>> {
+        let mut topics = BTreeSet::new();
+        for topic in self.topics(namespace, persistent).await? {
+            if topic.parse::<TopicName>()?.partitioned_parent().is_none() {
+                topics.insert(topic);
+            }
+        }
+        topics.extend(self.partitioned_topics(namespace, persistent).await?);
+        Ok(topics)
+    }
+
+    /// Admin API path of `subscription` on `topic`
+    pub fn subscription_path(topic: &TopicName, subscription: &str) -> String {
+        format!(
+            "{}/subscription/{}",
+            topic.admin_path(),
+            url::form_urlencoded::byte_serialize(subscription.as_bytes()).collect::<String>()
+        )
+    }
+
+    /// Names of the subscriptions of `topic`
+    pub async fn subscriptions(&self, topic: &TopicName) -> Result<Vec<String>> {
+        self.get(&format!("{}/subscriptions", topic.admin_path()))
+            .await
+    }
+}
@@ -0,0 +1,135 @@
+//! Typed views over the topic stats documents returned by the admin API. Only the fields
+//! pulsar-cli uses are declared; everything else is ignored.
+
+use crate::message_id::MessageId;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// The stats of a topic, its rates and those of its producers and subscriptions
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TopicStats {
+    pub msg_rate_in: f64,
+    pub msg_rate_out: f64,
+    pub msg_throughput_in: f64,
+    pub msg_throughput_out: f64,
+    pub storage_size: u64,
+    pub publishers: Vec<PublisherStats>,
+    pub subscriptions: BTreeMap<String, SubscriptionStats>,
+    /// Per-partition stats, only present for partitioned topics
+    pub partitions: BTreeMap<String, TopicStats>,
+    /// Geo-replication to each remote cluster
+    pub replication: BTreeMap<String, ReplicatorStats>,
+}
+
+/// The geo-replication of a topic to one remote cluster
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReplicatorStats {
+    pub msg_rate_in: f64,
+    pub msg_rate_out: f64,
+    /// Messages not replicated to the remote cluster yet
+    pub replication_backlog: u64,
+    pub connected: bool,
+    pub replication_delay_in_seconds: i64,
+}
+
+/// A producer connected to a topic
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PublisherStats {
+    pub producer_name: String,
+    pub address: String,
+    pub msg_rate_in: f64,
+}
+
+/// A subscription of a topic, with its backlog and consumers
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubscriptionStats {
+    #[serde(rename = "type")]
+    pub sub_type: String,
+    pub msg_rate_out: f64,
+    pub msg_backlog: u64,
+    /// Size of the backlog in bytes, only reported by recent brokers
+    pub backlog_size: u64,
+    pub unacked_messages: u64,
+    pub last_acked_timestamp: i64,
+    /// Whether its state is replicated to the remote clusters, with snapshots of the cursor
+    pub is_replicated: bool,
+    pub consumers: Vec<ConsumerStats>,
+}
+
+/// A consumer connected to a subscription
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ConsumerStats {
+    pub consumer_name: String,
+    pub address: String,
+    pub msg_rate_out: f64,
+    pub unacked_messages: u64,
+    pub connected_since: String,
+    pub available_permits: i64,
+    pub blocked_consumer_on_unacked_msgs: bool,
+}
+
+/// The internal (managed ledger) stats of a single, non-partitioned topic
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InternalStats {
+    pub number_of_entries: i64,
+    /// Position of the last entry written, as `ledger:entry`
+    pub last_confirmed_entry: String,
+    pub ledgers: Vec<LedgerInfo>,
+    pub cursors: BTreeMap<String, CursorStats>,
+    pub compacted_ledger: CompactedLedgerInfo,
+}
+
+impl InternalStats {
+    /// Estimates the number of entries after `from` up to and including `to`, using the entry
+    /// counts of the ledgers in between. The count of the ledger currently being written to
+    /// isn't known until it is closed, so `to`'s own entry ID is used for it.
+    pub fn entries_between(&self, from: &MessageId, to: &MessageId) -> i64 {
+        if from.ledger_id == to.ledger_id {
+            return (to.entry_id - from.entry_id).max(0);
+        }
+        if from.ledger_id > to.ledger_id {
+            return 0;
+        }
+
+        let mut entries = to.entry_id + 1;
+        for ledger in self.ledgers.iter() {
+            if ledger.ledger_id == from.ledger_id {
+                entries += (ledger.entries - from.entry_id - 1).max(0);
+            } else if ledger.ledger_id > from.ledger_id && ledger.ledger_id < to.ledger_id {
+                entries += ledger.entries;
+            }
+        }
+        entries
+    }
+}
+
+/// One of the ledgers a topic is stored in
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LedgerInfo {
+    pub ledger_id: i64,
+    pub entries: i64,
+}
+
+/// The ledger holding the result of the last compaction, with a ledger ID of -1 if the topic
+/// was never compacted
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CompactedLedgerInfo {
+    pub ledger_id: i64,
+    pub entries: i64,
+    pub size: u64,
+}
+
+/// The cursor of a subscription in the internal stats
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CursorStats {
+    pub mark_delete_position: String,
+}
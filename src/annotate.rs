@@ -0,0 +1,223 @@
+//! Marking messages while tailing a topic, for `consume --annotate`: pressing `m` appends the
+//! latest message to a notes file as a JSON line, with when and an optional comment typed
+//! after it. Keys are read on a thread of their own with the terminal in cbreak mode (keys
+//! are read as they are pressed, without echo, output and Ctrl-C working as usual), so that
+//! messages keep flowing, and the terminal is put back as it was on dropping the `Annotator`
+
+use crate::exit::{self, ExitError};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::mpsc;
+
+/// What was typed
+pub enum Event {
+    /// `m` was pressed
+    Mark,
+    /// The comment typed after `m`, if any
+    Comment(Option<String>),
+}
+
+pub struct Annotator {
+    path: PathBuf,
+    notes: File,
+    events: mpsc::UnboundedReceiver<Event>,
+    latest: Option<Value>,
+    /// The message marked and when, waiting for its comment
+    marked: Option<(Value, DateTime<Utc>)>,
+    annotated: usize,
+    terminal: Terminal,
+}
+
+impl Annotator {
+    /// Puts the terminal in cbreak mode and starts reading keys, failing unless both stdin and
+    /// stdout are terminals
+    pub fn start(path: &Path) -> Result<Self> {
+        if !termion::is_tty(&io::stdin()) || !termion::is_tty(&io::stdout()) {
+            return Err(ExitError::new(
+                exit::USAGE,
+                "--annotate reads keys from the terminal, it needs stdin and stdout to be one",
+            )
+            .into());
+        }
+        let notes = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed opening notes file {}", path.display()))?;
+        let terminal = Terminal::cbreak()?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let (original, cbreak, active) =
+            (terminal.original, terminal.cbreak, terminal.active.clone());
+        // Blocking on stdin until the process exits
+        std::thread::spawn(move || read_keys(sender, original, cbreak, active));
+        info!("Press m to save the latest message to {}", path.display());
+        Ok(Self {
+            path: path.to_owned(),
+            notes,
+            events,
+            latest: None,
+            marked: None,
+            annotated: 0,
+            terminal,
+        })
+    }
+
+    /// Keeps `message`, as `DisplayMessage::to_json` has it, as the one `m` marks
+    pub fn received(&mut self, message: Value) {
+        self.latest = Some(message);
+    }
+
+    /// Resolves with what is typed next, never once stdin is closed
+    pub async fn next(&mut self) -> Event {
+        match self.events.recv().await {
+            Some(event) => event,
+            None => futures::future::pending().await,
+        }
+    }
+
+    pub fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Mark => match &self.latest {
+                Some(message) => self.marked = Some((message.clone(), Utc::now())),
+                None => warn!("No message received yet, nothing to mark"),
+            },
+            Event::Comment(comment) => {
+                if let Some((message, marked_at)) = self.marked.take() {
+                    self.write(message, marked_at, comment)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write(
+        &mut self,
+        message: Value,
+        marked_at: DateTime<Utc>,
+        comment: Option<String>,
+    ) -> Result<()> {
+        let note = json!({
+            "markedAt": marked_at,
+            "comment": comment,
+            "message": message,
+        });
+        writeln!(self.notes, "{}", note)
+            .with_context(|| format!("Failed writing to notes file {}", self.path.display()))?;
+        self.annotated += 1;
+        info!(
+            "Saved message {} to {}",
+            self.annotated,
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Saves the message marked if its comment is still being typed, and the summary line
+    pub fn finish(mut self) -> Result<(String, Value)> {
+        if let Some((message, marked_at)) = self.marked.take() {
+            self.write(message, marked_at, None)?;
+        }
+        self.terminal.restore();
+        Ok((
+            format!(
+                "Annotations: {} messages saved to {}",
+                self.annotated,
+                self.path.display()
+            ),
+            json!({ "annotated": self.annotated, "notesFile": self.path }),
+        ))
+    }
+}
+
+fn read_keys(
+    sender: mpsc::UnboundedSender<Event>,
+    original: libc::termios,
+    cbreak: libc::termios,
+    active: Arc<AtomicBool>,
+) {
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let mut key = [0];
+    while let Ok(1) = stdin.read(&mut key) {
+        if !matches!(key[0], b'm' | b'M') || sender.send(Event::Mark).is_err() {
+            continue;
+        }
+        // Typed like any line, with echo and editing
+        let _ = set_attributes(&original);
+        eprint!("Comment on the marked message (Enter for none): ");
+        let mut comment = String::new();
+        let read = stdin.read_line(&mut comment);
+        if active.load(Ordering::SeqCst) {
+            let _ = set_attributes(&cbreak);
+        }
+        let comment = comment.trim();
+        let comment = if comment.is_empty() {
+            None
+        } else {
+            Some(comment.to_owned())
+        };
+        if sender.send(Event::Comment(comment)).is_err() || !matches!(read, Ok(n) if n > 0) {
+            break;
+        }
+    }
+}
+
+/// The settings of the terminal on stdin, put back as they were on dropping
+struct Terminal {
+    original: libc::termios,
+    cbreak: libc::termios,
+    /// Whether cbreak mode is still wanted, for the thread reading keys
+    active: Arc<AtomicBool>,
+}
+
+impl Terminal {
+    fn cbreak() -> Result<Self> {
+        // Safe: termios is plain data, filled in by tcgetattr
+        let mut original = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(io::Error::last_os_error()).context("Failed reading the terminal settings");
+        }
+        let mut cbreak = original;
+        cbreak.c_lflag &= !(libc::ICANON | libc::ECHO);
+        cbreak.c_cc[libc::VMIN] = 1;
+        cbreak.c_cc[libc::VTIME] = 0;
+        set_attributes(&cbreak).context("Failed setting the terminal to cbreak mode")?;
+        Ok(Self {
+            original,
+            cbreak,
+            active: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    fn restore(&self) {
+        if self.active.swap(false, Ordering::SeqCst) {
+            if let Err(e) = set_attributes(&self.original) {
+                warn!("Failed restoring the terminal settings: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+fn set_attributes(termios: &libc::termios) -> io::Result<()> {
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
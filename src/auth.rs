@@ -0,0 +1,538 @@
+//! Authentication settings, shared by the binary protocol connections and the admin API client
+
+use anyhow::{bail, format_err, Context, Result};
+use log::{debug, info};
+use pulsar::authentication::oauth2::{OAuth2Authentication, OAuth2Params};
+use serde::Deserialize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use structopt::{clap::ArgMatches, StructOpt};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// How long before it expires an OAuth2 access token is replaced
+const OAUTH2_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// How long `--auth-token-command` has to print a token
+const TOKEN_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "The options authenticating to brokers and the admin API")]
+#[derive(StructOpt)]
+pub struct AuthOpts {
+    /// Token to authenticate with
+    #[structopt(long, env = "PULSAR_TOKEN", hide_env_values = true)]
+    pub auth_token: Option<String>,
+
+    /// File containing the token to authenticate with, taking precedence over --auth-token.
+    /// It is read again on every connection attempt, so rotated tokens are picked up
+    #[structopt(long, env = "PULSAR_AUTH_TOKEN_FILE")]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Shell command printing the token to authenticate with, e.g. `vault read -field=token
+    /// secret/pulsar`, taking precedence over --auth-token-file. It is run again when the token
+    /// is rejected or --auth-refresh-interval passed, the new token being used on reconnecting
+    #[structopt(long, env = "PULSAR_AUTH_TOKEN_COMMAND")]
+    pub auth_token_command: Option<String>,
+
+    /// How long a token printed by --auth-token-command is used before running it again
+    #[structopt(
+        long,
+        env = "PULSAR_AUTH_REFRESH_INTERVAL",
+        requires = "auth-token-command"
+    )]
+    pub auth_refresh_interval: Option<humantime::Duration>,
+
+    /// Issuer to get OAuth2 access tokens from with the client credentials flow
+    #[structopt(
+        long,
+        env = "PULSAR_AUTH_OAUTH2_ISSUER_URL",
+        requires = "auth-oauth2-credentials-file"
+    )]
+    pub auth_oauth2_issuer_url: Option<Url>,
+
+    /// JSON key file with the OAuth2 client_id and client_secret
+    #[structopt(
+        long,
+        env = "PULSAR_AUTH_OAUTH2_CREDENTIALS_FILE",
+        requires = "auth-oauth2-issuer-url"
+    )]
+    pub auth_oauth2_credentials_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "PULSAR_AUTH_OAUTH2_AUDIENCE",
+        requires = "auth-oauth2-issuer-url"
+    )]
+    pub auth_oauth2_audience: Option<String>,
+
+    #[structopt(
+        long,
+        env = "PULSAR_AUTH_OAUTH2_SCOPE",
+        requires = "auth-oauth2-issuer-url"
+    )]
+    pub auth_oauth2_scope: Option<String>,
+
+    /// Basic auth credentials, as user:password
+    #[structopt(long, env = "PULSAR_AUTH_BASIC", hide_env_values = true)]
+    pub auth_basic: Option<String>,
+
+    /// File containing the basic auth credentials, as user:password
+    #[structopt(long, env = "PULSAR_AUTH_BASIC_FILE")]
+    pub auth_basic_file: Option<PathBuf>,
+}
+
+/// The arguments of all the authentication methods
+const AUTH_ARGS: &[&str] = &[
+    "auth-token",
+    "auth-token-file",
+    "auth-token-command",
+    "auth-oauth2-issuer-url",
+    "auth-oauth2-credentials-file",
+    "auth-oauth2-audience",
+    "auth-oauth2-scope",
+    "auth-basic",
+    "auth-basic-file",
+];
+
+impl AuthOpts {
+    /// Authentication methods exclude each other, so when one is given on the command line
+    /// whatever the environment has for the others is dropped rather than mixed with it
+    pub fn ignore_environment_if_given(&mut self, matches: &ArgMatches) {
+        let given = |name: &str| matches.occurrences_of(name) > 0;
+        if !AUTH_ARGS.iter().any(|name| given(name)) {
+            return;
+        }
+        if !given("auth-token") {
+            self.auth_token = None;
+        }
+        if !given("auth-token-file") {
+            self.auth_token_file = None;
+        }
+        if !given("auth-token-command") {
+            self.auth_token_command = None;
+            self.auth_refresh_interval = None;
+        }
+        if !given("auth-oauth2-issuer-url") {
+            self.auth_oauth2_issuer_url = None;
+            self.auth_oauth2_credentials_file = None;
+            self.auth_oauth2_audience = None;
+            self.auth_oauth2_scope = None;
+        }
+        if !given("auth-basic") {
+            self.auth_basic = None;
+        }
+        if !given("auth-basic-file") {
+            self.auth_basic_file = None;
+        }
+    }
+}
+
+/// How to authenticate. Deliberately not `Debug`, so that a token can't end up in the logs
+#[derive(Clone)]
+pub enum Auth {
+    None,
+    Token(String),
+    TokenFile(PathBuf),
+    TokenCommand(Arc<TokenCommand>),
+    OAuth2(Arc<OAuth2>),
+    Basic { user: String, password: String },
+}
+
+impl Auth {
+    /// Checks that a token or credentials file can be read up front, rather than failing on
+    /// the first connection
+    pub fn load(opts: &AuthOpts) -> Result<Self> {
+        if opts.auth_basic.is_some() && opts.auth_basic_file.is_some() {
+            bail!("Only one of --auth-basic and --auth-basic-file can be given");
+        }
+        if let Some(credentials) = &opts.auth_basic {
+            return Self::basic(credentials, "--auth-basic");
+        }
+        if let Some(path) = &opts.auth_basic_file {
+            let credentials = std::fs::read_to_string(path).map_err(|e| {
+                format_err!("Failed reading credentials file {}: {}", path.display(), e)
+            })?;
+            return Self::basic(credentials.trim(), &path.display().to_string());
+        }
+        let auth = match (
+            &opts.auth_oauth2_issuer_url,
+            &opts.auth_token_command,
+            &opts.auth_token_file,
+            &opts.auth_token,
+        ) {
+            (Some(issuer_url), _, _, _) => Self::OAuth2(Arc::new(OAuth2::load(issuer_url, opts)?)),
+            (None, Some(command), _, _) => Self::TokenCommand(Arc::new(TokenCommand::new(
+                command,
+                opts.auth_refresh_interval.map(Into::into),
+            ))),
+            (None, None, Some(path), _) => Self::TokenFile(path.clone()),
+            (None, None, None, Some(token)) => Self::Token(token.clone()),
+            (None, None, None, None) => Self::None,
+        };
+        if let Self::TokenFile(path) = &auth {
+            read_token_file(path)?;
+        }
+        Ok(auth)
+    }
+
+    fn basic(credentials: &str, source: &str) -> Result<Self> {
+        // Not showing the credentials, which contain a password
+        let (user, password) = credentials
+            .split_once(':')
+            .ok_or_else(|| format_err!("Expected user:password in {}", source))?;
+        Ok(Self::Basic {
+            user: user.to_owned(),
+            password: password.to_owned(),
+        })
+    }
+
+    /// The token to send as a bearer token, if any
+    async fn token(&self) -> Result<Option<String>> {
+        match self {
+            Self::None | Self::Basic { .. } => Ok(None),
+            Self::Token(token) => Ok(Some(token.clone())),
+            Self::TokenFile(path) => Ok(Some(read_token_file(path)?)),
+            Self::TokenCommand(command) => Ok(Some(command.token().await?)),
+            Self::OAuth2(oauth2) => Ok(Some(oauth2.access_token().await?)),
+        }
+    }
+
+    /// Adds the credentials to an admin API request
+    pub async fn authorize(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::RequestBuilder> {
+        if let Self::Basic { user, password } = self {
+            return Ok(request.basic_auth(user, Some(password)));
+        }
+        Ok(match self.token().await? {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    /// Applies the authentication to a Pulsar client builder
+    pub async fn apply(
+        &self,
+        builder: pulsar::PulsarBuilder<pulsar::TokioExecutor>,
+    ) -> Result<pulsar::PulsarBuilder<pulsar::TokioExecutor>> {
+        Ok(match self {
+            Self::None => builder,
+            Self::Token(_) | Self::TokenFile(_) | Self::TokenCommand(_) => {
+                builder.with_auth(pulsar::Authentication {
+                    name: "token".to_owned(),
+                    data: self.token().await?.unwrap_or_default().into_bytes(),
+                })
+            }
+            Self::OAuth2(oauth2) => {
+                // The client library refreshes tokens itself, but fetching one first reports an
+                // unreachable issuer or invalid credentials as such
+                oauth2.access_token().await?;
+                builder
+                    .with_auth_provider(OAuth2Authentication::client_credentials(oauth2.params()))
+            }
+            Self::Basic { user, password } => builder.with_auth(pulsar::Authentication {
+                name: "basic".to_owned(),
+                data: format!("{}:{}", user, password).into_bytes(),
+            }),
+        })
+    }
+
+    /// What is presented to authenticate, for error messages
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::None => "anonymous connection",
+            Self::Token(_) | Self::TokenFile(_) | Self::TokenCommand(_) => "token",
+            Self::OAuth2(_) => "OAuth2 access token",
+            Self::Basic { .. } => "basic auth user",
+        }
+    }
+
+    /// After the credentials were rejected, drops the token --auth-token-command printed so
+    /// that it is run again. Whether there is a new token to try
+    pub async fn forget_rejected(&self) -> bool {
+        match self {
+            Self::TokenCommand(command) => {
+                info!("The token was rejected, running --auth-token-command again");
+                command.forget().await;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The error to report when `target` refused the credentials
+    pub fn rejected_by(&self, target: &str, reason: impl fmt::Display) -> AuthenticationFailed {
+        AuthenticationFailed {
+            message: format!("{} rejected by {}: {}", self.describe(), target, reason),
+        }
+    }
+}
+
+fn read_token_file(path: &Path) -> Result<String> {
+    let token = std::fs::read_to_string(path)
+        .map_err(|e| format_err!("Failed reading token file {}: {}", path.display(), e))?;
+    Ok(token.trim().to_owned())
+}
+
+/// Credentials were presented and refused
+#[derive(Debug)]
+pub struct AuthenticationFailed {
+    message: String,
+}
+
+impl fmt::Display for AuthenticationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Authentication failed: {}", self.message)
+    }
+}
+
+impl std::error::Error for AuthenticationFailed {}
+
+/// Whether a broker refused to connect because of the credentials presented
+pub fn is_rejected(error: &pulsar::Error) -> bool {
+    matches!(
+        error,
+        pulsar::Error::Connection(pulsar::error::ConnectionError::PulsarError(
+            Some(pulsar::proto::ServerError::AuthenticationError),
+            _
+        ))
+    )
+}
+
+/// A shell command printing a token, run again once its token is too old or was rejected
+pub struct TokenCommand {
+    command: String,
+    refresh_interval: Option<Duration>,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl TokenCommand {
+    fn new(command: &str, refresh_interval: Option<Duration>) -> Self {
+        Self {
+            command: command.to_owned(),
+            refresh_interval,
+            token: Mutex::new(None),
+        }
+    }
+
+    async fn token(&self) -> Result<String> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, obtained_at)) = &*cached {
+            let expired = self
+                .refresh_interval
+                .map_or(false, |interval| obtained_at.elapsed() >= interval);
+            if !expired {
+                return Ok(token.clone());
+            }
+        }
+        let token = self.run().await?;
+        *cached = Some((token.clone(), Instant::now()));
+        Ok(token)
+    }
+
+    async fn forget(&self) {
+        *self.token.lock().await = None;
+    }
+
+    /// Runs the command, its errors telling what it wrote to stderr but never the token
+    async fn run(&self) -> Result<String> {
+        debug!("Running --auth-token-command {:?}", self.command);
+        let child = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed running --auth-token-command {:?}", self.command))?;
+        let output = tokio::time::timeout(TOKEN_COMMAND_TIMEOUT, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                format_err!(
+                    "--auth-token-command {:?} printed no token within {}",
+                    self.command,
+                    humantime::format_duration(TOKEN_COMMAND_TIMEOUT)
+                )
+            })??;
+        if !output.status.success() {
+            bail!(
+                "--auth-token-command {:?} failed ({}): {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let token = String::from_utf8(output.stdout)
+            .map_err(|_| {
+                format_err!(
+                    "--auth-token-command {:?} printed invalid UTF-8",
+                    self.command
+                )
+            })?
+            .trim()
+            .to_owned();
+        if token.is_empty() {
+            bail!("--auth-token-command {:?} printed no token", self.command);
+        }
+        Ok(token)
+    }
+}
+
+/// Client credentials flow against an OAuth2 issuer, keeping the access token until it expires
+pub struct OAuth2 {
+    issuer_url: Url,
+    credentials_file: PathBuf,
+    credentials: OAuth2Credentials,
+    audience: Option<String>,
+    scope: Option<String>,
+    http: reqwest::Client,
+    access_token: Mutex<Option<(String, Instant)>>,
+}
+
+/// The JSON key file issued for a service account
+#[derive(Deserialize)]
+struct OAuth2Credentials {
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Deserialize)]
+struct OpenIdConfiguration {
+    token_endpoint: Url,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+impl OAuth2 {
+    fn load(issuer_url: &Url, opts: &AuthOpts) -> Result<Self> {
+        let path = opts
+            .auth_oauth2_credentials_file
+            .as_ref()
+            .ok_or_else(|| format_err!("--auth-oauth2-credentials-file is required for OAuth2"))?;
+        let contents = std::fs::read(path).map_err(|e| {
+            format_err!(
+                "Failed reading OAuth2 credentials file {}: {}",
+                path.display(),
+                e
+            )
+        })?;
+        let credentials = serde_json::from_slice(&contents).map_err(|e| {
+            format_err!("Invalid OAuth2 credentials file {}: {}", path.display(), e)
+        })?;
+        Ok(Self {
+            issuer_url: issuer_url.clone(),
+            // Passed to the client library as a file:// URL, which needs an absolute path
+            credentials_file: path.canonicalize()?,
+            credentials,
+            audience: opts.auth_oauth2_audience.clone(),
+            scope: opts.auth_oauth2_scope.clone(),
+            http: reqwest::Client::new(),
+            access_token: Mutex::new(None),
+        })
+    }
+
+    fn params(&self) -> OAuth2Params {
+        OAuth2Params {
+            issuer_url: self.issuer_url.to_string(),
+            credentials_url: format!("file://{}", self.credentials_file.display()),
+            audience: self.audience.clone(),
+            scope: self.scope.clone(),
+        }
+    }
+
+    /// A valid access token, requesting a new one if there is none yet or it is about to expire
+    async fn access_token(&self) -> Result<String> {
+        let mut cached = self.access_token.lock().await;
+        if let Some((token, expires_at)) = &*cached {
+            if Instant::now() + OAUTH2_EXPIRY_MARGIN < *expires_at {
+                return Ok(token.clone());
+            }
+        }
+        let response = self.request_token().await?;
+        let lifetime = Duration::from_secs(response.expires_in.unwrap_or(3600));
+        *cached = Some((response.access_token.clone(), Instant::now() + lifetime));
+        Ok(response.access_token)
+    }
+
+    async fn request_token(&self) -> Result<TokenResponse> {
+        let unreachable = || format!("OAuth2 issuer {} is unreachable", self.issuer_url);
+        let discovery_url = Url::parse(&format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer_url.as_str().trim_end_matches('/')
+        ))?;
+        let configuration: OpenIdConfiguration = self
+            .http
+            .get(discovery_url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(unreachable)?
+            .json()
+            .await
+            .with_context(|| format!("Invalid OpenID configuration from {}", self.issuer_url))?;
+
+        let mut form = vec![
+            ("grant_type", "client_credentials"),
+            ("client_id", self.credentials.client_id.as_str()),
+            ("client_secret", self.credentials.client_secret.as_str()),
+        ];
+        if let Some(audience) = &self.audience {
+            form.push(("audience", audience));
+        }
+        if let Some(scope) = &self.scope {
+            form.push(("scope", scope));
+        }
+        let response = self
+            .http
+            .post(configuration.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .with_context(unreachable)?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+        let body = response.text().await.unwrap_or_default();
+        match serde_json::from_str::<TokenErrorResponse>(&body) {
+            Ok(error) if status.is_client_error() => Err(AuthenticationFailed {
+                message: format!(
+                    "invalid OAuth2 client credentials in {}: {}{}",
+                    self.credentials_file.display(),
+                    error.error,
+                    error
+                        .error_description
+                        .map(|description| format!(" ({})", description))
+                        .unwrap_or_default()
+                ),
+            }
+            .into()),
+            _ => Err(format_err!(
+                "OAuth2 issuer {} returned {}: {}",
+                self.issuer_url,
+                status,
+                body
+            )),
+        }
+    }
+}
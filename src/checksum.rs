@@ -0,0 +1,120 @@
+//! Digests of payloads: shown by `consume --show-checksum`, attached by `produce
+//! --prop-checksum` and checked by `consume --verify-checksum-prop`, so that payloads changed
+//! on the way (e.g. by a forwarding or transforming pipeline) are noticed
+
+use anyhow::{format_err, Result};
+use md5::Md5;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::str::FromStr;
+
+/// Property holding the digest of the payload, as `<algorithm>:<hex digest>`
+pub const CHECKSUM_PROPERTY: &str = "pulsar-cli-checksum";
+
+#[derive(Clone, Copy)]
+pub enum ChecksumAlgo {
+    Crc32c,
+    Sha256,
+    Md5,
+}
+
+impl FromStr for ChecksumAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "crc32c" => Ok(ChecksumAlgo::Crc32c),
+            "sha256" => Ok(ChecksumAlgo::Sha256),
+            "md5" => Ok(ChecksumAlgo::Md5),
+            other => Err(format_err!("Invalid checksum algorithm: {:?}", other)),
+        }
+    }
+}
+
+impl ChecksumAlgo {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgo::Crc32c => "crc32c",
+            ChecksumAlgo::Sha256 => "sha256",
+            ChecksumAlgo::Md5 => "md5",
+        }
+    }
+
+    /// The digest of `payload`, in hexadecimal
+    pub fn digest(self, payload: &[u8]) -> String {
+        match self {
+            ChecksumAlgo::Crc32c => format!("{:08x}", crc32c::crc32c(payload)),
+            ChecksumAlgo::Sha256 => format!("{:x}", Sha256::digest(payload)),
+            ChecksumAlgo::Md5 => format!("{:x}", Md5::digest(payload)),
+        }
+    }
+
+    /// The value of `CHECKSUM_PROPERTY` for `payload`
+    pub fn property(self, payload: &[u8]) -> String {
+        format!("{}:{}", self.name(), self.digest(payload))
+    }
+}
+
+/// The outcome of checking payloads against their `CHECKSUM_PROPERTY`
+#[derive(Default)]
+pub struct ChecksumCheck {
+    checked: u64,
+    mismatched: u64,
+    missing: u64,
+}
+
+impl ChecksumCheck {
+    /// Checks `payload` against `property`, the value of its `CHECKSUM_PROPERTY` if it has one,
+    /// giving why it doesn't match if it doesn't
+    pub fn check(&mut self, payload: &[u8], property: Option<&str>) -> Option<String> {
+        self.checked += 1;
+        let property = match property {
+            Some(property) => property,
+            None => {
+                self.missing += 1;
+                return None;
+            }
+        };
+        let (algo, expected) = match property.split_once(':') {
+            Some((algo, expected)) => match algo.parse::<ChecksumAlgo>() {
+                Ok(algo) => (algo, expected),
+                Err(e) => {
+                    self.mismatched += 1;
+                    return Some(format!("{} {:?}: {}", CHECKSUM_PROPERTY, property, e));
+                }
+            },
+            None => {
+                self.mismatched += 1;
+                return Some(format!("invalid {} {:?}", CHECKSUM_PROPERTY, property));
+            }
+        };
+        let actual = algo.digest(payload);
+        if actual.eq_ignore_ascii_case(expected) {
+            None
+        } else {
+            self.mismatched += 1;
+            Some(format!(
+                "{} {} of the payload, {} expected by {}",
+                algo.name(),
+                actual,
+                expected,
+                CHECKSUM_PROPERTY
+            ))
+        }
+    }
+
+    /// How many payloads were checked and didn't match, as text and as JSON
+    pub fn summary(&self) -> (String, Value) {
+        (
+            format!(
+                "Checksums: {} of {} payloads mismatched, {} without {}",
+                self.mismatched, self.checked, self.missing, CHECKSUM_PROPERTY
+            ),
+            json!({
+                "checked": self.checked,
+                "mismatched": self.mismatched,
+                "missing": self.missing,
+            }),
+        )
+    }
+}
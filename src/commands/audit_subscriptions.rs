@@ -0,0 +1,262 @@
+use crate::{
+    admin::{stats::TopicStats, AdminClient, AdminError},
+    commands::backlog::{self, Backlog},
+    confirm::confirm,
+    exit::{self, ExitError},
+    output::{self, Report},
+    topic_name::{NamespaceName, TopicName},
+    units::format_bytes,
+};
+use anyhow::Result;
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use serde_json::json;
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+use structopt::StructOpt;
+use termion::color;
+
+/// The options of `audit-subscriptions`
+#[derive(StructOpt)]
+pub struct AuditSubscriptionsOpts {
+    #[structopt(long)]
+    namespace: NamespaceName,
+
+    /// Flag the subscriptions without consumers whose oldest unacked message is older than
+    /// this as stale
+    #[structopt(long, default_value = "7d")]
+    stale_after: humantime::Duration,
+
+    /// Delete the stale subscriptions, after asking
+    #[structopt(long)]
+    delete_stale: bool,
+
+    /// Don't ask for confirmation
+    #[structopt(long, requires = "delete-stale")]
+    yes: bool,
+
+    /// How many partitions are polled at the same time
+    #[structopt(long, default_value = "8")]
+    concurrency: usize,
+}
+
+/// A subscription of the namespace, partitioned topics adding up their partitions
+#[derive(Default)]
+struct Audited {
+    backlog: Backlog,
+    consumers: usize,
+}
+
+/// Lists the subscriptions of the namespace without consumers, deleting the stale ones if asked
+pub async fn run(admin: &AdminClient, opts: &AuditSubscriptionsOpts) -> Result<Report> {
+    let subscriptions = poll(admin, opts).await?;
+    let now = Utc::now();
+    let stale_after = chrono::Duration::from_std(opts.stale_after.into())?;
+
+    let idle = subscriptions
+        .iter()
+        .filter(|(_, audited)| audited.consumers == 0)
+        .collect::<Vec<_>>();
+    let topic_width = idle
+        .iter()
+        .map(|((topic, _), _)| topic.len())
+        .chain(Some("TOPIC".len()))
+        .max()
+        .unwrap_or_default();
+    let subscription_width = idle
+        .iter()
+        .map(|((_, subscription), _)| subscription.len())
+        .chain(Some("SUBSCRIPTION".len()))
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:topic_width$}  {:subscription_width$}  {:>10}  {:>10}  {:>16}",
+        "TOPIC",
+        "SUBSCRIPTION",
+        "BACKLOG",
+        "SIZE",
+        "MARK-DELETE AGE",
+        topic_width = topic_width,
+        subscription_width = subscription_width
+    )?;
+    let mut listed = Vec::new();
+    let mut stale = Vec::new();
+    for ((topic, subscription), audited) in idle {
+        // The mark-delete position is as old as the first message after it
+        let age = audited.backlog.head.map(|head| now - head);
+        let is_stale = age.map_or(false, |age| age > stale_after);
+        let describe_age = age
+            .map(|age| {
+                humantime::format_duration(Duration::from_secs(age.num_seconds().max(0) as u64))
+                    .to_string()
+            })
+            .unwrap_or_else(|| "-".to_owned());
+        let line = format!(
+            "{:topic_width$}  {:subscription_width$}  {:>10}  {:>10}  {:>16}",
+            topic,
+            subscription,
+            audited.backlog.messages,
+            format_bytes(audited.backlog.bytes as f64),
+            describe_age,
+            topic_width = topic_width,
+            subscription_width = subscription_width
+        );
+        if is_stale {
+            writeln!(
+                out,
+                "{}{}  stale{}",
+                color::Fg(color::Red),
+                line,
+                color::Fg(color::Reset)
+            )?;
+            stale.push((topic.clone(), subscription.clone()));
+        } else {
+            writeln!(out, "{}", line)?;
+        }
+        listed.push(json!({
+            "topic": topic,
+            "subscription": subscription,
+            "backlog": audited.backlog.messages,
+            "backlogBytes": audited.backlog.bytes,
+            "head": audited.backlog.head,
+            "markDeleteAgeSeconds": age.map(|age| age.num_seconds().max(0)),
+            "stale": is_stale,
+        }));
+    }
+    writeln!(
+        out,
+        "{} of {} subscriptions without consumers, {} stale (older than {})",
+        listed.len(),
+        subscriptions.len(),
+        stale.len(),
+        opts.stale_after
+    )?;
+
+    if !opts.delete_stale || stale.is_empty() {
+        return Ok(Report::new(
+            out,
+            json!({ "subscriptions": listed, "stale": stale.len() }),
+        ));
+    }
+
+    if !output::is_json() {
+        output::write(&out)?;
+    }
+    confirm(
+        &format!(
+            "Delete {} stale subscription(s) from {}?",
+            stale.len(),
+            opts.namespace
+        ),
+        opts.yes,
+    )?;
+    let mut report = Report::default();
+    let mut results = Vec::new();
+    let mut failed = 0;
+    for (topic, subscription) in stale {
+        let path = AdminClient::subscription_path(&topic.parse::<TopicName>()?, &subscription);
+        let error = match admin.delete(&path).await {
+            Ok(()) => {
+                report.line(format!(
+                    "Deleted subscription {:?} from {}",
+                    subscription, topic
+                ));
+                None
+            }
+            Err(e) => {
+                failed += 1;
+                report.line(format!(
+                    "Failed deleting subscription {:?} from {}: {:#}",
+                    subscription, topic, e
+                ));
+                Some(format!("{:#}", e))
+            }
+        };
+        results.push(json!({
+            "topic": topic,
+            "subscription": subscription,
+            "deleted": error.is_none(),
+            "error": error,
+        }));
+    }
+    report.set_json(json!({ "subscriptions": listed, "deleted": results }));
+    if failed > 0 {
+        report.print()?;
+        return Err(ExitError::new(
+            exit::FAILURE,
+            format!("Failed deleting {} stale subscription(s)", failed),
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+/// Every subscription of the namespace's persistent topics, by topic and subscription
+async fn poll(
+    admin: &AdminClient,
+    opts: &AuditSubscriptionsOpts,
+) -> Result<BTreeMap<(String, String), Audited>> {
+    let mut partitions = Vec::new();
+    for topic in admin.topics(&opts.namespace, true).await? {
+        let partition = topic.parse::<TopicName>()?;
+        let topic = partition
+            .partitioned_parent()
+            .map_or_else(|| partition.to_string(), |(parent, _)| parent.to_string());
+        partitions.push((topic, partition));
+    }
+
+    let mut polled = stream::iter(partitions)
+        .map(|(topic, partition)| async move {
+            (topic, poll_partition(admin, &partition).await, partition)
+        })
+        .buffer_unordered(opts.concurrency.max(1));
+    let mut subscriptions = BTreeMap::<_, Audited>::new();
+    while let Some((topic, result, partition)) = polled.next().await {
+        match result {
+            Ok(polled) => {
+                for (subscription, backlog, consumers) in polled {
+                    let audited = subscriptions
+                        .entry((topic.clone(), subscription))
+                        .or_default();
+                    audited.backlog.add(&backlog);
+                    audited.consumers += consumers;
+                }
+            }
+            // Deleted since the topics were listed
+            Err(e) if AdminError::is_not_found(&e) => debug!("{} is gone: {}", partition, e),
+            Err(e) => warn!("Failed polling {}: {:#}", partition, e),
+        }
+    }
+    Ok(subscriptions)
+}
+
+/// The backlog and number of connected consumers of each subscription of a partition
+async fn poll_partition(
+    admin: &AdminClient,
+    partition: &TopicName,
+) -> Result<Vec<(String, Backlog, usize)>> {
+    let stats: TopicStats = admin
+        .get(&format!("{}/stats", partition.admin_path()))
+        .await?;
+    let internal = admin.internal_stats(partition).await?;
+    let mut subscriptions = Vec::with_capacity(stats.subscriptions.len());
+    for (subscription, subscription_stats) in stats.subscriptions.iter() {
+        let backlog = backlog::subscription_backlog(
+            admin,
+            partition,
+            subscription,
+            subscription_stats,
+            &internal,
+        )
+        .await?;
+        subscriptions.push((
+            subscription.clone(),
+            backlog,
+            subscription_stats.consumers.len(),
+        ));
+    }
+    Ok(subscriptions)
+}
@@ -0,0 +1,194 @@
+use crate::{
+    admin::{
+        stats::{InternalStats, SubscriptionStats},
+        AdminClient, AdminError,
+    },
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::Report,
+    picker,
+    topic_name::TopicName,
+    units::format_bytes,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// The options of `backlog`
+#[derive(StructOpt)]
+pub struct BacklogOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Picked among the subscriptions of the topic when not given, on a terminal
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    /// Break the backlog down by partition
+    #[structopt(long)]
+    per_partition: bool,
+}
+
+/// The backlog of a subscription, or the sum of those of its partitions
+#[derive(Default)]
+pub struct Backlog {
+    pub messages: u64,
+    pub entries: i64,
+    pub bytes: u64,
+    /// Publish time of the oldest message not acknowledged yet
+    pub head: Option<DateTime<Utc>>,
+}
+
+impl Backlog {
+    /// Adds `other` to this backlog, e.g. that of another partition
+    pub fn add(&mut self, other: &Backlog) {
+        self.messages += other.messages;
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self.head = match (self.head, other.head) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} messages in {} entries ({})",
+            self.messages,
+            self.entries,
+            format_bytes(self.bytes as f64)
+        )
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "messages": self.messages,
+            "entries": self.entries,
+            "bytes": self.bytes,
+            "head": self.head,
+        })
+    }
+}
+
+/// Estimates the backlog of the subscription, per partition if asked
+pub async fn run(admin: &AdminClient, opts: &BacklogOpts) -> Result<Report> {
+    let subscription =
+        &picker::subscription(admin, &opts.topic, opts.subscription.as_deref()).await?;
+    let mut report = Report::default();
+    let mut total = Backlog::default();
+    let mut partitions = serde_json::Map::new();
+    for partition in admin.partition_names(&opts.topic).await? {
+        let backlog = partition_backlog(admin, &partition, subscription).await?;
+        if opts.per_partition {
+            report.line(format!(
+                "{}: {}, head {}",
+                partition,
+                backlog.describe(),
+                describe_age(backlog.head)
+            ));
+            partitions.insert(partition.to_string(), backlog.to_json());
+        }
+        total.add(&backlog);
+    }
+
+    report.line(format!(
+        "Backlog of {:?} on {}: {}",
+        subscription,
+        opts.topic,
+        total.describe()
+    ));
+    match total.head {
+        Some(head) => report.line(format!(
+            "Oldest unprocessed message is {} old (published {})",
+            describe_age(Some(head)),
+            head
+        )),
+        None => report.line("No unprocessed messages"),
+    }
+    let mut json = total.to_json();
+    json["topic"] = json!(opts.topic.to_string());
+    json["subscription"] = json!(subscription);
+    if opts.per_partition {
+        json["partitions"] = Value::Object(partitions);
+    }
+    report.set_json(json);
+    Ok(report)
+}
+
+async fn partition_backlog(
+    admin: &AdminClient,
+    partition: &TopicName,
+    subscription: &str,
+) -> Result<Backlog> {
+    let stats = admin.topic_stats(partition).await?;
+    let subscription_stats = stats.subscriptions.get(subscription).ok_or_else(|| {
+        ExitError::new(
+            exit::NOT_FOUND,
+            format!("Subscription {:?} not found on {}", subscription, partition),
+        )
+    })?;
+    let internal = admin.internal_stats(partition).await?;
+    subscription_backlog(
+        admin,
+        partition,
+        subscription,
+        subscription_stats,
+        &internal,
+    )
+    .await
+}
+
+/// The backlog of `subscription` on a single partition, given the partition's stats
+pub async fn subscription_backlog(
+    admin: &AdminClient,
+    partition: &TopicName,
+    subscription: &str,
+    subscription_stats: &SubscriptionStats,
+    internal: &InternalStats,
+) -> Result<Backlog> {
+    // Entries are counted from the cursor's mark-delete position to the last written entry
+    let entries = match (
+        internal.cursors.get(subscription),
+        internal.last_confirmed_entry.parse::<MessageId>(),
+    ) {
+        (Some(cursor), Ok(last)) => match cursor.mark_delete_position.parse::<MessageId>() {
+            Ok(mark_delete) => internal.entries_between(&mark_delete, &last),
+            Err(_) => 0,
+        },
+        _ => 0,
+    };
+
+    let head = if subscription_stats.msg_backlog == 0 {
+        None
+    } else {
+        let path = format!(
+            "{}/position/1",
+            AdminClient::subscription_path(partition, subscription)
+        );
+        match admin.get_messages(&path).await {
+            Ok(messages) => messages.first().and_then(|message| message.publish_time),
+            // The backlog was consumed in the meantime
+            Err(e) if AdminError::is_not_found(&e) => None,
+            Err(e) => return Err(e),
+        }
+    };
+
+    Ok(Backlog {
+        messages: subscription_stats.msg_backlog,
+        entries,
+        bytes: subscription_stats.backlog_size,
+        head,
+    })
+}
+
+fn describe_age(head: Option<DateTime<Utc>>) -> String {
+    match head {
+        Some(head) => {
+            let age = (Utc::now() - head).to_std().unwrap_or_default();
+            humantime::format_duration(Duration::from_secs(age.as_secs())).to_string()
+        }
+        None => "none".to_owned(),
+    }
+}
@@ -0,0 +1,407 @@
+use crate::{
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    display::property,
+    metrics::{describe_latencies, STATS},
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{info, warn};
+use pulsar::{proto::CompressionType, ConsumerOptions, ProducerOptions, SubType};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+use tokio::sync::watch;
+
+const RUN_ID_PROPERTY: &str = "pulsar-cli-bench-run";
+const SEQUENCE_PROPERTY: &str = "pulsar-cli-bench-sequence";
+/// When the message was sent, in nanoseconds since the epoch
+const SENT_AT_PROPERTY: &str = "pulsar-cli-bench-sent-at";
+
+/// Shortest time between two rounds of sending, the messages due in between being sent together
+const MIN_TICK: Duration = Duration::from_millis(1);
+
+/// The options of `bench`
+#[derive(StructOpt)]
+pub struct BenchOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Messages to publish per second
+    #[structopt(long, default_value = "1000")]
+    rate: u32,
+
+    /// How long to publish for
+    #[structopt(long, default_value = "60s")]
+    duration: humantime::Duration,
+
+    #[structopt(long, default_value = "100")]
+    payload_size: usize,
+
+    /// Send messages in batches of this many, the last one being sent once publishing is done
+    #[structopt(long)]
+    batch_size: Option<u32>,
+
+    #[structopt(
+        long,
+        default_value = "none",
+        possible_values = &["none", "lz4", "zlib", "zstd", "snappy"]
+    )]
+    compression: Compression,
+
+    #[structopt(
+        long,
+        default_value = "exclusive",
+        possible_values = &["exclusive", "shared", "failover", "key-shared"]
+    )]
+    subscription_type: SubscriptionType,
+
+    /// How long to wait for the messages still on their way once publishing is done
+    #[structopt(long, default_value = "10s")]
+    drain_timeout: humantime::Duration,
+}
+
+#[derive(Clone, Copy)]
+enum Compression {
+    None,
+    Lz4,
+    Zlib,
+    Zstd,
+    Snappy,
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "lz4" => Ok(Compression::Lz4),
+            "zlib" => Ok(Compression::Zlib),
+            "zstd" => Ok(Compression::Zstd),
+            "snappy" => Ok(Compression::Snappy),
+            other => Err(format_err!("Invalid compression: {:?}", other)),
+        }
+    }
+}
+
+impl Compression {
+    fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+            Compression::Snappy => "snappy",
+        }
+    }
+
+    fn to_proto(self) -> Option<CompressionType> {
+        match self {
+            Compression::None => None,
+            Compression::Lz4 => Some(CompressionType::Lz4),
+            Compression::Zlib => Some(CompressionType::Zlib),
+            Compression::Zstd => Some(CompressionType::Zstd),
+            Compression::Snappy => Some(CompressionType::Snappy),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SubscriptionType {
+    Exclusive,
+    Shared,
+    Failover,
+    KeyShared,
+}
+
+impl FromStr for SubscriptionType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "exclusive" => Ok(SubscriptionType::Exclusive),
+            "shared" => Ok(SubscriptionType::Shared),
+            "failover" => Ok(SubscriptionType::Failover),
+            "key-shared" => Ok(SubscriptionType::KeyShared),
+            other => Err(format_err!("Invalid subscription type: {:?}", other)),
+        }
+    }
+}
+
+impl SubscriptionType {
+    fn name(self) -> &'static str {
+        match self {
+            SubscriptionType::Exclusive => "exclusive",
+            SubscriptionType::Shared => "shared",
+            SubscriptionType::Failover => "failover",
+            SubscriptionType::KeyShared => "key-shared",
+        }
+    }
+
+    fn to_sub_type(self) -> SubType {
+        match self {
+            SubscriptionType::Exclusive => SubType::Exclusive,
+            SubscriptionType::Shared => SubType::Shared,
+            SubscriptionType::Failover => SubType::Failover,
+            SubscriptionType::KeyShared => SubType::KeyShared,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Published {
+    published: u64,
+    failed: u64,
+    latencies: Vec<Duration>,
+    elapsed: Duration,
+}
+
+#[derive(Default)]
+struct Received {
+    sequences: HashSet<u64>,
+    duplicated: u64,
+    end_to_end: Vec<Duration>,
+    /// When the last message arrived, since publishing started
+    elapsed: Duration,
+}
+
+/// Publishes and consumes for the duration of the benchmark and reports how it went
+pub async fn run(url: &ServiceUrls, connector: &Connector, opts: &BenchOpts) -> Result<Report> {
+    let run_id = format!("{}-{}", Utc::now().timestamp_millis(), std::process::id());
+    let topic = opts.topic.to_string();
+    let subscription = format!("pulsar-cli-bench-{}", run_id);
+    let duration: Duration = opts.duration.into();
+    let count = (f64::from(opts.rate) * duration.as_secs_f64()) as u64;
+
+    // Subscribe first, so that the consumer starting at the latest message sees every message
+    let mut consumer = connector
+        .build_consumer(
+            url,
+            &ConsumerParams {
+                topic: &topic,
+                subscription: &subscription,
+                consumer_name: &subscription,
+                sub_type: opts.subscription_type.to_sub_type(),
+                options: ConsumerOptions {
+                    durable: Some(false),
+                    ..Default::default()
+                },
+            },
+        )
+        .await?;
+    let mut producer = connector
+        .build_producer_with_options(
+            url,
+            &topic,
+            None,
+            ProducerOptions {
+                batch_size: opts.batch_size,
+                compression: opts.compression.to_proto(),
+                ..Default::default()
+            },
+        )
+        .await?;
+    info!(
+        "Publishing {} messages to {} over {} with run {}",
+        count,
+        topic,
+        humantime::format_duration(duration),
+        run_id
+    );
+
+    // Tells the consumer how many messages were published once publishing is done
+    let (done, mut finished) = watch::channel(None);
+    let start = Instant::now();
+    let produce = async {
+        let interval = (Duration::from_secs(1) / opts.rate.max(1)).max(MIN_TICK);
+        let mut ticks = connection::pace(interval, true);
+        let payload = vec![b'x'; opts.payload_size];
+        let mut receipts = Vec::with_capacity(count as usize);
+        let mut failed = 0;
+        let mut sequence = 0;
+        while sequence < count {
+            ticks.tick().await;
+            // As many messages as are due by now, so that the rate holds even above one message
+            // per tick
+            let due = ((f64::from(opts.rate) * start.elapsed().as_secs_f64()) as u64).min(count);
+            while sequence < due {
+                let mut properties = HashMap::new();
+                properties.insert(RUN_ID_PROPERTY.to_owned(), run_id.clone());
+                properties.insert(SEQUENCE_PROPERTY.to_owned(), sequence.to_string());
+                properties.insert(
+                    SENT_AT_PROPERTY.to_owned(),
+                    Utc::now().timestamp_nanos().to_string(),
+                );
+                sequence += 1;
+                let sent = Instant::now();
+                let message = pulsar::producer::Message {
+                    payload: payload.clone(),
+                    properties,
+                    ..Default::default()
+                };
+                match producer.send(message).await {
+                    // Awaited in the background so the latency of each receipt is measured when
+                    // it arrives, not when the last message is sent
+                    Ok(receipt) => receipts.push(tokio::spawn(async move {
+                        receipt.await.map(|_| sent.elapsed())
+                    })),
+                    Err(e) => {
+                        warn!("Failed publishing to {}: {}", topic, e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        if opts.batch_size.is_some() {
+            producer.send_batch().await?;
+        }
+
+        let mut published = Published {
+            failed,
+            ..Default::default()
+        };
+        for receipt in receipts {
+            match receipt.await? {
+                Ok(latency) => {
+                    STATS.produced(&payload);
+                    published.published += 1;
+                    published.latencies.push(latency);
+                }
+                Err(e) => {
+                    warn!("Failed publishing to {}: {}", topic, e);
+                    published.failed += 1;
+                }
+            }
+        }
+        published.elapsed = start.elapsed();
+        let _ = done.send(Some((published.published, tokio::time::Instant::now())));
+        Ok::<_, anyhow::Error>(published)
+    };
+
+    let drain_timeout: Duration = opts.drain_timeout.into();
+    let consume = async {
+        let mut received = Received::default();
+        loop {
+            let state = *finished.borrow();
+            let deadline = match state {
+                Some((published, _)) if received.sequences.len() as u64 >= published => break,
+                Some((_, at)) => Some(at + drain_timeout),
+                None => None,
+            };
+            let next = async {
+                match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, consumer.try_next())
+                        .await
+                        .ok(),
+                    None => Some(consumer.try_next().await),
+                }
+            };
+            let message = tokio::select! {
+                message = next => match message {
+                    Some(message) => {
+                        message?.ok_or_else(|| format_err!("Consumer of {} closed", topic))?
+                    }
+                    None => break,
+                },
+                _ = finished.changed() => continue,
+            };
+            let now = Utc::now().timestamp_nanos();
+            connection::ack_with_retry(&mut consumer, &message, false, connection::ACK_RETRIES)
+                .await?;
+            if property(&message, RUN_ID_PROPERTY) != Some(run_id.as_str()) {
+                continue;
+            }
+            STATS.consumed(&message.payload.data);
+            STATS.observe_latency(message.metadata().publish_time);
+
+            let sequence = property(&message, SEQUENCE_PROPERTY)
+                .and_then(|sequence| sequence.parse::<u64>().ok())
+                .ok_or_else(|| format_err!("Message without a valid sequence number"))?;
+            if !received.sequences.insert(sequence) {
+                received.duplicated += 1;
+                continue;
+            }
+            if let Some(sent_at) =
+                property(&message, SENT_AT_PROPERTY).and_then(|at| at.parse::<i64>().ok())
+            {
+                let elapsed = (now - sent_at).max(0);
+                received
+                    .end_to_end
+                    .push(Duration::from_nanos(elapsed as u64));
+            }
+            received.elapsed = start.elapsed();
+        }
+        Ok::<_, anyhow::Error>(received)
+    };
+
+    let (published, received) = tokio::join!(produce, consume);
+    let mut published = published?;
+    let mut received = received?;
+
+    let lost = published
+        .published
+        .saturating_sub(received.sequences.len() as u64);
+    let rate = |messages: u64, elapsed: Duration| messages as f64 / elapsed.as_secs_f64().max(1e-9);
+    let (publish_rate, receive_rate) = (
+        rate(published.published, published.elapsed),
+        rate(received.sequences.len() as u64, received.elapsed),
+    );
+
+    let mut report = Report::default();
+    report.line(format!(
+        "Published {} in {:.1}s ({:.1} msg/s), {} failed",
+        published.published,
+        published.elapsed.as_secs_f64(),
+        publish_rate,
+        published.failed
+    ));
+    report.line(format!(
+        "Received {} ({:.1} msg/s): {} lost, {} duplicated",
+        received.sequences.len(),
+        receive_rate,
+        lost,
+        received.duplicated
+    ));
+    let mut json = json!({
+        "rate": opts.rate,
+        "payloadSize": opts.payload_size,
+        "batchSize": opts.batch_size,
+        "compression": opts.compression.name(),
+        "subscriptionType": opts.subscription_type.name(),
+        "published": published.published,
+        "failed": published.failed,
+        "received": received.sequences.len(),
+        "lost": lost,
+        "duplicated": received.duplicated,
+        "publishRate": publish_rate,
+        "receiveRate": receive_rate,
+    });
+    for (label, key, latencies) in [
+        (
+            "Publish latency",
+            "publishLatencyMs",
+            &mut published.latencies,
+        ),
+        (
+            "End-to-end latency",
+            "endToEndLatencyMs",
+            &mut received.end_to_end,
+        ),
+    ]
+    .iter_mut()
+    {
+        if let Some((text, percentiles)) = describe_latencies(label, latencies) {
+            report.line(text);
+            json[*key] = percentiles;
+        }
+    }
+    report.set_json(json);
+    Ok(report)
+}
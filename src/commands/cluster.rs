@@ -0,0 +1,163 @@
+use crate::{
+    admin::AdminClient,
+    commands::stats::print_or_watch,
+    output::{self, Report},
+};
+use anyhow::Result;
+use colored_json::to_colored_json_auto;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt::Write};
+use structopt::StructOpt;
+
+/// The options of `clusters`
+#[derive(StructOpt)]
+pub struct ClustersOpts {
+    #[structopt(long)]
+    json: bool,
+
+    /// Keep refreshing the list at this interval
+    #[structopt(long)]
+    watch: Option<humantime::Duration>,
+}
+
+/// The options of `brokers`
+#[derive(StructOpt)]
+pub struct BrokersOpts {
+    /// Only list the brokers of this cluster
+    #[structopt(long)]
+    cluster: Option<String>,
+
+    #[structopt(long)]
+    json: bool,
+
+    /// Keep refreshing the list at this interval
+    #[structopt(long)]
+    watch: Option<humantime::Duration>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+struct ClusterData {
+    #[serde(skip_deserializing)]
+    name: String,
+    service_url: String,
+    service_url_tls: String,
+    broker_service_url: String,
+    broker_service_url_tls: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BrokerInfo {
+    cluster: String,
+    /// The broker's ID, which is the host and port of its web service
+    broker: String,
+    leader: bool,
+    bundles: usize,
+}
+
+/// Lists the clusters
+pub async fn run_clusters(admin: &AdminClient, opts: &ClustersOpts) -> Result<Report> {
+    print_or_watch(opts.watch, || async {
+        let mut clusters = Vec::new();
+        for name in cluster_names(admin, None).await? {
+            let mut cluster: ClusterData = admin.get(&format!("clusters/{}", name)).await?;
+            cluster.name = name;
+            clusters.push(cluster);
+        }
+        if output::json(opts.json) {
+            return Ok(to_colored_json_auto(&serde_json::to_value(&clusters)?)?);
+        }
+
+        let mut out = String::new();
+        for cluster in clusters.iter() {
+            writeln!(out, "{}", cluster.name)?;
+            for (label, url) in [
+                ("Service URL", &cluster.service_url),
+                ("Service URL (TLS)", &cluster.service_url_tls),
+                ("Broker URL", &cluster.broker_service_url),
+                ("Broker URL (TLS)", &cluster.broker_service_url_tls),
+            ]
+            .iter()
+            {
+                if !url.is_empty() {
+                    writeln!(out, "  {:18} {}", format!("{}:", label), url)?;
+                }
+            }
+        }
+        Ok(out.trim_end().to_owned())
+    })
+    .await
+}
+
+/// Lists the active brokers with the bundles they own
+pub async fn run_brokers(admin: &AdminClient, opts: &BrokersOpts) -> Result<Report> {
+    print_or_watch(opts.watch, || async {
+        let leader = admin
+            .get::<Value>("brokers/leaderBroker")
+            .await
+            .ok()
+            .and_then(|leader| leader.get("serviceUrl")?.as_str().map(str::to_owned));
+
+        let mut brokers = Vec::new();
+        for cluster in cluster_names(admin, opts.cluster.as_deref()).await? {
+            let mut ids = admin
+                .get::<Vec<String>>(&format!("brokers/{}", cluster))
+                .await?;
+            ids.sort();
+            for broker in ids {
+                let bundles = admin
+                    .get::<BTreeMap<String, Value>>(&format!(
+                        "brokers/{}/{}/ownedNamespaces",
+                        cluster, broker
+                    ))
+                    .await?
+                    .len();
+                brokers.push(BrokerInfo {
+                    leader: leader
+                        .as_deref()
+                        .map_or(false, |leader| leader.ends_with(&broker)),
+                    cluster: cluster.clone(),
+                    broker,
+                    bundles,
+                });
+            }
+        }
+        if output::json(opts.json) {
+            return Ok(to_colored_json_auto(&serde_json::to_value(&brokers)?)?);
+        }
+
+        let width = brokers
+            .iter()
+            .map(|broker| broker.broker.len())
+            .chain(Some("BROKER".len()))
+            .max()
+            .unwrap_or_default();
+        let mut out = String::new();
+        writeln!(out, "{:width$}  CLUSTER  BUNDLES", "BROKER", width = width)?;
+        for broker in brokers.iter() {
+            writeln!(
+                out,
+                "{:width$}  {:7}  {}{}",
+                broker.broker,
+                broker.cluster,
+                broker.bundles,
+                if broker.leader { " (leader)" } else { "" },
+                width = width
+            )?;
+        }
+        Ok(out.trim_end().to_owned())
+    })
+    .await
+}
+
+/// The clusters of the instance, sorted, or only `only`
+pub async fn cluster_names(admin: &AdminClient, only: Option<&str>) -> Result<Vec<String>> {
+    if let Some(cluster) = only {
+        return Ok(vec![cluster.to_owned()]);
+    }
+    let mut names = admin.get::<Vec<String>>("clusters").await?;
+    names.sort();
+    Ok(names)
+}
@@ -0,0 +1,120 @@
+use crate::{
+    admin::AdminClient,
+    output::{self, Report},
+    topic_name::TopicName,
+    units::format_bytes,
+    ADMIN_POLL_INTERVAL,
+};
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::json;
+use std::{collections::BTreeMap, time::Instant};
+use structopt::StructOpt;
+
+/// The options of `compact`
+#[derive(StructOpt)]
+pub struct CompactOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Wait for compaction to finish, failing if it doesn't succeed
+    #[structopt(long)]
+    wait: bool,
+
+    /// How long to wait for with --wait
+    #[structopt(long, default_value = "10m")]
+    timeout: humantime::Duration,
+}
+
+/// Status of a long running operation such as compaction, as reported by the admin API
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OperationStatus {
+    status: String,
+    #[serde(default)]
+    last_error: Option<String>,
+}
+
+/// Triggers the compaction, and waits for it to finish if asked
+pub async fn run(admin: &AdminClient, opts: &CompactOpts) -> Result<Report> {
+    let partitions = admin.partition_names(&opts.topic).await?;
+    let names = partitions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    for partition in partitions.iter() {
+        admin.put(&compaction_path(partition), None).await?;
+        output::progress(format!("Triggered compaction of {}", partition))?;
+    }
+    if !opts.wait {
+        return Ok(Report::new(
+            "",
+            json!({ "topic": opts.topic.to_string(), "triggered": names }),
+        ));
+    }
+
+    let deadline = Instant::now() + opts.timeout.into();
+    let mut running = partitions
+        .iter()
+        .map(|partition| (partition, String::new()))
+        .collect::<BTreeMap<_, _>>();
+    let mut failures = 0;
+    loop {
+        for (partition, last_status) in running.iter_mut() {
+            let status: OperationStatus = admin.get(&compaction_path(partition)).await?;
+            if status.status != *last_status {
+                output::progress(format!("{}: {}", partition, status.status))?;
+                *last_status = status.status.clone();
+            }
+            match status.status.as_str() {
+                "SUCCESS" => {
+                    let ledger = admin.internal_stats(partition).await?.compacted_ledger;
+                    output::progress(format!(
+                        "{}: compacted ledger {} with {} entries ({})",
+                        partition,
+                        ledger.ledger_id,
+                        ledger.entries,
+                        format_bytes(ledger.size as f64)
+                    ))?;
+                }
+                "ERROR" => {
+                    failures += 1;
+                    eprintln!(
+                        "{}: compaction failed: {}",
+                        partition,
+                        status.last_error.unwrap_or_default()
+                    );
+                }
+                _ => {}
+            }
+        }
+        running.retain(|_, status| status != "SUCCESS" && status != "ERROR");
+        if running.is_empty() {
+            break;
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Timed out waiting for the compaction of {} partition(s) of {}",
+                running.len(),
+                opts.topic
+            );
+        }
+        tokio::time::sleep(ADMIN_POLL_INTERVAL).await;
+    }
+
+    if failures > 0 {
+        bail!(
+            "Compaction failed on {} of {} partition(s)",
+            failures,
+            partitions.len()
+        );
+    }
+    Ok(Report::new(
+        format!("Compacted {}", opts.topic),
+        json!({ "topic": opts.topic.to_string(), "compacted": names }),
+    ))
+}
+
+fn compaction_path(topic: &TopicName) -> String {
+    format!("{}/compaction", topic.admin_path())
+}
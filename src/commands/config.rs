@@ -0,0 +1,176 @@
+use crate::{
+    config::{self, Config, SECRET_KEYS},
+    output::Report,
+};
+use anyhow::{bail, format_err, Result};
+use serde_json::json;
+use std::{fs, io::Write, os::unix::fs::OpenOptionsExt};
+use structopt::StructOpt;
+
+/// Profile keys holding booleans rather than strings
+const BOOL_KEYS: &[&str] = &[
+    "tls_insecure",
+    "tls_hostname_verification",
+    "admin_tls_insecure",
+];
+
+/// The subcommands of `config`
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    /// List the profiles, marking the one in use
+    List,
+
+    /// Print the settings of a profile, with secrets masked
+    Show {
+        /// Defaults to the profile in use
+        name: Option<String>,
+    },
+
+    /// Change a setting of a profile, creating the profile if needed
+    Set {
+        profile: String,
+
+        /// Name of the global option, e.g. url, admin-url or auth-token-file, or topic-prefix
+        key: String,
+
+        value: String,
+    },
+}
+
+/// Runs `command`, `selected` being the profile given with `--profile`
+pub fn run(command: &ConfigCommand, selected: Option<&str>) -> Result<Report> {
+    match command {
+        ConfigCommand::List => {
+            let config = Config::load()?;
+            let selected = config.selected(selected)?;
+            let mut report = Report::default();
+            if config.profiles.is_empty() {
+                report.line(format!("No profiles in {}", config::path()?.display()));
+            }
+            for name in config.profiles.keys() {
+                let marker = if Some(name.as_str()) == selected {
+                    "*"
+                } else {
+                    " "
+                };
+                report.line(format!("{} {}", marker, name));
+            }
+            report.set_json(json!({
+                "profiles": config.profiles.keys().collect::<Vec<_>>(),
+                "selected": selected,
+            }));
+            Ok(report)
+        }
+
+        ConfigCommand::Show { name } => {
+            let config = Config::load()?;
+            let name = match name.as_deref() {
+                Some(name) => config.selected(Some(name))?,
+                None => config.selected(selected)?,
+            }
+            .ok_or_else(|| format_err!("No profile in use, pass the name of one to show"))?;
+            let document = read_document()?;
+            let profile = document
+                .get("profiles")
+                .and_then(|profiles| profiles.get(name))
+                .and_then(toml::Value::as_table)
+                .cloned()
+                .unwrap_or_default();
+            let mut report = Report::default();
+            let mut settings = serde_json::Map::new();
+            report.line(format!("[profiles.{}]", name));
+            for (key, value) in profile.iter() {
+                if SECRET_KEYS.contains(&key.as_str()) {
+                    report.line(format!("{} = \"****\"", key));
+                    settings.insert(key.clone(), json!("****"));
+                } else {
+                    report.line(format!("{} = {}", key, value));
+                    settings.insert(key.clone(), serde_json::to_value(value)?);
+                }
+            }
+            report.set_json(json!({ "profile": name, "settings": settings }));
+            Ok(report)
+        }
+
+        ConfigCommand::Set {
+            profile,
+            key,
+            value,
+        } => {
+            let key = key.trim_start_matches("--").replace('-', "_");
+            let value = if BOOL_KEYS.contains(&key.as_str()) {
+                toml::Value::Boolean(
+                    value
+                        .parse()
+                        .map_err(|_| format_err!("{} is either true or false", key))?,
+                )
+            } else {
+                toml::Value::String(value.clone())
+            };
+
+            let mut document = read_document()?;
+            let profiles = document
+                .as_table_mut()
+                .ok_or_else(|| format_err!("Invalid configuration file"))?
+                .entry("profiles")
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+            let settings = profiles
+                .as_table_mut()
+                .ok_or_else(|| format_err!("Invalid profiles in the configuration file"))?
+                .entry(profile.as_str())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+            match settings.as_table_mut() {
+                Some(settings) => settings.insert(key.clone(), value.clone()),
+                None => bail!("Invalid profile {:?} in the configuration file", profile),
+            };
+
+            // Checking the result the same way it is read, so that a typo in the key or an
+            // invalid value doesn't go unnoticed until the profile is next used
+            let contents = toml::to_string_pretty(&document)?;
+            Config::parse(&contents).map_err(|e| format_err!("Not setting {}: {}", key, e))?;
+            write_document(&contents)?;
+            if SECRET_KEYS.contains(&key.as_str()) {
+                Ok(Report::message(format!(
+                    "Set {} of profile {:?}",
+                    key, profile
+                )))
+            } else {
+                Ok(Report::message(format!(
+                    "Set {} of profile {:?} to {}",
+                    key, profile, value
+                )))
+            }
+        }
+    }
+}
+
+/// The configuration file as a TOML document, so that it can be changed without dropping
+/// anything it has
+fn read_document() -> Result<toml::Value> {
+    let path = config::path()?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => contents
+            .parse()
+            .map_err(|e| format_err!("Invalid configuration file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(toml::Value::Table(Default::default()))
+        }
+        Err(e) => bail!("Failed reading {}: {}", path.display(), e),
+    }
+}
+
+fn write_document(contents: &str) -> Result<()> {
+    let path = config::path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // Profiles may hold credentials
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| format_err!("Failed writing {}: {}", path.display(), e))
+}
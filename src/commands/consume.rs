@@ -0,0 +1,1420 @@
+use crate::{
+    ack_hold,
+    admin::AdminClient,
+    annotate, checksum,
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    connection_events, discovery,
+    display::{self, DisplayMessage, MessageFormatter},
+    exit::{self, ExitError},
+    expiry, field_tracker, forwarding, json_schema, kcat,
+    message_id::MessageId,
+    metrics::{self, STATS},
+    ordering,
+    output::{self, Report},
+    picker, preflight, printer, producers, rate_limit, redelivery, routing, silence, sink,
+    template, topic_files,
+    topic_name::{self, NamespaceName, TopicName},
+    units,
+};
+use anyhow::Result;
+use itertools::Itertools;
+use log::info;
+use pulsar::{consumer::InitialPosition, ConsumerOptions, SubType};
+use serde_json::json;
+use std::path::PathBuf;
+use structopt::{clap::ArgMatches, StructOpt};
+
+/// Logged when reconnecting to a non-persistent topic, so that the gap isn't taken for loss
+const RECONNECT_NOTICE: &str =
+    "The messages published on a non-persistent topic while reconnecting aren't received, so a gap is expected";
+
+/// The options of `consume`
+#[derive(StructOpt)]
+pub struct ConsumeOpts {
+    /// Picked among the topics of the namespace when not given, on a terminal
+    #[structopt(long)]
+    topic: Option<String>,
+
+    /// Consume a topic named from this template once at startup, e.g. `orders-test-{{uuid}}`
+    /// or `orders-test-{{now:%Y%m%d%H%M%S}}`, printing the name
+    #[structopt(long, conflicts_with_all = &["topic", "all-topics"])]
+    topic_template: Option<template::Template>,
+
+    /// Write the topic name --topic-template resolved to to this file
+    #[structopt(long, requires = "topic-template")]
+    topic_name_file: Option<PathBuf>,
+
+    /// A template such as those of --topic-template, with `{{hostname}}`, `{{user}}`, `{{pid}}`
+    /// and `{{n}}` as well. By default one per user, so that users of a shared host don't share it
+    #[structopt(long, short = "s", default_value = "pulsar-cli-{{user}}")]
+    subscription_name: template::Template,
+
+    /// A template like --subscription-name's. By default one per process, to tell instances
+    /// apart in the broker's stats
+    #[structopt(long, short = "c", default_value = "pulsar-cli-{{hostname}}-{{pid}}")]
+    consumer_name: template::Template,
+
+    #[structopt(long)]
+    durable: bool,
+
+    #[structopt(long)]
+    json: bool,
+
+    /// Pretty-print payloads as XML documents, warning in red about the ones that aren't
+    #[structopt(long, conflicts_with = "json")]
+    xml: bool,
+
+    #[structopt(long)]
+    shared: bool,
+
+    #[structopt(long)]
+    earliest: bool,
+
+    #[structopt(long)]
+    ack: bool,
+
+    /// How many times to try again acking a message before reconnecting, after which the
+    /// messages not acked yet are received again
+    #[structopt(long, default_value = "3")]
+    ack_retries: usize,
+
+    /// Keep consuming when acking fails even after reconnecting, leaving the messages
+    /// unacked
+    #[structopt(long, requires = "ack")]
+    ignore_ack_errors: bool,
+
+    /// Keep up to this many received messages unacked, acking the oldest once there are
+    /// more, e.g. to act as a slow consumer. How many are held is exposed to
+    /// --metrics-listen and printed when stopped with Ctrl-C
+    #[structopt(long, requires = "ack")]
+    ack_hold: Option<usize>,
+
+    /// Keep each received message unacked for this long before acking it
+    #[structopt(long, requires = "ack")]
+    ack_hold_time: Option<humantime::Duration>,
+
+    /// Also print what the broker knows of the entry each message is part of: its ID,
+    /// producer, sequence ID, publish time and position in its batch
+    #[structopt(long)]
+    show_entry_metadata: bool,
+
+    /// Print the cluster each message was replicated from with geo-replication, or [local]
+    /// for those produced on this cluster
+    #[structopt(long)]
+    show_replicated_from: bool,
+
+    /// Replicate the state of the subscription to the other clusters of a geo-replicated
+    /// namespace, so that consumers failing over to them resume where it is
+    #[structopt(long, requires = "durable", conflicts_with = "all-topics")]
+    replicate_subscription_state: bool,
+
+    /// Warn about messages close to being expired by the namespace's message TTL or
+    /// retention time, e.g. while replaying with --earliest
+    #[structopt(long)]
+    warn_near_expiry: bool,
+
+    /// Check that the messages of each partition key arrive in order of event time and
+    /// sequence ID, printing how many didn't when stopped with Ctrl-C. Forwarded messages
+    /// are tagged with their source position, for --verify-forwarded
+    #[structopt(long)]
+    verify_key_order: bool,
+
+    /// Check that messages forwarded with --verify-key-order kept the order of their key on
+    /// the source topic
+    #[structopt(long, conflicts_with = "verify-key-order")]
+    verify_forwarded: bool,
+
+    /// Consume at most this much payload per second, e.g. 5MB/s, so as not to flood the
+    /// terminal or the cluster forwarded to
+    #[structopt(long, parse(try_from_str = units::parse_byte_rate))]
+    max_rate: Option<u64>,
+
+    /// Consume at most this many messages per second
+    #[structopt(long)]
+    max_msg_rate: Option<u32>,
+
+    /// Reconnect when nothing was received for this long, in case the connection was
+    /// silently dropped (e.g. by a load balancer), printing how many times on Ctrl-C
+    #[structopt(long)]
+    liveness_timeout: Option<humantime::Duration>,
+
+    /// Forward each message, with its key, properties and event time, to this topic
+    #[structopt(long)]
+    forward_to_topic: Option<String>,
+
+    /// Forward each message whose property is the value given to a topic, as
+    /// `<property>=<value>:<topic>`, e.g. `env=prod:persistent://t/ns/prod-copy`, to split a
+    /// topic. Can be given several times, the first route matching applies
+    #[structopt(
+        long = "forward-route",
+        number_of_values = 1,
+        conflicts_with = "forward-to-topic"
+    )]
+    forward_routes: Vec<routing::Route>,
+
+    /// Topic to forward the messages no --forward-route matches to, rather than not forwarding
+    /// them
+    #[structopt(long, requires = "forward-routes")]
+    forward_default: Option<String>,
+
+    /// Cluster to forward to, defaults to --url
+    #[structopt(long)]
+    forward_to_url: Option<ServiceUrls>,
+
+    /// Only consume this partition of a partitioned --topic, by index, e.g. to isolate a
+    /// misbehaving one. Can be given several times, the partition of each message being
+    /// printed then
+    #[structopt(long = "partition", number_of_values = 1, requires = "topic")]
+    partitions: Vec<u32>,
+
+    /// Consume every persistent topic of --namespace, printing the topic of each message and
+    /// picking up the topics created later
+    #[structopt(long, requires = "namespace", conflicts_with_all = &["topic", "partitions"])]
+    all_topics: bool,
+
+    /// Namespace to consume with --all-topics, as <tenant>/<namespace>
+    #[structopt(long, requires = "all-topics")]
+    namespace: Option<NamespaceName>,
+
+    /// How often --all-topics looks for new topics
+    #[structopt(long, default_value = "1m")]
+    topic_refresh: humantime::Duration,
+
+    /// Consume at most this many topics with --all-topics, warning about the others
+    #[structopt(long, default_value = "100")]
+    max_topics: usize,
+
+    /// Warn, naming the most redelivered messages and their producers, when too many of the
+    /// latest messages are ones received before
+    #[structopt(long)]
+    detect_redelivery_storms: bool,
+
+    /// Percentage of redeliveries among the latest --storm-window messages above which it is
+    /// a storm
+    #[structopt(long, default_value = "20")]
+    storm_threshold: f64,
+
+    /// How many of the latest messages --detect-redelivery-storms looks at
+    #[structopt(long, default_value = "1000")]
+    storm_window: usize,
+
+    /// Exit with code 5 on the first redelivery storm, e.g. as a canary in CI
+    #[structopt(long, requires = "detect-redelivery-storms")]
+    fail_on_storm: bool,
+
+    /// Stream the messages as JSON lines to this socket instead of printing them:
+    /// tcp://<host>:<port> or unix://<path>
+    #[structopt(long)]
+    sink: Option<sink::SinkAddress>,
+
+    /// How many messages to buffer while the sink is away, dropping the oldest beyond that
+    #[structopt(long, default_value = "10000")]
+    sink_buffer: usize,
+
+    /// Print, when stopped with Ctrl-C, how many messages and bytes each producer published,
+    /// when, and with which property keys
+    #[structopt(long)]
+    producers_summary: bool,
+
+    /// Order the producers summary by message count or bytes (most first) rather than name
+    #[structopt(
+        long,
+        requires = "producers-summary",
+        possible_values = &["count", "bytes", "name"]
+    )]
+    sort: Option<String>,
+
+    /// Check every payload against this JSON Schema document, printing why in red under the
+    /// messages that don't match, and how many didn't when stopped with Ctrl-C
+    #[structopt(long)]
+    validate_schema: Option<PathBuf>,
+
+    /// Only print the messages that don't match --validate-schema
+    #[structopt(long, requires = "validate-schema")]
+    only_invalid: bool,
+
+    /// Exit with code 10 when stopped if any message didn't match --validate-schema
+    #[structopt(long, requires = "validate-schema")]
+    fail_on_invalid: bool,
+
+    /// Print the digest of each payload
+    #[structopt(long)]
+    show_checksum: bool,
+
+    #[structopt(
+        long,
+        default_value = "crc32c",
+        possible_values = &["crc32c", "sha256", "md5"]
+    )]
+    checksum_algo: checksum::ChecksumAlgo,
+
+    /// Check each payload against the digest produce --prop-checksum attached to it,
+    /// printing the ones that don't match in red, and how many didn't when stopped with
+    /// Ctrl-C
+    #[structopt(long)]
+    verify_checksum_prop: bool,
+
+    /// Write the messages of each topic as JSON lines to a file of its own in this
+    /// directory instead of printing them, e.g. with --all-topics or a partitioned topic, to
+    /// replay them per topic later. Appended to if the files exist
+    #[structopt(long, conflicts_with = "sink")]
+    output_dir_per_topic: Option<PathBuf>,
+
+    /// How many files --output-dir-per-topic keeps open at once, closing the least recently
+    /// written to first
+    #[structopt(long, default_value = "64")]
+    max_open_files: usize,
+
+    /// Instead of printing messages, count the values of this field of their JSON payloads
+    /// over --window, as payload.<path>, e.g. payload.status, refreshing the counts every
+    /// --track-interval: as a table on a terminal, a JSON line otherwise
+    #[structopt(long)]
+    track_field: Option<String>,
+
+    /// How far back --track-field counts
+    #[structopt(long, default_value = "1m")]
+    window: humantime::Duration,
+
+    /// How often --track-field refreshes the counts
+    #[structopt(long, default_value = "2s")]
+    track_interval: humantime::Duration,
+
+    /// Stop capturing to --output-dir-per-topic once this much was written, e.g. 10GB. The
+    /// capture also stops, rather than failing, when the disk is full
+    #[structopt(long, requires = "output-dir-per-topic", parse(try_from_str = units::parse_bytes))]
+    max_capture_bytes: Option<u64>,
+
+    /// Stop capturing to --output-dir-per-topic once this many messages were written
+    #[structopt(long, requires = "output-dir-per-topic")]
+    max_capture_messages: Option<u64>,
+
+    /// Start a new file for a topic of --output-dir-per-topic before it grows past this size,
+    /// e.g. 512MB, the full one renamed with the time it was rotated at
+    #[structopt(long, requires = "output-dir-per-topic", parse(try_from_str = units::parse_bytes))]
+    rotate_size: Option<u64>,
+
+    /// Start a new file for a topic of --output-dir-per-topic once its file is this old, e.g.
+    /// 1h
+    #[structopt(long, requires = "output-dir-per-topic")]
+    rotate_interval: Option<humantime::Duration>,
+
+    /// Gzip the files --rotate-size or --rotate-interval rotated
+    #[structopt(long, requires = "output-dir-per-topic")]
+    rotate_compress: bool,
+
+    /// Once the capture stopped, keep consuming (and acking with --ack) without writing the
+    /// messages, rather than stopping there
+    #[structopt(long, requires = "output-dir-per-topic")]
+    continue_after_capture: bool,
+
+    /// Print an alert when no message was received for this long, e.g. 10m, once per
+    /// silence. Reconnecting doesn't count as receiving
+    #[structopt(long)]
+    alert_after: Option<humantime::Duration>,
+
+    /// Run this shell command on each --alert-after alert, e.g. to page someone, with the
+    /// topic and the silence in $PULSAR_CLI_TOPIC and $PULSAR_CLI_SILENT_FOR
+    #[structopt(long, requires = "alert-after")]
+    alert_exec: Option<String>,
+
+    /// Exit with code 9 on the first --alert-after alert
+    #[structopt(long, requires = "alert-after")]
+    exit_on_silence: bool,
+
+    /// How --output kcat prints each message, as kcat's -f: %t topic, %p partition, %o
+    /// message ID, %k key, %K key length, %s payload, %S payload length, %h properties as
+    /// name=value,..., %T timestamp in milliseconds
+    #[structopt(long, default_value = "%k\\t%s\\n")]
+    kcat_format: kcat::KcatFormat,
+
+    /// What to do when printing can't keep up with the messages received: wait for it, or
+    /// not print the messages it is behind on (still acking and forwarding them), counting
+    /// them when stopped with Ctrl-C
+    #[structopt(
+        long,
+        default_value = "block",
+        possible_values = &["block", "drop-display"]
+    )]
+    overflow: printer::Overflow,
+
+    /// How many received messages wait to be printed at most before --overflow applies
+    #[structopt(long, default_value = "1000")]
+    display_buffer: usize,
+
+    /// Don't check with the admin API, before subscribing, that the consumers already
+    /// connected to the subscription let this one in
+    #[structopt(long)]
+    no_preflight: bool,
+
+    /// On a terminal, press m to save the latest message with its metadata, when, and an
+    /// optional comment typed then, to --notes-file, e.g. to mark messages while debugging live
+    #[structopt(long)]
+    annotate: bool,
+
+    /// File --annotate appends the messages marked to, as JSON lines
+    #[structopt(long, default_value = "session-notes.ndjson")]
+    notes_file: PathBuf,
+}
+
+impl ConsumeOpts {
+    /// Rejects the combinations of options that clap can't tell are wrong, `matches` being
+    /// those of the whole command line
+    pub fn validate(&self, matches: &ArgMatches) -> Result<()> {
+        let usage =
+            |message: String| -> Result<()> { Err(ExitError::new(exit::USAGE, message).into()) };
+        // Non-persistent topics keep no messages and no subscription cursors
+        match self {
+            ConsumeOpts {
+                topic: Some(topic),
+                durable: true,
+                ..
+            } if topic_name::is_non_persistent(topic) => usage(format!(
+                "{} is non-persistent, its subscriptions have no cursor for --durable to keep",
+                topic
+            )),
+            ConsumeOpts {
+                topic: Some(topic),
+                earliest: true,
+                ..
+            } if topic_name::is_non_persistent(topic) => usage(format!(
+                "{} is non-persistent, it keeps no messages for --earliest to start from: only those published while consuming are received",
+                topic
+            )),
+            ConsumeOpts {
+                topic: Some(topic),
+                ack: true,
+                ..
+            } if topic_name::is_non_persistent(topic) => usage(format!(
+                "{} is non-persistent, there is no backlog for --ack (or holding acks) to act on",
+                topic
+            )),
+            ConsumeOpts {
+                topic: Some(topic),
+                warn_near_expiry: true,
+                ..
+            } if topic_name::is_non_persistent(topic) => usage(format!(
+                "{} is non-persistent, its messages aren't retained for --warn-near-expiry to warn about",
+                topic
+            )),
+            ConsumeOpts {
+                topic: Some(topic),
+                forward_to_topic: Some(forward_to_topic),
+                forward_to_url: None,
+                ..
+            } if topic.parse::<TopicName>()? == forward_to_topic.parse::<TopicName>()? => usage(
+                format!(
+                    "--forward-to-topic is the topic consumed, {} would get each message forwarded back into it forever; pass --forward-to-url to forward to another cluster",
+                    topic
+                ),
+            ),
+            ConsumeOpts {
+                topic: Some(topic),
+                forward_routes,
+                forward_default,
+                forward_to_url: None,
+                ..
+            } if routing::destinations(forward_routes, forward_default.as_deref())
+                .map(str::parse::<TopicName>)
+                .collect::<Result<Vec<_>>>()?
+                .contains(&topic.parse::<TopicName>()?) =>
+            {
+                usage(format!(
+                    "A --forward-route or --forward-default is the topic consumed, {} would get messages forwarded back into it forever; pass --forward-to-url to forward to another cluster",
+                    topic
+                ))
+            }
+            ConsumeOpts {
+                rotate_compress: true,
+                rotate_size: None,
+                rotate_interval: None,
+                ..
+            } => usage(
+                "--rotate-compress only applies with --rotate-size or --rotate-interval".to_owned(),
+            ),
+            ConsumeOpts {
+                forward_to_url: Some(_),
+                forward_to_topic: None,
+                forward_routes,
+                ..
+            } if forward_routes.is_empty() => usage(
+                "--forward-to-url only applies with --forward-to-topic or --forward-route".to_owned(),
+            ),
+            ConsumeOpts {
+                namespace: Some(namespace),
+                forward_to_topic: Some(forward_to_topic),
+                forward_to_url: None,
+                ..
+            } if forward_to_topic.parse::<TopicName>()?.namespace_name() == *namespace => usage(
+                format!(
+                    "--forward-to-topic is in {}, so with --all-topics each message would get forwarded back into it forever; pass --forward-to-url to forward to another cluster",
+                    namespace
+                ),
+            ),
+            ConsumeOpts {
+                track_field: None,
+                ..
+            } if matches.subcommand_matches("consume").map_or(false, |consume| {
+                consume.occurrences_of("window") > 0 || consume.occurrences_of("track-interval") > 0
+            }) =>
+            {
+                usage("--window and --track-interval only apply with --track-field <field>".to_owned())
+            }
+            ConsumeOpts {
+                all_topics: false, ..
+            } if matches.subcommand_matches("consume").map_or(false, |consume| {
+                consume.occurrences_of("topic-refresh") > 0 || consume.occurrences_of("max-topics") > 0
+            }) =>
+            {
+                usage("--topic-refresh and --max-topics only apply with --all-topics".to_owned())
+            }
+            ConsumeOpts {
+                detect_redelivery_storms: false,
+                ..
+            } if matches.subcommand_matches("consume").map_or(false, |consume| {
+                consume.occurrences_of("storm-threshold") > 0
+                    || consume.occurrences_of("storm-window") > 0
+            }) =>
+            {
+                usage(
+                    "--storm-threshold and --storm-window only apply with --detect-redelivery-storms"
+                        .to_owned(),
+                )
+            }
+            ConsumeOpts {
+                show_checksum: false,
+                ..
+            } if matches
+                .subcommand_matches("consume")
+                .map_or(false, |consume| consume.occurrences_of("checksum-algo") > 0) =>
+            {
+                usage("--checksum-algo only applies with --show-checksum".to_owned())
+            }
+            ConsumeOpts {
+                output_dir_per_topic: None,
+                ..
+            } if matches
+                .subcommand_matches("consume")
+                .map_or(false, |consume| consume.occurrences_of("max-open-files") > 0) =>
+            {
+                usage("--max-open-files only applies with --output-dir-per-topic <dir>".to_owned())
+            }
+            ConsumeOpts { .. }
+                if !output::is_kcat()
+                    && matches
+                        .subcommand_matches("consume")
+                        .map_or(false, |consume| consume.occurrences_of("kcat-format") > 0) =>
+            {
+                usage("--kcat-format only applies with --output kcat".to_owned())
+            }
+            ConsumeOpts {
+                sink,
+                output_dir_per_topic,
+                ..
+            } if (sink.is_some() || output_dir_per_topic.is_some())
+                && matches.subcommand_matches("consume").map_or(false, |consume| {
+                    consume.occurrences_of("overflow") > 0
+                        || consume.occurrences_of("display-buffer") > 0
+                }) =>
+            {
+                usage(
+                    "--overflow and --display-buffer only apply to printing messages, not with --sink or --output-dir-per-topic"
+                        .to_owned(),
+                )
+            }
+            ConsumeOpts {
+                annotate: false, ..
+            } if matches
+                .subcommand_matches("consume")
+                .map_or(false, |consume| consume.occurrences_of("notes-file") > 0) =>
+            {
+                usage("--notes-file only applies with --annotate".to_owned())
+            }
+            ConsumeOpts { sink: None, .. }
+                if matches
+                    .subcommand_matches("consume")
+                    .map_or(false, |consume| consume.occurrences_of("sink-buffer") > 0) =>
+            {
+                usage("--sink-buffer only applies with --sink <address>".to_owned())
+            }
+            ConsumeOpts {
+                max_rate: Some(0), ..
+            }
+            | ConsumeOpts {
+                max_msg_rate: Some(0),
+                ..
+            } => usage("--max-rate and --max-msg-rate have to be above 0".to_owned()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Consumes until stopped with Ctrl-C (or an option says to stop), printing, forwarding or
+/// writing the messages as `opts` say, and reports what was consumed. The admin API client is
+/// only made when an option needs it
+pub async fn run(
+    url: &ServiceUrls,
+    connector: &Connector,
+    admin_client: impl Fn() -> Result<AdminClient>,
+    opts: &ConsumeOpts,
+) -> Result<Report> {
+    let ConsumeOpts {
+        subscription_name,
+        consumer_name,
+        topic,
+        topic_template,
+        topic_name_file,
+        durable,
+        earliest,
+        json,
+        xml,
+        forward_to_topic,
+        forward_routes,
+        forward_default,
+        forward_to_url,
+        shared,
+        ack,
+        ack_retries,
+        ignore_ack_errors,
+        ack_hold,
+        ack_hold_time,
+        show_entry_metadata,
+        show_replicated_from,
+        replicate_subscription_state,
+        warn_near_expiry,
+        verify_key_order,
+        verify_forwarded,
+        max_rate,
+        max_msg_rate,
+        liveness_timeout,
+        partitions,
+        all_topics: _,
+        namespace,
+        topic_refresh,
+        max_topics,
+        detect_redelivery_storms,
+        storm_threshold,
+        storm_window,
+        fail_on_storm,
+        sink,
+        sink_buffer,
+        producers_summary,
+        sort,
+        validate_schema,
+        only_invalid,
+        fail_on_invalid,
+        show_checksum,
+        checksum_algo,
+        verify_checksum_prop,
+        output_dir_per_topic,
+        max_open_files,
+        track_field,
+        window,
+        track_interval,
+        max_capture_bytes,
+        max_capture_messages,
+        rotate_size,
+        rotate_interval,
+        rotate_compress,
+        continue_after_capture,
+        alert_after,
+        alert_exec,
+        exit_on_silence,
+        kcat_format,
+        overflow,
+        display_buffer,
+        no_preflight,
+        annotate,
+        notes_file,
+    } = opts;
+    let subscription_name = &template::resolve_name(subscription_name, "subscription", 0)?;
+    let consumer_name = &template::resolve_name(consumer_name, "consumer", 0)?;
+    let topic = &match (topic, topic_template, namespace) {
+        (Some(topic), _, _) => topic.clone(),
+        (None, Some(template), _) => template::resolve_topic(template, topic_name_file.as_deref())?,
+        // Naming the topics of the namespace in messages
+        (None, None, Some(namespace)) => format!("the topics of {}", namespace),
+        (None, None, None) => picker::topic(&admin_client()?).await?,
+    };
+    let consumer_params = ConsumerParams {
+        topic,
+        subscription: subscription_name,
+        consumer_name,
+        sub_type: if *shared {
+            SubType::Shared
+        } else {
+            SubType::Exclusive
+        },
+        options: ConsumerOptions {
+            durable: Some(*durable),
+            initial_position: if *earliest {
+                InitialPosition::Earliest
+            } else {
+                InitialPosition::default()
+            },
+            ..Default::default()
+        },
+    };
+    let non_persistent = topic_name::is_non_persistent(topic);
+    if non_persistent {
+        info!(
+            "{} is non-persistent: only the messages published while connected are received",
+            topic
+        );
+    }
+    // The consumers of a non-persistent topic are checked by the broker alone
+    if !*no_preflight && namespace.is_none() && !non_persistent {
+        if let Ok(topic) = topic.parse::<TopicName>() {
+            preflight::check_subscription(
+                &admin_client()?,
+                &topic,
+                subscription_name,
+                &consumer_params.sub_type,
+            )
+            .await?;
+        }
+    }
+    if *durable && *earliest {
+        info!(
+            "--earliest only applies if subscription {:?} doesn't exist yet, an existing one resumes from its cursor (rewind it with `subscription reset-cursor --to-earliest`)",
+            subscription_name
+        );
+    }
+    let expiry = if *warn_near_expiry {
+        let admin = admin_client()?;
+        let policies = match namespace {
+            Some(namespace) => {
+                expiry::ExpiryPolicies::fetch_namespace(&admin, namespace.clone()).await?
+            }
+            None => expiry::ExpiryPolicies::fetch(&admin, &topic.parse()?).await?,
+        };
+        info!("{}", policies.describe());
+        Some(policies)
+    } else {
+        None
+    };
+
+    let mut discovery = match namespace {
+        Some(namespace) => Some((
+            admin_client()?,
+            discovery::TopicDiscovery::new(namespace.clone(), *max_topics),
+        )),
+        None => None,
+    };
+    let topics = match discovery.as_mut() {
+        Some((admin, discovery)) => loop {
+            let topics = discovery.discover(admin).await?;
+            if !topics.is_empty() {
+                break topics;
+            }
+            info!(
+                "No topics in {} yet, waiting for some",
+                discovery.namespace()
+            );
+            tokio::time::sleep((*topic_refresh).into()).await;
+        },
+        None if !partitions.is_empty() => {
+            let topic = topic.parse::<TopicName>()?;
+            let count = admin_client()?.partitions(&topic).await?;
+            if count == 0 {
+                return Err(ExitError::new(
+                    exit::USAGE,
+                    format!("{} isn't partitioned, --partition doesn't apply", topic),
+                )
+                .into());
+            }
+            if let Some(index) = partitions.iter().find(|index| **index >= count) {
+                return Err(ExitError::new(
+                    exit::USAGE,
+                    format!(
+                        "{} has {} partitions, numbered 0 to {}: there is no partition {}",
+                        topic,
+                        count,
+                        count - 1,
+                        index
+                    ),
+                )
+                .into());
+            }
+            info!(
+                "Consuming partition(s) {} of {}",
+                partitions.iter().sorted().dedup().join(", "),
+                topic
+            );
+            partitions
+                .iter()
+                .sorted()
+                .dedup()
+                .map(|index| topic.partition(*index).to_string())
+                .collect()
+        }
+        None => vec![topic.clone()],
+    };
+    let mut consumers = connection::ConsumerSet::new(
+        topics.clone(),
+        connector
+            .build_multi_topic_consumer(url, &topics, &consumer_params)
+            .await?,
+    );
+    // Through the admin API, once the subscription exists: the client library doesn't have
+    // the subscribe option
+    if *replicate_subscription_state {
+        let admin = admin_client()?;
+        for topic in topics.iter() {
+            let path = AdminClient::subscription_path(&topic.parse()?, subscription_name);
+            admin
+                .post(
+                    &format!("{}/replicatedSubscriptionStatus", path),
+                    Some(&json!(true)),
+                )
+                .await?;
+        }
+        info!(
+            "Replicating the state of subscription {:?} to the other clusters",
+            subscription_name
+        );
+    }
+    if let Some((_, discovery)) = &discovery {
+        info!("Consuming {} of {}", discovery.count(), topic);
+    }
+    let mut refresh = connection::pace((*topic_refresh).into(), false);
+    let mut track_ticks = connection::pace((*track_interval).into(), false);
+    let mut redraw = output::Redraw::stdout();
+
+    let mut forward_producer = if let Some(topic) = forward_to_topic {
+        let url = forward_to_url.as_ref().unwrap_or(url);
+        Some(connector.build_producer(url, topic, None).await?)
+    } else {
+        None
+    };
+
+    let mut router = if forward_routes.is_empty() {
+        None
+    } else {
+        Some(routing::Router::new(
+            forward_routes.clone(),
+            forward_default.clone(),
+        ))
+    };
+
+    let formatter = MessageFormatter {
+        json: *json,
+        xml: *xml,
+        entry_metadata: *show_entry_metadata,
+        replicated_from: *show_replicated_from,
+        topic: namespace.is_some() || partitions.len() > 1,
+    };
+    let mut key_order = if *verify_key_order || *verify_forwarded {
+        Some(ordering::KeyOrder::new(*verify_forwarded))
+    } else {
+        None
+    };
+    let mut storms = if *detect_redelivery_storms {
+        Some(redelivery::StormDetector::new(
+            *storm_window,
+            *storm_threshold,
+        ))
+    } else {
+        None
+    };
+    let sink = sink
+        .as_ref()
+        .map(|address| sink::Sink::spawn(address.clone(), *sink_buffer));
+    let mut topic_files = output_dir_per_topic
+        .as_deref()
+        .map(|dir| {
+            let budget = topic_files::CaptureBudget {
+                max_bytes: *max_capture_bytes,
+                max_messages: *max_capture_messages,
+            };
+            let rotation = topic_files::Rotation {
+                max_size: *rotate_size,
+                interval: rotate_interval.map(Into::into),
+                compress: *rotate_compress,
+            };
+            topic_files::TopicFiles::new(dir, *max_open_files, budget, rotation)
+        })
+        .transpose()?;
+    let mut validator = validate_schema
+        .as_deref()
+        .map(json_schema::SchemaValidator::load)
+        .transpose()?;
+    let mut producers = if *producers_summary {
+        Some(producers::ProducersSummary::default())
+    } else {
+        None
+    };
+    let mut tracker = track_field
+        .as_deref()
+        .map(|field| field_tracker::FieldTracker::new(field, (*window).into()))
+        .transpose()
+        .map_err(|e| ExitError::new(exit::USAGE, e.to_string()))?;
+    let mut printer = match (&sink, &topic_files) {
+        _ if tracker.is_some() => None,
+        (None, None) if output::is_kcat() => {
+            let kcat_format = kcat_format.clone();
+            Some(printer::Printer::spawn(
+                *display_buffer,
+                *overflow,
+                move |message| Ok(kcat_format.format(message)),
+            ))
+        }
+        (None, None) => Some(printer::Printer::spawn(
+            *display_buffer,
+            *overflow,
+            move |message| formatter.format(&DisplayMessage::from_message(message)),
+        )),
+        _ => None,
+    };
+    let mut annotator = if *annotate {
+        Some(annotate::Annotator::start(notes_file)?)
+    } else {
+        None
+    };
+    let holding = ack_hold.is_some() || ack_hold_time.is_some();
+    // Also holding the acks of the messages printed until they are, so that they are
+    // received again if stdout is closed before
+    let mut ack_hold = if holding || (*ack && printer.is_some()) {
+        Some(ack_hold::AckHold::new(
+            *ack_hold,
+            ack_hold_time.map(Into::into),
+        ))
+    } else {
+        None
+    };
+    let summarized = key_order.is_some()
+        || liveness_timeout.is_some()
+        || storms.is_some()
+        || sink.is_some()
+        || topic_files.is_some()
+        || producers.is_some()
+        || validator.is_some()
+        || *verify_checksum_prop
+        || *overflow == printer::Overflow::DropDisplay
+        || holding
+        || annotator.is_some()
+        || router.is_some()
+        || topic_template.is_some()
+        || tracker.is_some()
+        // For it to get the counts of connection events on Ctrl-C
+        || connection_events::is_enabled();
+    let mut checksums = if *verify_checksum_prop {
+        Some(checksum::ChecksumCheck::default())
+    } else {
+        None
+    };
+    let mut silence = alert_after.map(|after| silence::SilenceAlarm::new(after.into()));
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+    let mut byte_limiter = max_rate.map(|rate| rate_limit::RateLimiter::new(rate as f64));
+    let mut message_limiter =
+        max_msg_rate.map(|rate| rate_limit::RateLimiter::new(f64::from(rate)));
+    let mut throttling = false;
+    // Whether the consumer was rebuilt because acking failed, and no ack worked since
+    let mut reconnected_for_ack = false;
+    loop {
+        let receive = async {
+            match liveness_timeout {
+                Some(timeout) => tokio::time::timeout((*timeout).into(), consumers.next())
+                    .await
+                    .ok(),
+                None => Some(consumers.next().await),
+            }
+        };
+        let alarm = async {
+            match &silence {
+                Some(alarm) => alarm.silent().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let printed = printer.as_ref().map_or(u64::MAX, |p| p.printed());
+        // Once the oldest held message waits for printing, printing more is what wakes the
+        // loop rather than its hold time, which may well be over already
+        let hold_due = async {
+            match &ack_hold {
+                Some(hold) => hold.next_due(printed).await,
+                None => futures::future::pending().await,
+            }
+        };
+        let printing = printer.is_some();
+        let waits_for_printing = ack_hold
+            .as_ref()
+            .map_or(false, |hold| hold.waits_for_printing(printed));
+        let printed_more = async {
+            match printer.as_mut() {
+                Some(printer) => printer.printed_more().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let annotation = async {
+            match annotator.as_mut() {
+                Some(annotator) => annotator.next().await,
+                None => futures::future::pending().await,
+            }
+        };
+        let mut silent = false;
+        let mut releasing = false;
+        let mut typed = None;
+        let mut tracked = false;
+        let next = tokio::select! {
+            next = receive => Some(next),
+            _ = refresh.tick(), if discovery.is_some() => None,
+            _ = alarm => {
+                silent = true;
+                None
+            }
+            _ = hold_due => {
+                releasing = true;
+                None
+            }
+            _ = printed_more, if waits_for_printing => {
+                releasing = true;
+                None
+            }
+            event = annotation => {
+                typed = Some(event);
+                None
+            }
+            _ = track_ticks.tick(), if tracker.is_some() => {
+                tracked = true;
+                None
+            }
+            // Only listened to when there is a summary to print, or messages still to
+            // print or a sink or files to flush, on stopping
+            _ = &mut shutdown, if summarized || printing => break,
+        };
+        let next = match next {
+            Some(next) => next,
+            None if silent => {
+                if let Some(alarm) = silence.as_mut() {
+                    let alert = alarm.alert(topic, alert_exec.as_deref())?;
+                    if *exit_on_silence {
+                        return Err(ExitError::new(exit::TIMEOUT, alert).into());
+                    }
+                }
+                continue;
+            }
+            None if typed.is_some() => {
+                if let (Some(annotator), Some(event)) = (annotator.as_mut(), typed) {
+                    annotator.handle(event)?;
+                }
+                continue;
+            }
+            None if tracked => {
+                if let Some(tracker) = tracker.as_mut() {
+                    tracker.render(&mut redraw)?;
+                }
+                continue;
+            }
+            None if releasing => {
+                if let Some(hold) = ack_hold.as_mut() {
+                    let printed = printer.as_ref().map_or(u64::MAX, |p| p.printed());
+                    match ack_hold::ack(&mut consumers, hold.due(printed), *ack_retries).await {
+                        Ok(()) => reconnected_for_ack = false,
+                        Err((_, e)) if *ignore_ack_errors => log::warn!("{}", e),
+                        Err((_, e)) => return Err(e),
+                    }
+                }
+                continue;
+            }
+            None => {
+                if let Some((admin, discovery)) = discovery.as_mut() {
+                    match discovery.discover(admin).await {
+                        Ok(topics) if topics.is_empty() => {}
+                        Ok(topics) => {
+                            info!("Consuming new topics {}", topics.join(", "));
+                            consumers
+                                .add(connector, url, topics, &consumer_params)
+                                .await?;
+                        }
+                        Err(e) => {
+                            log::warn!("Failed listing the topics of {}: {:#}", topic, e)
+                        }
+                    }
+                }
+                continue;
+            }
+        };
+        let (source, next) = match next {
+            Some(next) => next,
+            None => {
+                log::warn!(
+                    "Nothing received from {} within --liveness-timeout, reconnecting in case the connection was dropped",
+                    topic
+                );
+                if non_persistent {
+                    info!("{}", RECONNECT_NOTICE);
+                }
+                connection_events::record(
+                    connection_events::Kind::Disconnected,
+                    format!("{}: nothing received within --liveness-timeout", topic),
+                    None,
+                );
+                connector.forget(url);
+                metrics::add(&STATS.consumer_reconnects, 1);
+                metrics::add(&STATS.idle_reconnects, 1);
+                consumers
+                    .rebuild_all(connector, url, &consumer_params)
+                    .await?;
+                continue;
+            }
+        };
+        let message = match next {
+            Ok(message) => message,
+            Err(e) => {
+                // Possibly failing over to another of the service URLs
+                log::warn!("Error receiving from {}: {}. Reconnecting...", topic, e);
+                if non_persistent {
+                    info!("{}", RECONNECT_NOTICE);
+                }
+                connection_events::record(
+                    connection_events::Kind::Disconnected,
+                    format!("{}: error receiving: {}", topic, e),
+                    None,
+                );
+                connector.forget(url);
+                metrics::add(&STATS.consumer_reconnects, 1);
+                consumers
+                    .rebuild(source, connector, url, &consumer_params)
+                    .await?;
+                continue;
+            }
+        };
+        if let Some(message) = message {
+            if let Some(alarm) = silence.as_mut() {
+                alarm.reset();
+            }
+            STATS.consumed(&message.payload.data);
+            STATS.observe_latency(message.metadata().publish_time);
+
+            // Before printing and forwarding, which are what the limits protect
+            let mut waited = false;
+            if let Some(limiter) = byte_limiter.as_mut() {
+                waited |= limiter.acquire(message.payload.data.len() as f64).await;
+            }
+            if let Some(limiter) = message_limiter.as_mut() {
+                waited |= limiter.acquire(1.0).await;
+            }
+            if waited {
+                metrics::add(&STATS.throttled, 1);
+            }
+            if waited != throttling {
+                throttling = waited;
+                if throttling {
+                    info!("Throttling to stay under the rate limit");
+                } else {
+                    info!("No longer throttling");
+                }
+            }
+
+            let payload = &message.payload.data;
+            let errors = validator
+                .as_mut()
+                .map(|validator| validator.validate(payload))
+                .unwrap_or_default();
+            let mismatch = checksums.as_mut().and_then(|checksums| {
+                checksums.check(
+                    payload,
+                    display::property(&message, checksum::CHECKSUM_PROPERTY),
+                )
+            });
+            let digest = if *show_checksum {
+                Some(checksum_algo.digest(payload))
+            } else {
+                None
+            };
+            // Printed once forwarded, from a queue so that printing falling behind
+            // doesn't hold up receiving, and acked once printed
+            let mut printed = None;
+            if !errors.is_empty() || !*only_invalid {
+                let display = DisplayMessage::from_message(&message);
+                match (&sink, topic_files.as_mut()) {
+                    (None, None) if output::is_kcat() => printed = Some((Vec::new(), Vec::new())),
+                    (None, None) => {
+                        let lines = digest
+                            .iter()
+                            .map(|digest| format!("{}: {}", checksum_algo.name(), digest))
+                            .collect::<Vec<_>>();
+                        let problems = errors
+                            .iter()
+                            .map(|error| format!("invalid: {}", error))
+                            .chain(
+                                mismatch
+                                    .iter()
+                                    .map(|mismatch| format!("checksum mismatch: {}", mismatch)),
+                            )
+                            .collect::<Vec<_>>();
+                        printed = Some((lines, problems));
+                    }
+                    (sink, files) => {
+                        let mut line = display.to_json();
+                        if !errors.is_empty() {
+                            line["schemaErrors"] = json!(errors);
+                        }
+                        if let Some(digest) = &digest {
+                            line["checksum"] =
+                                json!(format!("{}:{}", checksum_algo.name(), digest));
+                        }
+                        if let Some(mismatch) = &mismatch {
+                            line["checksumMismatch"] = json!(mismatch);
+                        }
+                        if let Some(sink) = sink {
+                            sink.send(line.to_string());
+                        }
+                        if let Some(files) = files {
+                            // Not acking the message that wasn't written
+                            if !files.write(&message.topic, &line.to_string())?
+                                && !*continue_after_capture
+                            {
+                                info!("Stopping with the capture, --continue-after-capture would keep consuming");
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(tracker) = tracker.as_mut() {
+                tracker.observe(payload);
+            }
+            if let Some(annotator) = annotator.as_mut() {
+                annotator.received(DisplayMessage::from_message(&message).to_json());
+            }
+            if let Some(key_order) = key_order.as_mut() {
+                key_order.check(&message);
+            }
+            if let Some(producers) = producers.as_mut() {
+                producers.record(&message);
+            }
+            if let Some(storm) = storms.as_mut().and_then(|storms| storms.observe(&message)) {
+                storm.warn();
+                if *fail_on_storm {
+                    return Err(ExitError::new(
+                        exit::THRESHOLD_CROSSED,
+                        format!(
+                            "Redelivery storm: {} of the last {} messages were redeliveries",
+                            storm.redelivered, storm.window
+                        ),
+                    )
+                    .into());
+                }
+            }
+            if let Some(warning) = expiry
+                .as_ref()
+                .and_then(|expiry| expiry.check(message.metadata().publish_time))
+            {
+                log::warn!(
+                    "Message {}: {}",
+                    MessageId::from(&message.message_id.id),
+                    warning
+                );
+            }
+
+            if let Some(forwarder) = forward_producer.as_mut() {
+                let mut copy = forwarding::copy_of(&message);
+                if *verify_key_order {
+                    ordering::tag_source(&mut copy, &message);
+                }
+                forwarder.send(copy).await.map_err(|e| {
+                    metrics::add(&STATS.send_failures, 1);
+                    e
+                })?;
+                STATS.produced(&message.payload.data);
+                metrics::add(&STATS.forwarded, 1);
+            }
+            if let Some(router) = router.as_mut() {
+                let mut copy = forwarding::copy_of(&message);
+                if *verify_key_order {
+                    ordering::tag_source(&mut copy, &message);
+                }
+                let forwarded = router
+                    .forward(
+                        connector,
+                        forward_to_url.as_ref().unwrap_or(url),
+                        &display::properties(&message),
+                        copy,
+                    )
+                    .await
+                    .map_err(|e| {
+                        metrics::add(&STATS.send_failures, 1);
+                        e
+                    })?;
+                if forwarded {
+                    STATS.produced(&message.payload.data);
+                    metrics::add(&STATS.forwarded, 1);
+                }
+            }
+
+            let held = ack_hold
+                .as_ref()
+                .map(|_| ack_hold::Held::of(source, &message));
+            let mut acked = None;
+            if *ack && held.is_none() {
+                acked = Some(
+                    connection::ack_with_retry(
+                        consumers.get(source),
+                        &message,
+                        false,
+                        *ack_retries,
+                    )
+                    .await
+                    .map_err(|e| (source, e)),
+                );
+            }
+            let printing = match (printer.as_mut(), printed) {
+                (Some(printer), Some((lines, problems))) => {
+                    printer
+                        .print(printer::Printed {
+                            message,
+                            lines,
+                            problems,
+                        })
+                        .await?
+                }
+                _ => None,
+            };
+            if let (Some(hold), Some(held)) = (ack_hold.as_mut(), held) {
+                hold.hold(held, printing);
+                let printed = printer.as_ref().map_or(u64::MAX, |p| p.printed());
+                acked = Some(ack_hold::ack(&mut consumers, hold.due(printed), *ack_retries).await);
+            }
+            if let Some(acked) = acked {
+                match acked {
+                    Ok(()) => reconnected_for_ack = false,
+                    Err((_, e)) if *ignore_ack_errors => log::warn!("{}", e),
+                    Err((_, e)) if reconnected_for_ack => {
+                        return Err(e.context(format!(
+                            "Acking kept failing after reconnecting ({} failed acks)",
+                            metrics::get(&STATS.ack_failures)
+                        )))
+                    }
+                    Err((failed, e)) => {
+                        // The unacked messages are redelivered to the new consumer
+                        log::warn!("{}. Reconnecting...", e);
+                        connection_events::record(
+                            connection_events::Kind::Disconnected,
+                            format!("{}: {:#}", topic, e),
+                            None,
+                        );
+                        connector.forget(url);
+                        metrics::add(&STATS.consumer_reconnects, 1);
+                        consumers
+                            .rebuild(failed, connector, url, &consumer_params)
+                            .await?;
+                        reconnected_for_ack = true;
+                    }
+                }
+            }
+        }
+    }
+    if let Some(printer) = printer {
+        printer.finish()?;
+    }
+    if let Some(hold) = ack_hold.as_mut() {
+        // Everything was printed
+        if let Err((_, e)) = ack_hold::ack(&mut consumers, hold.due(u64::MAX), *ack_retries).await {
+            if !*ignore_ack_errors {
+                return Err(e);
+            }
+            log::warn!("{}", e);
+        }
+    }
+
+    let mut report = Report::default();
+    let mut summary = serde_json::Map::new();
+    if topic_template.is_some() {
+        report.line(format!("Topic: {}", topic));
+        summary.insert("topic".to_owned(), json!(topic));
+    }
+    if let Some(annotator) = annotator {
+        let (line, json) = annotator.finish()?;
+        report.line(line);
+        summary.insert("annotations".to_owned(), json);
+    }
+    if let Some(hold) = ack_hold.filter(|_| holding) {
+        report.line(format!(
+            "Ack hold: {} messages left unacked, to be received again",
+            hold.count()
+        ));
+        summary.insert("heldUnacked".to_owned(), json!(hold.count()));
+    }
+    if *overflow == printer::Overflow::DropDisplay {
+        let dropped = metrics::get(&STATS.display_dropped);
+        report.line(format!(
+            "Display: {} messages not printed because printing couldn't keep up",
+            dropped
+        ));
+        summary.insert("displayDropped".to_owned(), json!(dropped));
+    }
+    if let Some(key_order) = key_order {
+        let (lines, json) = key_order.summary();
+        for line in lines {
+            report.line(line);
+        }
+        summary.insert("keyOrder".to_owned(), json);
+    }
+    if liveness_timeout.is_some() {
+        let idle_reconnects = metrics::get(&STATS.idle_reconnects);
+        report.line(format!(
+            "Reconnected {} times after receiving nothing for --liveness-timeout",
+            idle_reconnects
+        ));
+        summary.insert("idleReconnects".to_owned(), json!(idle_reconnects));
+    }
+    if let Some(mut tracker) = tracker {
+        let (table, json) = tracker.summary();
+        report.line(table.trim_end());
+        summary.insert("trackedField".to_owned(), json);
+    }
+    if let Some(router) = router {
+        let (line, json) = router.summary();
+        report.line(line);
+        summary.insert("routes".to_owned(), json);
+    }
+    if let Some(producers) = producers {
+        let (table, json) = producers.summary(sort.as_deref());
+        report.line(table.trim_end());
+        summary.insert("producers".to_owned(), json);
+    }
+    if let Some(storms) = storms {
+        report.line(format!("Redelivery storms: {}", storms.storms()));
+        summary.insert("redeliveryStorms".to_owned(), json!(storms.storms()));
+    }
+    if let Some(sink) = sink {
+        sink.close().await;
+        let dropped = metrics::get(&STATS.sink_dropped);
+        report.line(format!(
+            "Sink: {} messages dropped because its buffer was full",
+            dropped
+        ));
+        summary.insert("sinkDropped".to_owned(), json!(dropped));
+    }
+    if let Some(mut files) = topic_files {
+        files.flush()?;
+        let (line, json) = files.summary();
+        report.line(line);
+        summary.insert("topicFiles".to_owned(), json);
+    }
+    if let Some(checksums) = checksums {
+        let (line, json) = checksums.summary();
+        report.line(line);
+        summary.insert("checksums".to_owned(), json);
+    }
+    let mut invalid = 0;
+    if let Some(validator) = validator {
+        let (line, json) = validator.summary();
+        report.line(line);
+        summary.insert("schema".to_owned(), json);
+        invalid = validator.invalid();
+    }
+    if summarized {
+        report.set_json(summary.into());
+    }
+    if *fail_on_invalid && invalid > 0 {
+        report.print()?;
+        return Err(ExitError::new(
+            exit::INVALID_DATA,
+            format!("{} messages didn't match the schema", invalid),
+        )
+        .into());
+    }
+    Ok(report)
+}
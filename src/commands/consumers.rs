@@ -0,0 +1,164 @@
+use crate::{
+    admin::{stats::ConsumerStats, AdminClient},
+    exit::{self, ExitError},
+    output::Report,
+    picker,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+use structopt::StructOpt;
+
+/// The options of `consumers`
+#[derive(StructOpt)]
+pub struct ConsumersOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Picked among the subscriptions of the topic when not given, on a terminal
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    /// Order by unacked messages (most first) or connection time (oldest first) rather than name
+    #[structopt(long, possible_values = &["unacked", "connected"])]
+    sort: Option<String>,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// A consumer, merged across the partitions it is connected to
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsumerSummary {
+    consumer_name: String,
+    addresses: BTreeSet<String>,
+    /// The earliest connection time across partitions
+    connected_since: String,
+    unacked_messages: u64,
+    available_permits: i64,
+    blocked_consumer_on_unacked_msgs: bool,
+    partitions: usize,
+}
+
+impl ConsumerSummary {
+    fn merge(&mut self, consumer: &ConsumerStats) {
+        self.addresses.insert(consumer.address.clone());
+        if self.connected_since.is_empty() || consumer.connected_since < self.connected_since {
+            self.connected_since = consumer.connected_since.clone();
+        }
+        self.unacked_messages += consumer.unacked_messages;
+        self.available_permits += consumer.available_permits;
+        self.blocked_consumer_on_unacked_msgs |= consumer.blocked_consumer_on_unacked_msgs;
+        self.partitions += 1;
+    }
+}
+
+/// Lists the consumers of the subscription
+pub async fn run(admin: &AdminClient, opts: &ConsumersOpts) -> Result<Report> {
+    let subscription =
+        &picker::subscription(admin, &opts.topic, opts.subscription.as_deref()).await?;
+    let stats = admin.topic_stats(&opts.topic).await?;
+    // Partitioned topics only have consumer details in their per-partition stats
+    let partitions = if stats.partitions.is_empty() {
+        vec![&stats]
+    } else {
+        stats.partitions.values().collect()
+    };
+
+    let mut found = false;
+    let mut consumers = BTreeMap::<&str, ConsumerSummary>::new();
+    for partition in partitions {
+        let subscription_stats = match partition.subscriptions.get(subscription) {
+            Some(subscription_stats) => subscription_stats,
+            None => continue,
+        };
+        found = true;
+        for consumer in subscription_stats.consumers.iter() {
+            consumers
+                .entry(consumer.consumer_name.as_str())
+                .or_insert_with(|| ConsumerSummary {
+                    consumer_name: consumer.consumer_name.clone(),
+                    addresses: BTreeSet::new(),
+                    connected_since: String::new(),
+                    unacked_messages: 0,
+                    available_permits: 0,
+                    blocked_consumer_on_unacked_msgs: false,
+                    partitions: 0,
+                })
+                .merge(consumer);
+        }
+    }
+    if !found {
+        return Err(ExitError::new(
+            exit::NOT_FOUND,
+            format!(
+                "Subscription {:?} not found on {}",
+                subscription, opts.topic
+            ),
+        )
+        .into());
+    }
+
+    let mut consumers = consumers.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+    match opts.sort.as_deref() {
+        Some("unacked") => consumers.sort_by(|a, b| b.unacked_messages.cmp(&a.unacked_messages)),
+        Some("connected") => consumers.sort_by(|a, b| a.connected_since.cmp(&b.connected_since)),
+        _ => {}
+    }
+
+    let json = serde_json::to_value(&consumers)?;
+    if opts.json {
+        return Ok(Report::json(json));
+    }
+    if consumers.is_empty() {
+        return Ok(Report::new(
+            format!("No consumers connected to {:?}", subscription),
+            json,
+        ));
+    }
+    let width = consumers
+        .iter()
+        .map(|consumer| consumer.consumer_name.len())
+        .chain(Some("CONSUMER".len()))
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:width$}  {:>8}  {:>8}  {:7}  {:28}  ADDRESS",
+        "CONSUMER",
+        "UNACKED",
+        "PERMITS",
+        "BLOCKED",
+        "CONNECTED SINCE",
+        width = width
+    )?;
+    for consumer in consumers.iter() {
+        writeln!(
+            out,
+            "{:width$}  {:>8}  {:>8}  {:7}  {:28}  {}",
+            consumer.consumer_name,
+            consumer.unacked_messages,
+            consumer.available_permits,
+            if consumer.blocked_consumer_on_unacked_msgs {
+                "yes"
+            } else {
+                "no"
+            },
+            consumer.connected_since,
+            consumer
+                .addresses
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(","),
+            width = width
+        )?;
+    }
+    Ok(Report::new(out, json))
+}
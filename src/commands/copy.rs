@@ -0,0 +1,257 @@
+use crate::{
+    admin::AdminClient,
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    display::timestamp_from_millis,
+    exit::{self, ExitError},
+    forwarding::{copy_of, position_of, reached, Position},
+    ordering::{self, KeyOrder},
+    output::Report,
+    topic_name::TopicName,
+    units::parse_time,
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use log::{info, warn};
+use pulsar::{consumer::InitialPosition, ConsumerOptions, SubType};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+/// The options of `copy`
+#[derive(StructOpt)]
+pub struct CopyOpts {
+    #[structopt(long)]
+    from_topic: TopicName,
+
+    #[structopt(long)]
+    to_topic: TopicName,
+
+    /// Cluster to copy from, defaults to --url. The admin API (--admin-url) must be the one of
+    /// this cluster
+    #[structopt(long)]
+    from_url: Option<ServiceUrls>,
+
+    /// Cluster to copy to, defaults to --url
+    #[structopt(long)]
+    to_url: Option<ServiceUrls>,
+
+    /// Only copy messages published after this time (RFC 3339, or a duration before now)
+    #[structopt(long, parse(try_from_str = parse_time))]
+    since: Option<DateTime<Utc>>,
+
+    /// Stop once every message published before the copy started has been copied
+    #[structopt(long)]
+    until_latest: bool,
+
+    /// Use a durable subscription, so an interrupted copy resumes where it stopped
+    #[structopt(long)]
+    durable: bool,
+
+    #[structopt(long, default_value = "pulsar-cli-copy")]
+    subscription_name: String,
+
+    #[structopt(long, default_value = "10s")]
+    progress_interval: humantime::Duration,
+
+    /// Check that the messages of each partition key are received in order, and tag the copies
+    /// with their source position for `consume --verify-forwarded` on the destination
+    #[structopt(long)]
+    verify_key_order: bool,
+}
+
+/// Copies the messages, from and to `url` unless `opts` give other clusters
+pub async fn run(
+    url: &ServiceUrls,
+    admin: &AdminClient,
+    connector: &Connector,
+    opts: &CopyOpts,
+) -> Result<Report> {
+    let from_url = opts.from_url.as_ref().unwrap_or(url);
+    let to_url = opts.to_url.as_ref().unwrap_or(url);
+    if opts.from_topic == opts.to_topic && from_url.to_string() == to_url.to_string() {
+        return Err(ExitError::new(
+            exit::USAGE,
+            format!(
+                "Not copying {} into itself, which would copy each message again and again; pass --to-url to copy it to another cluster",
+                opts.from_topic
+            ),
+        )
+        .into());
+    }
+
+    // The partitions that still have messages to copy from before the copy started
+    let mut snapshot = HashMap::new();
+    if opts.until_latest {
+        for partition in admin.partition_names(&opts.from_topic).await? {
+            let last = admin.last_message_id(&partition).await?;
+            if last.entry_id >= 0 {
+                snapshot.insert(partition, last);
+            }
+        }
+        if snapshot.is_empty() {
+            return Ok(Report::new(
+                format!("{} is empty, nothing to copy", opts.from_topic),
+                json!({ "copied": 0 }),
+            ));
+        }
+    }
+    let mut pending = snapshot.keys().cloned().collect::<HashSet<_>>();
+    let mut progress = Progress::new(estimate_total(admin, &opts.from_topic).await);
+
+    let from_topic = opts.from_topic.to_string();
+    let consumer_params = ConsumerParams {
+        topic: &from_topic,
+        subscription: &opts.subscription_name,
+        consumer_name: &opts.subscription_name,
+        sub_type: SubType::Exclusive,
+        options: ConsumerOptions {
+            durable: Some(opts.durable),
+            initial_position: InitialPosition::Earliest,
+            ..Default::default()
+        },
+    };
+    let mut consumer = connector.build_consumer(from_url, &consumer_params).await?;
+    let to_topic = opts.to_topic.to_string();
+    let mut producer = connector.build_producer(to_url, &to_topic, None).await?;
+    info!("Copying {} to {}", opts.from_topic, opts.to_topic);
+
+    // Reconnecting a non-durable subscription starts over from its initial position, so the
+    // last copied position is tracked to skip what was already copied
+    let mut copied = HashMap::<TopicName, Position>::new();
+    let mut key_order = KeyOrder::new(false);
+    let interval = Duration::from(opts.progress_interval);
+    let mut next_report = Instant::now() + interval;
+    while !opts.until_latest || !pending.is_empty() {
+        let message = match consumer.try_next().await {
+            Ok(Some(message)) => message,
+            Ok(None) => bail!("Consumer of {} closed", opts.from_topic),
+            Err(e) => {
+                warn!(
+                    "Error receiving from {}: {}. Reconnecting...",
+                    opts.from_topic, e
+                );
+                consumer = connector.build_consumer(from_url, &consumer_params).await?;
+                continue;
+            }
+        };
+
+        let partition = message.topic.parse::<TopicName>()?;
+        let position = position_of(&message);
+        let already_copied = copied
+            .get(&partition)
+            .map_or(false, |last| position <= *last);
+        let publish_time = timestamp_from_millis(message.metadata().publish_time);
+        // A publish time that can't be read is taken to be recent enough
+        let recent = match (opts.since, publish_time) {
+            (Some(since), Some(publish_time)) => publish_time >= since,
+            _ => true,
+        };
+        if !already_copied && opts.verify_key_order {
+            key_order.check(&message);
+        }
+        if !already_copied && recent {
+            let mut copy = copy_of(&message);
+            if opts.verify_key_order {
+                ordering::tag_source(&mut copy, &message);
+            }
+            connector
+                .send_with_retry(&mut producer, &to_topic, copy)
+                .await?;
+            progress.copied += 1;
+        }
+        copied.insert(partition.clone(), position);
+        let acked =
+            connection::ack_with_retry(&mut consumer, &message, false, connection::ACK_RETRIES)
+                .await;
+        if let Err(e) = acked {
+            warn!("{}", e);
+        }
+
+        if let Some(last) = snapshot.get(&partition) {
+            if reached(&message, position, last) {
+                pending.remove(&partition);
+            }
+        }
+        if Instant::now() >= next_report {
+            progress.report();
+            next_report += interval;
+        }
+    }
+
+    progress.report();
+    let mut report = Report::default();
+    report.line(format!(
+        "Copied {} messages from {} to {}",
+        progress.copied, opts.from_topic, opts.to_topic
+    ));
+    let mut json = json!({ "copied": progress.copied });
+    if opts.verify_key_order {
+        let (lines, key_order) = key_order.summary();
+        for line in lines {
+            report.line(line);
+        }
+        json["keyOrder"] = key_order;
+    }
+    report.set_json(json);
+    Ok(report)
+}
+
+/// Estimated number of messages to copy, from the number of entries stored by the topic
+async fn estimate_total(admin: &AdminClient, topic: &TopicName) -> Option<u64> {
+    let mut total = 0;
+    for partition in admin.partition_names(topic).await.ok()? {
+        total += admin
+            .internal_stats(&partition)
+            .await
+            .ok()?
+            .number_of_entries
+            .max(0) as u64;
+    }
+    Some(total)
+}
+
+struct Progress {
+    total: Option<u64>,
+    copied: u64,
+    started: Instant,
+}
+
+impl Progress {
+    fn new(total: Option<u64>) -> Self {
+        Self {
+            total,
+            copied: 0,
+            started: Instant::now(),
+        }
+    }
+
+    fn report(&self) {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = if elapsed > 0.0 {
+            self.copied as f64 / elapsed
+        } else {
+            0.0
+        };
+        match self.total {
+            Some(total) if rate > 0.0 && total > self.copied => {
+                let eta = Duration::from_secs(((total - self.copied) as f64 / rate) as u64);
+                eprintln!(
+                    "Copied {}/~{} messages ({:.1} msg/s, ETA {})",
+                    self.copied,
+                    total,
+                    rate,
+                    humantime::format_duration(eta)
+                );
+            }
+            Some(total) => eprintln!(
+                "Copied {}/~{} messages ({:.1} msg/s)",
+                self.copied, total, rate
+            ),
+            None => eprintln!("Copied {} messages ({:.1} msg/s)", self.copied, rate),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    admin::AdminClient,
+    commands::subscription,
+    confirm::confirm,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::{self, Report},
+    topic_name::TopicName,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::PathBuf};
+use structopt::StructOpt;
+
+/// The subcommands of `cursor`
+#[derive(StructOpt)]
+pub enum CursorCommand {
+    /// Save the mark-delete position of a subscription on each partition to a file, e.g. to
+    /// roll back consumption after a risky deploy
+    Export {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        subscription: String,
+
+        #[structopt(long)]
+        out: PathBuf,
+    },
+
+    /// Reset a subscription to the positions saved by `cursor export`
+    Import {
+        #[structopt(long = "in")]
+        input: PathBuf,
+
+        /// Reset the partitions the export has even if partitions were added since, leaving the
+        /// others as they are
+        #[structopt(long)]
+        force: bool,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+}
+
+impl CursorCommand {
+    /// Whether the command asks for confirmation, moving a cursor
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, CursorCommand::Import { .. })
+    }
+}
+
+/// The file written by `cursor export`
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CursorExport {
+    topic: String,
+    subscription: String,
+    exported_at: DateTime<Utc>,
+    /// 0 if the topic isn't partitioned
+    partitions: u32,
+    /// Mark-delete position by partition, as `ledger:entry`
+    positions: BTreeMap<String, String>,
+}
+
+/// Runs `command`
+pub async fn run(admin: &AdminClient, command: &CursorCommand) -> Result<Report> {
+    match command {
+        CursorCommand::Export {
+            topic,
+            subscription,
+            out,
+        } => {
+            let partitions = admin.partitions(topic).await?;
+            let export = CursorExport {
+                topic: topic.to_string(),
+                subscription: subscription.clone(),
+                exported_at: Utc::now(),
+                partitions,
+                positions: subscription::positions(admin, topic, subscription)
+                    .await?
+                    .into_iter()
+                    .map(|(partition, position)| (partition.to_string(), position))
+                    .collect(),
+            };
+            if export.positions.len() != partitions.max(1) as usize {
+                warn!(
+                    "Subscription {:?} only exists on {} of the partitions of {}, importing will leave the others as they are",
+                    subscription,
+                    export.positions.len(),
+                    topic
+                );
+            }
+            std::fs::write(out, serde_json::to_vec_pretty(&export)?)
+                .with_context(|| format!("Failed writing {}", out.display()))?;
+            let mut report = Report::default();
+            for (partition, position) in export.positions.iter() {
+                report.line(format!("{} ({})", position, partition));
+            }
+            report.line(format!(
+                "Exported the cursor of subscription {:?} on {} to {}",
+                subscription,
+                topic,
+                out.display()
+            ));
+            report.set_json(serde_json::to_value(&export)?);
+            Ok(report)
+        }
+
+        CursorCommand::Import { input, force, yes } => {
+            let contents = std::fs::read(input)
+                .with_context(|| format!("Failed reading {}", input.display()))?;
+            let export = serde_json::from_slice::<CursorExport>(&contents)
+                .with_context(|| format!("Invalid cursor export {}", input.display()))?;
+            let topic = export.topic.parse::<TopicName>()?;
+            let subscription = &export.subscription;
+
+            let partitions = admin.partitions(&topic).await?;
+            if partitions != export.partitions && !force {
+                return Err(ExitError::new(
+                    exit::REFUSED,
+                    format!(
+                        "{} had {} partition(s) when exported and has {} now; pass --force to reset the partitions the export has, leaving the others as they are",
+                        topic, export.partitions, partitions
+                    ),
+                )
+                .into());
+            }
+            let mut targets = Vec::new();
+            for partition in admin.partition_names(&topic).await? {
+                match export.positions.get(&partition.to_string()) {
+                    Some(position) => targets.push((partition, position.parse::<MessageId>()?)),
+                    None => warn!(
+                        "{} isn't in the export, leaving its cursor as it is",
+                        partition
+                    ),
+                }
+            }
+            if targets.is_empty() {
+                return Err(ExitError::new(
+                    exit::NOT_FOUND,
+                    format!("None of the partitions of {} now are in the export", topic),
+                )
+                .into());
+            }
+
+            let before = subscription::positions(admin, &topic, subscription).await?;
+            for (partition, position) in before.iter() {
+                output::progress(format!("Before: {} ({})", position, partition))?;
+            }
+            confirm(
+                &format!(
+                    "Reset subscription {:?} on {} to its positions of {} ({} of {} partition(s))? This rewinds or skips ahead its consumers.",
+                    subscription,
+                    topic,
+                    export.exported_at,
+                    targets.len(),
+                    before.len()
+                ),
+                *yes,
+            )?;
+
+            let mut failures = 0;
+            for (partition, position) in targets.iter() {
+                let mut body = position.to_admin_json();
+                // Excluding the mark-delete position itself, so that reading resumes after it
+                body["isExcluded"] = true.into();
+                let path = format!(
+                    "{}/resetcursor",
+                    AdminClient::subscription_path(partition, subscription)
+                );
+                if let Err(e) = admin.post(&path, Some(&body)).await {
+                    failures += 1;
+                    eprintln!("{}: failed resetting the cursor: {}", partition, e);
+                }
+            }
+
+            let after = subscription::positions(admin, &topic, subscription).await?;
+            let mut report = Report::default();
+            for (partition, position) in after.iter() {
+                report.line(format!("After: {} ({})", position, partition));
+            }
+            report.set_json(serde_json::json!({
+                "topic": topic.to_string(),
+                "subscription": subscription,
+                "exportedAt": export.exported_at,
+                "before": subscription::positions_json(&before),
+                "after": subscription::positions_json(&after),
+            }));
+            if failures > 0 {
+                report.print()?;
+                bail!(
+                    "Resetting the cursor failed on {} of {} partition(s)",
+                    failures,
+                    targets.len()
+                );
+            }
+            report.line(format!(
+                "Reset subscription {:?} to the positions exported at {}",
+                subscription, export.exported_at
+            ));
+            Ok(report)
+        }
+    }
+}
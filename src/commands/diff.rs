@@ -0,0 +1,388 @@
+use crate::{
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    display,
+    exit::{self, ExitError},
+    json_diff,
+    output::{self, Report},
+    topic_name::TopicName,
+};
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::{info, warn};
+use pulsar::{ConsumerOptions, SubType};
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+/// How often the messages still waiting for their counterpart are checked for expiry
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The options of `diff`
+#[derive(StructOpt)]
+pub struct DiffOpts {
+    #[structopt(long)]
+    topic_a: TopicName,
+
+    #[structopt(long)]
+    topic_b: TopicName,
+
+    /// Cluster of --topic-b, defaults to --url
+    #[structopt(long)]
+    url_b: Option<ServiceUrls>,
+
+    /// Field of the JSON payloads telling which message of one topic is which of the other: a
+    /// top-level field name, or a JSON pointer such as /order/id
+    #[structopt(long)]
+    key_field: String,
+
+    /// How long to compare the messages published on both topics for
+    #[structopt(long, default_value = "5m")]
+    duration: humantime::Duration,
+
+    /// How long a message waits for its counterpart on the other topic before it is reported as
+    /// only on its own
+    #[structopt(long, default_value = "30s")]
+    window: humantime::Duration,
+
+    /// How many messages wait for their counterpart at most, the oldest being reported as
+    /// unmatched early beyond that
+    #[structopt(long, default_value = "100000")]
+    max_pending: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    fn index(self) -> usize {
+        match self {
+            Side::A => 0,
+            Side::B => 1,
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Side::A => "A",
+            Side::B => "B",
+        }
+    }
+}
+
+struct Pending {
+    id: u64,
+    payload: Vec<u8>,
+}
+
+/// The messages of each side waiting for the message of the same key on the other side, up to
+/// a window of time and a number of messages
+struct Matcher {
+    window: Duration,
+    max_pending: usize,
+    /// By side, then key, oldest first
+    pending: [HashMap<String, VecDeque<Pending>>; 2],
+    /// Every message held, in the order received, to expire them. Messages matched since stay
+    /// in here until reached, and are skipped then
+    order: VecDeque<(Instant, u64, Side, String)>,
+    held: usize,
+    next_id: u64,
+}
+
+impl Matcher {
+    fn new(window: Duration, max_pending: usize) -> Self {
+        Self {
+            window,
+            max_pending: max_pending.max(1),
+            pending: [HashMap::new(), HashMap::new()],
+            order: VecDeque::new(),
+            held: 0,
+            next_id: 0,
+        }
+    }
+
+    /// The oldest message of the other side with `key`, no longer waiting
+    fn take(&mut self, side: Side, key: &str) -> Option<Vec<u8>> {
+        let pending = &mut self.pending[side.other().index()];
+        let queue = pending.get_mut(key)?;
+        let taken = queue.pop_front();
+        if queue.is_empty() {
+            pending.remove(key);
+        }
+        if taken.is_some() {
+            self.held -= 1;
+        }
+        taken.map(|taken| taken.payload)
+    }
+
+    fn hold(&mut self, side: Side, key: String, payload: Vec<u8>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending[side.index()]
+            .entry(key.clone())
+            .or_default()
+            .push_back(Pending { id, payload });
+        self.order.push_back((Instant::now(), id, side, key));
+        self.held += 1;
+    }
+
+    /// The messages that waited longer than the window, or the oldest beyond `max_pending`
+    fn expire(&mut self) -> Vec<(Side, String)> {
+        let mut expired = Vec::new();
+        while let Some((received, _, _, _)) = self.order.front() {
+            if received.elapsed() < self.window && self.held <= self.max_pending {
+                break;
+            }
+            let (_, id, side, key) = self.order.pop_front().expect("front checked above");
+            let pending = &mut self.pending[side.index()];
+            let queue = match pending.get_mut(&key) {
+                Some(queue) => queue,
+                None => continue,
+            };
+            // Otherwise matched already
+            if queue.front().map_or(false, |oldest| oldest.id == id) {
+                queue.pop_front();
+                if queue.is_empty() {
+                    pending.remove(&key);
+                }
+                self.held -= 1;
+                expired.push((side, key));
+            }
+        }
+        expired
+    }
+
+    /// Every message still waiting, e.g. once comparing is over
+    fn drain(&mut self) -> Vec<(Side, String)> {
+        let mut drained = Vec::new();
+        for (_, id, side, key) in self.order.drain(..) {
+            let queue = match self.pending[side.index()].get_mut(&key) {
+                Some(queue) => queue,
+                None => continue,
+            };
+            if queue.front().map_or(false, |oldest| oldest.id == id) {
+                queue.pop_front();
+                drained.push((side, key));
+            }
+        }
+        self.held = 0;
+        drained
+    }
+
+    fn is_empty(&self) -> bool {
+        self.held == 0
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    matched: u64,
+    different: u64,
+    only: [u64; 2],
+    unkeyed: [u64; 2],
+}
+
+/// Consumes both topics for the time given and reports how they differ
+pub async fn run(url: &ServiceUrls, connector: &Connector, opts: &DiffOpts) -> Result<Report> {
+    let url_b = opts.url_b.as_ref().unwrap_or(url);
+    let subscription = format!(
+        "pulsar-cli-diff-{}-{}",
+        Utc::now().timestamp_millis(),
+        std::process::id()
+    );
+    let topics = [opts.topic_a.to_string(), opts.topic_b.to_string()];
+    let mut consumer_a = connector
+        .build_consumer(url, &params(&topics[0], &subscription))
+        .await?;
+    let mut consumer_b = connector
+        .build_consumer(url_b, &params(&topics[1], &subscription))
+        .await?;
+    info!(
+        "Comparing {} (A) and {} (B) by {} for {}",
+        topics[0], topics[1], opts.key_field, opts.duration
+    );
+
+    let mut matcher = Matcher::new(opts.window.into(), opts.max_pending);
+    let mut counts = Counts::default();
+    let mut expiry = connection::pace(EXPIRY_INTERVAL, false);
+    let mut end = tokio::time::Instant::now() + *opts.duration;
+    // Once the duration is over, only the messages still waiting are matched, for one more
+    // window at most
+    let mut draining = false;
+    let report_only = |counts: &mut Counts, side: Side, key: &str| -> Result<()> {
+        counts.only[side.index()] += 1;
+        if !output::is_json() {
+            output::write(&display::highlight(&[format!(
+                "only on {} ({}): {}",
+                side.name(),
+                topics[side.index()],
+                key
+            )]))?;
+        }
+        Ok(())
+    };
+
+    loop {
+        let (side, message) = tokio::select! {
+            message = consumer_a.try_next() => (Side::A, message?),
+            message = consumer_b.try_next() => (Side::B, message?),
+            _ = expiry.tick() => {
+                for (side, key) in matcher.expire() {
+                    report_only(&mut counts, side, &key)?;
+                }
+                continue;
+            }
+            _ = tokio::time::sleep_until(end) => {
+                if draining {
+                    break;
+                }
+                draining = true;
+                end += *opts.window;
+                continue;
+            }
+        };
+        let message =
+            message.ok_or_else(|| format_err!("Consumer of {} closed", topics[side.index()]))?;
+        let consumer = match side {
+            Side::A => &mut consumer_a,
+            Side::B => &mut consumer_b,
+        };
+        connection::ack_with_retry(consumer, &message, false, connection::ACK_RETRIES).await?;
+
+        let payload = message.payload.data;
+        let key = match key_of(&payload, &opts.key_field) {
+            Some(key) => key,
+            None => {
+                if counts.unkeyed[side.index()] == 0 {
+                    warn!(
+                        "Messages of {} without {} in a JSON payload are not compared",
+                        topics[side.index()],
+                        opts.key_field
+                    );
+                }
+                counts.unkeyed[side.index()] += 1;
+                continue;
+            }
+        };
+        match matcher.take(side, &key) {
+            Some(other) => {
+                counts.matched += 1;
+                let (a, b) = match side {
+                    Side::A => (payload, other),
+                    Side::B => (other, payload),
+                };
+                if let Some(differences) = compare(&a, &b) {
+                    counts.different += 1;
+                    if !output::is_json() {
+                        output::write(&format!("different: {}\n{}", key, differences))?;
+                    }
+                }
+            }
+            None if draining => {}
+            None => matcher.hold(side, key, payload),
+        }
+        if draining && matcher.is_empty() {
+            break;
+        }
+    }
+    for (side, key) in matcher.drain() {
+        report_only(&mut counts, side, &key)?;
+    }
+
+    let mut report = Report::default();
+    report.line(format!(
+        "{} matched, {} different, {} only on A ({}), {} only on B ({})",
+        counts.matched, counts.different, counts.only[0], topics[0], counts.only[1], topics[1]
+    ));
+    if counts.unkeyed.iter().sum::<u64>() > 0 {
+        report.line(format!(
+            "Not compared for lack of {}: {} on A, {} on B",
+            opts.key_field, counts.unkeyed[0], counts.unkeyed[1]
+        ));
+    }
+    let ok = counts.different + counts.only[0] + counts.only[1] == 0;
+    report.set_json(json!({
+        "topicA": topics[0],
+        "topicB": topics[1],
+        "matched": counts.matched,
+        "different": counts.different,
+        "onlyA": counts.only[0],
+        "onlyB": counts.only[1],
+        "unkeyedA": counts.unkeyed[0],
+        "unkeyedB": counts.unkeyed[1],
+        "ok": ok,
+    }));
+    if !ok {
+        report.print()?;
+        return Err(ExitError::new(
+            exit::FAILURE,
+            format!("{} and {} differ", topics[0], topics[1]),
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+/// A non-durable subscription, so that nothing is left behind
+fn params<'a>(topic: &'a str, subscription: &'a str) -> ConsumerParams<'a> {
+    ConsumerParams {
+        topic,
+        subscription,
+        consumer_name: subscription,
+        sub_type: SubType::Exclusive,
+        options: ConsumerOptions {
+            durable: Some(false),
+            ..Default::default()
+        },
+    }
+}
+
+/// The value of `field` in a JSON `payload`, strings without their quotes
+fn key_of(payload: &[u8], field: &str) -> Option<String> {
+    let document = serde_json::from_slice::<Value>(payload).ok()?;
+    let key = if field.starts_with('/') {
+        document.pointer(field)
+    } else {
+        document.get(field)
+    }?;
+    Some(match key {
+        Value::String(key) => key.clone(),
+        other => other.to_string(),
+    })
+}
+
+/// How the payloads differ, if they do: structurally if both are JSON
+fn compare(a: &[u8], b: &[u8]) -> Option<String> {
+    match (
+        serde_json::from_slice::<Value>(a),
+        serde_json::from_slice::<Value>(b),
+    ) {
+        (Ok(a), Ok(b)) => {
+            let differences = json_diff::diff(&a, &b);
+            if differences.is_empty() {
+                None
+            } else {
+                Some(json_diff::describe(&differences))
+            }
+        }
+        _ if a == b => None,
+        _ => Some(display::highlight(&[format!(
+            "  payloads differ: {:?} -> {:?}",
+            String::from_utf8_lossy(a),
+            String::from_utf8_lossy(b)
+        )])),
+    }
+}
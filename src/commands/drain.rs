@@ -0,0 +1,205 @@
+use crate::{
+    admin::AdminClient,
+    commands::subscription::subscription_backlog,
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    display::{DisplayMessage, MessageFormatter},
+    forwarding::{position_of, reached},
+    output::Report,
+    picker,
+    topic_name::TopicName,
+    units::format_bytes,
+};
+use anyhow::{format_err, Result};
+use futures::TryStreamExt;
+use log::warn;
+use pulsar::{consumer::Message, ConsumerOptions, SubType};
+use regex::Regex;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+/// Number of messages drained between two cumulative acks of a partition
+const ACK_BATCH_SIZE: u64 = 1000;
+
+/// The options of `drain`
+#[derive(StructOpt)]
+pub struct DrainOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Picked among the subscriptions of the topic when not given, on a terminal
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    /// Only drain the messages whose payload matches this regex, leaving the others in the
+    /// backlog
+    #[structopt(long)]
+    grep: Option<Regex>,
+
+    /// Print every drained message
+    #[structopt(long)]
+    verbose: bool,
+
+    #[structopt(long, default_value = "pulsar-cli-drain")]
+    consumer_name: String,
+
+    #[structopt(long, default_value = "5s")]
+    progress_interval: humantime::Duration,
+}
+
+/// Acks the backlog of the subscription, or the messages of it matching the filter
+pub async fn run(
+    url: &ServiceUrls,
+    admin: &AdminClient,
+    connector: &Connector,
+    opts: &DrainOpts,
+) -> Result<Report> {
+    let subscription =
+        &picker::subscription(admin, &opts.topic, opts.subscription.as_deref()).await?;
+    // Messages published after this point are left to the regular consumers
+    let mut pending = HashMap::new();
+    for partition in admin.partition_names(&opts.topic).await? {
+        if subscription_backlog(admin, &partition, subscription).await? > 0 {
+            let last = admin.last_message_id(&partition).await?;
+            pending.insert(partition, last);
+        }
+    }
+    if pending.is_empty() {
+        return Ok(Report::new(
+            format!(
+                "Subscription {:?} on {} has no backlog",
+                subscription, opts.topic
+            ),
+            json!({ "drained": 0, "bytes": 0, "kept": 0 }),
+        ));
+    }
+
+    let topic = opts.topic.to_string();
+    let mut consumer = connector
+        .build_consumer(
+            url,
+            &ConsumerParams {
+                topic: &topic,
+                subscription,
+                consumer_name: &opts.consumer_name,
+                sub_type: SubType::Exclusive,
+                options: ConsumerOptions {
+                    durable: Some(true),
+                    ..Default::default()
+                },
+            },
+        )
+        .await?;
+
+    let formatter = MessageFormatter {
+        json: false,
+        xml: false,
+        entry_metadata: false,
+        replicated_from: false,
+        topic: false,
+    };
+    // Without a filter every message is drained, so acking the last one of each partition
+    // cumulatively is enough
+    let mut unacked = HashMap::<TopicName, (Message<Vec<u8>>, u64)>::new();
+    let mut drained = 0u64;
+    let mut bytes = 0u64;
+    let mut kept = 0u64;
+    let started = Instant::now();
+    let mut progress = tokio::time::interval(opts.progress_interval.into());
+    progress.tick().await;
+    let mut stopped = None;
+    while !pending.is_empty() {
+        tokio::select! {
+            message = consumer.try_next() => {
+                let message = message?.ok_or_else(|| format_err!("Consumer of {} closed", topic))?;
+                let partition = message.topic.parse::<TopicName>()?;
+                let position = position_of(&message);
+                if let Some(last) = pending.get(&partition) {
+                    if reached(&message, position, last) {
+                        pending.remove(&partition);
+                    }
+                }
+
+                let matches = opts.grep.as_ref().map_or(true, |grep| {
+                    grep.is_match(&String::from_utf8_lossy(&message.payload.data))
+                });
+                if !matches {
+                    kept += 1;
+                    continue;
+                }
+                if opts.verbose {
+                    if let Err(e) = formatter.print(&DisplayMessage::from_message(&message)) {
+                        // Stopping with what was drained so far acked
+                        stopped = Some(e);
+                        break;
+                    }
+                }
+                drained += 1;
+                bytes += message.payload.data.len() as u64;
+
+                if opts.grep.is_some() {
+                    connection::ack_with_retry(&mut consumer, &message, false, connection::ACK_RETRIES)
+                        .await?;
+                    continue;
+                }
+                let count = unacked.get(&partition).map_or(0, |(_, count)| *count) + 1;
+                if count >= ACK_BATCH_SIZE {
+                    connection::ack_with_retry(&mut consumer, &message, true, connection::ACK_RETRIES)
+                        .await?;
+                    unacked.remove(&partition);
+                } else {
+                    unacked.insert(partition, (message, count));
+                }
+            }
+
+            _ = progress.tick() => {
+                let mut backlog = 0;
+                for partition in pending.keys() {
+                    match subscription_backlog(admin, partition, subscription).await {
+                        Ok(partition_backlog) => backlog += partition_backlog,
+                        Err(e) => warn!("Failed fetching the backlog of {}: {}", partition, e),
+                    }
+                }
+                eprintln!(
+                    "Drained {} messages, {} left in the backlog of {} partition(s)",
+                    drained,
+                    backlog,
+                    pending.len()
+                );
+            }
+        }
+    }
+    for (message, _) in unacked.values() {
+        connection::ack_with_retry(&mut consumer, message, true, connection::ACK_RETRIES).await?;
+    }
+    if let Some(e) = stopped {
+        return Err(e);
+    }
+
+    let elapsed = Duration::from_millis(started.elapsed().as_millis() as u64);
+    let mut report = Report::default();
+    report.line(format!(
+        "Drained {} messages ({}) from subscription {:?} on {} in {}",
+        drained,
+        format_bytes(bytes as f64),
+        subscription,
+        opts.topic,
+        humantime::format_duration(elapsed)
+    ));
+    if kept > 0 {
+        report.line(format!(
+            "Left {} messages not matching --grep in the backlog",
+            kept
+        ));
+    }
+    report.set_json(json!({
+        "drained": drained,
+        "bytes": bytes,
+        "kept": kept,
+        "seconds": elapsed.as_secs_f64(),
+    }));
+    Ok(report)
+}
@@ -0,0 +1,86 @@
+use crate::{
+    admin::AdminClient,
+    display::MessageFormatter,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::{self, Report},
+    topic_name::TopicName,
+};
+use anyhow::{bail, Result};
+use structopt::StructOpt;
+
+/// The options of `get-message`
+#[derive(StructOpt)]
+pub struct GetMessageOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// ledger:entry[:partition[:batchIndex]]. Without a batch index, every message of a
+    /// batched entry is printed
+    #[structopt(long)]
+    message_id: MessageId,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Fetches the message and prints it
+pub async fn run(admin: &AdminClient, opts: &GetMessageOpts) -> Result<Report> {
+    let id = &opts.message_id;
+    let topic = match (admin.partitions(&opts.topic).await?, id.partition) {
+        (0, _) => opts.topic.clone(),
+        (partitions, Some(partition)) if partition >= 0 && (partition as u32) < partitions => {
+            opts.topic.partition(partition as u32)
+        }
+        (partitions, Some(partition)) => bail!(
+            "Partition {} is out of range, {} has {} partitions",
+            partition,
+            opts.topic,
+            partitions
+        ),
+        (_, None) => bail!(
+            "{} is partitioned, the message ID must include the partition (ledger:entry:partition)",
+            opts.topic
+        ),
+    };
+
+    let messages = admin
+        .get_messages(&format!(
+            "{}/ledger/{}/entry/{}",
+            topic.admin_path(),
+            id.ledger_id,
+            id.entry_id
+        ))
+        .await?;
+    let selected = messages.iter().filter(|message| match id.batch_index {
+        None => true,
+        Some(index) => message.message_id.and_then(|id| id.batch_index) == Some(index),
+    });
+
+    let formatter = MessageFormatter {
+        json: output::json(opts.json),
+        xml: false,
+        entry_metadata: false,
+        replicated_from: false,
+        topic: false,
+    };
+    let mut found = false;
+    for message in selected {
+        found = true;
+        formatter.print(&message.display())?;
+    }
+    if !found {
+        return Err(ExitError::new(
+            exit::NOT_FOUND,
+            format!(
+                "Entry {}:{} has {} message(s), batch index {} not found",
+                id.ledger_id,
+                id.entry_id,
+                messages.len(),
+                id.batch_index.unwrap_or_default()
+            ),
+        )
+        .into());
+    }
+    Ok(Report::default())
+}
@@ -0,0 +1,192 @@
+use crate::{
+    admin::{stats::TopicStats, AdminClient, AdminError},
+    commands::backlog::{self, Backlog},
+    connection,
+    output::{self, Report},
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use regex::Regex;
+use serde_json::json;
+use std::{collections::BTreeMap, str::FromStr};
+use structopt::StructOpt;
+
+const CSV_HEADER: &str =
+    "timestamp,topic,subscription,backlog_entries,backlog_bytes,oldest_message_age_seconds,breach";
+
+/// The options of `lag`
+#[derive(StructOpt)]
+pub struct LagOpts {
+    #[structopt(long)]
+    namespace: NamespaceName,
+
+    /// Only poll topics whose full name matches this regular expression
+    #[structopt(long)]
+    topic_filter: Option<Regex>,
+
+    #[structopt(long, default_value = "30s")]
+    interval: humantime::Duration,
+
+    /// Record format, JSON with --output json
+    #[structopt(long, default_value = "csv", possible_values = &["csv", "json"])]
+    format: LagFormat,
+
+    /// Flag the subscriptions with more backlog entries than this
+    #[structopt(long)]
+    threshold: Option<i64>,
+
+    /// How many partitions are polled at the same time
+    #[structopt(long, default_value = "8")]
+    concurrency: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum LagFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for LagFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(LagFormat::Csv),
+            "json" => Ok(LagFormat::Json),
+            other => Err(format_err!("Invalid lag format: {:?}", other)),
+        }
+    }
+}
+
+/// Records the backlogs at every interval until stopped
+pub async fn run(admin: &AdminClient, opts: &LagOpts) -> Result<Report> {
+    let json = opts.format == LagFormat::Json || output::is_json();
+    if !json {
+        output::write_line(CSV_HEADER)?;
+    }
+    let mut ticks = connection::pace(opts.interval.into(), true);
+    loop {
+        ticks.tick().await;
+        let timestamp = Utc::now();
+        let backlogs = match poll(admin, opts).await {
+            Ok(backlogs) => backlogs,
+            Err(e) => {
+                warn!("Failed listing the topics of {}: {:#}", opts.namespace, e);
+                continue;
+            }
+        };
+
+        let mut out = String::new();
+        for ((topic, subscription), backlog) in backlogs {
+            let age = backlog
+                .head
+                .map(|head| (timestamp - head).num_seconds().max(0));
+            let breach = opts
+                .threshold
+                .map_or(false, |threshold| backlog.entries > threshold);
+            let record = if json {
+                json!({
+                    "timestamp": timestamp.to_rfc3339(),
+                    "topic": topic,
+                    "subscription": subscription,
+                    "backlogEntries": backlog.entries,
+                    "backlogBytes": backlog.bytes,
+                    "oldestMessageAgeSeconds": age,
+                    "breach": breach,
+                })
+                .to_string()
+            } else {
+                [
+                    timestamp.to_rfc3339(),
+                    csv_field(&topic),
+                    csv_field(&subscription),
+                    backlog.entries.to_string(),
+                    backlog.bytes.to_string(),
+                    age.map(|age| age.to_string()).unwrap_or_default(),
+                    breach.to_string(),
+                ]
+                .join(",")
+            };
+            out.push_str(&record);
+            out.push('\n');
+        }
+        output::write(&out)?;
+    }
+}
+
+/// The backlog of every subscription of the namespace's topics, by topic (partitioned topics
+/// adding up their partitions) and subscription
+async fn poll(admin: &AdminClient, opts: &LagOpts) -> Result<BTreeMap<(String, String), Backlog>> {
+    let mut partitions = Vec::new();
+    for topic in admin.topics(&opts.namespace, true).await? {
+        let partition = topic.parse::<TopicName>()?;
+        let topic = partition
+            .partitioned_parent()
+            .map_or_else(|| partition.to_string(), |(parent, _)| parent.to_string());
+        if opts
+            .topic_filter
+            .as_ref()
+            .map_or(true, |filter| filter.is_match(&topic))
+        {
+            partitions.push((topic, partition));
+        }
+    }
+
+    let mut polled = stream::iter(partitions)
+        .map(|(topic, partition)| async move {
+            (topic, poll_partition(admin, &partition).await, partition)
+        })
+        .buffer_unordered(opts.concurrency.max(1));
+    let mut backlogs = BTreeMap::<_, Backlog>::new();
+    while let Some((topic, result, partition)) = polled.next().await {
+        match result {
+            Ok(subscriptions) => {
+                for (subscription, backlog) in subscriptions {
+                    backlogs
+                        .entry((topic.clone(), subscription))
+                        .or_default()
+                        .add(&backlog);
+                }
+            }
+            // Deleted since the topics were listed
+            Err(e) if AdminError::is_not_found(&e) => debug!("{} is gone: {}", partition, e),
+            Err(e) => warn!("Failed polling {}: {:#}", partition, e),
+        }
+    }
+    Ok(backlogs)
+}
+
+async fn poll_partition(
+    admin: &AdminClient,
+    partition: &TopicName,
+) -> Result<Vec<(String, Backlog)>> {
+    let stats: TopicStats = admin
+        .get(&format!("{}/stats", partition.admin_path()))
+        .await?;
+    let internal = admin.internal_stats(partition).await?;
+    let mut backlogs = Vec::with_capacity(stats.subscriptions.len());
+    for (subscription, subscription_stats) in stats.subscriptions.iter() {
+        let backlog = backlog::subscription_backlog(
+            admin,
+            partition,
+            subscription,
+            subscription_stats,
+            &internal,
+        )
+        .await?;
+        backlogs.push((subscription.clone(), backlog));
+    }
+    Ok(backlogs)
+}
+
+/// `value` quoted if it has to be to fit in a CSV field
+fn csv_field(value: &str) -> String {
+    if value.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
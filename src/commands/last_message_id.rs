@@ -0,0 +1,77 @@
+use crate::{
+    admin::AdminClient,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use serde_json::json;
+use structopt::StructOpt;
+
+/// The options of `last-message-id`
+#[derive(StructOpt)]
+pub struct LastMessageIdOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Also estimate how many entries this subscription is behind
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Prints the ID of the last message of the topic
+pub async fn run(admin: &AdminClient, opts: &LastMessageIdOpts) -> Result<Report> {
+    let mut results = Vec::new();
+    for partition in admin.partition_names(&opts.topic).await? {
+        let last = admin.last_message_id(&partition).await?;
+        let behind = match &opts.subscription {
+            None => None,
+            Some(subscription) => {
+                let stats = admin.internal_stats(&partition).await?;
+                let cursor = stats.cursors.get(subscription).ok_or_else(|| {
+                    ExitError::new(
+                        exit::NOT_FOUND,
+                        format!("Subscription {:?} not found on {}", subscription, partition),
+                    )
+                })?;
+                let mark_delete = cursor.mark_delete_position.parse::<MessageId>()?;
+                Some((mark_delete, stats.entries_between(&mark_delete, &last)))
+            }
+        };
+        results.push((partition, last, behind));
+    }
+
+    let json = json!(results
+        .iter()
+        .map(|(partition, last, behind)| {
+            let mut result = json!({
+                "topic": partition.to_string(),
+                "lastMessageId": last.to_string(),
+            });
+            if let Some((mark_delete, entries)) = behind {
+                result["markDeletePosition"] = json!(mark_delete.to_string());
+                result["entriesBehind"] = json!(entries);
+            }
+            result
+        })
+        .collect::<Vec<_>>());
+    if opts.json {
+        return Ok(Report::json(json));
+    }
+    let mut report = Report::default();
+    for (partition, last, behind) in results.iter() {
+        match behind {
+            None => report.line(format!("{}: {}", partition, last)),
+            Some((mark_delete, entries)) => report.line(format!(
+                "{}: {} (mark-delete position {}, ~{} entries behind)",
+                partition, last, mark_delete, entries
+            )),
+        }
+    }
+    report.set_json(json);
+    Ok(report)
+}
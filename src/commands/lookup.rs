@@ -0,0 +1,94 @@
+use crate::{
+    connection::{Connector, ServiceUrls},
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use serde_json::json;
+use std::fmt::Write;
+use structopt::StructOpt;
+
+/// The options of `lookup`
+#[derive(StructOpt)]
+pub struct LookupOpts {
+    #[structopt(long)]
+    topic: TopicName,
+}
+
+/// The options of `partitions`
+#[derive(StructOpt)]
+pub struct PartitionsOpts {
+    #[structopt(long)]
+    topic: TopicName,
+}
+
+/// Prints the broker serving the topic, or each of its partitions
+pub async fn run_lookup(
+    url: &ServiceUrls,
+    connector: &Connector,
+    opts: &LookupOpts,
+) -> Result<Report> {
+    let pulsar = connector.connect(url).await?;
+    let topic = opts.topic.to_string();
+    if pulsar.lookup_partitioned_topic_number(&topic).await? == 0 {
+        let address = pulsar.lookup_topic(&topic).await?;
+        let mut json = serde_json::Map::new();
+        json.insert(topic, to_json(&address));
+        return Ok(Report::new(describe(&address), json.into()));
+    }
+
+    let rows = pulsar
+        .lookup_partitioned_topic(&topic)
+        .await?
+        .into_iter()
+        .collect::<Vec<_>>();
+    let width = rows.iter().map(|(partition, _)| partition.len()).max();
+    let width = width.unwrap_or_default().max("PARTITION".len());
+    let mut out = String::new();
+    writeln!(out, "{:width$}  BROKER", "PARTITION", width = width)?;
+    let mut json = serde_json::Map::new();
+    for (partition, address) in rows {
+        writeln!(
+            out,
+            "{:width$}  {}",
+            partition,
+            describe(&address),
+            width = width
+        )?;
+        json.insert(partition, to_json(&address));
+    }
+    Ok(Report::new(out, json.into()))
+}
+
+/// Prints the number of partitions of the topic
+pub async fn run_partitions(
+    url: &ServiceUrls,
+    connector: &Connector,
+    opts: &PartitionsOpts,
+) -> Result<Report> {
+    let pulsar = connector.connect(url).await?;
+    let partitions = pulsar
+        .lookup_partitioned_topic_number(opts.topic.to_string())
+        .await?;
+    Ok(Report::new(
+        partitions.to_string(),
+        json!({ "partitions": partitions }),
+    ))
+}
+
+fn to_json(address: &pulsar::BrokerAddress) -> serde_json::Value {
+    json!({
+        "brokerUrl": address.broker_url,
+        "url": address.url.to_string(),
+        "proxy": address.proxy,
+    })
+}
+
+/// The broker at `address`, and the proxy reaching it if any
+pub fn describe(address: &pulsar::BrokerAddress) -> String {
+    if address.proxy {
+        format!("{} (through proxy {})", address.broker_url, address.url)
+    } else {
+        address.broker_url.clone()
+    }
+}
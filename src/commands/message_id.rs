@@ -0,0 +1,153 @@
+use crate::{
+    admin::AdminClient,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use colored_json::to_colored_json_auto;
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use structopt::StructOpt;
+
+/// The subcommands of `message-id`
+#[derive(StructOpt)]
+pub enum MessageIdCommand {
+    /// Check a message ID, as ledger:entry[:partition[:batchIndex]], and print its components
+    Parse { id: MessageId },
+
+    /// Print whether the first message ID is earlier than, later than or equal to the second
+    Compare { a: MessageId, b: MessageId },
+
+    /// Estimate how many entries there are from the first message ID to the second, from the
+    /// ledgers of the topic
+    Distance {
+        #[structopt(long)]
+        topic: TopicName,
+
+        from: MessageId,
+
+        to: MessageId,
+    },
+}
+
+/// Runs `command`, with an admin API client only for those that need one
+pub async fn run(
+    admin_client: impl Fn() -> Result<AdminClient>,
+    command: &MessageIdCommand,
+) -> Result<Report> {
+    match command {
+        MessageIdCommand::Parse { id } => {
+            let json = components(id);
+            Ok(Report::new(to_colored_json_auto(&json)?, json))
+        }
+
+        MessageIdCommand::Compare { a, b } => {
+            let result = match order(a, b)? {
+                Ordering::Less => "earlier",
+                Ordering::Greater => "later",
+                Ordering::Equal => "equal",
+            };
+            Ok(Report::new(
+                result,
+                json!({ "a": a.to_string(), "b": b.to_string(), "result": result }),
+            ))
+        }
+
+        MessageIdCommand::Distance { topic, from, to } => {
+            let admin = admin_client()?;
+            let ordering = order(from, to)?;
+            let partition = match from.partition.or(to.partition).filter(|p| *p >= 0) {
+                Some(_) if topic.partitioned_parent().is_some() => topic.clone(),
+                Some(index) => topic.partition(index as u32),
+                None if admin.partitions(topic).await? > 0 => {
+                    return Err(ExitError::new(
+                        exit::USAGE,
+                        format!(
+                            "{} is partitioned, give the partition of the message IDs as ledger:entry:partition",
+                            topic
+                        ),
+                    )
+                    .into())
+                }
+                None => topic.clone(),
+            };
+            let stats = admin.internal_stats(&partition).await?;
+            let (earlier, later) = match ordering {
+                Ordering::Greater => (to, from),
+                _ => (from, to),
+            };
+            let entries = stats.entries_between(earlier, later);
+            let distance = if ordering == Ordering::Greater {
+                -entries
+            } else {
+                entries
+            };
+
+            let mut caveats = vec![
+                "entries rather than messages: a batch entry holds several".to_owned(),
+                "the ledger being written to counts up to the later ID's entry".to_owned(),
+            ];
+            let first_ledger = stats.ledgers.iter().map(|ledger| ledger.ledger_id).min();
+            if first_ledger.map_or(false, |first| earlier.ledger_id < first) {
+                caveats.push(format!(
+                    "ledger {} was deleted (e.g. by retention), its entries aren't counted",
+                    earlier.ledger_id
+                ));
+            }
+            if later.ledger_id != earlier.ledger_id
+                && !stats
+                    .ledgers
+                    .iter()
+                    .any(|ledger| ledger.ledger_id == later.ledger_id)
+            {
+                caveats.push(format!(
+                    "ledger {} isn't one of the topic's, the estimate is likely off",
+                    later.ledger_id
+                ));
+            }
+
+            let mut report = Report::default();
+            report.line(format!(
+                "~{} entries from {} to {} on {}",
+                distance, from, to, partition
+            ));
+            report.line("An estimate, with caveats:");
+            for caveat in caveats.iter() {
+                report.line(format!("  - {}", caveat));
+            }
+            report.set_json(json!({
+                "topic": partition.to_string(),
+                "from": from.to_string(),
+                "to": to.to_string(),
+                "entries": distance,
+                "caveats": caveats,
+            }));
+            Ok(report)
+        }
+    }
+}
+
+fn components(id: &MessageId) -> Value {
+    json!({
+        "ledgerId": id.ledger_id,
+        "entryId": id.entry_id,
+        "partition": id.partition,
+        "batchIndex": id.batch_index,
+    })
+}
+
+/// How `a` compares to `b`, failing if they are on different partitions
+fn order(a: &MessageId, b: &MessageId) -> Result<Ordering> {
+    a.compare(b).ok_or_else(|| {
+        ExitError::new(
+            exit::USAGE,
+            format!(
+                "{} and {} are on different partitions, they can't be compared",
+                a, b
+            ),
+        )
+        .into()
+    })
+}
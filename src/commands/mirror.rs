@@ -0,0 +1,323 @@
+use crate::{
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    exit::{self, ExitError},
+    forwarding::{copy_of, position_of, Position},
+    metrics::{self, STATS},
+    ordering::{self, KeyOrder},
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::{bail, Context, Result};
+use futures::TryStreamExt;
+use log::{info, warn};
+use pulsar::{
+    consumer::{InitialPosition, Message},
+    producer::SendFuture,
+    ConsumerOptions, SubType,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use structopt::StructOpt;
+
+/// The options of `mirror`
+#[derive(StructOpt)]
+pub struct MirrorOpts {
+    #[structopt(long)]
+    from_topic: TopicName,
+
+    /// Destination topic, defaults to the name of the source topic
+    #[structopt(long)]
+    to_topic: Option<TopicName>,
+
+    /// Cluster to mirror from, defaults to --url
+    #[structopt(long)]
+    from_url: Option<ServiceUrls>,
+
+    /// Cluster to mirror to
+    #[structopt(long)]
+    to_url: ServiceUrls,
+
+    /// File recording the last mirrored message of each partition, so that a restart resumes
+    /// where the previous run stopped
+    #[structopt(long)]
+    checkpoint_file: PathBuf,
+
+    #[structopt(long, default_value = "pulsar-cli-mirror")]
+    subscription_name: String,
+
+    /// Name of the destination producer. Keep it stable across restarts, so that brokers with
+    /// deduplication enabled drop messages resent after a crash
+    #[structopt(long, default_value = "pulsar-cli-mirror")]
+    producer_name: String,
+
+    /// Maximum number of messages sent to the destination and not acknowledged yet
+    #[structopt(long, default_value = "100")]
+    max_in_flight: usize,
+
+    #[structopt(long, default_value = "5s")]
+    checkpoint_interval: humantime::Duration,
+
+    #[structopt(long, default_value = "30s")]
+    stats_interval: humantime::Duration,
+
+    /// Check that the messages of each partition key are received in order, and tag the copies
+    /// with their source position for `consume --verify-forwarded` on the destination
+    #[structopt(long)]
+    verify_key_order: bool,
+}
+
+/// The last message of each source partition known to have reached the destination
+#[derive(Default, Serialize, Deserialize)]
+struct Checkpoint {
+    positions: BTreeMap<String, Position>,
+}
+
+impl Checkpoint {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed reading checkpoint {}", path.display()))?;
+        serde_json::from_slice(&contents)
+            .with_context(|| format!("Invalid checkpoint file {}", path.display()))
+    }
+
+    /// Writes the checkpoint to a temporary file first, so a crash never leaves a truncated one
+    fn save(&self, path: &Path) -> Result<()> {
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed writing checkpoint {}", temp.display()))?;
+        std::fs::rename(&temp, path)
+            .with_context(|| format!("Failed writing checkpoint {}", path.display()))?;
+        Ok(())
+    }
+
+    fn contains(&self, partition: &str, position: Position) -> bool {
+        self.positions
+            .get(partition)
+            .map_or(false, |last| position <= *last)
+    }
+}
+
+/// A message sent to the destination whose receipt hasn't arrived yet
+struct InFlight {
+    receipt: SendFuture,
+    source: Message<Vec<u8>>,
+    partition: String,
+    position: Position,
+}
+
+/// Mirrors the topic until stopped
+pub async fn run(url: &ServiceUrls, connector: &Connector, opts: &MirrorOpts) -> Result<Report> {
+    let from_url = opts.from_url.as_ref().unwrap_or(url);
+    let from_topic = opts.from_topic.to_string();
+    let to_topic = opts
+        .to_topic
+        .as_ref()
+        .unwrap_or(&opts.from_topic)
+        .to_string();
+    if from_topic == to_topic && from_url.to_string() == opts.to_url.to_string() {
+        return Err(ExitError::new(
+            exit::USAGE,
+            format!(
+                "Not mirroring {} into itself; --to-url is the cluster mirrored from, pass another one or --to-topic",
+                from_topic
+            ),
+        )
+        .into());
+    }
+
+    let mut checkpoint = Checkpoint::load(&opts.checkpoint_file)?;
+    let consumer_params = ConsumerParams {
+        topic: &from_topic,
+        subscription: &opts.subscription_name,
+        consumer_name: &opts.subscription_name,
+        sub_type: SubType::Exclusive,
+        options: ConsumerOptions {
+            durable: Some(true),
+            initial_position: InitialPosition::Earliest,
+            ..Default::default()
+        },
+    };
+    let mut consumer = connector.build_consumer(from_url, &consumer_params).await?;
+    let build_producer =
+        || connector.build_producer(&opts.to_url, &to_topic, Some(&opts.producer_name));
+    let mut producer = build_producer().await?;
+    let copy = |message: &Message<Vec<u8>>| {
+        let mut forwarded = copy_of(message);
+        if opts.verify_key_order {
+            ordering::tag_source(&mut forwarded, message);
+        }
+        forwarded
+    };
+    let mut key_order = KeyOrder::new(false);
+    info!(
+        "Mirroring {} to {} on {}",
+        from_topic, to_topic, opts.to_url
+    );
+
+    let mut in_flight = VecDeque::<InFlight>::new();
+    // The last message of each partition handed to the producer, which is ahead of the
+    // checkpoint by the messages in flight
+    let mut sent = HashMap::<String, Position>::new();
+    let started = Instant::now();
+    let mut checkpoint_timer = tokio::time::interval(opts.checkpoint_interval.into());
+    let mut stats_timer = tokio::time::interval(opts.stats_interval.into());
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+
+            message = consumer.try_next(), if in_flight.len() < opts.max_in_flight => {
+                let message = match message {
+                    Ok(Some(message)) => message,
+                    Ok(None) => bail!("Consumer of {} closed", from_topic),
+                    Err(e) => {
+                        warn!("Error receiving from {}: {}. Reconnecting...", from_topic, e);
+                        metrics::add(&STATS.consumer_reconnects, 1);
+                        consumer = connector.build_consumer(from_url, &consumer_params).await?;
+                        continue;
+                    }
+                };
+                STATS.consumed(&message.payload.data);
+                STATS.observe_latency(message.metadata().publish_time);
+                let partition = message.topic.parse::<TopicName>()?.to_string();
+                let position = position_of(&message);
+                if checkpoint.contains(&partition, position) {
+                    // Already mirrored by a previous run, only the source ack was lost
+                    connection::ack_with_retry(&mut consumer, &message, false, connection::ACK_RETRIES)
+                        .await?;
+                    continue;
+                }
+                if sent.get(&partition).map_or(false, |last| position <= *last) {
+                    // Redelivered after reconnecting, the original is still in flight
+                    continue;
+                }
+                sent.insert(partition.clone(), position);
+                if opts.verify_key_order {
+                    key_order.check(&message);
+                }
+                match producer.send(copy(&message)).await {
+                    Ok(receipt) => in_flight.push_back(InFlight { receipt, source: message, partition, position }),
+                    Err(e) => {
+                        warn!("Failed sending to {}: {}. Reconnecting...", to_topic, e);
+                        metrics::add(&STATS.send_failures, 1);
+                        metrics::add(&STATS.producer_reconnects, 1);
+                        producer = build_producer().await?;
+                        let receipt = producer.send(copy(&message)).await?;
+                        in_flight.push_back(InFlight { receipt, source: message, partition, position });
+                    }
+                }
+            }
+
+            receipt = async { (&mut in_flight.front_mut().unwrap().receipt).await }, if !in_flight.is_empty() => {
+                if let Err(e) = receipt {
+                    // Only resend the messages whose receipts are failures as well, the others
+                    // already reached the destination
+                    warn!("Failed sending to {}: {}. Reconnecting...", to_topic, e);
+                    metrics::add(&STATS.send_failures, 1);
+                    let failed = in_flight.pop_front().unwrap();
+                    let mut settled = vec![(failed, false)];
+                    settled.extend(receipts(&mut in_flight, connector).await);
+                    metrics::add(&STATS.producer_reconnects, 1);
+                    producer = build_producer().await?;
+                    for (entry, delivered) in settled {
+                        if !delivered {
+                            connector.send_with_retry(&mut producer, &to_topic, copy(&entry.source)).await?;
+                        }
+                        complete(&mut consumer, &mut checkpoint, entry).await;
+                    }
+                    continue;
+                }
+                let entry = in_flight.pop_front().unwrap();
+                complete(&mut consumer, &mut checkpoint, entry).await;
+            }
+
+            _ = checkpoint_timer.tick() => checkpoint.save(&opts.checkpoint_file)?,
+
+            _ = stats_timer.tick() => {
+                let elapsed = started.elapsed().as_secs_f64().max(1.0);
+                eprintln!(
+                    "Mirrored {} messages ({:.1} msg/s), {} in flight, {} source / {} destination reconnects",
+                    metrics::get(&STATS.forwarded),
+                    metrics::get(&STATS.forwarded) as f64 / elapsed,
+                    in_flight.len(),
+                    metrics::get(&STATS.consumer_reconnects),
+                    metrics::get(&STATS.producer_reconnects)
+                );
+            }
+        }
+    }
+
+    info!("Waiting for {} messages in flight", in_flight.len());
+    let settled = receipts(&mut in_flight, connector).await;
+    if settled.iter().any(|(_, delivered)| !delivered) {
+        metrics::add(&STATS.producer_reconnects, 1);
+        producer = build_producer().await?;
+    }
+    for (entry, delivered) in settled {
+        if !delivered {
+            connector
+                .send_with_retry(&mut producer, &to_topic, copy(&entry.source))
+                .await?;
+        }
+        complete(&mut consumer, &mut checkpoint, entry).await;
+    }
+    checkpoint.save(&opts.checkpoint_file)?;
+    let mirrored = metrics::get(&STATS.forwarded);
+    let mut report = Report::default();
+    report.line(format!("Mirrored {} messages", mirrored));
+    let mut json = json!({ "mirrored": mirrored });
+    if opts.verify_key_order {
+        let (lines, key_order) = key_order.summary();
+        for line in lines {
+            report.line(line);
+        }
+        json["keyOrder"] = key_order;
+    }
+    report.set_json(json);
+    Ok(report)
+}
+
+/// Waits for the receipts of the messages in flight, each for up to the operation timeout, and
+/// returns them in order with whether they reached the destination
+async fn receipts(
+    in_flight: &mut VecDeque<InFlight>,
+    connector: &Connector,
+) -> Vec<(InFlight, bool)> {
+    let mut settled = Vec::with_capacity(in_flight.len());
+    for mut entry in in_flight.drain(..) {
+        let receipt = tokio::time::timeout(connector.operation_timeout(), &mut entry.receipt).await;
+        let delivered = matches!(receipt, Ok(Ok(_)));
+        if !delivered {
+            metrics::add(&STATS.send_failures, 1);
+        }
+        settled.push((entry, delivered));
+    }
+    settled
+}
+
+/// Records a message as having reached the destination, and only then acks it on the source
+async fn complete(
+    consumer: &mut pulsar::Consumer<Vec<u8>, pulsar::TokioExecutor>,
+    checkpoint: &mut Checkpoint,
+    entry: InFlight,
+) {
+    checkpoint.positions.insert(entry.partition, entry.position);
+    STATS.produced(&entry.source.payload.data);
+    metrics::add(&STATS.forwarded, 1);
+    let acked =
+        connection::ack_with_retry(consumer, &entry.source, false, connection::ACK_RETRIES).await;
+    if let Err(e) = acked {
+        // The checkpoint keeps the message from being mirrored twice when it is redelivered
+        warn!("{}", e);
+    }
+}
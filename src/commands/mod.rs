@@ -0,0 +1,66 @@
+//! The subcommands, each with its options and a `run` function
+
+/// Finding the subscriptions of a namespace nobody consumes anymore
+pub mod audit_subscriptions;
+/// The size and age of the backlog of a subscription
+pub mod backlog;
+/// Publishing and consuming at a fixed rate to measure throughput and latencies
+pub mod bench;
+/// The clusters of the instance and their brokers
+pub mod cluster;
+/// Triggering the compaction of a topic
+pub mod compact;
+/// The profiles of the configuration file
+pub mod config;
+/// Consuming topics, printing the messages or forwarding them
+pub mod consume;
+/// The consumers connected to a subscription
+pub mod consumers;
+/// Copying the messages of a topic to another
+pub mod copy;
+/// Exporting and restoring the cursor positions of a subscription
+pub mod cursor;
+/// Comparing the messages published on two topics
+pub mod diff;
+/// Consuming and acking the backlog of a subscription
+pub mod drain;
+/// Fetching a single message by its ID
+pub mod get_message;
+/// Recording the backlogs of the subscriptions of a namespace over time
+pub mod lag;
+/// The ID of the last message published on a topic
+pub mod last_message_id;
+/// The brokers serving topics, and their partitions
+pub mod lookup;
+/// Parsing and comparing message IDs
+pub mod message_id;
+/// Mirroring a topic to another cluster
+pub mod mirror;
+/// Tenants and namespaces, and the policies of namespaces
+pub mod namespace;
+/// Showing the backlog of a subscription without consuming it
+pub mod peek;
+/// The permissions of roles on topics and namespaces
+pub mod permissions;
+/// Checking that a broker or the admin API answers
+pub mod ping;
+/// Publishing messages
+pub mod produce;
+/// Running the steps of a scenario file
+pub mod run_scenario;
+/// The schemas of topics
+pub mod schema;
+/// The statistics of a topic
+pub mod stats;
+/// Creating, deleting and moving the cursors of subscriptions
+pub mod subscription;
+/// The subscriptions of a topic
+pub mod subscriptions;
+/// Creating, deleting and repartitioning topics
+pub mod topic;
+/// The topics of a namespace
+pub mod topics;
+/// Checking that messages arrive exactly once and in order
+pub mod verify;
+/// Following the stats of a topic
+pub mod watch;
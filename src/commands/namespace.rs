@@ -0,0 +1,532 @@
+use crate::{
+    admin::AdminClient,
+    commands::cluster,
+    confirm::confirm,
+    exit::{self, ExitError},
+    output::Report,
+    topic_name::{check_name, NamespaceName, TopicName},
+    units::{format_bytes, parse_bytes},
+};
+use anyhow::{format_err, Result};
+use futures::{stream, StreamExt};
+use itertools::Itertools;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Write},
+    time::Duration,
+};
+use structopt::StructOpt;
+
+/// The options of `tenants`
+#[derive(StructOpt)]
+pub struct TenantsOpts {
+    #[structopt(long)]
+    json: bool,
+}
+
+/// The options of `namespaces`
+#[derive(StructOpt)]
+pub struct NamespacesOpts {
+    /// Defaults to the global --tenant
+    #[structopt(long)]
+    tenant: Option<String>,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// The subcommands of `namespace`
+#[derive(StructOpt)]
+pub enum NamespaceCommand {
+    /// Print the policies (retention, TTL, deduplication...) of a namespace
+    Policies {
+        #[structopt(long)]
+        namespace: NamespaceName,
+    },
+
+    /// Print how long and how much acknowledged data the namespace keeps
+    GetRetention {
+        #[structopt(long)]
+        namespace: NamespaceName,
+    },
+
+    /// Set how long and how much acknowledged data the namespace keeps
+    SetRetention {
+        #[structopt(long)]
+        namespace: NamespaceName,
+
+        /// Size such as 10G, or "infinite"
+        #[structopt(long, parse(try_from_str = parse_retention_size))]
+        size: i64,
+
+        /// Duration such as 7d, or "infinite"
+        #[structopt(long, parse(try_from_str = parse_retention_time))]
+        time: i64,
+    },
+
+    /// Print after how long unacknowledged messages expire
+    GetMessageTtl {
+        #[structopt(long)]
+        namespace: NamespaceName,
+    },
+
+    /// Set after how long unacknowledged messages expire
+    SetMessageTtl {
+        #[structopt(long)]
+        namespace: NamespaceName,
+
+        /// Seconds, or a duration such as 1h
+        #[structopt(long, parse(try_from_str = parse_ttl))]
+        ttl: u64,
+    },
+
+    /// List the bundles of a namespace with the broker owning each one and the topics it
+    /// contains, e.g. to pick the bundle to unload from a hot broker
+    Bundles {
+        #[structopt(long)]
+        namespace: NamespaceName,
+
+        /// How many topics are looked up at the same time to find their bundle
+        #[structopt(long, default_value = "8")]
+        concurrency: usize,
+    },
+
+    /// Unload a bundle of a namespace from its broker, or all of its bundles, forcing their
+    /// ownership to move
+    Unload {
+        #[structopt(long)]
+        namespace: NamespaceName,
+
+        /// The range of the bundle as `namespace bundles` lists it, e.g.
+        /// 0x40000000_0x80000000. Unloads every bundle without it
+        #[structopt(long)]
+        bundle: Option<String>,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Deserialize)]
+struct BundlesData {
+    /// The hash of the first topic of each bundle, then the end of the last one
+    boundaries: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Bundle {
+    range: String,
+    /// The broker's ID, which is the host and port of its web service, if the bundle is loaded
+    owner: Option<String>,
+    topics: Vec<String>,
+}
+
+/// Retention policies as the admin API represents them, -1 meaning infinite
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetentionPolicies {
+    pub retention_time_in_minutes: i64,
+    pub retention_size_in_mb: i64,
+}
+
+impl fmt::Display for RetentionPolicies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retention_time_in_minutes {
+            -1 => write!(f, "time: infinite")?,
+            minutes => write!(
+                f,
+                "time: {}",
+                humantime::format_duration(Duration::from_secs(minutes.max(0) as u64 * 60))
+            )?,
+        }
+        match self.retention_size_in_mb {
+            -1 => write!(f, ", size: infinite"),
+            megabytes => write!(
+                f,
+                ", size: {}",
+                format_bytes(megabytes.max(0) as f64 * 1024.0 * 1024.0)
+            ),
+        }
+    }
+}
+
+fn parse_retention_size(s: &str) -> Result<i64> {
+    if s == "infinite" || s == "-1" {
+        return Ok(-1);
+    }
+    // Rounded up, so that a size below a megabyte doesn't disable retention
+    let megabytes = (parse_bytes(s)? as f64 / (1024.0 * 1024.0)).ceil();
+    Ok(megabytes as i64)
+}
+
+fn parse_retention_time(s: &str) -> Result<i64> {
+    if s == "infinite" || s == "-1" {
+        return Ok(-1);
+    }
+    let duration = humantime::parse_duration(s)
+        .map_err(|e| format_err!("Invalid retention time {:?}: {}", s, e))?;
+    Ok((duration.as_secs() / 60) as i64)
+}
+
+fn parse_ttl(s: &str) -> Result<u64> {
+    if let Ok(seconds) = s.parse() {
+        return Ok(seconds);
+    }
+    let duration =
+        humantime::parse_duration(s).map_err(|e| format_err!("Invalid TTL {:?}: {}", s, e))?;
+    Ok(duration.as_secs())
+}
+
+/// Lists the tenants
+pub async fn run_tenants(admin: &AdminClient, opts: &TenantsOpts) -> Result<Report> {
+    let tenants = admin.get::<Vec<String>>("tenants").await?;
+    print_list(tenants, opts.json)
+}
+
+/// Lists the namespaces of the tenant given, or of `default_tenant`
+pub async fn run_namespaces(
+    admin: &AdminClient,
+    default_tenant: Option<&str>,
+    opts: &NamespacesOpts,
+) -> Result<Report> {
+    let tenant =
+        opts.tenant.as_deref().or(default_tenant).ok_or_else(|| {
+            format_err!("Pass the tenant to list the namespaces of with --tenant")
+        })?;
+    check_name("tenant", tenant)?;
+    let namespaces = admin
+        .get::<Vec<String>>(&format!("namespaces/{}", tenant))
+        .await?;
+    print_list(namespaces, opts.json)
+}
+
+/// Runs `command`
+pub async fn run(admin: &AdminClient, command: &NamespaceCommand) -> Result<Report> {
+    match command {
+        NamespaceCommand::Policies { namespace } => {
+            let policies = admin
+                .get::<Value>(&format!("namespaces/{}", namespace))
+                .await?;
+            Ok(Report::json(policies))
+        }
+
+        NamespaceCommand::GetRetention { namespace } => {
+            let retention = retention(admin, namespace).await?;
+            Ok(Report::new(retention.to_string(), json!(retention)))
+        }
+
+        NamespaceCommand::SetRetention {
+            namespace,
+            size,
+            time,
+        } => {
+            let path = retention_path(namespace);
+            let previous = retention(admin, namespace).await?;
+            let retention = RetentionPolicies {
+                retention_time_in_minutes: *time,
+                retention_size_in_mb: *size,
+            };
+            admin.post(&path, Some(&json!(retention))).await?;
+            Ok(Report::new(
+                format!("Retention of {}: {} -> {}", namespace, previous, retention),
+                json!({ "previous": previous, "current": retention }),
+            ))
+        }
+
+        NamespaceCommand::GetMessageTtl { namespace } => {
+            let ttl = message_ttl(admin, namespace).await?;
+            Ok(Report::new(describe_ttl(ttl), json!({ "ttl": ttl })))
+        }
+
+        NamespaceCommand::SetMessageTtl { namespace, ttl } => {
+            let previous = message_ttl(admin, namespace).await?;
+            admin
+                .post(&message_ttl_path(namespace), Some(&json!(ttl)))
+                .await?;
+            Ok(Report::new(
+                format!(
+                    "Message TTL of {}: {} -> {}",
+                    namespace,
+                    describe_ttl(previous),
+                    describe_ttl(Some(*ttl))
+                ),
+                json!({ "previous": previous, "current": ttl }),
+            ))
+        }
+
+        NamespaceCommand::Bundles {
+            namespace,
+            concurrency,
+        } => bundles(admin, namespace, *concurrency).await,
+
+        NamespaceCommand::Unload {
+            namespace,
+            bundle,
+            yes,
+        } => {
+            let ranges = bundle_ranges(admin, namespace).await?;
+            match bundle {
+                Some(bundle) if !ranges.contains(bundle) => Err(ExitError::new(
+                    exit::NOT_FOUND,
+                    format!(
+                        "{} has no bundle {}, its bundles are: {}",
+                        namespace,
+                        bundle,
+                        ranges.join(", ")
+                    ),
+                )
+                .into()),
+                Some(bundle) => {
+                    let owner = bundle_owners(admin, namespace).await?.remove(bundle);
+                    confirm(
+                        &format!(
+                            "Unload bundle {} of {}{}? The clients of its topics disconnect until another broker takes it over.",
+                            bundle,
+                            namespace,
+                            owner
+                                .as_ref()
+                                .map(|owner| format!(" from {}", owner))
+                                .unwrap_or_default()
+                        ),
+                        *yes,
+                    )?;
+                    admin
+                        .put(&format!("namespaces/{}/{}/unload", namespace, bundle), None)
+                        .await?;
+                    Ok(Report::new(
+                        format!("Unloaded bundle {} of {}", bundle, namespace),
+                        json!({
+                            "namespace": namespace.to_string(),
+                            "bundle": bundle,
+                            "previousOwner": owner,
+                        }),
+                    ))
+                }
+                None => {
+                    confirm(
+                        &format!(
+                            "Unload all {} bundle(s) of {}? The clients of its topics disconnect until other brokers take them over.",
+                            ranges.len(),
+                            namespace
+                        ),
+                        *yes,
+                    )?;
+                    admin
+                        .put(&format!("namespaces/{}/unload", namespace), None)
+                        .await?;
+                    Ok(Report::new(
+                        format!("Unloaded {} bundle(s) of {}", ranges.len(), namespace),
+                        json!({ "namespace": namespace.to_string(), "bundles": ranges }),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The ranges of the bundles of `namespace`, e.g. 0x00000000_0x40000000
+async fn bundle_ranges(admin: &AdminClient, namespace: &NamespaceName) -> Result<Vec<String>> {
+    let bundles = admin
+        .get::<BundlesData>(&format!("namespaces/{}/bundles", namespace))
+        .await?;
+    Ok(bundles
+        .boundaries
+        .iter()
+        .tuple_windows()
+        .map(|(lower, upper)| format!("{}_{}", lower, upper))
+        .collect())
+}
+
+/// The broker owning each loaded bundle of `namespace`, by range. A broker that can't be asked
+/// leaves its bundles without an owner rather than failing
+async fn bundle_owners(
+    admin: &AdminClient,
+    namespace: &NamespaceName,
+) -> Result<HashMap<String, String>> {
+    let prefix = format!("{}/", namespace);
+    let mut owners = HashMap::new();
+    for cluster in cluster::cluster_names(admin, None).await? {
+        let brokers = admin
+            .get::<Vec<String>>(&format!("brokers/{}", cluster))
+            .await?;
+        for broker in brokers {
+            let owned = admin
+                .get::<BTreeMap<String, Value>>(&format!(
+                    "brokers/{}/{}/ownedNamespaces",
+                    cluster, broker
+                ))
+                .await;
+            match owned {
+                Ok(owned) => {
+                    for bundle in owned.keys() {
+                        if let Some(range) = bundle.strip_prefix(&prefix) {
+                            owners.insert(range.to_owned(), broker.clone());
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed listing the bundles {} owns: {:#}", broker, e),
+            }
+        }
+    }
+    Ok(owners)
+}
+
+/// The bundles of `namespace` with their owner and the topics they contain, as the lookup
+/// service maps them, `concurrency` topics at a time
+async fn bundles(
+    admin: &AdminClient,
+    namespace: &NamespaceName,
+    concurrency: usize,
+) -> Result<Report> {
+    let ranges = bundle_ranges(admin, namespace).await?;
+    let mut owners = bundle_owners(admin, namespace).await?;
+
+    let mut topics = admin.topics(namespace, true).await?;
+    match admin.topics(namespace, false).await {
+        Ok(non_persistent) => topics.extend(non_persistent),
+        Err(e) => warn!(
+            "Failed listing the non-persistent topics of {}: {:#}",
+            namespace, e
+        ),
+    }
+    let mut looked_up = stream::iter(topics)
+        .map(|topic| async move {
+            let range = match topic.parse::<TopicName>() {
+                Ok(name) => admin.topic_bundle(&name).await,
+                Err(e) => Err(e),
+            };
+            (topic, range)
+        })
+        .buffer_unordered(concurrency.max(1));
+    let mut by_range = HashMap::<String, Vec<String>>::new();
+    let mut unmapped = Vec::new();
+    while let Some((topic, range)) = looked_up.next().await {
+        match range {
+            // A bundle split since the ranges were listed
+            Ok(range) if !ranges.contains(&range) => unmapped.push(topic),
+            Ok(range) => by_range.entry(range).or_default().push(topic),
+            Err(e) => {
+                warn!("Failed looking up the bundle of {}: {:#}", topic, e);
+                unmapped.push(topic);
+            }
+        }
+    }
+    unmapped.sort();
+
+    let bundles = ranges
+        .into_iter()
+        .map(|range| {
+            let mut topics = by_range.remove(&range).unwrap_or_default();
+            topics.sort();
+            Bundle {
+                owner: owners.remove(&range),
+                range,
+                topics,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let width = bundles
+        .iter()
+        .map(|bundle| bundle.range.len())
+        .chain(Some("BUNDLE".len()))
+        .max()
+        .unwrap_or_default();
+    let owner_width = bundles
+        .iter()
+        .filter_map(|bundle| bundle.owner.as_ref().map(String::len))
+        .chain(Some("OWNER".len()))
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:width$}  {:owner_width$}  TOPICS",
+        "BUNDLE",
+        "OWNER",
+        width = width,
+        owner_width = owner_width
+    )?;
+    for bundle in bundles.iter() {
+        writeln!(
+            out,
+            "{:width$}  {:owner_width$}  {}",
+            bundle.range,
+            bundle.owner.as_deref().unwrap_or("-"),
+            bundle.topics.len(),
+            width = width,
+            owner_width = owner_width
+        )?;
+        for topic in bundle.topics.iter() {
+            writeln!(out, "  {}", topic)?;
+        }
+    }
+    if !unmapped.is_empty() {
+        writeln!(
+            out,
+            "{} topic(s) couldn't be mapped to a bundle: {}",
+            unmapped.len(),
+            unmapped.join(", ")
+        )?;
+    }
+    Ok(Report::new(
+        out.trim_end(),
+        json!({ "bundles": bundles, "unmapped": unmapped }),
+    ))
+}
+
+fn retention_path(namespace: &NamespaceName) -> String {
+    format!("namespaces/{}/retention", namespace)
+}
+
+fn message_ttl_path(namespace: &NamespaceName) -> String {
+    format!("namespaces/{}/messageTTL", namespace)
+}
+
+/// The retention policies of `namespace`
+pub async fn retention(
+    admin: &AdminClient,
+    namespace: &NamespaceName,
+) -> Result<RetentionPolicies> {
+    admin.get(&retention_path(namespace)).await
+}
+
+/// The message TTL of `namespace` in seconds, `None` if it isn't set
+pub async fn message_ttl(admin: &AdminClient, namespace: &NamespaceName) -> Result<Option<u64>> {
+    // An unset TTL comes back as an empty body rather than as JSON
+    let body = admin.get_text(&message_ttl_path(namespace)).await?;
+    match body.trim() {
+        "" | "null" => Ok(None),
+        ttl => {
+            Ok(Some(ttl.parse().map_err(|_| {
+                format_err!("Unexpected message TTL {:?}", ttl)
+            })?))
+        }
+    }
+}
+
+/// A message TTL as `message_ttl` returns it, for printing
+pub fn describe_ttl(ttl: Option<u64>) -> String {
+    match ttl {
+        None => "not set".to_owned(),
+        Some(0) => "disabled".to_owned(),
+        Some(seconds) => format!(
+            "{}s ({})",
+            seconds,
+            humantime::format_duration(Duration::from_secs(seconds))
+        ),
+    }
+}
+
+fn print_list(mut items: Vec<String>, json: bool) -> Result<Report> {
+    items.sort();
+    if json {
+        return Ok(Report::json(json!(items)));
+    }
+    Ok(Report::new(items.join("\n"), json!(items)))
+}
@@ -0,0 +1,59 @@
+use crate::{
+    admin::{AdminClient, AdminError},
+    display::MessageFormatter,
+    output::{self, Report},
+    picker,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use structopt::StructOpt;
+
+/// The options of `peek`
+#[derive(StructOpt)]
+pub struct PeekOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Picked among the subscriptions of the topic when not given, on a terminal
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    /// Number of messages to peek (on each partition of partitioned topics)
+    #[structopt(long, default_value = "1")]
+    count: u32,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Prints the next messages of the backlog
+pub async fn run(admin: &AdminClient, opts: &PeekOpts) -> Result<Report> {
+    let subscription =
+        &picker::subscription(admin, &opts.topic, opts.subscription.as_deref()).await?;
+    let formatter = MessageFormatter {
+        json: output::json(opts.json),
+        xml: false,
+        entry_metadata: false,
+        replicated_from: false,
+        topic: false,
+    };
+    for partition in admin.partition_names(&opts.topic).await? {
+        for position in 1..=opts.count {
+            let path = format!(
+                "{}/position/{}",
+                AdminClient::subscription_path(&partition, subscription),
+                position
+            );
+            let messages = match admin.get_messages(&path).await {
+                Ok(messages) => messages,
+                // Reached the end of the backlog
+                Err(e) if AdminError::is_not_found(&e) => break,
+                Err(e) => return Err(e),
+            };
+            for message in messages.iter() {
+                formatter.print(&message.display())?;
+            }
+        }
+    }
+    Ok(Report::default())
+}
@@ -0,0 +1,161 @@
+use crate::{
+    admin::AdminClient,
+    output::Report,
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::Result;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+};
+use structopt::{clap::ArgGroup, StructOpt};
+
+/// Actions a role can be granted, as named by the admin API
+const ACTIONS: &[&str] = &[
+    "produce",
+    "consume",
+    "functions",
+    "sources",
+    "sinks",
+    "packages",
+];
+
+/// The subcommands of `permissions`
+#[derive(StructOpt)]
+pub enum PermissionsCommand {
+    /// Print the actions each role is allowed to perform
+    #[structopt(group = ArgGroup::with_name("target").required(true))]
+    Get {
+        #[structopt(flatten)]
+        target: Target,
+
+        #[structopt(long)]
+        json: bool,
+    },
+
+    /// Allow a role to perform some actions, replacing the ones it was allowed before
+    #[structopt(group = ArgGroup::with_name("target").required(true))]
+    Grant {
+        #[structopt(flatten)]
+        target: Target,
+
+        #[structopt(long)]
+        role: String,
+
+        /// Comma-separated list of actions
+        #[structopt(long, required = true, use_delimiter = true, possible_values = ACTIONS)]
+        actions: Vec<String>,
+    },
+
+    /// Remove all the permissions of a role
+    #[structopt(group = ArgGroup::with_name("target").required(true))]
+    Revoke {
+        #[structopt(flatten)]
+        target: Target,
+
+        #[structopt(long)]
+        role: String,
+    },
+}
+
+/// The topic or namespace whose permissions are managed
+#[derive(StructOpt)]
+pub struct Target {
+    #[structopt(long, group = "target")]
+    topic: Option<TopicName>,
+
+    #[structopt(long, group = "target")]
+    namespace: Option<NamespaceName>,
+}
+
+impl Target {
+    fn permissions_path(&self) -> String {
+        match (&self.topic, &self.namespace) {
+            (Some(topic), _) => format!("{}/permissions", topic.admin_path()),
+            (_, Some(namespace)) => format!("namespaces/{}/permissions", namespace),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.topic, &self.namespace) {
+            (Some(topic), _) => write!(f, "{}", topic),
+            (_, Some(namespace)) => write!(f, "namespace {}", namespace),
+            _ => unreachable!(),
+        }
+    }
+}
+
+type Permissions = BTreeMap<String, Vec<String>>;
+
+/// Runs `command`
+pub async fn run(admin: &AdminClient, command: &PermissionsCommand) -> Result<Report> {
+    match command {
+        PermissionsCommand::Get { target, json } => {
+            let permissions: Permissions = admin.get(&target.permissions_path()).await?;
+            if *json {
+                Ok(Report::json(json!(permissions)))
+            } else if permissions.is_empty() {
+                Ok(Report::new(
+                    format!("No permissions granted on {}", target),
+                    json!(permissions),
+                ))
+            } else {
+                Ok(Report::new(render_table(&permissions), json!(permissions)))
+            }
+        }
+
+        PermissionsCommand::Grant {
+            target,
+            role,
+            actions,
+        } => {
+            let path = format!("{}/{}", target.permissions_path(), role);
+            admin.post(&path, Some(&json!(actions))).await?;
+            let permissions: Permissions = admin.get(&target.permissions_path()).await?;
+            Ok(Report::new(
+                format!(
+                    "Granted {} to {:?} on {}\n{}",
+                    actions.join(","),
+                    role,
+                    target,
+                    render_table(&permissions)
+                ),
+                json!(permissions),
+            ))
+        }
+
+        PermissionsCommand::Revoke { target, role } => {
+            let path = format!("{}/{}", target.permissions_path(), role);
+            admin.delete(&path).await?;
+            let permissions: Permissions = admin.get(&target.permissions_path()).await?;
+            Ok(Report::new(
+                format!(
+                    "Revoked the permissions of {:?} on {}\n{}",
+                    role,
+                    target,
+                    render_table(&permissions)
+                ),
+                json!(permissions),
+            ))
+        }
+    }
+}
+
+fn render_table(permissions: &Permissions) -> String {
+    let width = permissions
+        .keys()
+        .map(String::len)
+        .chain(Some("ROLE".len()))
+        .max()
+        .unwrap_or_default();
+    let mut out = String::new();
+    let _ = writeln!(out, "{:width$}  ACTIONS", "ROLE", width = width);
+    for (role, actions) in permissions.iter() {
+        let _ = writeln!(out, "{:width$}  {}", role, actions.join(","), width = width);
+    }
+    out
+}
@@ -0,0 +1,139 @@
+use crate::{
+    admin::AdminClient,
+    commands::lookup::describe,
+    connection::{Connector, ServiceUrls},
+    exit::{self, ExitError},
+    output::{self, Report},
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use log::warn;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// The options of `ping`
+#[derive(StructOpt)]
+pub struct PingOpts {
+    /// Check the admin API's broker health endpoint instead of the binary protocol
+    #[structopt(long)]
+    admin: bool,
+
+    /// Topic looked up to check that the broker serves requests
+    #[structopt(long, default_value = "persistent://public/default/healthcheck")]
+    health_topic: TopicName,
+
+    #[structopt(long, short = "c", default_value = "1")]
+    count: u32,
+
+    #[structopt(long, short = "i", default_value = "1s")]
+    interval: humantime::Duration,
+}
+
+/// Pings the broker, or the admin API, failing if it doesn't answer
+pub async fn run(
+    url: &ServiceUrls,
+    admin: &AdminClient,
+    connector: &Connector,
+    opts: &PingOpts,
+) -> Result<Report> {
+    let target = if opts.admin {
+        // The version endpoint only exists on the admin API, the binary protocol client doesn't
+        // expose the version the broker announces
+        match admin.get_text("brokers/version").await {
+            Ok(version) => output::progress(format!(
+                "Broker version {}",
+                version.trim().trim_matches('"')
+            ))?,
+            Err(e) => warn!("Failed fetching the broker version: {}", e),
+        }
+        admin.base_url().to_string()
+    } else {
+        url.to_string()
+    };
+
+    let mut round_trips = Vec::new();
+    for i in 0..opts.count {
+        if i > 0 {
+            tokio::time::sleep(opts.interval.into()).await;
+        }
+        let started = Instant::now();
+        let reply = if opts.admin {
+            admin
+                .get_text("brokers/health")
+                .await
+                .map(|status| format!("health={}", status.trim()))
+        } else {
+            ping_broker(url, connector, &opts.health_topic, started).await
+        };
+        let elapsed = started.elapsed();
+        match reply {
+            Ok(reply) => {
+                output::progress(format!(
+                    "Reply from {}: seq={} time={:.1}ms {}",
+                    target,
+                    i,
+                    millis(elapsed),
+                    reply
+                ))?;
+                round_trips.push(elapsed);
+            }
+            Err(e) => output::progress(format!("No reply from {}: seq={} {}", target, i, e))?,
+        }
+    }
+
+    let sent = opts.count as usize;
+    let loss = (sent - round_trips.len()) as f64 * 100.0 / sent.max(1) as f64;
+    let summary = format!(
+        "--- {}: {} sent, {} received, {:.0}% loss",
+        target,
+        sent,
+        round_trips.len(),
+        loss
+    );
+    if round_trips.is_empty() {
+        output::progress(summary)?;
+        return Err(ExitError::new(exit::UNREACHABLE, format!("{} is unreachable", target)).into());
+    }
+    let total: Duration = round_trips.iter().sum();
+    let (min, avg, max) = (
+        millis(*round_trips.iter().min().unwrap()),
+        millis(total / round_trips.len() as u32),
+        millis(*round_trips.iter().max().unwrap()),
+    );
+    Ok(Report::new(
+        format!(
+            "{}\nrtt min/avg/max = {:.1}/{:.1}/{:.1} ms",
+            summary, min, avg, max
+        ),
+        json!({
+            "target": target,
+            "sent": sent,
+            "received": round_trips.len(),
+            "lossPercent": loss,
+            "rttMs": { "min": min, "avg": avg, "max": max },
+        }),
+    ))
+}
+
+/// Connects to the broker with a new client and looks up `topic`, timing both steps
+async fn ping_broker(
+    url: &ServiceUrls,
+    connector: &Connector,
+    topic: &TopicName,
+    started: Instant,
+) -> Result<String> {
+    let pulsar = connector.connect(url).await?;
+    let connected = started.elapsed();
+    let address = pulsar.lookup_topic(topic.to_string()).await?;
+    Ok(format!(
+        "connect={:.1}ms lookup={:.1}ms broker={}",
+        millis(connected),
+        millis(started.elapsed() - connected),
+        describe(&address)
+    ))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
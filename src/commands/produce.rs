@@ -0,0 +1,751 @@
+use crate::{
+    admin::AdminClient,
+    checksum,
+    connection::{self, Connector, ServiceUrls, TimeoutOpts},
+    csv_source,
+    exit::{self, ExitError},
+    http_source,
+    message_id::MessageId,
+    metrics::{self, STATS},
+    output::Report,
+    replay_source, template,
+    topic_name::TopicName,
+    transform, ADMIN_POLL_INTERVAL,
+};
+use anyhow::{format_err, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use pulsar::{Producer, TokioExecutor};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use structopt::{clap::ArgMatches, StructOpt};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// Maximum number of messages waiting to be sent to a single produce destination
+const FANOUT_QUEUE_SIZE: usize = 100;
+
+/// The options of `produce`
+#[derive(StructOpt)]
+pub struct ProduceOpts {
+    #[structopt(long = "topic", required_unless = "topic-template")]
+    topics: Vec<String>,
+
+    /// Produce to a topic named from this template once at startup, e.g.
+    /// `orders-test-{{uuid}}` or `orders-test-{{now:%Y%m%d%H%M%S}}`, printing the name
+    #[structopt(long, conflicts_with = "topics")]
+    topic_template: Option<template::Template>,
+
+    /// Write the topic name --topic-template resolved to to this file, for the consuming side
+    /// of a test to pick it up
+    #[structopt(long, requires = "topic-template")]
+    topic_name_file: Option<PathBuf>,
+
+    #[structopt(long, default_value = "all", possible_values = &["all", "round-robin"])]
+    fanout: Fanout,
+
+    /// A template such as those of --topic-template, with `{{hostname}}`, `{{user}}`, `{{pid}}`
+    /// and `{{n}}`, the number of the producer among those of the topics, as well. By default one
+    /// per process and topic, as the broker refuses a second producer of the same name
+    #[structopt(
+        long,
+        short = "p",
+        default_value = "pulsar-cli-{{hostname}}-{{pid}}-{{n}}"
+    )]
+    producer_name: template::Template,
+
+    #[structopt(long, default_value = "5s")]
+    interval: humantime::Duration,
+
+    /// Send the first message right away rather than after --interval
+    #[structopt(long)]
+    immediate: bool,
+
+    /// Stop after publishing this many messages, e.g. for a step of run-scenario
+    #[structopt(long)]
+    count: Option<u64>,
+
+    /// Once there is nothing left to publish, how long to wait for the queued messages to be
+    /// sent, those left counting as failed
+    #[structopt(long, default_value = "60s")]
+    drain_timeout: humantime::Duration,
+
+    /// A property of every message, as <name>=<value>. The value can be a template rendered
+    /// for each message, e.g. trace-id={{uuid}} or seq={{iteration}}
+    #[structopt(long = "prop")]
+    properties: Vec<String>,
+
+    /// Don't start producing until this subscription exists on every topic
+    #[structopt(long)]
+    wait_for_subscription: Option<String>,
+
+    #[structopt(long, default_value = "60s")]
+    wait_timeout: humantime::Duration,
+
+    /// Publish the body fetched from this URL instead of generated messages, once or with
+    /// --poll repeatedly
+    #[structopt(long)]
+    from_url: Option<url::Url>,
+
+    /// Fetch --from-url again and publish it at this interval
+    #[structopt(long, requires = "from-url")]
+    poll: Option<humantime::Duration>,
+
+    /// With --poll, skip bodies that are the same as the last one published
+    #[structopt(long, requires = "poll")]
+    only_on_change: bool,
+
+    /// How many redirects to follow when fetching --from-url
+    #[structopt(long, default_value = "5")]
+    max_redirects: usize,
+
+    /// Attach the digest of each payload as the pulsar-cli-checksum property, for consume
+    /// --verify-checksum-prop
+    #[structopt(long, possible_values = &["crc32c", "sha256", "md5"])]
+    prop_checksum: Option<checksum::ChecksumAlgo>,
+
+    #[structopt(flatten)]
+    csv: csv_source::CsvOpts,
+
+    /// Publish the messages of the files consume --output-dir-per-topic wrote that match this
+    /// glob, e.g. 'captures/orders*', in the order of their names: rotated files before the
+    /// current one, gzipped or not
+    #[structopt(long, conflicts_with_all = &["from-url", "stdin-format"])]
+    replay: Option<String>,
+
+    /// Publish the messages of --replay by their recorded publish times (original), at a
+    /// steady rate (uniform:<messages per second>) or faster than recorded (scaled:<factor>,
+    /// e.g. scaled:10) rather than as fast as they are read, logging the progress
+    #[structopt(long, requires = "replay")]
+    replay_timing: Option<replay_source::ReplayTiming>,
+
+    /// The longest wait between two messages of --replay-timing, e.g. over the nights of a
+    /// capture
+    #[structopt(long, requires = "replay-timing")]
+    max_gap: Option<humantime::Duration>,
+
+    /// Change the JSON payloads before producing them, in the order given: `set
+    /// payload.<path> = <value>`, `del payload.<path>` or `rename payload.<path> to <name>`
+    #[structopt(long = "transform", number_of_values = 1)]
+    transforms: Vec<transform::Transform>,
+
+    /// Skip the messages a --transform fails on (e.g. because they aren't JSON) rather than
+    /// stopping
+    #[structopt(long, default_value = "abort", possible_values = &["skip", "abort"])]
+    transform_failure: transform::TransformFailure,
+}
+
+impl ProduceOpts {
+    /// Rejects the combinations of options that clap can't tell are wrong, `matches` being
+    /// those of the whole command line
+    pub fn validate(&self, matches: &ArgMatches) -> Result<()> {
+        let usage =
+            |message: String| -> Result<()> { Err(ExitError::new(exit::USAGE, message).into()) };
+        match self {
+            ProduceOpts {
+                wait_for_subscription: None,
+                ..
+            } if matches
+                .subcommand_matches("produce")
+                .map_or(false, |produce| produce.occurrences_of("wait-timeout") > 0) =>
+            {
+                usage(
+                    "--wait-timeout only applies with --wait-for-subscription <subscription>"
+                        .to_owned(),
+                )
+            }
+            ProduceOpts { transforms, .. }
+                if transforms.is_empty()
+                    && matches
+                        .subcommand_matches("produce")
+                        .map_or(false, |produce| {
+                            produce.occurrences_of("transform-failure") > 0
+                        }) =>
+            {
+                usage("--transform-failure only applies with --transform <transform>".to_owned())
+            }
+            ProduceOpts { from_url: None, .. }
+                if matches
+                    .subcommand_matches("produce")
+                    .map_or(false, |produce| produce.occurrences_of("max-redirects") > 0) =>
+            {
+                usage("--max-redirects only applies with --from-url <url>".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Publishes generated messages, or the rows of stdin, a capture or bodies fetched from a URL,
+/// to the topics of `opts` until stopped with Ctrl-C, `--count` messages were published or there
+/// is nothing left to publish, and reports how many were published. The admin API client is
+/// only made when an option needs it
+pub async fn run(
+    url: &ServiceUrls,
+    connector: &Arc<Connector>,
+    admin_client: impl Fn() -> Result<AdminClient>,
+    timeouts: &TimeoutOpts,
+    opts: &ProduceOpts,
+) -> Result<Report> {
+    let ProduceOpts {
+        topics,
+        topic_template,
+        topic_name_file,
+        fanout,
+        producer_name,
+        interval,
+        immediate,
+        count,
+        drain_timeout,
+        properties,
+        wait_for_subscription,
+        wait_timeout,
+        from_url,
+        poll,
+        only_on_change,
+        max_redirects,
+        prop_checksum,
+        csv,
+        replay,
+        replay_timing,
+        max_gap,
+        transforms,
+        transform_failure,
+    } = opts;
+    let topics = &match topic_template {
+        Some(template) => vec![template::resolve_topic(
+            template,
+            topic_name_file.as_deref(),
+        )?],
+        None => topics.clone(),
+    };
+    let source = match from_url {
+        Some(url) => Some(http_source::HttpSource::new(
+            url.clone(),
+            timeouts,
+            *max_redirects,
+        )?),
+        None => None,
+    };
+    // The templated values are rendered for each message, the others are the same for all
+    let mut templated = Vec::new();
+    let mut static_properties = HashMap::new();
+    for (key, value) in parse_properties(properties)? {
+        let template = value
+            .parse::<template::Template>()
+            .with_context(|| format!("Invalid --prop {}={:?}", key, value))?;
+        if template.is_static() {
+            static_properties.insert(key, value);
+        } else {
+            // So that a template that can't be rendered fails now rather than when sending
+            let sample = template
+                .render(&template::Variables {
+                    iteration: Some(0),
+                    ..Default::default()
+                })
+                .with_context(|| format!("Invalid --prop {}={:?}", key, value))?;
+            log::debug!("Property {} is templated, e.g. {:?}", key, sample);
+            templated.push((key, template));
+        }
+    }
+    let properties = static_properties;
+    // Read before connecting, to fail right away on a missing column or capture file
+    let mut rows = match csv.stdin_format {
+        Some(format) => Some(csv_source::CsvSource::new(format, csv)?.spawn(properties.clone())),
+        None => None,
+    };
+    let mut replayed = match replay {
+        Some(pattern) => Some(replay_source::ReplaySource::new(pattern)?.spawn(
+            properties.clone(),
+            *replay_timing,
+            max_gap.map(Into::into),
+        )),
+        None => None,
+    };
+    let from_rows = rows.is_some() || replayed.is_some();
+
+    if let Some(subscription) = wait_for_subscription {
+        let admin = admin_client()?;
+        for topic in topics {
+            wait_for_subscription_to_exist(
+                &admin,
+                &topic.parse()?,
+                subscription,
+                (*wait_timeout).into(),
+            )
+            .await?;
+        }
+    }
+
+    let mut outputs = Vec::with_capacity(topics.len());
+    for (n, topic) in topics.iter().enumerate() {
+        let producer_name = template::resolve_name(producer_name, "producer", n as u64)?;
+        let producer = connector
+            .build_producer(url, topic, Some(&producer_name))
+            .await?;
+        outputs.push(FanoutOutput::spawn(
+            connector.clone(),
+            url.clone(),
+            topic.clone(),
+            producer_name,
+            producer,
+        ));
+    }
+    info!("Connected to Pulsar");
+
+    // The URL is fetched right away, then every --poll
+    let mut ticks = match &source {
+        Some(_) => connection::pace(poll.unwrap_or(*interval).into(), true),
+        None => connection::pace((*interval).into(), *immediate),
+    };
+    let mut last_hash = None;
+    // Those skipped by the transforms or unchanged with --only-on-change not counting
+    let mut produced = 0;
+    let generate = async {
+        for i in 0.. {
+            if Some(produced) == *count {
+                break;
+            }
+            // Rows of stdin and replayed messages are published as fast as they are read, or
+            // when --replay-timing has them due
+            if !from_rows {
+                ticks.tick().await;
+            }
+            let mut message = match (&mut rows, &mut replayed, &source) {
+                (Some(rows), _, _) => match rows.recv().await {
+                    Some(message) => message?,
+                    None => break,
+                },
+                (None, Some(replayed), _) => match replayed.next().await {
+                    Some(message) => message?,
+                    None => break,
+                },
+                (None, None, Some(source)) => {
+                    let fetched = match source.fetch().await {
+                        Ok(fetched) => fetched,
+                        Err(e) => match poll {
+                            Some(poll) => {
+                                log::warn!("{:#}, trying again in {}", e, poll);
+                                continue;
+                            }
+                            None => return Err(e),
+                        },
+                    };
+                    let hash = http_source::hash(&fetched.body);
+                    if *only_on_change && last_hash == Some(hash) {
+                        log::debug!("{} unchanged, not publishing it", source.url());
+                        continue;
+                    }
+                    last_hash = Some(hash);
+                    let mut properties = properties.clone();
+                    if let Some(content_type) = fetched.content_type {
+                        properties
+                            .insert(http_source::CONTENT_TYPE_PROPERTY.to_owned(), content_type);
+                    }
+                    pulsar::producer::Message {
+                        payload: fetched.body,
+                        properties,
+                        ..Default::default()
+                    }
+                }
+                (None, None, None) => generated_message(i, Utc::now(), &properties)?,
+            };
+
+            if !transforms.is_empty() {
+                let transformed =
+                    transform::apply_or_skip(transforms, *transform_failure, i, &message.payload)?;
+                match transformed {
+                    Some(payload) => message.payload = payload,
+                    None => {
+                        if source.is_some() && poll.is_none() {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            // Whatever the message comes from, the properties of its own (e.g. the columns of
+            // a row) taking precedence as for the static ones
+            let variables = template::Variables {
+                iteration: Some(i),
+                ..Default::default()
+            };
+            for (key, template) in templated.iter() {
+                if !message.properties.contains_key(key) {
+                    message
+                        .properties
+                        .insert(key.clone(), template.render(&variables)?);
+                }
+            }
+
+            if let Some(algo) = prop_checksum {
+                message.properties.insert(
+                    checksum::CHECKSUM_PROPERTY.to_owned(),
+                    algo.property(&message.payload),
+                );
+            }
+
+            let targets = match fanout {
+                Fanout::All => outputs.iter().collect(),
+                Fanout::RoundRobin => vec![&outputs[i as usize % outputs.len()]],
+            };
+            for output in targets {
+                // Every row has to be published, even if it means waiting for the
+                // topics to catch up
+                if from_rows {
+                    output.send(i, message.clone()).await;
+                } else {
+                    output.submit(i, message.clone());
+                }
+            }
+            produced += 1;
+            if source.is_some() && poll.is_none() {
+                break;
+            }
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // Once everything was generated, e.g. --from-url without --poll or all of stdin, the
+    // queued messages are sent before the summary, for up to --drain-timeout; on Ctrl-C they
+    // aren't waited for
+    let generated = tokio::select! {
+        res = generate => {
+            res?;
+            true
+        }
+        _ = tokio::signal::ctrl_c() => false,
+    };
+    let mut closed = outputs
+        .into_iter()
+        .map(FanoutOutput::close)
+        .collect::<Vec<_>>();
+    if generated {
+        let sent = futures::future::join_all(closed.iter_mut().map(|(_, _, task)| task));
+        tokio::select! {
+            _ = tokio::time::timeout((*drain_timeout).into(), sent) => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+
+    let mut report = Report::default();
+    let mut per_topic = serde_json::Map::new();
+    let (mut total_published, mut total_failed) = (0, 0);
+    let mut last_message_ids = Vec::new();
+    report.line("Summary:");
+    for (topic, counters, task) in closed {
+        task.abort();
+        // Those still queued or being sent weren't published, as far as can be told
+        let unsent = counters.pending.swap(0, Ordering::Relaxed);
+        counters.failed.fetch_add(unsent, Ordering::Relaxed);
+        metrics::add(&STATS.send_failures, unsent);
+        let published = counters.published.load(Ordering::Relaxed);
+        let failed = counters.failed.load(Ordering::Relaxed);
+        let last_message_id = counters
+            .last_message_id
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|id| id.to_string());
+        report.line(format!(
+            "  {}: {} published, {} failed",
+            topic, published, failed,
+        ));
+        total_published += published;
+        total_failed += failed;
+        per_topic.insert(
+            topic,
+            json!({
+                "published": published,
+                "failed": failed,
+                "last_message_id": last_message_id,
+            }),
+        );
+        last_message_ids.push(last_message_id);
+    }
+    // The IDs of messages of different topics don't tell which was published last
+    let last_message_id = match last_message_ids.as_slice() {
+        [id] => id.clone(),
+        _ => None,
+    };
+    report.set_json(json!({
+        "published": total_published,
+        "failed": total_failed,
+        "last_message_id": last_message_id,
+        "topics": per_topic,
+    }));
+    if total_failed > 0 {
+        report.print()?;
+        return Err(ExitError::new(
+            exit::FAILURE,
+            format!("{} messages couldn't be published", total_failed),
+        )
+        .into());
+    }
+    Ok(report)
+}
+
+/// The `--prop` options, as <name>=<value>
+fn parse_properties(properties: &[String]) -> Result<HashMap<String, String>> {
+    properties
+        .iter()
+        .map(|attr| {
+            let (key, value) = attr
+                .split_once('=')
+                .ok_or_else(|| format_err!("Invalid attr: {:?}", attr))?;
+            Ok((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// The `i`th message produced when there is nothing to read from, generated at `now`
+fn generated_message(
+    i: u64,
+    now: DateTime<Utc>,
+    properties: &HashMap<String, String>,
+) -> Result<pulsar::producer::Message> {
+    let payload = serde_json::to_vec(&json!({
+        "iteration": i,
+        "timestamp": now,
+    }))?;
+    Ok(pulsar::producer::Message {
+        payload,
+        properties: properties.clone(),
+        ..Default::default()
+    })
+}
+
+/// Polls the subscriptions of `topic` until `subscription` shows up, failing after `timeout`
+async fn wait_for_subscription_to_exist(
+    admin: &AdminClient,
+    topic: &TopicName,
+    subscription: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    for attempt in 1.. {
+        info!(
+            "Waiting for subscription {:?} on {} (attempt #{})",
+            subscription, topic, attempt
+        );
+        match admin.subscriptions(topic).await {
+            Ok(subscriptions) if subscriptions.iter().any(|s| s == subscription) => {
+                info!("Subscription {:?} found on {}", subscription, topic);
+                return Ok(());
+            }
+            Ok(_) => {}
+            // The topic itself may not have been created yet
+            Err(e) => info!("Failed listing subscriptions of {}: {}", topic, e),
+        }
+        if Instant::now() + ADMIN_POLL_INTERVAL > deadline {
+            break;
+        }
+        tokio::time::sleep(ADMIN_POLL_INTERVAL).await;
+    }
+    Err(ExitError::new(
+        exit::TIMEOUT,
+        format!(
+            "Timed out after {} waiting for subscription {:?} on {}",
+            humantime::format_duration(timeout),
+            subscription,
+            topic
+        ),
+    )
+    .into())
+}
+
+#[derive(Clone, Copy)]
+enum Fanout {
+    All,
+    RoundRobin,
+}
+
+impl FromStr for Fanout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "all" => Ok(Fanout::All),
+            "round-robin" => Ok(Fanout::RoundRobin),
+            other => Err(format_err!("Invalid fanout mode: {:?}", other)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct FanoutCounters {
+    published: AtomicU64,
+    failed: AtomicU64,
+    /// Queued and not published or given up on yet
+    pending: AtomicU64,
+    last_message_id: Mutex<Option<MessageId>>,
+}
+
+/// A single destination topic of the produce command. Each output sends from its own task, so
+/// a topic that is stuck retrying does not hold back the others
+struct FanoutOutput {
+    topic: String,
+    sender: mpsc::Sender<(u64, pulsar::producer::Message)>,
+    counters: Arc<FanoutCounters>,
+    task: JoinHandle<()>,
+}
+
+impl FanoutOutput {
+    /// Spawns the task sending with `producer`, which reconnects through `connector` when
+    /// sending fails, until its retry policy gives up on the message
+    fn spawn(
+        connector: Arc<Connector>,
+        url: ServiceUrls,
+        topic: String,
+        producer_name: String,
+        producer: Producer<TokioExecutor>,
+    ) -> Self {
+        let (sender, mut receiver) = mpsc::channel(FANOUT_QUEUE_SIZE);
+        let counters = Arc::new(FanoutCounters::default());
+        let task_counters = counters.clone();
+        let task_topic = topic.clone();
+
+        let task = tokio::spawn(async move {
+            let producer = tokio::sync::Mutex::new(producer);
+            while let Some((i, message)) = receiver.recv().await {
+                let what = format!("publishing message #{} to {}", i, task_topic);
+                let sent = connector
+                    .retry(&what, || async {
+                        let mut producer = producer.lock().await;
+                        let e = match connection::send(
+                            &mut producer,
+                            message.clone(),
+                            connector.operation_timeout(),
+                        )
+                        .await
+                        {
+                            Ok(receipt) => return Ok(receipt),
+                            Err(e) => e,
+                        };
+                        metrics::add(&STATS.send_failures, 1);
+                        log::warn!(
+                            "Error publishing message #{} to {}: {}. Reconnecting...",
+                            i,
+                            task_topic,
+                            e
+                        );
+                        match connector
+                            .build_producer(&url, &task_topic, Some(&producer_name))
+                            .await
+                        {
+                            Ok(reconnected) => {
+                                *producer = reconnected;
+                                metrics::add(&STATS.producer_reconnects, 1);
+                            }
+                            Err(e) => log::warn!("Failed reconnecting to {}: {}", url, e),
+                        }
+                        Err(e)
+                    })
+                    .await;
+                task_counters.pending.fetch_sub(1, Ordering::Relaxed);
+                let receipt = match sent {
+                    Ok(receipt) => receipt,
+                    Err(e) => {
+                        log::warn!("{:#}", e);
+                        task_counters.failed.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                };
+                info!("Published message #{} to {}", i, task_topic);
+                task_counters.published.fetch_add(1, Ordering::Relaxed);
+                if let Some(id) = &receipt.message_id {
+                    *task_counters.last_message_id.lock().unwrap() = Some(MessageId::from(id));
+                }
+                STATS.produced(&message.payload);
+            }
+        });
+
+        Self {
+            topic,
+            sender,
+            counters,
+            task,
+        }
+    }
+
+    /// Stops taking messages, giving the topic, its counters and the task still sending the
+    /// queued ones
+    fn close(self) -> (String, Arc<FanoutCounters>, JoinHandle<()>) {
+        (self.topic, self.counters, self.task)
+    }
+
+    /// Queues a message for sending, waiting for the topic to catch up if it has too many
+    /// pending sends
+    async fn send(&self, i: u64, message: pulsar::producer::Message) {
+        self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send((i, message)).await.is_err() {
+            self.counters.pending.fetch_sub(1, Ordering::Relaxed);
+            // The sending task is gone
+            self.counters.failed.fetch_add(1, Ordering::Relaxed);
+            metrics::add(&STATS.send_failures, 1);
+        }
+    }
+
+    /// Queues a message for sending. If the topic has fallen too far behind the message is
+    /// dropped and counted as failed instead of blocking the caller
+    fn submit(&self, i: u64, message: pulsar::producer::Message) {
+        self.counters.pending.fetch_add(1, Ordering::Relaxed);
+        if self.sender.try_send((i, message)).is_err() {
+            self.counters.pending.fetch_sub(1, Ordering::Relaxed);
+            log::warn!(
+                "Dropping message #{} for {}: too many pending sends",
+                i,
+                self.topic
+            );
+            self.counters.failed.fetch_add(1, Ordering::Relaxed);
+            metrics::add(&STATS.send_failures, 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn properties_split_at_the_first_equals_sign() {
+        let properties =
+            parse_properties(&strings(&["source=cli", "filter=a=b", "empty="])).unwrap();
+        assert_eq!(properties.len(), 3);
+        assert_eq!(properties["source"], "cli");
+        assert_eq!(properties["filter"], "a=b");
+        assert_eq!(properties["empty"], "");
+        assert!(parse_properties(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn properties_without_a_value_are_rejected() {
+        assert!(parse_properties(&strings(&["source=cli", "source"])).is_err());
+    }
+
+    #[test]
+    fn generated_messages() {
+        let now = "2021-03-04T05:06:07.890Z".parse::<DateTime<Utc>>().unwrap();
+        let properties = parse_properties(&strings(&["source=cli"])).unwrap();
+        let message = generated_message(42, now, &properties).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&message.payload).unwrap(),
+            json!({"iteration": 42, "timestamp": "2021-03-04T05:06:07.890Z"})
+        );
+        assert_eq!(message.properties, properties);
+        assert_eq!(message.partition_key, None);
+    }
+}
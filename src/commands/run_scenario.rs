@@ -0,0 +1,314 @@
+use crate::{
+    exit::{self, ExitError},
+    output::{self, Report},
+    Command, Session,
+};
+use anyhow::{format_err, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+/// The options of `run-scenario`
+#[derive(StructOpt)]
+pub struct RunScenarioOpts {
+    /// YAML file of the steps to run
+    file: PathBuf,
+
+    /// Run the following steps when one fails, rather than stopping there
+    #[structopt(long)]
+    keep_going: bool,
+}
+
+/// A scenario file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    steps: Vec<StepFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepFile {
+    name: Option<String>,
+    /// A pulsar-cli command line without the global options, those of run-scenario applying
+    run: Option<CommandLine>,
+    /// How long to wait, instead of running a command
+    wait: Option<String>,
+    /// How long the command gets before it fails with a timeout (exit code 9)
+    timeout: Option<String>,
+    /// The exit code the command is expected to fail with, e.g. 5 for a watch threshold
+    #[serde(default)]
+    expect_exit: i32,
+    /// Checks of the JSON result of the command, as `<JSON pointer> <op> <value>`
+    #[serde(default)]
+    expect: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CommandLine {
+    Line(String),
+    Args(Vec<String>),
+}
+
+enum Action {
+    Run {
+        command: Command,
+        timeout: Option<Duration>,
+        expect_exit: i32,
+        expect: Vec<Expectation>,
+    },
+    Wait(Duration),
+}
+
+struct Step {
+    name: String,
+    action: Action,
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A check of a value of a JSON result, e.g. `/matched >= 100`
+struct Expectation {
+    text: String,
+    pointer: String,
+    comparison: Comparison,
+    value: Value,
+}
+
+impl Expectation {
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = || {
+            format_err!(
+                "Invalid expectation {:?}, expected `<JSON pointer> <==|!=|<|<=|>|>=> <value>`",
+                text
+            )
+        };
+        let mut parts = text.trim().splitn(3, char::is_whitespace);
+        let (pointer, comparison, value) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(pointer), Some(comparison), Some(value)) if pointer.starts_with('/') => {
+                (pointer, comparison, value.trim())
+            }
+            _ => return Err(invalid()),
+        };
+        let comparison = match comparison {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<" => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">" => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => return Err(invalid()),
+        };
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+        if !matches!(comparison, Comparison::Eq | Comparison::Ne) && !value.is_number() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            text: text.to_owned(),
+            pointer: pointer.to_owned(),
+            comparison,
+            value,
+        })
+    }
+
+    /// Why `result` doesn't meet the expectation, if it doesn't
+    fn check(&self, result: &Value) -> Option<String> {
+        let actual = match result.pointer(&self.pointer) {
+            Some(actual) => actual,
+            None => return Some(format!("{}: {} is missing", self.text, self.pointer)),
+        };
+        let met = match (self.comparison, actual.as_f64(), self.value.as_f64()) {
+            (Comparison::Eq, _, _) => *actual == self.value,
+            (Comparison::Ne, _, _) => *actual != self.value,
+            (Comparison::Lt, Some(actual), Some(value)) => actual < value,
+            (Comparison::Le, Some(actual), Some(value)) => actual <= value,
+            (Comparison::Gt, Some(actual), Some(value)) => actual > value,
+            (Comparison::Ge, Some(actual), Some(value)) => actual >= value,
+            _ => false,
+        };
+        if met {
+            None
+        } else {
+            Some(format!("{}: it is {}", self.text, actual))
+        }
+    }
+}
+
+/// Reads and checks every step of the scenario, before any runs
+fn load(session: &Session<'_>, path: &Path) -> Result<Vec<Step>> {
+    let contents =
+        std::fs::read(path).with_context(|| format!("Failed reading {}", path.display()))?;
+    let file = serde_yaml::from_slice::<ScenarioFile>(&contents)
+        .with_context(|| format!("Invalid scenario file {}", path.display()))?;
+    let duration = |text: &str| {
+        humantime::parse_duration(text)
+            .map_err(|e| format_err!("Invalid duration {:?}: {}", text, e))
+    };
+    file.steps
+        .into_iter()
+        .map(|step| {
+            let (default_name, action) = match (step.run, step.wait) {
+                (Some(run), None) => {
+                    let args = match run {
+                        CommandLine::Line(line) => shell_words::split(&line)
+                            .with_context(|| format!("Invalid command line {:?}", line))?,
+                        CommandLine::Args(args) => args,
+                    };
+                    let action = Action::Run {
+                        command: crate::parse_step(session.opts, &args)?,
+                        timeout: step.timeout.as_deref().map(duration).transpose()?,
+                        expect_exit: step.expect_exit,
+                        expect: step
+                            .expect
+                            .iter()
+                            .map(|text| Expectation::parse(text))
+                            .collect::<Result<_>>()?,
+                    };
+                    (shell_words::join(&args), action)
+                }
+                (None, Some(wait)) => {
+                    let duration = duration(&wait)?;
+                    let name = format!("wait {}", humantime::format_duration(duration));
+                    (name, Action::Wait(duration))
+                }
+                _ => return Err(format_err!("Has to either `run` a command or `wait`")),
+            };
+            let name = step.name.unwrap_or(default_name);
+            Ok(Step { name, action })
+        })
+        .enumerate()
+        .map(|(i, step)| step.with_context(|| format!("Step {} of {}", i + 1, path.display())))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| ExitError::new(exit::USAGE, format!("{:#}", e)).into())
+}
+
+/// Runs the steps in order, each command connecting the way `session` was set up from the
+/// global options of run-scenario
+pub async fn run(session: &Session<'_>, opts: &RunScenarioOpts) -> Result<Report> {
+    let steps = load(session, &opts.file)?;
+    let mut report = Report::default();
+    let mut results = Vec::new();
+    let mut failures = 0;
+    for (i, step) in steps.iter().enumerate() {
+        if failures > 0 && !opts.keep_going {
+            break;
+        }
+        output::progress(format!("[{}/{}] {}", i + 1, steps.len(), step.name))?;
+        let started = Instant::now();
+        let (failure, result) = match &step.action {
+            Action::Wait(duration) => {
+                tokio::time::sleep(*duration).await;
+                (None, None)
+            }
+            Action::Run {
+                command,
+                timeout,
+                expect_exit,
+                expect,
+            } => {
+                let run = crate::run_command(session, command);
+                let result = match timeout {
+                    Some(timeout) => tokio::time::timeout(*timeout, run)
+                        .await
+                        .unwrap_or_else(|_| Err(ExitError::new(exit::TIMEOUT, "Timeout").into())),
+                    None => run.await,
+                };
+                match result {
+                    Ok(step_report) => {
+                        if !output::is_json() {
+                            step_report.print()?;
+                        }
+                        let json = step_report.json_value().cloned();
+                        let failure = if *expect_exit != 0 {
+                            Some(format!("exited with 0 rather than {}", expect_exit))
+                        } else {
+                            match &json {
+                                Some(json) => {
+                                    let unmet = expect
+                                        .iter()
+                                        .filter_map(|expectation| expectation.check(json))
+                                        .collect::<Vec<_>>();
+                                    if unmet.is_empty() {
+                                        None
+                                    } else {
+                                        Some(unmet.join("; "))
+                                    }
+                                }
+                                None if !expect.is_empty() => {
+                                    Some("the command has no JSON result to check".to_owned())
+                                }
+                                None => None,
+                            }
+                        };
+                        (failure, json)
+                    }
+                    // Stopping everything, e.g. `| head`
+                    Err(e) if output::is_stdout_closed(&e) => return Err(e),
+                    Err(e) => {
+                        let code = exit::code_for(&e);
+                        if code == *expect_exit {
+                            (None, None)
+                        } else {
+                            (Some(format!("exited with {}: {:#}", code, e)), None)
+                        }
+                    }
+                }
+            }
+        };
+        let elapsed = started.elapsed();
+        match &failure {
+            Some(failure) => {
+                failures += 1;
+                report.line(format!(
+                    "FAIL  {:>8.1}s  {}: {}",
+                    elapsed.as_secs_f64(),
+                    step.name,
+                    failure
+                ));
+            }
+            None => report.line(format!(
+                "PASS  {:>8.1}s  {}",
+                elapsed.as_secs_f64(),
+                step.name
+            )),
+        }
+        results.push(json!({
+            "name": step.name,
+            "passed": failure.is_none(),
+            "seconds": elapsed.as_secs_f64(),
+            "failure": failure,
+            "result": result,
+        }));
+    }
+    let skipped = steps.len() - results.len();
+    if skipped > 0 {
+        report.line(format!("{} step(s) not run after the failure", skipped));
+    }
+    report.set_json(json!({
+        "passed": failures == 0,
+        "steps": results,
+        "skipped": skipped,
+    }));
+    if failures > 0 {
+        report.print()?;
+        return Err(ExitError::new(
+            exit::FAILURE,
+            format!("{} of {} step(s) failed", failures, steps.len() - skipped),
+        )
+        .into());
+    }
+    Ok(report)
+}
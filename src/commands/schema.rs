@@ -0,0 +1,223 @@
+use crate::{
+    admin::{AdminClient, AdminError},
+    confirm::confirm,
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::{bail, format_err, Context, Result};
+use chrono::{TimeZone, Utc};
+use colored_json::to_colored_json_auto;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt::Write, path::PathBuf, str::FromStr};
+use structopt::StructOpt;
+
+/// The subcommands of `schema`
+#[derive(StructOpt)]
+pub enum SchemaCommand {
+    /// Print the current schema of a topic, or a specific version of it
+    Get {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        version: Option<u64>,
+    },
+
+    /// List all the schema versions of a topic
+    Versions {
+        #[structopt(long)]
+        topic: TopicName,
+    },
+
+    /// Upload a new schema version
+    Upload {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        file: PathBuf,
+
+        #[structopt(long = "type", possible_values = &["json", "avro"])]
+        schema_type: SchemaType,
+    },
+
+    /// Delete the schema of a topic
+    Delete {
+        #[structopt(long)]
+        topic: TopicName,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+}
+
+impl SchemaCommand {
+    /// Whether the command asks for confirmation, deleting something
+    pub fn is_destructive(&self) -> bool {
+        matches!(self, SchemaCommand::Delete { .. })
+    }
+}
+
+/// The schema types `schema upload` can register
+#[derive(Clone, Copy)]
+pub enum SchemaType {
+    Json,
+    Avro,
+}
+
+impl FromStr for SchemaType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(SchemaType::Json),
+            "avro" => Ok(SchemaType::Avro),
+            other => Err(format_err!("Invalid schema type: {:?}", other)),
+        }
+    }
+}
+
+impl SchemaType {
+    fn name(self) -> &'static str {
+        match self {
+            SchemaType::Json => "JSON",
+            SchemaType::Avro => "AVRO",
+        }
+    }
+
+    /// Checks that `definition` is a valid schema of this type before it is sent to the broker
+    fn validate(self, definition: &str) -> Result<()> {
+        match self {
+            SchemaType::Json => {
+                serde_json::from_str::<Value>(definition)?;
+            }
+            SchemaType::Avro => {
+                avro_rs::Schema::parse_str(definition)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct SchemaInfo {
+    version: u64,
+    #[serde(rename = "type")]
+    schema_type: String,
+    #[serde(default)]
+    timestamp: i64,
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    properties: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AllSchemas {
+    get_schema_responses: Vec<SchemaInfo>,
+}
+
+/// Runs `command`
+pub async fn run(admin: &AdminClient, command: &SchemaCommand) -> Result<Report> {
+    match command {
+        SchemaCommand::Get { topic, version } => {
+            let mut path = format!("{}/schema", topic.schemas_admin_path());
+            if let Some(version) = version {
+                path = format!("{}/{}", path, version);
+            }
+            let raw = admin.get::<Value>(&path).await?;
+            let schema = serde_json::from_value::<SchemaInfo>(raw.clone())?;
+            let mut out = String::new();
+            writeln!(out, "Type:    {}", schema.schema_type)?;
+            writeln!(out, "Version: {}", schema.version)?;
+            writeln!(out, "Created: {}", format_timestamp(schema.timestamp))?;
+            for (key, value) in schema.properties.iter() {
+                writeln!(out, "  {}={}", key, value)?;
+            }
+            match serde_json::from_str::<Value>(&schema.data) {
+                Ok(definition) => writeln!(out, "{}", to_colored_json_auto(&definition)?)?,
+                Err(_) => writeln!(out, "{}", schema.data)?,
+            }
+            Ok(Report::new(out, raw))
+        }
+
+        SchemaCommand::Versions { topic } => {
+            let raw = admin
+                .get::<Value>(&format!("{}/getAllSchemas", topic.schemas_admin_path()))
+                .await?;
+            let schemas = serde_json::from_value::<AllSchemas>(raw.clone())?;
+            let mut out = String::new();
+            for schema in schemas.get_schema_responses.iter() {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}",
+                    schema.version,
+                    schema.schema_type,
+                    format_timestamp(schema.timestamp)
+                )?;
+            }
+            Ok(Report::new(out, raw))
+        }
+
+        SchemaCommand::Upload {
+            topic,
+            file,
+            schema_type,
+        } => {
+            let definition = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed reading {}", file.display()))?;
+            schema_type.validate(&definition).with_context(|| {
+                format!(
+                    "{} is not a valid {} schema",
+                    file.display(),
+                    schema_type.name()
+                )
+            })?;
+
+            let payload = json!({
+                "type": schema_type.name(),
+                "schema": definition,
+                "properties": {},
+            });
+            let path = format!("{}/schema", topic.schemas_admin_path());
+            match admin.post(&path, Some(&payload)).await {
+                Ok(()) => Ok(Report::message(format!(
+                    "Uploaded {} schema to {}",
+                    schema_type.name(),
+                    topic
+                ))),
+                Err(e) => match AdminError::find(&e) {
+                    Some(e) if e.status == StatusCode::CONFLICT => {
+                        bail!("Schema rejected as incompatible: {}", e.reason)
+                    }
+                    _ => Err(e),
+                },
+            }
+        }
+
+        SchemaCommand::Delete { topic, yes } => {
+            confirm(
+                &format!(
+                    "Delete the schema of {}? Every version of it is deleted.",
+                    topic
+                ),
+                *yes,
+            )?;
+            admin
+                .delete(&format!("{}/schema", topic.schemas_admin_path()))
+                .await?;
+            Ok(Report::message(format!("Deleted the schema of {}", topic)))
+        }
+    }
+}
+
+fn format_timestamp(millis: i64) -> String {
+    match Utc.timestamp_millis_opt(millis).single() {
+        Some(time) if millis > 0 => time.to_string(),
+        _ => "-".to_owned(),
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    admin::{stats::TopicStats, AdminClient},
+    output::{self, Report},
+    topic_name::TopicName,
+    units::format_bytes,
+};
+use anyhow::Result;
+use colored_json::to_colored_json_auto;
+use std::{fmt::Write, future::Future};
+use structopt::StructOpt;
+use termion::{clear, color, cursor};
+
+/// The options of `stats`
+#[derive(StructOpt)]
+pub struct StatsOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Print the raw stats document
+    #[structopt(long)]
+    json: bool,
+
+    /// Keep refreshing the stats at this interval
+    #[structopt(long)]
+    watch: Option<humantime::Duration>,
+}
+
+/// Prints the stats of the topic
+pub async fn run(admin: &AdminClient, opts: &StatsOpts) -> Result<Report> {
+    print_or_watch(opts.watch, || async {
+        if output::json(opts.json) {
+            let raw = admin.topic_stats_raw(&opts.topic).await?;
+            Ok(to_colored_json_auto(&raw)?)
+        } else {
+            let stats = admin.topic_stats(&opts.topic).await?;
+            Ok(render(&opts.topic, &stats))
+        }
+    })
+    .await
+}
+
+/// Prints what `render` returns once, or every `watch` interval on a cleared screen, showing
+/// errors in place of the output rather than stopping
+pub async fn print_or_watch<F, Fut>(
+    watch: Option<humantime::Duration>,
+    mut render: F,
+) -> Result<Report>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<String>>,
+{
+    loop {
+        let rendered = render().await;
+        match watch {
+            None => {
+                output::write_line(rendered?)?;
+                return Ok(Report::default());
+            }
+            Some(interval) => {
+                let rendered = match rendered {
+                    Ok(rendered) => rendered,
+                    Err(e) => format!(
+                        "{}Refresh failed: {}{}",
+                        color::Fg(color::Red),
+                        e,
+                        color::Fg(color::Reset)
+                    ),
+                };
+                output::write_line(format!("{}{}{}", clear::All, cursor::Goto(1, 1), rendered))?;
+                tokio::time::sleep(interval.into()).await;
+            }
+        }
+    }
+}
+
+fn render(topic: &TopicName, stats: &TopicStats) -> String {
+    let mut out = String::new();
+    // Writing to a String never fails
+    let _ = write_stats(&mut out, topic, stats);
+    out
+}
+
+fn write_stats(out: &mut String, topic: &TopicName, stats: &TopicStats) -> std::fmt::Result {
+    writeln!(out, "{}", topic)?;
+    writeln!(
+        out,
+        "  Rate in:  {:.2} msg/s ({}/s)",
+        stats.msg_rate_in,
+        format_bytes(stats.msg_throughput_in)
+    )?;
+    writeln!(
+        out,
+        "  Rate out: {:.2} msg/s ({}/s)",
+        stats.msg_rate_out,
+        format_bytes(stats.msg_throughput_out)
+    )?;
+    writeln!(
+        out,
+        "  Storage:  {}",
+        format_bytes(stats.storage_size as f64)
+    )?;
+    if !stats.partitions.is_empty() {
+        writeln!(out, "  Partitions: {}", stats.partitions.len())?;
+    }
+
+    writeln!(out, "Producers ({}):", stats.publishers.len())?;
+    for publisher in stats.publishers.iter() {
+        writeln!(
+            out,
+            "  {}{}{} ({}) {:.2} msg/s",
+            color::Fg(color::Magenta),
+            publisher.producer_name,
+            color::Fg(color::Reset),
+            publisher.address,
+            publisher.msg_rate_in
+        )?;
+    }
+
+    if !stats.replication.is_empty() {
+        writeln!(out, "Replication ({}):", stats.replication.len())?;
+        for (cluster, replicator) in stats.replication.iter() {
+            writeln!(
+                out,
+                "  {}{}{} {}backlog: {}, delay: {}s, {:.2} msg/s out, {:.2} msg/s in",
+                color::Fg(color::Magenta),
+                cluster,
+                color::Fg(color::Reset),
+                if replicator.connected {
+                    ""
+                } else {
+                    "(disconnected) "
+                },
+                replicator.replication_backlog,
+                replicator.replication_delay_in_seconds,
+                replicator.msg_rate_out,
+                replicator.msg_rate_in
+            )?;
+        }
+    }
+
+    writeln!(out, "Subscriptions ({}):", stats.subscriptions.len())?;
+    for (name, subscription) in stats.subscriptions.iter() {
+        writeln!(
+            out,
+            "  {}{}{} [{}{}] backlog: {}, {:.2} msg/s out",
+            color::Fg(color::Magenta),
+            name,
+            color::Fg(color::Reset),
+            subscription.sub_type,
+            if subscription.is_replicated {
+                ", replicated"
+            } else {
+                ""
+            },
+            subscription.msg_backlog,
+            subscription.msg_rate_out
+        )?;
+        for consumer in subscription.consumers.iter() {
+            writeln!(
+                out,
+                "    consumer {} ({})",
+                consumer.consumer_name, consumer.address
+            )?;
+        }
+    }
+    Ok(())
+}
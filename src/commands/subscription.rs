@@ -0,0 +1,495 @@
+use crate::{
+    admin::AdminClient,
+    confirm::confirm,
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::{self, Report},
+    topic_name::TopicName,
+    units::parse_time,
+};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use structopt::{clap::ArgGroup, StructOpt};
+
+/// Prefix of the subscription names pulsar-cli consumers use by default
+const OWN_SUBSCRIPTION_PREFIX: &str = "pulsar-cli";
+
+/// The subcommands of `subscription`
+#[derive(StructOpt)]
+pub enum SubscriptionCommand {
+    /// Delete a subscription
+    Delete {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long, required_unless = "cleanup-own", conflicts_with = "cleanup-own")]
+        subscription: Option<String>,
+
+        /// Delete every subscription created by pulsar-cli with its default name
+        #[structopt(long)]
+        cleanup_own: bool,
+
+        /// Delete subscriptions even if consumers are connected to them
+        #[structopt(long)]
+        force: bool,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+
+    /// Move the cursor of a subscription to a point in time or a specific message
+    #[structopt(group = ArgGroup::with_name("target").required(true))]
+    ResetCursor {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        subscription: String,
+
+        /// RFC 3339 timestamp, or a duration before now such as 2h
+        #[structopt(long, group = "target", parse(try_from_str = parse_time))]
+        to_time: Option<DateTime<Utc>>,
+
+        /// ledger:entry[:partition]
+        #[structopt(long, group = "target")]
+        to_message_id: Option<MessageId>,
+
+        #[structopt(long, group = "target")]
+        to_earliest: bool,
+
+        #[structopt(long, group = "target")]
+        to_latest: bool,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+
+    /// Discard messages from the backlog of a subscription without consuming them
+    #[structopt(group = ArgGroup::with_name("amount").required(true))]
+    Skip {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        subscription: String,
+
+        /// Number of messages to skip (on each partition of partitioned topics)
+        #[structopt(long, group = "amount")]
+        count: Option<u64>,
+
+        /// Skip the entire backlog
+        #[structopt(long, group = "amount")]
+        all: bool,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+
+    /// Discard the messages of a subscription's backlog older than a given age
+    Expire {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(
+            long,
+            required_unless = "all-subscriptions",
+            conflicts_with = "all-subscriptions"
+        )]
+        subscription: Option<String>,
+
+        /// Expire messages on every subscription of the topic
+        #[structopt(long)]
+        all_subscriptions: bool,
+
+        /// Age of the oldest messages to keep, such as 24h
+        #[structopt(long)]
+        older_than: humantime::Duration,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+}
+
+/// Runs `command`
+pub async fn run(admin: &AdminClient, command: &SubscriptionCommand) -> Result<Report> {
+    match command {
+        SubscriptionCommand::Delete {
+            topic,
+            subscription,
+            cleanup_own,
+            force,
+            yes,
+        } => {
+            let stats = admin.topic_stats(topic).await?;
+            let names = match subscription {
+                Some(subscription) => {
+                    if !stats.subscriptions.contains_key(subscription) {
+                        return Err(ExitError::new(
+                            exit::NOT_FOUND,
+                            format!("Subscription {:?} not found on {}", subscription, topic),
+                        )
+                        .into());
+                    }
+                    confirm(
+                        &format!(
+                            "Delete subscription {:?} from {} ({} message(s) in its backlog)?",
+                            subscription, topic, stats.subscriptions[subscription].msg_backlog
+                        ),
+                        *yes,
+                    )?;
+                    vec![subscription.clone()]
+                }
+                None => {
+                    debug_assert!(*cleanup_own);
+                    let names = stats
+                        .subscriptions
+                        .keys()
+                        .filter(|name| name.starts_with(OWN_SUBSCRIPTION_PREFIX))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if names.is_empty() {
+                        return Ok(Report::new(
+                            format!("No pulsar-cli subscriptions on {}", topic),
+                            json!({ "deleted": names }),
+                        ));
+                    }
+                    for name in names.iter() {
+                        output::progress(format!("  {}", name))?;
+                    }
+                    confirm(
+                        &format!("Delete {} subscription(s) from {}?", names.len(), topic),
+                        *yes,
+                    )?;
+                    names
+                }
+            };
+
+            for name in names.iter() {
+                let consumers = &stats.subscriptions[name].consumers;
+                if !consumers.is_empty() && !force {
+                    for consumer in consumers.iter() {
+                        eprintln!("  {} ({})", consumer.consumer_name, consumer.address);
+                    }
+                    return Err(ExitError::new(
+                        exit::REFUSED,
+                        format!(
+                            "Subscription {:?} has {} connected consumer(s), pass --force to delete it anyway",
+                            name,
+                            consumers.len()
+                        ),
+                    )
+                    .into());
+                }
+            }
+
+            let mut report = Report::default();
+            for name in names.iter() {
+                let mut path = AdminClient::subscription_path(topic, name);
+                if *force {
+                    path.push_str("?force=true");
+                }
+                admin.delete(&path).await?;
+                report.line(format!("Deleted subscription {:?} from {}", name, topic));
+            }
+            report.set_json(json!({ "deleted": names }));
+            Ok(report)
+        }
+
+        SubscriptionCommand::ResetCursor {
+            topic,
+            subscription,
+            to_time,
+            to_message_id,
+            to_earliest,
+            to_latest,
+            yes,
+        } => {
+            let (description, message_id) = match (to_time, to_message_id) {
+                (Some(time), _) => (time.to_string(), None),
+                (_, Some(message_id)) => (format!("message {}", message_id), Some(*message_id)),
+                _ if *to_earliest => ("earliest".to_owned(), Some(MessageId::EARLIEST)),
+                _ => {
+                    debug_assert!(*to_latest);
+                    ("latest".to_owned(), Some(MessageId::LATEST))
+                }
+            };
+
+            let before = positions(admin, topic, subscription).await?;
+            for (partition, position) in before.iter() {
+                output::progress(format!("Before: {} ({})", position, partition))?;
+            }
+            confirm(
+                &format!(
+                    "Reset subscription {:?} on {} to {}? This rewinds its consumers.",
+                    subscription, topic, description
+                ),
+                *yes,
+            )?;
+
+            let path = format!(
+                "{}/resetcursor",
+                AdminClient::subscription_path(topic, subscription)
+            );
+            match (to_time, message_id) {
+                (Some(time), _) => {
+                    admin
+                        .post(&format!("{}/{}", path, time.timestamp_millis()), None)
+                        .await?
+                }
+                (_, Some(message_id)) => {
+                    admin.post(&path, Some(&message_id.to_admin_json())).await?
+                }
+                _ => unreachable!(),
+            }
+            let after = positions(admin, topic, subscription).await?;
+            let mut report = Report::default();
+            report.line(format!(
+                "Reset subscription {:?} to {}",
+                subscription, description
+            ));
+            for (partition, position) in after.iter() {
+                report.line(format!("After: {} ({})", position, partition));
+            }
+            report.set_json(json!({
+                "subscription": subscription,
+                "resetTo": description,
+                "before": positions_json(&before),
+                "after": positions_json(&after),
+            }));
+            Ok(report)
+        }
+
+        SubscriptionCommand::Skip {
+            topic,
+            subscription,
+            count,
+            all,
+            yes,
+        } => {
+            let partitions = admin.partition_names(topic).await?;
+            let mut backlog = 0;
+            for partition in partitions.iter() {
+                backlog += subscription_backlog(admin, partition, subscription).await?;
+            }
+            let amount = match count {
+                Some(count) if partitions.len() > 1 => format!(
+                    "{} message(s) on each of the {} partitions",
+                    count,
+                    partitions.len()
+                ),
+                Some(count) => format!("{} message(s)", count),
+                None => "the entire backlog".to_owned(),
+            };
+            confirm(
+                &format!(
+                    "Skip {} of the backlog of {} message(s) of subscription {:?} on {}?",
+                    amount, backlog, subscription, topic
+                ),
+                *yes,
+            )?;
+
+            let mut report = Report::default();
+            let mut backlogs = Map::new();
+            let mut failures = 0;
+            for partition in partitions.iter() {
+                let before = subscription_backlog(admin, partition, subscription).await?;
+                let path = match count {
+                    Some(count) => format!(
+                        "{}/skip/{}",
+                        AdminClient::subscription_path(partition, subscription),
+                        count
+                    ),
+                    None => format!(
+                        "{}/skip_all",
+                        AdminClient::subscription_path(partition, subscription)
+                    ),
+                };
+                match admin.post(&path, None).await {
+                    Ok(()) => {
+                        let after = subscription_backlog(admin, partition, subscription).await?;
+                        report.line(format!("{}: backlog {} -> {}", partition, before, after));
+                        backlogs.insert(
+                            partition.to_string(),
+                            json!({ "before": before, "after": after }),
+                        );
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        eprintln!("{}: failed skipping messages: {}", partition, e);
+                    }
+                }
+            }
+            if failures > 0 {
+                report.print()?;
+                bail!(
+                    "Skipping failed on {} of {} partition(s)",
+                    failures,
+                    partitions.len()
+                );
+            }
+            report.set_json(Value::Object(backlogs));
+            Ok(report)
+        }
+
+        SubscriptionCommand::Expire {
+            topic,
+            subscription,
+            all_subscriptions,
+            older_than,
+            yes,
+        } => {
+            let partitions = admin.partition_names(topic).await?;
+            let before = subscription_backlogs(admin, &partitions, subscription.as_deref()).await?;
+            if before.is_empty() {
+                return Ok(Report::new(
+                    format!("No subscriptions on {}", topic),
+                    json!({}),
+                ));
+            }
+            let target = match subscription {
+                Some(subscription) => format!("subscription {:?}", subscription),
+                None => {
+                    debug_assert!(*all_subscriptions);
+                    "every subscription".to_owned()
+                }
+            };
+            confirm(
+                &format!(
+                    "Expire messages older than {} on {} of {} ({} message(s) in the backlog)?",
+                    older_than,
+                    target,
+                    topic,
+                    before.values().sum::<u64>()
+                ),
+                *yes,
+            )?;
+
+            let seconds = older_than.as_secs();
+            let mut failures = 0;
+            for partition in partitions.iter() {
+                let path = match subscription {
+                    Some(subscription) => format!(
+                        "{}/expireMessages/{}",
+                        AdminClient::subscription_path(partition, subscription),
+                        seconds
+                    ),
+                    None => format!(
+                        "{}/all_subscription/expireMessages/{}",
+                        partition.admin_path(),
+                        seconds
+                    ),
+                };
+                if let Err(e) = admin.post(&path, None).await {
+                    failures += 1;
+                    eprintln!("{}: failed expiring messages: {}", partition, e);
+                }
+            }
+
+            let after = subscription_backlogs(admin, &partitions, subscription.as_deref()).await?;
+            let mut report = Report::default();
+            let mut totals = BTreeMap::<&str, (u64, u64)>::new();
+            for (key, backlog) in before.iter() {
+                let (partition, name) = key;
+                let remaining = after.get(key).copied().unwrap_or_default();
+                if partitions.len() > 1 {
+                    report.line(format!(
+                        "{} {:?}: backlog {} -> {}",
+                        partition, name, backlog, remaining
+                    ));
+                }
+                let total = totals.entry(name.as_str()).or_default();
+                total.0 += backlog;
+                total.1 += remaining;
+            }
+            let mut json = Map::new();
+            for (name, (backlog, remaining)) in totals {
+                report.line(format!("{:?}: backlog {} -> {}", name, backlog, remaining));
+                json.insert(
+                    name.to_owned(),
+                    json!({ "before": backlog, "after": remaining }),
+                );
+            }
+            if failures > 0 {
+                report.print()?;
+                bail!(
+                    "Expiring messages failed on {} of {} partition(s)",
+                    failures,
+                    partitions.len()
+                );
+            }
+            report.set_json(Value::Object(json));
+            Ok(report)
+        }
+    }
+}
+
+/// Backlog of `subscription` on a single non-partitioned topic or partition
+pub async fn subscription_backlog(
+    admin: &AdminClient,
+    topic: &TopicName,
+    subscription: &str,
+) -> Result<u64> {
+    let stats = admin.topic_stats(topic).await?;
+    match stats.subscriptions.get(subscription) {
+        Some(stats) => Ok(stats.msg_backlog),
+        None => Err(ExitError::new(
+            exit::NOT_FOUND,
+            format!("Subscription {:?} not found on {}", subscription, topic),
+        )
+        .into()),
+    }
+}
+
+/// Backlog of each subscription (or only of `subscription`) on each of `partitions`
+async fn subscription_backlogs(
+    admin: &AdminClient,
+    partitions: &[TopicName],
+    subscription: Option<&str>,
+) -> Result<BTreeMap<(String, String), u64>> {
+    let mut backlogs = BTreeMap::new();
+    for partition in partitions {
+        match subscription {
+            Some(subscription) => {
+                let backlog = subscription_backlog(admin, partition, subscription).await?;
+                backlogs.insert((partition.to_string(), subscription.to_owned()), backlog);
+            }
+            None => {
+                for (name, stats) in admin.topic_stats(partition).await?.subscriptions {
+                    backlogs.insert((partition.to_string(), name), stats.msg_backlog);
+                }
+            }
+        }
+    }
+    Ok(backlogs)
+}
+
+/// The mark-delete position of `subscription` on each partition of `topic`
+pub async fn positions(
+    admin: &AdminClient,
+    topic: &TopicName,
+    subscription: &str,
+) -> Result<Vec<(TopicName, String)>> {
+    let mut positions = admin.mark_delete_positions(topic).await?;
+    positions.remove(subscription).ok_or_else(|| {
+        ExitError::new(
+            exit::NOT_FOUND,
+            format!("Subscription {:?} not found on {}", subscription, topic),
+        )
+        .into()
+    })
+}
+
+/// The cursor positions of partitions as a JSON object, by partition
+pub fn positions_json(positions: &[(TopicName, String)]) -> Value {
+    positions
+        .iter()
+        .map(|(partition, position)| (partition.to_string(), json!(position)))
+        .collect::<Map<_, _>>()
+        .into()
+}
@@ -0,0 +1,153 @@
+use crate::{
+    admin::{stats::SubscriptionStats, AdminClient},
+    exit::{self, ExitError},
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use std::fmt::Write;
+use structopt::StructOpt;
+use termion::color;
+
+/// The options of `subscriptions`
+#[derive(StructOpt)]
+pub struct SubscriptionsOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Show the details of a single subscription
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Lists the subscriptions of the topic, or shows the one asked for
+pub async fn run(admin: &AdminClient, opts: &SubscriptionsOpts) -> Result<Report> {
+    let stats = admin.topic_stats(&opts.topic).await?;
+    let positions = admin.mark_delete_positions(&opts.topic).await?;
+    let position_of = |name: &str| -> Value {
+        positions
+            .get(name)
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|(partition, position)| (partition.to_string(), json!(position)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut out = String::new();
+    let json = match &opts.subscription {
+        None => {
+            let summaries = stats
+                .subscriptions
+                .iter()
+                .map(|(name, subscription)| {
+                    json!({
+                        "name": name,
+                        "type": subscription.sub_type,
+                        "backlog": subscription.msg_backlog,
+                        "consumers": subscription.consumers.len(),
+                        "lastAckedTimestamp": subscription.last_acked_timestamp,
+                        "markDeletePosition": position_of(name),
+                    })
+                })
+                .collect::<Vec<_>>();
+            for (name, subscription) in stats.subscriptions.iter() {
+                write_summary(&mut out, name, subscription, &position_of(name))?;
+            }
+            json!(summaries)
+        }
+
+        Some(name) => {
+            let subscription = stats.subscriptions.get(name).ok_or_else(|| {
+                ExitError::new(
+                    exit::NOT_FOUND,
+                    format!("Subscription {:?} not found on {}", name, opts.topic),
+                )
+            })?;
+
+            let consumers = subscription
+                .consumers
+                .iter()
+                .map(|consumer| {
+                    json!({
+                        "consumerName": consumer.consumer_name,
+                        "address": consumer.address,
+                        "unackedMessages": consumer.unacked_messages,
+                        "msgRateOut": consumer.msg_rate_out,
+                    })
+                })
+                .collect::<Vec<_>>();
+            write_summary(&mut out, name, subscription, &position_of(name))?;
+            writeln!(out, "  unacked messages: {}", subscription.unacked_messages)?;
+            writeln!(out, "  rate out: {:.2} msg/s", subscription.msg_rate_out)?;
+            for consumer in subscription.consumers.iter() {
+                writeln!(
+                    out,
+                    "  consumer {} ({}): {} unacked, {:.2} msg/s",
+                    consumer.consumer_name,
+                    consumer.address,
+                    consumer.unacked_messages,
+                    consumer.msg_rate_out
+                )?;
+            }
+            json!({
+                "name": name,
+                "type": subscription.sub_type,
+                "backlog": subscription.msg_backlog,
+                "unackedMessages": subscription.unacked_messages,
+                "msgRateOut": subscription.msg_rate_out,
+                "lastAckedTimestamp": subscription.last_acked_timestamp,
+                "markDeletePosition": position_of(name),
+                "consumers": consumers,
+            })
+        }
+    };
+    if opts.json {
+        return Ok(Report::json(json));
+    }
+    Ok(Report::new(out, json))
+}
+
+fn write_summary(
+    out: &mut String,
+    name: &str,
+    subscription: &SubscriptionStats,
+    positions: &Value,
+) -> std::fmt::Result {
+    writeln!(
+        out,
+        "{}{}{} [{}] backlog: {}, consumers: {}",
+        color::Fg(color::Magenta),
+        name,
+        color::Fg(color::Reset),
+        subscription.sub_type,
+        subscription.msg_backlog,
+        subscription.consumers.len()
+    )?;
+    let last_acked = match Utc
+        .timestamp_millis_opt(subscription.last_acked_timestamp)
+        .single()
+    {
+        Some(time) if subscription.last_acked_timestamp > 0 => time.to_string(),
+        _ => "never".to_owned(),
+    };
+    writeln!(out, "  last acked: {}", last_acked)?;
+    if let Some(positions) = positions.as_object() {
+        for (partition, position) in positions.iter() {
+            writeln!(
+                out,
+                "  mark-delete position: {} ({})",
+                position.as_str().unwrap_or_default(),
+                partition
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,335 @@
+use crate::{
+    admin::AdminClient,
+    confirm::confirm,
+    connection::{Connector, ServiceUrls},
+    exit::{self, ExitError},
+    message_id::MessageId,
+    output::Report,
+    topic_name::TopicName,
+    units::format_bytes,
+};
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+use structopt::StructOpt;
+
+/// The subcommands of `topic`
+#[derive(StructOpt)]
+pub enum TopicCommand {
+    /// Create a topic, partitioned if --partitions is given
+    Create {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        partitions: Option<u32>,
+    },
+
+    /// Delete a topic
+    Delete {
+        #[structopt(long)]
+        topic: TopicName,
+
+        /// Delete the topic even if it has subscriptions or connected clients
+        #[structopt(long)]
+        force: bool,
+
+        /// Don't ask for confirmation
+        #[structopt(long)]
+        yes: bool,
+    },
+
+    /// Increase the number of partitions of a partitioned topic
+    UpdatePartitions {
+        #[structopt(long)]
+        topic: TopicName,
+
+        #[structopt(long)]
+        partitions: u32,
+    },
+
+    /// Unload a topic from its broker, forcing its ownership to move
+    Unload(PartitionActionOpts),
+
+    /// Seal a topic so that nothing can be published to it anymore
+    Terminate(PartitionActionOpts),
+
+    /// Delete all the data of a topic, keeping the topic itself
+    Truncate(PartitionActionOpts),
+}
+
+impl TopicCommand {
+    /// Whether the command asks for confirmation, deleting or disrupting something
+    pub fn is_destructive(&self) -> bool {
+        !matches!(
+            self,
+            TopicCommand::Create { .. } | TopicCommand::UpdatePartitions { .. }
+        )
+    }
+}
+
+/// The options of `topic unload`, `terminate` and `truncate`
+#[derive(StructOpt)]
+pub struct PartitionActionOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Print the result of each partition as JSON
+    #[structopt(long)]
+    json: bool,
+
+    /// Don't ask for confirmation
+    #[structopt(long)]
+    yes: bool,
+}
+
+/// Result of an action on a single partition (or non-partitioned topic)
+#[derive(Serialize)]
+struct Outcome {
+    topic: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// ID of the last message of a terminated topic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_message_id: Option<String>,
+    /// Whether creating a producer failed after terminating the topic
+    #[serde(skip_serializing_if = "Option::is_none")]
+    producers_rejected: Option<bool>,
+    #[serde(skip)]
+    exit_code: i32,
+}
+
+impl Outcome {
+    fn new(topic: &TopicName, result: Result<()>) -> Self {
+        let (error, exit_code) = match result {
+            Ok(()) => (None, 0),
+            Err(e) => (Some(e.to_string()), exit::code_for(&e)),
+        };
+        Self {
+            topic: topic.to_string(),
+            error,
+            last_message_id: None,
+            producers_rejected: None,
+            exit_code,
+        }
+    }
+}
+
+/// Runs `command`
+pub async fn run(
+    url: &ServiceUrls,
+    admin: &AdminClient,
+    connector: &Connector,
+    command: &TopicCommand,
+) -> Result<Report> {
+    match command {
+        TopicCommand::Create { topic, partitions } => {
+            let text = match partitions {
+                Some(partitions) => {
+                    admin
+                        .put(
+                            &format!("{}/partitions", topic.admin_path()),
+                            Some(&json!(partitions)),
+                        )
+                        .await?;
+                    format!("Created {} with {} partitions", topic, partitions)
+                }
+                None => {
+                    admin.put(&topic.admin_path(), None).await?;
+                    format!("Created {}", topic)
+                }
+            };
+            Ok(Report::new(
+                text,
+                json!({ "created": topic.to_string(), "partitions": partitions.unwrap_or(0) }),
+            ))
+        }
+
+        TopicCommand::Delete { topic, force, yes } => {
+            let subscriptions = admin.subscriptions(topic).await?;
+            if !subscriptions.is_empty() && !force {
+                for subscription in subscriptions.iter() {
+                    eprintln!("  {}", subscription);
+                }
+                return Err(ExitError::new(
+                    exit::REFUSED,
+                    format!(
+                        "{} has {} subscription(s), pass --force to delete it anyway",
+                        topic,
+                        subscriptions.len()
+                    ),
+                )
+                .into());
+            }
+
+            let stats = admin.topic_stats(topic).await?;
+            let backlog = stats
+                .subscriptions
+                .values()
+                .map(|subscription| subscription.msg_backlog)
+                .max()
+                .unwrap_or_default();
+            confirm(
+                &format!(
+                    "Delete {} ({}, {} subscription(s), up to {} unconsumed message(s))? This can't be undone.",
+                    topic,
+                    format_bytes(stats.storage_size as f64),
+                    subscriptions.len(),
+                    backlog
+                ),
+                *yes,
+            )?;
+
+            let mut path = topic.admin_path();
+            if admin.partitions(topic).await? > 0 {
+                path.push_str("/partitions");
+            }
+            if *force {
+                path.push_str("?force=true");
+            }
+            admin.delete(&path).await?;
+            Ok(Report::new(
+                format!("Deleted {}", topic),
+                json!({ "deleted": topic.to_string() }),
+            ))
+        }
+
+        TopicCommand::UpdatePartitions { topic, partitions } => {
+            let current = admin.partitions(topic).await?;
+            admin
+                .post(
+                    &format!("{}/partitions", topic.admin_path()),
+                    Some(&json!(partitions)),
+                )
+                .await?;
+            Ok(Report::new(
+                format!(
+                    "Updated {} from {} to {} partitions",
+                    topic, current, partitions
+                ),
+                json!({ "topic": topic.to_string(), "previous": current, "partitions": partitions }),
+            ))
+        }
+
+        TopicCommand::Unload(opts) => {
+            confirm(
+                &format!(
+                    "Unload {}? Its clients disconnect until another broker takes it over.",
+                    opts.topic
+                ),
+                opts.yes,
+            )?;
+            let mut outcomes = Vec::new();
+            for partition in admin.partition_names(&opts.topic).await? {
+                let result = admin
+                    .put(&format!("{}/unload", partition.admin_path()), None)
+                    .await;
+                outcomes.push(Outcome::new(&partition, result));
+            }
+            report("Unloaded", &outcomes, opts.json)
+        }
+
+        TopicCommand::Terminate(opts) => {
+            confirm(
+                &format!(
+                    "Terminate {}? Nothing can be published to it anymore, this can't be undone.",
+                    opts.topic
+                ),
+                opts.yes,
+            )?;
+            let pulsar = connector.connect(url).await?;
+            let mut outcomes = Vec::new();
+            for partition in admin.partition_names(&opts.topic).await? {
+                let last = admin
+                    .post_for(&format!("{}/terminate", partition.admin_path()), None)
+                    .await
+                    .and_then(MessageId::from_admin_json);
+                let outcome = match last {
+                    Err(e) => Outcome::new(&partition, Err(e)),
+                    Ok(last) => {
+                        // The broker refuses new producers on terminated topics
+                        let rejected = pulsar
+                            .producer()
+                            .with_topic(partition.to_string())
+                            .build()
+                            .await
+                            .is_err();
+                        let mut outcome = Outcome::new(&partition, Ok(()));
+                        outcome.last_message_id = Some(last.to_string());
+                        outcome.producers_rejected = Some(rejected);
+                        if !rejected {
+                            outcome.error = Some("still accepts producers".to_owned());
+                            outcome.exit_code = exit::FAILURE;
+                        }
+                        outcome
+                    }
+                };
+                outcomes.push(outcome);
+            }
+            report("Terminated", &outcomes, opts.json)
+        }
+
+        TopicCommand::Truncate(opts) => {
+            confirm(
+                &format!("Truncate {}? This deletes all of its messages.", opts.topic),
+                opts.yes,
+            )?;
+            let mut outcomes = Vec::new();
+            for partition in admin.partition_names(&opts.topic).await? {
+                let result = admin
+                    .delete(&format!("{}/truncate", partition.admin_path()))
+                    .await;
+                outcomes.push(Outcome::new(&partition, result));
+            }
+            report("Truncated", &outcomes, opts.json)
+        }
+    }
+}
+
+/// The outcome of an action on each partition, failing with the exit code of the failed
+/// partitions if they agree on one, after printing it
+fn report(action: &str, outcomes: &[Outcome], json: bool) -> Result<Report> {
+    let mut report = if json {
+        Report::json(serde_json::to_value(outcomes)?)
+    } else {
+        let mut report = Report::default();
+        for outcome in outcomes.iter() {
+            match (&outcome.error, &outcome.last_message_id) {
+                (Some(error), _) => eprintln!("{}: failed: {}", outcome.topic, error),
+                (None, Some(last)) => report.line(format!(
+                    "{} {} (last message {})",
+                    action, outcome.topic, last
+                )),
+                (None, None) => report.line(format!("{} {}", action, outcome.topic)),
+            }
+        }
+        report
+    };
+    report.set_json(serde_json::to_value(outcomes)?);
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.error.is_some())
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        return Ok(report);
+    }
+    report.print()?;
+    let code = if failed
+        .iter()
+        .all(|outcome| outcome.exit_code == failed[0].exit_code)
+    {
+        failed[0].exit_code
+    } else {
+        exit::FAILURE
+    };
+    Err(ExitError::new(
+        code,
+        format!(
+            "Failed on {} of {} partition(s)",
+            failed.len(),
+            outcomes.len()
+        ),
+    )
+    .into())
+}
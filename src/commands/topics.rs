@@ -0,0 +1,94 @@
+use crate::{
+    admin::AdminClient,
+    output::Report,
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::Result;
+use regex::Regex;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use structopt::StructOpt;
+
+/// The options of `topics`
+#[derive(StructOpt)]
+pub struct TopicsOpts {
+    #[structopt(long)]
+    namespace: NamespaceName,
+
+    /// Include partitioned topics in the listing
+    #[structopt(long)]
+    include_partitioned: bool,
+
+    /// List each partition of partitioned topics instead of collapsing them
+    #[structopt(long, requires = "include-partitioned")]
+    expand_partitions: bool,
+
+    /// Only list topics whose full name matches this regular expression
+    #[structopt(long)]
+    filter: Option<Regex>,
+
+    /// List non-persistent topics instead of persistent ones
+    #[structopt(long)]
+    non_persistent: bool,
+
+    #[structopt(long)]
+    json: bool,
+}
+
+/// Lists the topics of the namespace
+pub async fn run(admin: &AdminClient, opts: &TopicsOpts) -> Result<Report> {
+    let persistent = !opts.non_persistent;
+
+    // Individual partitions show up in the plain listing too, they're reported through their
+    // partitioned topic instead
+    let mut topics = BTreeSet::new();
+    for topic in admin.topics(&opts.namespace, persistent).await? {
+        let name = topic.parse::<TopicName>()?;
+        if name.partitioned_parent().is_none() {
+            topics.insert(name.to_string());
+        }
+    }
+
+    let mut partitioned = BTreeMap::new();
+    if opts.include_partitioned {
+        for topic in admin
+            .partitioned_topics(&opts.namespace, persistent)
+            .await?
+        {
+            let name = topic.parse::<TopicName>()?;
+            let partitions = admin.partitions(&name).await?;
+            topics.remove(&topic);
+            partitioned.insert(name, partitions);
+        }
+    }
+
+    let mut lines = topics
+        .into_iter()
+        .map(|topic| (topic, None))
+        .collect::<Vec<_>>();
+    for (name, partitions) in partitioned {
+        if opts.expand_partitions {
+            lines.extend((0..partitions).map(|i| (name.partition(i).to_string(), None)));
+        } else {
+            lines.push((name.to_string(), Some(partitions)));
+        }
+    }
+    if let Some(filter) = &opts.filter {
+        lines.retain(|(topic, _)| filter.is_match(topic));
+    }
+    lines.sort();
+
+    let names = lines.iter().map(|(topic, _)| topic).collect::<Vec<_>>();
+    if opts.json {
+        return Ok(Report::json(json!(names)));
+    }
+    let mut report = Report::default();
+    for (topic, partitions) in lines.iter() {
+        match partitions {
+            Some(partitions) => report.line(format!("{} ({} partitions)", topic, partitions)),
+            None => report.line(topic),
+        }
+    }
+    report.set_json(json!(names));
+    Ok(report)
+}
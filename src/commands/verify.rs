@@ -0,0 +1,233 @@
+use crate::{
+    connection::{self, Connector, ConsumerParams, ServiceUrls},
+    display::property,
+    exit::{self, ExitError},
+    metrics::describe_latencies,
+    output::Report,
+    topic_name::TopicName,
+};
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use futures::TryStreamExt;
+use log::info;
+use pulsar::{ConsumerOptions, SubType};
+use serde_json::json;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+
+const RUN_ID_PROPERTY: &str = "pulsar-cli-verify-run";
+const SEQUENCE_PROPERTY: &str = "pulsar-cli-verify-sequence";
+const CHECKSUM_PROPERTY: &str = "pulsar-cli-verify-checksum";
+const SENT_AT_PROPERTY: &str = "pulsar-cli-verify-sent-at";
+
+/// The options of `verify`
+#[derive(StructOpt)]
+pub struct VerifyOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Number of messages to publish
+    #[structopt(long, default_value = "1000")]
+    count: u64,
+
+    /// Number of distinct keys the messages are spread over, ordering being checked per key
+    #[structopt(long, default_value = "10")]
+    keys: u64,
+
+    #[structopt(long, default_value = "100")]
+    payload_size: usize,
+
+    /// Give up waiting for messages after this long
+    #[structopt(long, default_value = "60s")]
+    timeout: humantime::Duration,
+}
+
+#[derive(Default)]
+struct Received {
+    sequences: HashSet<u64>,
+    duplicated: u64,
+    out_of_order: u64,
+    corrupted: u64,
+    end_to_end: Vec<Duration>,
+}
+
+/// Publishes the messages, consumes them back and reports those lost, duplicated or out of order
+pub async fn run(url: &ServiceUrls, connector: &Connector, opts: &VerifyOpts) -> Result<Report> {
+    let run_id = format!("{}-{}", Utc::now().timestamp_millis(), std::process::id());
+    let topic = opts.topic.to_string();
+    let subscription = format!("pulsar-cli-verify-{}", run_id);
+
+    // Subscribe first, so that the consumer starting at the latest message sees every message
+    let mut consumer = connector
+        .build_consumer(
+            url,
+            &ConsumerParams {
+                topic: &topic,
+                subscription: &subscription,
+                consumer_name: &subscription,
+                sub_type: SubType::Exclusive,
+                options: ConsumerOptions {
+                    durable: Some(false),
+                    ..Default::default()
+                },
+            },
+        )
+        .await?;
+    let mut producer = connector.build_producer(url, &topic, None).await?;
+    info!("Verifying {} with run {}", topic, run_id);
+
+    let produce = async {
+        let mut receipts = Vec::with_capacity(opts.count as usize);
+        for sequence in 0..opts.count {
+            let payload = payload_for(&run_id, sequence, opts.payload_size);
+            let mut properties = HashMap::new();
+            properties.insert(RUN_ID_PROPERTY.to_owned(), run_id.clone());
+            properties.insert(SEQUENCE_PROPERTY.to_owned(), sequence.to_string());
+            properties.insert(CHECKSUM_PROPERTY.to_owned(), checksum(&payload));
+            properties.insert(
+                SENT_AT_PROPERTY.to_owned(),
+                Utc::now().timestamp_millis().to_string(),
+            );
+            let sent = Instant::now();
+            let receipt = producer
+                .send(pulsar::producer::Message {
+                    payload,
+                    properties,
+                    partition_key: Some(format!("key-{}", sequence % opts.keys.max(1))),
+                    ..Default::default()
+                })
+                .await?;
+            // Awaited in the background so the latency of each receipt is measured when it
+            // arrives, not when the last message is sent
+            receipts.push(tokio::spawn(async move {
+                receipt.await.map(|_| sent.elapsed())
+            }));
+        }
+        let mut latencies = Vec::with_capacity(receipts.len());
+        for receipt in receipts {
+            latencies.push(receipt.await??);
+        }
+        Ok::<_, anyhow::Error>(latencies)
+    };
+
+    let deadline = tokio::time::Instant::now() + opts.timeout.into();
+    let consume = async {
+        let mut received = Received::default();
+        let mut last_of_key = HashMap::<String, u64>::new();
+        while (received.sequences.len() as u64) < opts.count {
+            let message = match tokio::time::timeout_at(deadline, consumer.try_next()).await {
+                Ok(message) => {
+                    message?.ok_or_else(|| format_err!("Consumer of {} closed", topic))?
+                }
+                Err(_) => break,
+            };
+            connection::ack_with_retry(&mut consumer, &message, false, connection::ACK_RETRIES)
+                .await?;
+            if property(&message, RUN_ID_PROPERTY) != Some(run_id.as_str()) {
+                continue;
+            }
+            let sequence = property(&message, SEQUENCE_PROPERTY)
+                .and_then(|sequence| sequence.parse::<u64>().ok())
+                .ok_or_else(|| format_err!("Message without a valid sequence number"))?;
+            if let Some(sent_at) =
+                property(&message, SENT_AT_PROPERTY).and_then(|at| at.parse::<i64>().ok())
+            {
+                let elapsed = (Utc::now().timestamp_millis() - sent_at).max(0);
+                received
+                    .end_to_end
+                    .push(Duration::from_millis(elapsed as u64));
+            }
+
+            if !received.sequences.insert(sequence) {
+                received.duplicated += 1;
+                continue;
+            }
+            if property(&message, CHECKSUM_PROPERTY)
+                != Some(checksum(&message.payload.data).as_str())
+            {
+                received.corrupted += 1;
+            }
+            let key = message.metadata().partition_key.clone().unwrap_or_default();
+            if let Some(last) = last_of_key.insert(key, sequence) {
+                if last > sequence {
+                    received.out_of_order += 1;
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(received)
+    };
+
+    let (publish_latencies, received) = tokio::join!(produce, consume);
+    let mut publish_latencies = publish_latencies?;
+    let mut received = received?;
+
+    let lost = opts.count - received.sequences.len() as u64;
+    let mut report = Report::default();
+    report.line(format!(
+        "Published {}, received {}: {} lost, {} duplicated, {} out of order, {} corrupted",
+        opts.count,
+        received.sequences.len(),
+        lost,
+        received.duplicated,
+        received.out_of_order,
+        received.corrupted
+    ));
+    let mut json = json!({
+        "published": opts.count,
+        "received": received.sequences.len(),
+        "lost": lost,
+        "duplicated": received.duplicated,
+        "outOfOrder": received.out_of_order,
+        "corrupted": received.corrupted,
+    });
+    for (label, key, latencies) in [
+        (
+            "Publish latency",
+            "publishLatencyMs",
+            &mut publish_latencies,
+        ),
+        (
+            "End-to-end latency",
+            "endToEndLatencyMs",
+            &mut received.end_to_end,
+        ),
+    ]
+    .iter_mut()
+    {
+        if let Some((text, percentiles)) = describe_latencies(label, latencies) {
+            report.line(text);
+            json[*key] = percentiles;
+        }
+    }
+
+    let ok = lost + received.duplicated + received.out_of_order + received.corrupted == 0;
+    json["ok"] = json!(ok);
+    report.set_json(json);
+    if !ok {
+        report.print()?;
+        return Err(
+            ExitError::new(exit::FAILURE, format!("Verification of {} failed", topic)).into(),
+        );
+    }
+    report.line("OK");
+    Ok(report)
+}
+
+/// A payload unique to the message, so that a mixed up payload fails the checksum
+fn payload_for(run_id: &str, sequence: u64, size: usize) -> Vec<u8> {
+    format!("{}/{} ", run_id, sequence)
+        .bytes()
+        .cycle()
+        .take(size)
+        .collect()
+}
+
+fn checksum(payload: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    payload.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
@@ -0,0 +1,293 @@
+use crate::{
+    admin::{stats::TopicStats, AdminClient},
+    exit::{self, ExitError},
+    output::{self, Report},
+    topic_name::TopicName,
+    units::format_bytes,
+};
+use anyhow::{bail, format_err, Result};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{
+    fmt::{self, Write as _},
+    str::FromStr,
+};
+use structopt::StructOpt;
+use termion::color;
+
+/// The options of `watch`
+#[derive(StructOpt)]
+pub struct WatchOpts {
+    #[structopt(long)]
+    topic: TopicName,
+
+    /// Only show this subscription, whose backlog is the one thresholds apply to
+    #[structopt(long)]
+    subscription: Option<String>,
+
+    #[structopt(long, default_value = "2s")]
+    interval: humantime::Duration,
+
+    /// Exit when a metric crosses a value, e.g. backlog>10000. One of backlog, rate-in,
+    /// rate-out, storage, producers or consumers; the backlog is the largest one of the
+    /// subscriptions shown
+    #[structopt(long = "threshold")]
+    thresholds: Vec<Threshold>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Metric {
+    Backlog,
+    RateIn,
+    RateOut,
+    Storage,
+    Producers,
+    Consumers,
+}
+
+const METRICS: &[(&str, Metric)] = &[
+    ("backlog", Metric::Backlog),
+    ("rate-in", Metric::RateIn),
+    ("rate-out", Metric::RateOut),
+    ("storage", Metric::Storage),
+    ("producers", Metric::Producers),
+    ("consumers", Metric::Consumers),
+];
+
+/// A value of a metric for `--threshold`, which is crossed going above or below it
+pub struct Threshold {
+    metric: Metric,
+    above: bool,
+    value: f64,
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (index, above) = match (s.find('>'), s.find('<')) {
+            (Some(index), None) => (index, true),
+            (None, Some(index)) => (index, false),
+            _ => bail!(
+                "Invalid threshold {:?}, expected e.g. backlog>10000 or rate-out<1",
+                s
+            ),
+        };
+        let name = s[..index].trim();
+        let metric = METRICS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, metric)| *metric)
+            .ok_or_else(|| format_err!("Unknown metric {:?} in threshold {:?}", name, s))?;
+        let value = s[index + 1..]
+            .trim()
+            .parse()
+            .map_err(|_| format_err!("Invalid value in threshold {:?}", s))?;
+        Ok(Self {
+            metric,
+            above,
+            value,
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = METRICS
+            .iter()
+            .find(|(_, metric)| *metric == self.metric)
+            .map_or("?", |(name, _)| *name);
+        let operator = if self.above { '>' } else { '<' };
+        write!(f, "{}{}{}", name, operator, self.value)
+    }
+}
+
+struct SubscriptionRow<'a> {
+    name: &'a str,
+    sub_type: &'a str,
+    backlog: u64,
+    rate_out: f64,
+    consumers: usize,
+}
+
+/// The figures shown on each refresh
+struct Sample<'a> {
+    stats: &'a TopicStats,
+    subscriptions: Vec<SubscriptionRow<'a>>,
+}
+
+impl<'a> Sample<'a> {
+    fn new(stats: &'a TopicStats, subscription: Option<&str>) -> Result<Self> {
+        let subscriptions = stats
+            .subscriptions
+            .iter()
+            .filter(|(name, _)| subscription.map_or(true, |only| only == name.as_str()))
+            .map(|(name, stats)| SubscriptionRow {
+                name,
+                sub_type: &stats.sub_type,
+                backlog: stats.msg_backlog,
+                rate_out: stats.msg_rate_out,
+                consumers: stats.consumers.len(),
+            })
+            .collect::<Vec<_>>();
+        if let (Some(subscription), true) = (subscription, subscriptions.is_empty()) {
+            bail!("Subscription {:?} not found", subscription);
+        }
+        Ok(Self {
+            stats,
+            subscriptions,
+        })
+    }
+
+    fn value(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Backlog => self
+                .subscriptions
+                .iter()
+                .map(|row| row.backlog)
+                .max()
+                .unwrap_or(0) as f64,
+            Metric::RateIn => self.stats.msg_rate_in,
+            Metric::RateOut => self.stats.msg_rate_out,
+            Metric::Storage => self.stats.storage_size as f64,
+            Metric::Producers => self.stats.publishers.len() as f64,
+            Metric::Consumers => self
+                .subscriptions
+                .iter()
+                .map(|row| row.consumers)
+                .sum::<usize>() as f64,
+        }
+    }
+
+    fn write_table(&self, out: &mut String, topic: &TopicName) -> fmt::Result {
+        writeln!(out, "{}  {}", topic, Utc::now().format("%H:%M:%S"))?;
+        writeln!(
+            out,
+            "IN {:.1} msg/s  OUT {:.1} msg/s  STORAGE {}  PRODUCERS {}  CONSUMERS {}",
+            self.value(Metric::RateIn),
+            self.value(Metric::RateOut),
+            format_bytes(self.value(Metric::Storage)),
+            self.value(Metric::Producers),
+            self.value(Metric::Consumers)
+        )?;
+        let width = self
+            .subscriptions
+            .iter()
+            .map(|row| row.name.len())
+            .chain(Some("SUBSCRIPTION".len()))
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{:width$}  {:10}  {:>10}  {:>10}  CONSUMERS",
+            "SUBSCRIPTION",
+            "TYPE",
+            "BACKLOG",
+            "OUT MSG/S",
+            width = width
+        )?;
+        for row in self.subscriptions.iter() {
+            writeln!(
+                out,
+                "{:width$}  {:10}  {:>10}  {:>10.1}  {}",
+                row.name,
+                row.sub_type,
+                row.backlog,
+                row.rate_out,
+                row.consumers,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&self, out: &mut String) -> fmt::Result {
+        write!(
+            out,
+            "{} in={:.1} out={:.1} storage={} producers={} consumers={}",
+            Utc::now().to_rfc3339(),
+            self.value(Metric::RateIn),
+            self.value(Metric::RateOut),
+            self.stats.storage_size,
+            self.value(Metric::Producers),
+            self.value(Metric::Consumers)
+        )?;
+        for row in self.subscriptions.iter() {
+            write!(out, " backlog[{}]={}", row.name, row.backlog)?;
+        }
+        writeln!(out)
+    }
+
+    fn to_json(&self) -> Value {
+        let backlogs = self
+            .subscriptions
+            .iter()
+            .map(|row| (row.name.to_owned(), json!(row.backlog)))
+            .collect::<serde_json::Map<_, _>>();
+        json!({
+            "timestamp": Utc::now().to_rfc3339(),
+            "rateIn": self.value(Metric::RateIn),
+            "rateOut": self.value(Metric::RateOut),
+            "storage": self.stats.storage_size,
+            "producers": self.stats.publishers.len(),
+            "consumers": self.subscriptions.iter().map(|row| row.consumers).sum::<usize>(),
+            "backlogs": backlogs,
+        })
+    }
+}
+
+/// Prints the stats periodically until stopped or a threshold is crossed
+pub async fn run(admin: &AdminClient, opts: &WatchOpts) -> Result<Report> {
+    // Redrawing in place only makes sense on a terminal, otherwise (e.g. when logging to a
+    // file) a line is appended on each refresh, a JSON object with --output json
+    let json = output::is_json();
+    let mut redraw = output::Redraw::stdout();
+    let tty = redraw.is_tty();
+    loop {
+        let stats = admin.topic_stats(&opts.topic).await;
+        let sample = stats
+            .as_ref()
+            .map_err(|e| format_err!("Failed fetching stats: {}", e))
+            .and_then(|stats| Sample::new(stats, opts.subscription.as_deref()));
+
+        let mut out = String::new();
+        // Writing to a String never fails
+        let _ = match (&sample, tty) {
+            (Ok(sample), _) if json => writeln!(out, "{}", sample.to_json()),
+            (Err(e), _) if json => writeln!(out, "{}", json!({ "error": e.to_string() })),
+            (Ok(sample), true) => sample.write_table(&mut out, &opts.topic),
+            (Ok(sample), false) => sample.write_line(&mut out),
+            (Err(e), true) => writeln!(
+                out,
+                "{}{}{}",
+                color::Fg(color::Red),
+                e,
+                color::Fg(color::Reset)
+            ),
+            (Err(e), false) => writeln!(out, "{} {}", Utc::now().to_rfc3339(), e),
+        };
+        redraw.draw(&out)?;
+
+        if let Ok(sample) = &sample {
+            for threshold in opts.thresholds.iter() {
+                let value = sample.value(threshold.metric);
+                let crossed = if threshold.above {
+                    value > threshold.value
+                } else {
+                    value < threshold.value
+                };
+                if crossed {
+                    return Err(ExitError::new(
+                        exit::THRESHOLD_CROSSED,
+                        format!(
+                            "Threshold {} crossed on {}: {}",
+                            threshold, opts.topic, value
+                        ),
+                    )
+                    .into());
+                }
+            }
+        }
+        tokio::time::sleep(opts.interval.into()).await;
+    }
+}
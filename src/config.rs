@@ -0,0 +1,319 @@
+//! Named profiles from `~/.config/pulsar-cli/config.toml`, filling in the global options that
+//! aren't given on the command line or in the environment:
+//!
+//! ```toml
+//! [profiles.staging]
+//! url = "pulsar+ssl://pulsar.staging:6651"
+//! admin_url = "https://pulsar.staging:8443"
+//! tenant = "acme"
+//! namespace = "orders"
+//! auth_token_file = "/home/me/.pulsar/staging.jwt"
+//! tls_ca_cert = "/home/me/.pulsar/staging-ca.pem"
+//! ```
+//!
+//! The keys are the names of the global options. The profile named `default`, if any, is used
+//! when `--profile` isn't given.
+
+use crate::{connection::ServiceUrls, topic_name, Opts};
+use anyhow::{bail, format_err, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::PathBuf};
+use url::Url;
+
+const DEFAULT_PROFILE: &str = "default";
+
+/// Keys whose values are never printed
+pub const SECRET_KEYS: &[&str] = &["auth_token", "auth_basic", "admin_token"];
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    url: Option<ServiceUrls>,
+    admin_url: Option<Url>,
+    /// Namespace bare topic names live in, instead of `public/default`
+    topic_prefix: Option<String>,
+    tenant: Option<String>,
+    namespace: Option<String>,
+
+    auth_token: Option<String>,
+    auth_token_file: Option<PathBuf>,
+    auth_token_command: Option<String>,
+    auth_oauth2_issuer_url: Option<Url>,
+    auth_oauth2_credentials_file: Option<PathBuf>,
+    auth_oauth2_audience: Option<String>,
+    auth_oauth2_scope: Option<String>,
+    auth_basic: Option<String>,
+    auth_basic_file: Option<PathBuf>,
+    admin_token: Option<String>,
+
+    tls_ca_cert: Option<PathBuf>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_insecure: Option<bool>,
+    tls_hostname_verification: Option<bool>,
+    admin_tls_ca_cert: Option<PathBuf>,
+    admin_tls_insecure: Option<bool>,
+}
+
+/// Where the configuration file is, following the XDG base directory conventions
+pub fn path() -> Result<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .ok_or_else(|| format_err!("Can't locate the configuration file without $HOME"))?,
+    };
+    Ok(config_dir.join("pulsar-cli").join("config.toml"))
+}
+
+impl Config {
+    /// Reads the configuration file, which doesn't have to exist
+    pub fn load() -> Result<Self> {
+        let path = path()?;
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)
+                .map_err(|e| format_err!("Invalid configuration file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => bail!("Failed reading {}: {}", path.display(), e),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        for (name, profile) in config.profiles.iter() {
+            topic_name::topic_prefix_with(
+                profile.topic_prefix(),
+                profile.tenant(),
+                profile.namespace(),
+            )
+            .map_err(|e| format_err!("In profile {:?}: {}", name, e))?;
+        }
+        Ok(config)
+    }
+
+    /// The name of the profile in use: the one asked for, which has to exist, or else the
+    /// default one if there is one
+    pub fn selected<'a>(&self, name: Option<&'a str>) -> Result<Option<&'a str>> {
+        match name {
+            Some(name) if !self.profiles.contains_key(name) => {
+                bail!("No profile {:?} in {}", name, path()?.display())
+            }
+            Some(name) => Ok(Some(name)),
+            None if self.profiles.contains_key(DEFAULT_PROFILE) => Ok(Some(DEFAULT_PROFILE)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<&Profile>> {
+        Ok(self
+            .selected(name)?
+            .and_then(|name| self.profiles.get(name)))
+    }
+}
+
+/// Whether the environment variable `name` turns a switch on
+pub fn env_switch(name: &str) -> Result<bool> {
+    match std::env::var(name).as_deref() {
+        Err(_) | Ok("") | Ok("0") | Ok("false") => Ok(false),
+        Ok("1") | Ok("true") => Ok(true),
+        Ok(_) => bail!("Invalid value for {}, expected true or false", name),
+    }
+}
+
+/// Sets `option` from the profile unless it was given
+fn fill<T: Clone>(option: &mut Option<T>, profile: &Option<T>) {
+    if option.is_none() {
+        *option = profile.clone();
+    }
+}
+
+impl Profile {
+    pub fn topic_prefix(&self) -> Option<&str> {
+        self.topic_prefix.as_deref()
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Fills in the options that weren't given on the command line or in the environment
+    pub fn apply(&self, opts: &mut Opts) {
+        if opts.url.is_empty() {
+            opts.url.extend(self.url.clone());
+        }
+        fill(&mut opts.topics.tenant, &self.tenant);
+        fill(&mut opts.topics.namespace, &self.namespace);
+        fill(&mut opts.admin.admin_url, &self.admin_url);
+        fill(&mut opts.admin.admin_token, &self.admin_token);
+        fill(&mut opts.admin.admin_tls_ca_cert, &self.admin_tls_ca_cert);
+        opts.admin.admin_tls_insecure |= self.admin_tls_insecure.unwrap_or(false);
+
+        // Authentication methods exclude each other, so the profile's only applies if none was
+        // given at all
+        let auth = &mut opts.auth;
+        if auth.auth_token.is_none()
+            && auth.auth_token_file.is_none()
+            && auth.auth_token_command.is_none()
+            && auth.auth_oauth2_issuer_url.is_none()
+            && auth.auth_basic.is_none()
+            && auth.auth_basic_file.is_none()
+        {
+            auth.auth_token = self.auth_token.clone();
+            auth.auth_token_file = self.auth_token_file.clone();
+            auth.auth_token_command = self.auth_token_command.clone();
+            auth.auth_oauth2_issuer_url = self.auth_oauth2_issuer_url.clone();
+            auth.auth_oauth2_credentials_file = self.auth_oauth2_credentials_file.clone();
+            auth.auth_oauth2_audience = self.auth_oauth2_audience.clone();
+            auth.auth_oauth2_scope = self.auth_oauth2_scope.clone();
+            auth.auth_basic = self.auth_basic.clone();
+            auth.auth_basic_file = self.auth_basic_file.clone();
+        }
+
+        let tls = &mut opts.tls;
+        fill(&mut tls.tls_ca_cert, &self.tls_ca_cert);
+        if tls.tls_cert.is_none() && tls.tls_key.is_none() {
+            tls.tls_cert = self.tls_cert.clone();
+            tls.tls_key = self.tls_key.clone();
+        }
+        tls.tls_insecure |= self.tls_insecure.unwrap_or(false);
+        fill(
+            &mut tls.tls_hostname_verification,
+            &self.tls_hostname_verification,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    const PROFILE: &str = r#"
+        [profiles.staging]
+        url = "pulsar+ssl://pulsar.staging:6651"
+        admin_url = "https://pulsar.staging:8443"
+        tenant = "acme"
+        namespace = "orders"
+        auth_token = "profile-token"
+        tls_cert = "/profile/cert.pem"
+        tls_key = "/profile/key.pem"
+    "#;
+
+    fn applied(config: &str, profile: Option<&str>, args: &[&str]) -> Opts {
+        let config = Config::parse(config).unwrap();
+        let mut opts = Opts::from_iter_safe(
+            ["pulsar-cli"]
+                .iter()
+                .chain(args)
+                .chain(&["consume", "--topic", "t"]),
+        )
+        .unwrap();
+        if let Some(profile) = config.profile(profile).unwrap() {
+            profile.apply(&mut opts);
+        }
+        opts
+    }
+
+    fn urls(opts: &Opts) -> Vec<String> {
+        opts.url.iter().map(ToString::to_string).collect()
+    }
+
+    /// The only test touching the environment, which the tests share
+    #[test]
+    fn flags_then_environment_then_profile_then_defaults() {
+        for name in &["PULSAR_URL", "PULSAR_ADMIN_URL", "PULSAR_TENANT"] {
+            std::env::remove_var(name);
+        }
+        std::env::set_var("PULSAR_NAMESPACE", "from-env");
+        let opts = applied(PROFILE, Some("staging"), &["--tenant", "from-flag"]);
+        std::env::remove_var("PULSAR_NAMESPACE");
+        assert_eq!(opts.topics.tenant.as_deref(), Some("from-flag"));
+        assert_eq!(opts.topics.namespace.as_deref(), Some("from-env"));
+        assert_eq!(urls(&opts), vec!["pulsar+ssl://pulsar.staging:6651"]);
+        assert_eq!(
+            opts.admin.admin_url.as_ref().map(Url::as_str),
+            Some("https://pulsar.staging:8443/")
+        );
+
+        // Without a profile, what isn't given stays to its default
+        let opts = applied("", None, &[]);
+        assert!(opts.url.is_empty());
+        assert_eq!(opts.topics.tenant, None);
+        assert_eq!(opts.admin.admin_url, None);
+    }
+
+    #[test]
+    fn default_profile() {
+        let config = format!("{}\n[profiles.default]\ntenant = \"fallback\"\n", PROFILE);
+        let opts = applied(&config, None, &[]);
+        assert_eq!(opts.topics.tenant.as_deref(), Some("fallback"));
+        let opts = applied(&config, Some("staging"), &[]);
+        assert_eq!(opts.topics.tenant.as_deref(), Some("acme"));
+        assert!(Config::parse(&config)
+            .unwrap()
+            .profile(Some("prod"))
+            .is_err());
+    }
+
+    #[test]
+    fn urls_on_the_command_line_replace_those_of_the_profile() {
+        let opts = applied(
+            PROFILE,
+            Some("staging"),
+            &["--url", "pulsar://a:6650", "--url", "pulsar://b:6650"],
+        );
+        assert_eq!(urls(&opts), vec!["pulsar://a:6650", "pulsar://b:6650"]);
+    }
+
+    #[test]
+    fn authentication_applies_as_a_whole() {
+        let opts = applied(PROFILE, Some("staging"), &[]);
+        assert_eq!(opts.auth.auth_token.as_deref(), Some("profile-token"));
+        let opts = applied(
+            PROFILE,
+            Some("staging"),
+            &["--auth-token-file", "/flag/token"],
+        );
+        assert_eq!(opts.auth.auth_token, None);
+        assert_eq!(
+            opts.auth.auth_token_file,
+            Some(PathBuf::from("/flag/token"))
+        );
+    }
+
+    #[test]
+    fn client_certificate_applies_as_a_pair() {
+        let opts = applied(PROFILE, Some("staging"), &[]);
+        assert_eq!(opts.tls.tls_cert, Some(PathBuf::from("/profile/cert.pem")));
+        assert_eq!(opts.tls.tls_key, Some(PathBuf::from("/profile/key.pem")));
+        let opts = applied(
+            PROFILE,
+            Some("staging"),
+            &["--tls-cert", "/flag/cert.pem", "--tls-key", "/flag/key.pem"],
+        );
+        assert_eq!(opts.tls.tls_cert, Some(PathBuf::from("/flag/cert.pem")));
+        assert_eq!(opts.tls.tls_key, Some(PathBuf::from("/flag/key.pem")));
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        for config in &[
+            "[profiles.a]\nunknown = 1\n",
+            "[profiles.a]\ntopic_prefix = \"kafka://a/b\"\n",
+            "[profiles.a]\ntenant = \"a/b\"\n",
+        ] {
+            assert!(Config::parse(config).is_err(), "{}", config);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Confirmation of destructive operations: every command that deletes or discards something
+//! says what first, then asks on a terminal, refuses without one unless given `--yes`, and only
+//! says what it would have done with `--dry-run`
+
+use crate::exit::{self, ExitError};
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+};
+use structopt::StructOpt;
+
+static SETTINGS: OnceCell<ConfirmOpts> = OnceCell::new();
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "Whether destructive operations go ahead")]
+#[derive(Clone, Default, StructOpt)]
+pub struct ConfirmOpts {
+    /// Don't ask for confirmation of destructive operations, whichever the command
+    #[structopt(long = "yes")]
+    pub yes: bool,
+
+    /// Only print what a destructive operation would do, without doing it
+    #[structopt(long)]
+    pub dry_run: bool,
+}
+
+/// Makes `opts` apply to every `confirm` that follows
+pub fn set_opts(opts: &ConfirmOpts) {
+    // Only ever set once, before any command runs
+    let _ = SETTINGS.set(opts.clone());
+}
+
+/// Whether `--dry-run` was given
+pub fn is_dry_run() -> bool {
+    SETTINGS.get().map_or(false, |settings| settings.dry_run)
+}
+
+/// What a destructive operation would have done, with `--dry-run`. Ends the command, which
+/// then exits successfully
+#[derive(Debug)]
+pub struct DryRun(pub String);
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Dry run, nothing done: {}", self.0)
+    }
+}
+
+impl std::error::Error for DryRun {}
+
+/// The dry run that ended the command with `error`, if it was one
+pub fn dry_run(error: &anyhow::Error) -> Option<&DryRun> {
+    error.chain().find_map(|e| e.downcast_ref::<DryRun>())
+}
+
+/// Asks the user to confirm a destructive operation, described by `prompt` as what will be
+/// affected, failing if they decline. Without a TTY to ask on, the operation only proceeds
+/// when `yes` (the command's `--yes`) or the global `--yes` is given. With `--dry-run`, it
+/// never does
+pub fn confirm(prompt: &str, yes: bool) -> Result<()> {
+    let settings = SETTINGS.get().cloned().unwrap_or_default();
+    let stdin = io::stdin();
+    ask(
+        prompt,
+        yes,
+        &settings,
+        termion::is_tty(&stdin),
+        &mut stdin.lock(),
+    )
+}
+
+/// Confirms as `confirm` does, as `settings` say, asking on `terminal` (stdin) if it `is_tty`
+fn ask(
+    prompt: &str,
+    yes: bool,
+    settings: &ConfirmOpts,
+    is_tty: bool,
+    terminal: &mut impl BufRead,
+) -> Result<()> {
+    if settings.dry_run {
+        return Err(DryRun(prompt.to_owned()).into());
+    }
+    if yes || settings.yes {
+        return Ok(());
+    }
+    if !is_tty {
+        return Err(ExitError::new(
+            exit::REFUSED,
+            format!("{} Refusing to continue without --yes", prompt),
+        )
+        .into());
+    }
+
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    terminal.read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(ExitError::new(exit::REFUSED, "Aborted").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPT: &str = "Deleting persistent://t/ns/orders.";
+
+    /// Confirms with the command's `--yes` if `yes`, the global options `settings`, a terminal
+    /// or not, and `answer` typed on it
+    fn asked(yes: bool, settings: ConfirmOpts, is_tty: bool, answer: &str) -> Result<()> {
+        ask(PROMPT, yes, &settings, is_tty, &mut answer.as_bytes())
+    }
+
+    fn global_yes() -> ConfirmOpts {
+        ConfirmOpts {
+            yes: true,
+            dry_run: false,
+        }
+    }
+
+    fn dry_run_opts(yes: bool) -> ConfirmOpts {
+        ConfirmOpts { yes, dry_run: true }
+    }
+
+    fn assert_refused(result: Result<()>, message: &str) {
+        let error = result.unwrap_err();
+        assert_eq!(exit::code_for(&error), exit::REFUSED, "{}", error);
+        assert_eq!(error.to_string(), message);
+    }
+
+    #[test]
+    fn tty_asks() {
+        for answer in &["y\n", "Y\n", "yes\n", " YES \n"] {
+            assert!(
+                asked(false, ConfirmOpts::default(), true, answer).is_ok(),
+                "{:?}",
+                answer
+            );
+        }
+        for answer in &["n\n", "\n", "", "no\n", "yess\n"] {
+            assert_refused(
+                asked(false, ConfirmOpts::default(), true, answer),
+                "Aborted",
+            );
+        }
+    }
+
+    #[test]
+    fn no_tty_refuses() {
+        // Without reading anything
+        assert_refused(
+            asked(false, ConfirmOpts::default(), false, "y\n"),
+            "Deleting persistent://t/ns/orders. Refusing to continue without --yes",
+        );
+    }
+
+    #[test]
+    fn yes_doesnt_ask() {
+        for is_tty in &[true, false] {
+            assert!(asked(true, ConfirmOpts::default(), *is_tty, "n\n").is_ok());
+            assert!(asked(false, global_yes(), *is_tty, "n\n").is_ok());
+        }
+    }
+
+    #[test]
+    fn dry_run_never_proceeds() {
+        for is_tty in &[true, false] {
+            for (yes, settings) in &[
+                (false, dry_run_opts(false)),
+                (true, dry_run_opts(false)),
+                (false, dry_run_opts(true)),
+            ] {
+                let error = asked(*yes, settings.clone(), *is_tty, "y\n").unwrap_err();
+                assert_eq!(
+                    dry_run(&error).map(|dry_run| dry_run.0.as_str()),
+                    Some(PROMPT)
+                );
+            }
+        }
+    }
+}
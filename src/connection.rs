@@ -0,0 +1,729 @@
+//! Connecting to Pulsar over the binary protocol
+
+use crate::{
+    auth::{self, Auth, AuthenticationFailed},
+    connection_events::{self, Kind},
+    exit::{self, ExitError},
+    metrics::{self, STATS},
+    tls::TlsConfig,
+};
+use anyhow::{bail, format_err, Result};
+use futures::TryStreamExt;
+use itertools::Itertools;
+use log::{info, warn};
+use pulsar::{
+    consumer::Message,
+    producer,
+    proto::{CommandSendReceipt, MessageIdData},
+    ConnectionRetryOptions, Consumer, ConsumerOptions, OperationRetryOptions, Producer,
+    ProducerOptions, Pulsar, SubType, TokioExecutor,
+};
+use serde::{Deserialize, Deserializer};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+use url::Url;
+
+const SEND_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How many times a failed ack is tried again where there is no option to say so
+pub const ACK_RETRIES: usize = 3;
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "How long to wait for brokers and the admin API")]
+#[derive(StructOpt)]
+pub struct TimeoutOpts {
+    /// How long to wait for a connection to a broker or the admin API
+    #[structopt(long, env = "PULSAR_CONNECT_TIMEOUT", default_value = "10s")]
+    pub connect_timeout: humantime::Duration,
+
+    /// How long to wait for a broker or the admin API to answer, e.g. to acknowledge a sent
+    /// message, before trying again
+    #[structopt(long, env = "PULSAR_OPERATION_TIMEOUT", default_value = "30s")]
+    pub operation_timeout: humantime::Duration,
+
+    /// How often idle connections to brokers are pinged, e.g. so that load balancers don't
+    /// drop them
+    #[structopt(long, env = "PULSAR_KEEP_ALIVE_INTERVAL", default_value = "60s")]
+    pub keep_alive_interval: humantime::Duration,
+}
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "How connecting to brokers is retried")]
+#[derive(StructOpt)]
+pub struct RetryOpts {
+    /// How many times to try connecting, building consumers and producers, including on
+    /// reconnecting, and sending a message before giving up, 0 to try forever
+    #[structopt(long, env = "PULSAR_RETRY_MAX_ATTEMPTS", default_value = "10")]
+    pub retry_max_attempts: usize,
+
+    /// Delay before the first retry, doubling with each one
+    #[structopt(long, env = "PULSAR_RETRY_BASE_DELAY", default_value = "1s")]
+    pub retry_base_delay: humantime::Duration,
+
+    /// Longest delay between retries
+    #[structopt(long, env = "PULSAR_RETRY_MAX_DELAY", default_value = "30s")]
+    pub retry_max_delay: humantime::Duration,
+
+    /// Randomize the delays between retries, so that many instances don't retry in lockstep
+    /// [env: PULSAR_RETRY_JITTER]
+    #[structopt(long)]
+    pub retry_jitter: bool,
+}
+
+impl RetryOpts {
+    fn policy(&self) -> again::RetryPolicy {
+        let max_retries = match self.retry_max_attempts {
+            0 => usize::MAX,
+            attempts => attempts - 1,
+        };
+        again::RetryPolicy::exponential(self.retry_base_delay.into())
+            .with_max_delay(self.retry_max_delay.into())
+            .with_max_retries(max_retries)
+            .with_jitter(self.retry_jitter)
+    }
+}
+
+/// The service URLs of a cluster, e.g. of several proxies in front of it, which connections
+/// try in order. Clones share which one is in use, so that reconnecting starts from the one
+/// that last worked
+#[derive(Clone)]
+pub struct ServiceUrls {
+    urls: Vec<Url>,
+    current: Arc<AtomicUsize>,
+}
+
+impl ServiceUrls {
+    /// The URLs given with several options, e.g. `--url` given more than once
+    pub fn concat(list: &[ServiceUrls]) -> Option<Self> {
+        if list.is_empty() {
+            return None;
+        }
+        Some(Self {
+            urls: list.iter().flat_map(|urls| urls.urls.clone()).collect(),
+            current: Default::default(),
+        })
+    }
+
+    /// The URL connections are attempted to first
+    pub fn first(&self) -> &Url {
+        &self.urls[0]
+    }
+}
+
+impl FromStr for ServiceUrls {
+    type Err = anyhow::Error;
+
+    /// Parses a comma-separated list of URLs
+    fn from_str(s: &str) -> Result<Self> {
+        let urls = s
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .map(|url| Url::parse(url).map_err(|e| format_err!("Invalid URL {:?}: {}", url, e)))
+            .collect::<Result<Vec<_>>>()?;
+        if urls.is_empty() {
+            bail!("No URL in {:?}", s);
+        }
+        Ok(Self {
+            urls,
+            current: Default::default(),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ServiceUrls {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for ServiceUrls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.urls.iter().join(","))
+    }
+}
+
+/// What to build a consumer for
+pub struct ConsumerParams<'a> {
+    pub topic: &'a str,
+    pub subscription: &'a str,
+    pub consumer_name: &'a str,
+    pub sub_type: SubType,
+    pub options: ConsumerOptions,
+}
+
+/// Makes connections to Pulsar clusters, applying the same retry policy, TLS and
+/// authentication settings whichever cluster URL they are made to
+pub struct Connector {
+    /// The clients consumers and producers are built with, by service URLs, so that those of
+    /// the same cluster share one
+    clients: Mutex<HashMap<String, Pulsar<TokioExecutor>>>,
+    retry_policy: again::RetryPolicy,
+    tls: TlsConfig,
+    auth: Auth,
+    /// The proxy of the admin API, which connections to brokers can't go through
+    proxy: Option<Url>,
+    connect_timeout: Duration,
+    operation_timeout: Duration,
+    keep_alive_interval: Duration,
+}
+
+impl Connector {
+    /// A connector yet to connect to anything, retrying by `retry` and timing out by `timeouts`.
+    /// With `proxy` it refuses to connect, brokers not being reachable through it
+    pub fn new(
+        retry: &RetryOpts,
+        tls: TlsConfig,
+        auth: Auth,
+        proxy: Option<Url>,
+        timeouts: &TimeoutOpts,
+    ) -> Self {
+        Self {
+            clients: Default::default(),
+            retry_policy: retry.policy(),
+            tls,
+            auth,
+            proxy,
+            connect_timeout: timeouts.connect_timeout.into(),
+            operation_timeout: timeouts.operation_timeout.into(),
+            keep_alive_interval: timeouts.keep_alive_interval.into(),
+        }
+    }
+
+    /// How long a broker has to answer, from `--operation-timeout`
+    pub fn operation_timeout(&self) -> Duration {
+        self.operation_timeout
+    }
+
+    /// Runs `task` until it succeeds or the retry policy gives up, failing with its last
+    /// error then. Usage errors, which trying again can't fix, fail right away
+    pub async fn retry<T, F, Fut>(&self, what: &str, mut task: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let attempts = AtomicUsize::new(0);
+        let retriable = |e: &anyhow::Error| exit::code_for(e) != exit::USAGE;
+        self.retry_policy
+            .retry_if(
+                || {
+                    let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+                    if attempt > 0 {
+                        connection_events::record(
+                            Kind::Retry,
+                            format!("{} (attempt {})", what, attempt + 1),
+                            None,
+                        );
+                    }
+                    task()
+                },
+                retriable,
+            )
+            .await
+            .map_err(|e| {
+                if !retriable(&e) {
+                    return e;
+                }
+                e.context(format!(
+                    "Gave up {} after {} attempts",
+                    what,
+                    attempts.load(Ordering::Relaxed)
+                ))
+            })
+    }
+
+    /// Sends `message` until the broker acknowledges it within the operation timeout or the
+    /// retry policy gives up
+    pub async fn send_with_retry(
+        &self,
+        producer: &mut Producer<TokioExecutor>,
+        topic: &str,
+        message: producer::Message,
+    ) -> Result<CommandSendReceipt> {
+        let producer = tokio::sync::Mutex::new(producer);
+        self.retry(&format!("publishing to {}", topic), || async {
+            let mut producer = producer.lock().await;
+            send(&mut **producer, message.clone(), self.operation_timeout)
+                .await
+                .map_err(|e| {
+                    info!("Error publishing message to {}: {:?} ", topic, e);
+                    e
+                })
+        })
+        .await
+    }
+
+    /// Connects to the first of `urls` that can be reached, starting from the one in use
+    pub async fn connect(&self, urls: &ServiceUrls) -> Result<Pulsar<TokioExecutor>> {
+        self.refuse_proxy(urls)?;
+        self.refuse_identity(urls)?;
+        let start = urls.current.load(Ordering::Relaxed);
+        let mut last_error = None;
+        for offset in 0..urls.urls.len() {
+            let index = (start + offset) % urls.urls.len();
+            let url = &urls.urls[index];
+            let started = Instant::now();
+            let result = self.connect_to(url).await;
+            match &result {
+                Ok(_) => connection_events::record(Kind::Connected, url, Some(started.elapsed())),
+                Err(e) => connection_events::record(
+                    Kind::ConnectFailed,
+                    format!("{}: {:#}", url, e),
+                    Some(started.elapsed()),
+                ),
+            }
+            match result {
+                Ok(pulsar) => {
+                    if index != start {
+                        warn!("Failed over from {} to {}", urls.urls[start], url);
+                        connection_events::record(
+                            Kind::FailedOver,
+                            format!("from {} to {}", urls.urls[start], url),
+                            None,
+                        );
+                        urls.current.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(pulsar);
+                }
+                // Credentials refused by one of them would be by the others as well
+                Err(e) if e.downcast_ref::<AuthenticationFailed>().is_some() => return Err(e),
+                Err(e) => {
+                    if urls.urls.len() > 1 {
+                        warn!("{}", e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    /// Refuses to connect to `urls` when it would be without the client certificate that was
+    /// asked for: the Pulsar client library can't present one, and connecting without it
+    /// would authenticate some other way than expected
+    fn refuse_identity(&self, urls: &ServiceUrls) -> Result<()> {
+        if self.tls.identity.is_none() {
+            return Ok(());
+        }
+        if let Some(url) = urls.urls.iter().find(|url| url.scheme() == "pulsar+ssl") {
+            return Err(ExitError::new(
+                exit::USAGE,
+                format!(
+                    "--tls-cert is only used for the admin API, the client library can't present it to {}: authenticate to brokers another way",
+                    url
+                ),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Refuses to connect to `urls` when a proxy was asked for: the Pulsar client library can't
+    /// connect through one, and connecting directly would bypass it
+    fn refuse_proxy(&self, urls: &ServiceUrls) -> Result<()> {
+        match &self.proxy {
+            Some(proxy) => Err(ExitError::new(
+                exit::USAGE,
+                format!(
+                    "--proxy only applies to the admin API, the client library can't reach {} through {}: forward a port to the brokers or a Pulsar proxy instead",
+                    urls, proxy
+                ),
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The client for `urls`, connecting if there isn't one yet
+    pub async fn client(&self, urls: &ServiceUrls) -> Result<Pulsar<TokioExecutor>> {
+        let key = urls.to_string();
+        let cached = self.clients.lock().unwrap().get(&key).cloned();
+        if let Some(pulsar) = cached {
+            return Ok(pulsar);
+        }
+        let pulsar = self.connect(urls).await?;
+        self.clients.lock().unwrap().insert(key, pulsar.clone());
+        Ok(pulsar)
+    }
+
+    /// Drops the client for `urls` after a failure, so that the next one connects again,
+    /// possibly failing over to another of them
+    pub fn forget(&self, urls: &ServiceUrls) {
+        self.clients.lock().unwrap().remove(&urls.to_string());
+    }
+
+    async fn connect_to(&self, url: &Url) -> Result<Pulsar<TokioExecutor>> {
+        match self.try_connect_to(url).await {
+            Err(e)
+                if e.downcast_ref::<AuthenticationFailed>().is_some()
+                    && self.auth.forget_rejected().await =>
+            {
+                self.try_connect_to(url).await
+            }
+            result => result,
+        }
+    }
+
+    async fn try_connect_to(&self, url: &Url) -> Result<Pulsar<TokioExecutor>> {
+        let mut builder = Pulsar::builder(url.as_str(), TokioExecutor)
+            .with_connection_retry_options(ConnectionRetryOptions {
+                connection_timeout: self.connect_timeout,
+                keep_alive: self.keep_alive_interval,
+                ..Default::default()
+            })
+            .with_operation_retry_options(OperationRetryOptions {
+                operation_timeout: self.operation_timeout,
+                ..Default::default()
+            })
+            .with_allow_insecure_connection(self.tls.insecure)
+            .with_tls_hostname_verification_enabled(self.tls.hostname_verification);
+        if let Some(ca_chain) = &self.tls.ca_chain {
+            builder = builder.with_certificate_chain(ca_chain.clone());
+        }
+        builder = self.auth.apply(builder).await?;
+        // The client library retries on its own, the whole attempt is bounded so that an
+        // unreachable broker is reported rather than waited for
+        match tokio::time::timeout(self.connect_timeout, builder.build()).await {
+            Ok(Ok(pulsar)) => Ok(pulsar),
+            Ok(Err(e)) if auth::is_rejected(&e) => {
+                Err(self.auth.rejected_by(url.as_str(), e).into())
+            }
+            Ok(Err(e)) => Err(ExitError::new(
+                exit::pulsar_code(&e),
+                format!(
+                    "Failed connecting to {} ({}): {}",
+                    url,
+                    resolved_addresses(url).await,
+                    e
+                ),
+            )
+            .into()),
+            Err(_) => Err(ExitError::new(
+                exit::TIMEOUT,
+                format!(
+                    "Timed out after {} connecting to {} ({})",
+                    humantime::format_duration(self.connect_timeout),
+                    url,
+                    resolved_addresses(url).await
+                ),
+            )
+            .into()),
+        }
+    }
+
+    /// Connects to the Pulsar cluster at `urls` and builds a consumer, retrying according to
+    /// the retry policy
+    pub async fn build_consumer(
+        &self,
+        urls: &ServiceUrls,
+        params: &ConsumerParams<'_>,
+    ) -> Result<Consumer<Vec<u8>, TokioExecutor>> {
+        self.build_multi_topic_consumer(urls, &[params.topic.to_owned()], params)
+            .await
+    }
+
+    /// Like `build_consumer`, subscribing to all of `topics` at once, `params.topic` only naming
+    /// them in messages
+    pub async fn build_multi_topic_consumer(
+        &self,
+        urls: &ServiceUrls,
+        topics: &[String],
+        params: &ConsumerParams<'_>,
+    ) -> Result<Consumer<Vec<u8>, TokioExecutor>> {
+        // Not worth retrying
+        self.refuse_proxy(urls)?;
+        self.refuse_identity(urls)?;
+        let started = Instant::now();
+        self.retry(&format!("subscribing to {}", params.topic), || async {
+            self.client(urls)
+                .await
+                .map_err(|e| {
+                    log::error!("Failed connecting to Pulsar: {:?}", e);
+                    e
+                })?
+                .consumer()
+                .with_consumer_name(params.consumer_name)
+                .with_subscription(params.subscription)
+                .with_subscription_type(params.sub_type)
+                .with_topics(topics)
+                .with_options(params.options.clone())
+                .build::<Vec<u8>>()
+                .await
+                .map_err(|e| {
+                    log::error!("Error trying to connect: {:?}. Retrying...", e);
+                    self.forget(urls);
+                    broker_error(e, params.topic)
+                })
+        })
+        .await
+        .map(|consumer| {
+            connection_events::record(Kind::Subscribed, params.topic, Some(started.elapsed()));
+            consumer
+        })
+    }
+
+    /// Connects to the Pulsar cluster at `urls` and builds a producer for `topic`, retrying
+    /// according to the retry policy
+    pub async fn build_producer(
+        &self,
+        urls: &ServiceUrls,
+        topic: &str,
+        name: Option<&str>,
+    ) -> Result<Producer<TokioExecutor>> {
+        self.build_producer_with_options(urls, topic, name, ProducerOptions::default())
+            .await
+    }
+
+    /// Like `build_producer`, with e.g. batching or compression set in `options`
+    pub async fn build_producer_with_options(
+        &self,
+        urls: &ServiceUrls,
+        topic: &str,
+        name: Option<&str>,
+        options: ProducerOptions,
+    ) -> Result<Producer<TokioExecutor>> {
+        self.refuse_proxy(urls)?;
+        self.refuse_identity(urls)?;
+        let started = Instant::now();
+        self.retry(&format!("creating a producer for {}", topic), || async {
+            let mut builder = self
+                .client(urls)
+                .await?
+                .producer()
+                .with_topic(topic)
+                .with_options(options.clone());
+            if let Some(name) = name {
+                builder = builder.with_name(name);
+            }
+            builder.build().await.map_err(|e| {
+                log::error!(
+                    "Error creating producer for {}: {:?}. Retrying...",
+                    topic,
+                    e
+                );
+                self.forget(urls);
+                broker_error(e, topic)
+            })
+        })
+        .await
+        .map(|producer| {
+            connection_events::record(Kind::ProducerCreated, topic, Some(started.elapsed()));
+            producer
+        })
+    }
+}
+
+/// `e`, from subscribing to or producing on `topic` once connected to the service URL, with a
+/// hint when the broker serving the topic is the one that couldn't be reached
+fn broker_error(e: pulsar::Error, topic: &str) -> anyhow::Error {
+    let code = exit::pulsar_code(&e);
+    if code != exit::UNREACHABLE && code != exit::TIMEOUT {
+        return e.into();
+    }
+    ExitError::new(
+        code,
+        format!(
+            "{} (the service URL answered but the broker serving {} didn't; brokers advertising several listeners may have returned an address only routable from their own network, use the URL of a Pulsar proxy then)",
+            e, topic
+        ),
+    )
+    .into()
+}
+
+/// The addresses the host of `url` resolves to, to tell which one a failed connection was made
+/// to
+async fn resolved_addresses(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port().unwrap_or(if url.scheme() == "pulsar+ssl" {
+        6651
+    } else {
+        6650
+    });
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(addresses) => addresses.map(|address| address.to_string()).join(", "),
+        Err(e) => format!("failed resolving {}: {}", host, e),
+    }
+}
+
+/// The consumers of a consume session: the one it started with and, with `--all-topics`, one
+/// more for each batch of topics discovered since. Consumers are told apart by their index
+pub struct ConsumerSet {
+    consumers: Vec<(Vec<String>, Consumer<Vec<u8>, TokioExecutor>)>,
+}
+
+impl ConsumerSet {
+    /// A set with only `consumer`, subscribed to `topics`
+    pub fn new(topics: Vec<String>, consumer: Consumer<Vec<u8>, TokioExecutor>) -> Self {
+        Self {
+            consumers: vec![(topics, consumer)],
+        }
+    }
+
+    /// The next message of any of the consumers, with the index of the one it came from
+    pub async fn next(&mut self) -> (usize, Result<Option<Message<Vec<u8>>>, pulsar::Error>) {
+        let (next, i, _) = futures::future::select_all(
+            self.consumers
+                .iter_mut()
+                .map(|(_, consumer)| consumer.try_next()),
+        )
+        .await;
+        (i, next)
+    }
+
+    /// The consumer at index `i`, e.g. to ack a message `next` returned with it
+    pub fn get(&mut self, i: usize) -> &mut Consumer<Vec<u8>, TokioExecutor> {
+        &mut self.consumers[i].1
+    }
+
+    /// Subscribes `topics` with a consumer of their own
+    pub async fn add(
+        &mut self,
+        connector: &Connector,
+        urls: &ServiceUrls,
+        topics: Vec<String>,
+        params: &ConsumerParams<'_>,
+    ) -> Result<()> {
+        let consumer = connector
+            .build_multi_topic_consumer(urls, &topics, params)
+            .await?;
+        self.consumers.push((topics, consumer));
+        Ok(())
+    }
+
+    /// Replaces consumer `i` with a new one for the same topics
+    pub async fn rebuild(
+        &mut self,
+        i: usize,
+        connector: &Connector,
+        urls: &ServiceUrls,
+        params: &ConsumerParams<'_>,
+    ) -> Result<()> {
+        let (topics, consumer) = &mut self.consumers[i];
+        *consumer = connector
+            .build_multi_topic_consumer(urls, topics, params)
+            .await?;
+        Ok(())
+    }
+
+    /// Replaces every consumer, e.g. when their connection may have been dropped
+    pub async fn rebuild_all(
+        &mut self,
+        connector: &Connector,
+        urls: &ServiceUrls,
+        params: &ConsumerParams<'_>,
+    ) -> Result<()> {
+        for i in 0..self.consumers.len() {
+            self.rebuild(i, connector, urls, params).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Ticks every `interval`, the first time right away with `immediate`. Falling behind (e.g.
+/// while a full queue blocks) delays the following ticks rather than bursting to catch up
+pub fn pace(interval: Duration, immediate: bool) -> tokio::time::Interval {
+    let first = if immediate {
+        tokio::time::Instant::now()
+    } else {
+        tokio::time::Instant::now() + interval
+    };
+    let mut ticks = tokio::time::interval_at(first, interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticks
+}
+
+/// Sends `message`, failing if the broker doesn't acknowledge it within `timeout`
+pub async fn send(
+    producer: &mut Producer<TokioExecutor>,
+    message: producer::Message,
+    timeout: Duration,
+) -> Result<CommandSendReceipt> {
+    tokio::time::timeout(timeout, async { producer.send(message).await?.await })
+        .await
+        .map_err(|_| ExitError::new(exit::TIMEOUT, "Timeout"))?
+        .map_err(anyhow::Error::from)
+}
+
+/// Acks `message` (and with `cumulative`, every message before it on its partition), trying
+/// again up to `retries` times. Each failure is counted, whether it is tried again or not
+pub async fn ack_with_retry(
+    consumer: &mut Consumer<Vec<u8>, TokioExecutor>,
+    message: &Message<Vec<u8>>,
+    cumulative: bool,
+    retries: usize,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let result = if cumulative {
+            consumer.cumulative_ack(message).await
+        } else {
+            consumer.ack(message).await
+        };
+        match result {
+            Ok(()) => {
+                metrics::add(&STATS.acked, 1);
+                return Ok(());
+            }
+            Err(e) => ack_failed(&message.topic, &mut attempt, retries, e).await?,
+        }
+    }
+}
+
+/// Acks the message of `topic` with ID `id`, e.g. one no longer at hand, trying again up to
+/// `retries` times like `ack_with_retry`
+pub async fn ack_id_with_retry(
+    consumer: &mut Consumer<Vec<u8>, TokioExecutor>,
+    topic: &str,
+    id: &MessageIdData,
+    retries: usize,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match consumer.ack_with_id(topic, id.clone()).await {
+            Ok(()) => {
+                metrics::add(&STATS.acked, 1);
+                return Ok(());
+            }
+            Err(e) => ack_failed(topic, &mut attempt, retries, e).await?,
+        }
+    }
+}
+
+/// Counts a failed ack, failing once there were `retries` attempts after the first, and
+/// waiting before the next one otherwise
+async fn ack_failed(
+    topic: &str,
+    attempt: &mut usize,
+    retries: usize,
+    e: impl fmt::Display,
+) -> Result<()> {
+    metrics::add(&STATS.ack_failures, 1);
+    if *attempt >= retries {
+        return Err(format_err!(
+            "Failed acking a message of {} after {} attempts: {}",
+            topic,
+            *attempt + 1,
+            e
+        ));
+    }
+    *attempt += 1;
+    warn!("Failed acking a message of {}: {}. Retrying...", topic, e);
+    connection_events::record(
+        Kind::Retry,
+        format!("acking a message of {}: {}", topic, e),
+        None,
+    );
+    tokio::time::sleep(SEND_RETRY_DELAY).await;
+    Ok(())
+}
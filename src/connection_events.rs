@@ -0,0 +1,161 @@
+//! The timeline of connections to Pulsar for `--show-connection-events`: connecting to a
+//! service URL, failing over, subscribing, creating producers, the attempts retried and the
+//! disconnects consume reconnects after, each printed to stderr as it happens with
+//! when and how long it took, and counted for the summary. Nothing is recorded otherwise
+//!
+//! The client library doesn't expose its own connection state (e.g. lookup redirects), so
+//! disconnects are those pulsar-cli notices: a failed receive, send or ack, or silence past
+//! `--liveness-timeout`
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use std::{
+    fmt, io,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+use termion::color;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Printing JSON lines rather than text, with `--log-format json`
+static JSON: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Connected,
+    ConnectFailed,
+    FailedOver,
+    Subscribed,
+    ProducerCreated,
+    Retry,
+    Disconnected,
+}
+
+const KINDS: &[Kind] = &[
+    Kind::Connected,
+    Kind::ConnectFailed,
+    Kind::FailedOver,
+    Kind::Subscribed,
+    Kind::ProducerCreated,
+    Kind::Retry,
+    Kind::Disconnected,
+];
+
+/// By `Kind`, in the same order
+static COUNTS: [AtomicU64; 7] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Connected => "connected",
+            Kind::ConnectFailed => "connect-failed",
+            Kind::FailedOver => "failed-over",
+            Kind::Subscribed => "subscribed",
+            Kind::ProducerCreated => "producer-created",
+            Kind::Retry => "retry",
+            Kind::Disconnected => "disconnected",
+        }
+    }
+
+    fn is_failure(self) -> bool {
+        matches!(
+            self,
+            Kind::ConnectFailed | Kind::FailedOver | Kind::Retry | Kind::Disconnected
+        )
+    }
+}
+
+/// Starts recording events, `json` printing them as JSON lines
+pub fn enable(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Records that `kind` happened to `what`, e.g. a topic or service URL, which took `took`
+pub fn record(kind: Kind, what: impl fmt::Display, took: Option<Duration>) {
+    if !is_enabled() {
+        return;
+    }
+    COUNTS[kind as usize].fetch_add(1, Ordering::Relaxed);
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    if JSON.load(Ordering::Relaxed) {
+        eprintln!(
+            "{}",
+            json!({
+                "timestamp": time,
+                "connectionEvent": kind.name(),
+                "what": what.to_string(),
+                "millis": took.map(|took| took.as_millis() as u64),
+            })
+        );
+        return;
+    }
+    let took = took
+        .map(|took| format!(" ({}ms)", took.as_millis()))
+        .unwrap_or_default();
+    if termion::is_tty(&io::stderr()) {
+        let (start, end) = if kind.is_failure() {
+            (
+                color::Fg(color::Red).to_string(),
+                color::Fg(color::Reset).to_string(),
+            )
+        } else {
+            (
+                color::Fg(color::Cyan).to_string(),
+                color::Fg(color::Reset).to_string(),
+            )
+        };
+        eprintln!(
+            "{}{} {:<16} {}{}{}",
+            start,
+            time,
+            kind.name(),
+            what,
+            took,
+            end
+        );
+    } else {
+        eprintln!("{} {:<16} {}{}", time, kind.name(), what, took);
+    }
+}
+
+/// How many events of each kind there were, as text and as JSON, if they are recorded
+pub fn summary() -> Option<(String, Value)> {
+    if !is_enabled() {
+        return None;
+    }
+    let counts = KINDS
+        .iter()
+        .map(|kind| (kind.name(), COUNTS[*kind as usize].load(Ordering::Relaxed)))
+        .filter(|(_, count)| *count > 0)
+        .collect::<Vec<_>>();
+    let line = if counts.is_empty() {
+        "Connection events: none".to_owned()
+    } else {
+        format!(
+            "Connection events: {}",
+            counts
+                .iter()
+                .map(|(name, count)| format!("{} {}", count, name))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    };
+    let json = counts
+        .into_iter()
+        .map(|(name, count)| (name.to_owned(), Value::from(count)))
+        .collect::<Map<_, _>>();
+    Some((line, json.into()))
+}
@@ -0,0 +1,207 @@
+//! Messages read from CSV or TSV rows on stdin, for `produce --stdin-format`: each row is a
+//! message, the header row naming the columns that become its payload, properties and key
+
+use crate::exit::{self, ExitError};
+use anyhow::{format_err, Result};
+use itertools::Itertools;
+use serde_json::{Map, Value};
+use std::{collections::HashMap, io, str::FromStr, thread};
+use structopt::StructOpt;
+use tokio::sync::mpsc;
+
+/// Rows read ahead of publishing
+const ROW_QUEUE_SIZE: usize = 100;
+
+#[derive(StructOpt)]
+pub struct CsvOpts {
+    /// Publish a message for each row of stdin, in this format, instead of generated messages
+    #[structopt(long, possible_values = &["csv", "tsv"], conflicts_with = "from-url")]
+    pub stdin_format: Option<StdinFormat>,
+
+    /// Column of the rows of stdin that is the payload
+    #[structopt(long, requires = "stdin-format", conflicts_with = "payload")]
+    payload_column: Option<String>,
+
+    /// Payload made of the rows of stdin instead of a single column: rest-as-json for the
+    /// columns that aren't properties or the key, as a JSON object
+    #[structopt(long, requires = "stdin-format", possible_values = &["rest-as-json"])]
+    payload: Option<PayloadColumns>,
+
+    /// Column of the rows of stdin that becomes a property of the same name
+    #[structopt(long = "prop-column", requires = "stdin-format")]
+    prop_columns: Vec<String>,
+
+    /// Column of the rows of stdin that is the partition key
+    #[structopt(long, requires = "stdin-format")]
+    key_column: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+pub enum StdinFormat {
+    Csv,
+    Tsv,
+}
+
+impl FromStr for StdinFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(StdinFormat::Csv),
+            "tsv" => Ok(StdinFormat::Tsv),
+            other => Err(format_err!("Invalid stdin format: {:?}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum PayloadColumns {
+    RestAsJson,
+}
+
+impl FromStr for PayloadColumns {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rest-as-json" => Ok(PayloadColumns::RestAsJson),
+            other => Err(format_err!("Invalid payload: {:?}", other)),
+        }
+    }
+}
+
+/// Where the payload of a row comes from
+enum Payload {
+    Column(usize),
+    /// The columns that aren't properties or the key, with their names
+    RestAsJson(Vec<(String, usize)>),
+}
+
+pub struct CsvSource {
+    records: csv::StringRecordsIntoIter<io::Stdin>,
+    payload: Payload,
+    properties: Vec<(String, usize)>,
+    key: Option<usize>,
+}
+
+impl CsvSource {
+    /// Reads the header row of stdin, failing if it lacks one of the columns of `opts`
+    pub fn new(format: StdinFormat, opts: &CsvOpts) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(match format {
+                StdinFormat::Csv => b',',
+                StdinFormat::Tsv => b'\t',
+            })
+            .from_reader(io::stdin());
+        let headers = reader
+            .headers()
+            .map_err(|e| format_err!("Failed reading the header row of stdin: {}", e))?
+            .clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| {
+                    ExitError::new(
+                        exit::USAGE,
+                        format!(
+                            "No column {:?} in the header row of stdin, which has {}",
+                            name,
+                            headers.iter().map(|h| format!("{:?}", h)).join(", ")
+                        ),
+                    )
+                })
+        };
+
+        let properties = opts
+            .prop_columns
+            .iter()
+            .map(|name| Ok((name.clone(), column(name)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let key = opts.key_column.as_deref().map(column).transpose()?;
+        let payload = match (&opts.payload_column, opts.payload) {
+            (Some(name), _) => Payload::Column(column(name)?),
+            (None, Some(PayloadColumns::RestAsJson)) => Payload::RestAsJson(
+                headers
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| {
+                        Some(*i) != key && properties.iter().all(|(_, property)| property != i)
+                    })
+                    .map(|(i, name)| (name.to_owned(), i))
+                    .collect(),
+            ),
+            (None, None) => {
+                return Err(ExitError::new(
+                    exit::USAGE,
+                    "--stdin-format needs --payload-column <column> or --payload rest-as-json",
+                )
+                .into())
+            }
+        };
+
+        Ok(Self {
+            records: reader.into_records(),
+            payload,
+            properties,
+            key,
+        })
+    }
+
+    /// Reads the rows from a thread of their own, so that waiting on stdin doesn't hold up
+    /// anything else, e.g. Ctrl-C. The messages end at the end of stdin or after the first
+    /// invalid row
+    pub fn spawn(
+        mut self,
+        properties: HashMap<String, String>,
+    ) -> mpsc::Receiver<Result<pulsar::producer::Message>> {
+        let (sender, receiver) = mpsc::channel(ROW_QUEUE_SIZE);
+        // Not a blocking task of the runtime, which would wait for stdin on shutting down
+        thread::spawn(move || {
+            while let Some(row) = self.next(&properties).transpose() {
+                let invalid = row.is_err();
+                if sender.blocking_send(row).is_err() || invalid {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// The message of the next row, with `properties` besides those of its columns, or `None`
+    /// at the end of stdin
+    fn next(
+        &mut self,
+        properties: &HashMap<String, String>,
+    ) -> Result<Option<pulsar::producer::Message>> {
+        let record = match self.records.next() {
+            Some(record) => record.map_err(|e| {
+                ExitError::new(exit::INVALID_DATA, format!("Invalid row on stdin: {}", e))
+            })?,
+            None => return Ok(None),
+        };
+        // Rows have as many fields as the header row, or reading them fails
+        let field = |i: usize| record.get(i).unwrap_or_default().to_owned();
+
+        let payload = match &self.payload {
+            Payload::Column(i) => field(*i).into_bytes(),
+            Payload::RestAsJson(columns) => {
+                let object = columns
+                    .iter()
+                    .map(|(name, i)| (name.clone(), Value::String(field(*i))))
+                    .collect::<Map<_, _>>();
+                serde_json::to_vec(&object)?
+            }
+        };
+        let mut properties = properties.clone();
+        for (name, i) in self.properties.iter() {
+            properties.insert(name.clone(), field(*i));
+        }
+        Ok(Some(pulsar::producer::Message {
+            payload,
+            properties,
+            partition_key: self.key.map(field),
+            ..Default::default()
+        }))
+    }
+}
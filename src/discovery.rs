@@ -0,0 +1,56 @@
+//! Finding the topics of a namespace as they are created, for `consume --all-topics`
+
+use crate::{admin::AdminClient, topic_name::NamespaceName};
+use anyhow::Result;
+use log::warn;
+use std::collections::BTreeSet;
+
+pub struct TopicDiscovery {
+    namespace: NamespaceName,
+    max_topics: usize,
+    known: BTreeSet<String>,
+    /// Whether topics were left out for --max-topics, only warned about once
+    capped: bool,
+}
+
+impl TopicDiscovery {
+    pub fn new(namespace: NamespaceName, max_topics: usize) -> Self {
+        Self {
+            namespace,
+            max_topics,
+            known: BTreeSet::new(),
+            capped: false,
+        }
+    }
+
+    /// The persistent topics of the namespace not found by the previous calls, as many as fit
+    /// under `max_topics` with those
+    pub async fn discover(&mut self, admin: &AdminClient) -> Result<Vec<String>> {
+        let topics = admin.top_level_topics(&self.namespace, true).await?;
+        let new = topics
+            .into_iter()
+            .filter(|topic| !self.known.contains(topic))
+            .collect::<Vec<_>>();
+        let room = self.max_topics.saturating_sub(self.known.len());
+        if new.len() > room && !self.capped {
+            warn!(
+                "{} has more than --max-topics {} topics, not consuming {} of them",
+                self.namespace,
+                self.max_topics,
+                new.len() - room
+            );
+            self.capped = true;
+        }
+        let new = new.into_iter().take(room).collect::<Vec<_>>();
+        self.known.extend(new.iter().cloned());
+        Ok(new)
+    }
+
+    pub fn namespace(&self) -> &NamespaceName {
+        &self.namespace
+    }
+
+    pub fn count(&self) -> usize {
+        self.known.len()
+    }
+}
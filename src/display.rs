@@ -0,0 +1,355 @@
+//! Printing of messages, shared by every command that shows message contents, whether they were
+//! received by a consumer or fetched through the admin API
+
+use crate::{output, xml};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use colored_json::to_colored_json_auto;
+use log::warn;
+use pulsar::consumer::Message;
+use serde_json::{json, Map, Value};
+use std::{convert::TryFrom, fmt, ops::RangeInclusive};
+use termion::color;
+
+/// Timestamps outside of these years are taken to be corrupt or in another unit than
+/// milliseconds, e.g. microseconds
+const PLAUSIBLE_YEARS: RangeInclusive<i32> = 1990..=2999;
+
+/// A message as it is displayed
+pub struct DisplayMessage<'a> {
+    /// The event time of the message if it has one, its publish time otherwise
+    pub time: Timestamp,
+    /// The topic of the message, when it is known
+    pub topic: Option<&'a str>,
+    pub properties: Vec<(&'a str, &'a str)>,
+    /// What the broker knows of the entry the message is part of, only shown with
+    /// `--show-entry-metadata`
+    pub entry: Vec<(&'static str, String)>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> DisplayMessage<'a> {
+    /// A message received by a consumer
+    pub fn from_message(message: &'a Message<Vec<u8>>) -> Self {
+        let metadata = message.metadata();
+        let id = &message.message_id.id;
+        let mut entry = vec![
+            ("id", format!("{}:{}", id.ledger_id, id.entry_id)),
+            ("producer", metadata.producer_name.clone()),
+            ("sequence_id", metadata.sequence_id.to_string()),
+            (
+                "publish_time",
+                Timestamp::from_millis(metadata.publish_time).to_string(),
+            ),
+        ];
+        if let Some(batch_size) = message.message_id.batch_size {
+            entry.push((
+                "batch",
+                format!("{}/{}", id.batch_index.unwrap_or(0) + 1, batch_size),
+            ));
+        }
+        if let Some(key) = &metadata.partition_key {
+            entry.push(("key", key.clone()));
+        }
+        if let Some(cluster) = &metadata.replicated_from {
+            entry.push(("replicated_from", cluster.clone()));
+        }
+        DisplayMessage {
+            time: Timestamp::from_millis(metadata.event_time.unwrap_or(metadata.publish_time)),
+            topic: Some(&message.topic),
+            properties: properties(message),
+            entry,
+            payload: &message.payload.data,
+        }
+    }
+
+    /// The message as a single JSON object, e.g. for a line of `consume --sink`. The payload
+    /// is embedded as JSON if it is JSON, as text otherwise
+    pub fn to_json(&self) -> Value {
+        let payload = serde_json::from_slice::<Value>(self.payload)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(self.payload).into_owned()));
+        json!({
+            "time": self.time.to_string(),
+            "topic": self.topic,
+            "properties": self
+                .properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), Value::from(*value)))
+                .collect::<Map<_, _>>(),
+            "entry": self
+                .entry
+                .iter()
+                .map(|(key, value)| (key.to_string(), Value::from(value.as_str())))
+                .collect::<Map<_, _>>(),
+            "payload": payload,
+        })
+    }
+}
+
+/// The value of property `key` of a consumed message, as `properties` has it
+pub fn property<'a>(message: &'a Message<Vec<u8>>, key: &str) -> Option<&'a str> {
+    properties(message)
+        .into_iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
+
+/// Lines printed under a message about what is wrong with it, in red
+pub fn highlight(lines: &[String]) -> String {
+    let mut out = String::new();
+    for line in lines {
+        out.push_str(&format!(
+            "{}{}{}\n",
+            color::Fg(color::Red),
+            line,
+            color::Fg(color::Reset)
+        ));
+    }
+    out
+}
+
+/// The properties of a consumed message, which for a batched one are its own rather than those
+/// of the first message of the batch. Displaying, checking and forwarding messages all go by
+/// these
+pub fn properties(message: &Message<Vec<u8>>) -> Vec<(&str, &str)> {
+    message
+        .payload
+        .metadata
+        .properties
+        .iter()
+        .map(|item| (item.key.as_str(), item.value.as_str()))
+        .collect()
+}
+
+/// How consume and the commands fetching messages print them
+pub struct MessageFormatter {
+    /// Whether to print each message as a JSON line
+    pub json: bool,
+    /// Whether to pretty-print payloads as XML documents
+    pub xml: bool,
+    /// Whether to print the entry metadata of messages
+    pub entry_metadata: bool,
+    /// Whether to print the cluster each message was replicated from, or that it was produced
+    /// locally
+    pub replicated_from: bool,
+    /// Whether to print the topic of messages, e.g. when consuming several
+    pub topic: bool,
+}
+
+impl MessageFormatter {
+    /// Prints `message` to stdout, failing with `output::StdoutClosed` if it was closed
+    pub fn print(&self, message: &DisplayMessage<'_>) -> Result<()> {
+        output::write(&self.format(message)?)
+    }
+
+    /// `message` as `print` prints it
+    pub fn format(&self, message: &DisplayMessage<'_>) -> Result<String> {
+        if let Timestamp::Implausible(millis) = message.time {
+            warn!(
+                "Message timestamp {} is not a plausible time in milliseconds, printing it as is",
+                millis
+            );
+        }
+        let mut out = match message.topic {
+            Some(topic) if self.topic => format!("-- {} {}:\n", message.time, topic),
+            _ => format!("-- {}:\n", message.time),
+        };
+        if self.entry_metadata && !message.entry.is_empty() {
+            let entry = message
+                .entry
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>();
+            out.push_str(&format!(
+                "{}[entry {}]{}\n",
+                color::Fg(color::Blue),
+                entry.join(" "),
+                color::Fg(color::Reset)
+            ));
+        }
+        if self.replicated_from {
+            let origin = match message
+                .entry
+                .iter()
+                .find(|(key, _)| *key == "replicated_from")
+            {
+                Some((_, cluster)) => format!("replicated from {}", cluster),
+                None => "local".to_owned(),
+            };
+            out.push_str(&format!(
+                "{}[{}]{}\n",
+                color::Fg(color::Blue),
+                origin,
+                color::Fg(color::Reset)
+            ));
+        }
+        for (key, value) in message.properties.iter() {
+            out.push_str(&format!(
+                "{}{}={}{}\n",
+                color::Fg(color::Magenta),
+                key,
+                value,
+                color::Fg(color::Reset)
+            ));
+        }
+        if self.json {
+            match serde_json::from_slice::<Value>(message.payload) {
+                Ok(val) => out.push_str(&format!("{}\n", to_colored_json_auto(&val)?)),
+                Err(_) => eprintln!(
+                    "{}Value {:?} is not JSON{}",
+                    color::Fg(color::Red),
+                    String::from_utf8_lossy(message.payload),
+                    color::Fg(color::Reset)
+                ),
+            }
+        } else if self.xml {
+            match xml::pretty(message.payload, termion::is_tty(&std::io::stdout())) {
+                Ok(document) => out.push_str(&format!("{}\n", document)),
+                Err(e) => eprintln!(
+                    "{}Value {:?} is not XML: {}{}",
+                    color::Fg(color::Red),
+                    String::from_utf8_lossy(message.payload),
+                    e,
+                    color::Fg(color::Reset)
+                ),
+            }
+        } else {
+            out.push_str(&format!("{}\n", String::from_utf8_lossy(message.payload)));
+        }
+        Ok(out)
+    }
+}
+
+/// A timestamp of a message, as sent by its producer or set by the broker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timestamp {
+    Time(DateTime<Utc>),
+    /// Milliseconds since the epoch that aren't a plausible time
+    Implausible(u64),
+}
+
+impl Timestamp {
+    /// The time `millis` after the epoch, if it is plausibly one
+    pub fn from_millis(millis: u64) -> Self {
+        match timestamp_from_millis(millis) {
+            Some(time) => Timestamp::Time(time),
+            None => Timestamp::Implausible(millis),
+        }
+    }
+
+    /// Milliseconds since the epoch
+    pub fn millis(&self) -> u64 {
+        match self {
+            Timestamp::Time(time) => time.timestamp_millis().max(0) as u64,
+            Timestamp::Implausible(millis) => *millis,
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timestamp::Time(time) => write!(f, "{}", time),
+            Timestamp::Implausible(millis) => write!(f, "{} (implausible timestamp)", millis),
+        }
+    }
+}
+
+/// Converts a Pulsar timestamp (milliseconds since the epoch) to a `DateTime`, unless it is out
+/// of range or not a plausible time
+pub fn timestamp_from_millis(millis: u64) -> Option<DateTime<Utc>> {
+    i64::try_from(millis)
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .filter(|time| PLAUSIBLE_YEARS.contains(&time.year()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_in_milliseconds() {
+        assert_eq!(
+            timestamp_from_millis(1_600_000_000_123),
+            "2020-09-13T12:26:40.123Z".parse::<DateTime<Utc>>().ok()
+        );
+        assert_eq!(
+            Timestamp::from_millis(1_600_000_000_123).millis(),
+            1_600_000_000_123
+        );
+    }
+
+    #[test]
+    fn implausible_timestamps() {
+        for millis in &[
+            0,
+            // Seconds
+            1_600_000_000,
+            // Microseconds
+            1_600_000_000_000_000,
+            // Out of the range of DateTime
+            i64::MAX as u64,
+            // A negative timestamp
+            -1i64 as u64,
+        ] {
+            assert_eq!(timestamp_from_millis(*millis), None, "{}", millis);
+            let timestamp = Timestamp::from_millis(*millis);
+            assert_eq!(timestamp, Timestamp::Implausible(*millis));
+            assert_eq!(timestamp.millis(), *millis);
+        }
+    }
+
+    fn message(payload: &[u8]) -> DisplayMessage<'_> {
+        DisplayMessage {
+            time: Timestamp::from_millis(1_600_000_000_123),
+            topic: Some("persistent://t/ns/orders"),
+            properties: vec![("source", "cli")],
+            entry: vec![("id", "12:34".to_owned())],
+            payload,
+        }
+    }
+
+    fn formatter() -> MessageFormatter {
+        MessageFormatter {
+            json: false,
+            xml: false,
+            entry_metadata: false,
+            topic: false,
+        }
+    }
+
+    #[test]
+    fn formats_the_time_properties_and_payload() {
+        let out = formatter().format(&message(b"hello")).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "-- 2020-09-13 12:26:40.123 UTC:");
+        assert!(lines[1].contains("source=cli"), "{:?}", lines[1]);
+        assert_eq!(lines[2], "hello");
+        assert!(!out.contains("orders"));
+        assert!(!out.contains("[entry"));
+    }
+
+    #[test]
+    fn formats_the_topic_and_entry_metadata_when_asked() {
+        let formatter = MessageFormatter {
+            entry_metadata: true,
+            topic: true,
+            ..formatter()
+        };
+        let out = formatter.format(&message(b"hello")).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "-- 2020-09-13 12:26:40.123 UTC persistent://t/ns/orders:"
+        );
+        assert!(lines[1].contains("[entry id=12:34]"), "{:?}", lines[1]);
+    }
+
+    #[test]
+    fn formats_payloads_that_are_not_text() {
+        let out = formatter().format(&message(b"\xff\xfebin")).unwrap();
+        assert!(out.ends_with("\u{fffd}\u{fffd}bin\n"), "{:?}", out);
+    }
+}
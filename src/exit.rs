@@ -0,0 +1,267 @@
+//! Process exit codes, so that commands compose in shell scripts
+
+use crate::{admin::AdminError, auth::AuthenticationFailed};
+use pulsar::{
+    error::{ConnectionError, ConsumerError, ProducerError, ServiceDiscoveryError},
+    proto::ServerError,
+};
+use reqwest::StatusCode;
+use std::{fmt, io};
+
+/// Any failure that has no code of its own
+pub const FAILURE: i32 = 1;
+/// The topic, subscription or other resource asked about doesn't exist
+pub const NOT_FOUND: i32 = 2;
+/// What was to be created exists already
+pub const ALREADY_EXISTS: i32 = 3;
+/// The command refused to run, e.g. because it would have destroyed data without `--force`
+pub const REFUSED: i32 = 4;
+/// A watched metric crossed the threshold it was given
+pub const THRESHOLD_CROSSED: i32 = 5;
+/// Invalid arguments or configuration
+pub const USAGE: i32 = 6;
+/// The brokers or the admin API couldn't be reached
+pub const UNREACHABLE: i32 = 7;
+/// Credentials were refused, or don't allow what was asked
+pub const UNAUTHORIZED: i32 = 8;
+/// What was waited for didn't happen in time
+pub const TIMEOUT: i32 = 9;
+/// Something received couldn't be decoded, or didn't match its schema
+pub const INVALID_DATA: i32 = 10;
+
+/// An error that makes pulsar-cli exit with a specific code
+#[derive(Debug)]
+pub struct ExitError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl ExitError {
+    /// An error exiting with `code`, printing `message`
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/// The exit code for a failed invocation, from the first error in its chain that tells what
+/// kind of failure it was
+pub fn code_for(error: &anyhow::Error) -> i32 {
+    error.chain().find_map(classify).unwrap_or(FAILURE)
+}
+
+fn classify(error: &(dyn std::error::Error + 'static)) -> Option<i32> {
+    if let Some(exit) = error.downcast_ref::<ExitError>() {
+        Some(exit.code)
+    } else if error.is::<AuthenticationFailed>() {
+        Some(UNAUTHORIZED)
+    } else if let Some(admin) = error.downcast_ref::<AdminError>() {
+        Some(status_code(admin.status))
+    } else if let Some(pulsar) = error.downcast_ref::<pulsar::Error>() {
+        Some(pulsar_code(pulsar))
+    } else if let Some(http) = error.downcast_ref::<reqwest::Error>() {
+        if http.is_timeout() {
+            Some(TIMEOUT)
+        } else if http.is_connect() {
+            Some(UNREACHABLE)
+        } else if http.is_decode() {
+            Some(INVALID_DATA)
+        } else {
+            http.status().map(status_code)
+        }
+    } else if error.is::<tokio::time::error::Elapsed>() {
+        Some(TIMEOUT)
+    } else if error.is::<serde_json::Error>() {
+        Some(INVALID_DATA)
+    } else {
+        None
+    }
+}
+
+fn status_code(status: StatusCode) -> i32 {
+    match status {
+        StatusCode::NOT_FOUND => NOT_FOUND,
+        StatusCode::CONFLICT => ALREADY_EXISTS,
+        StatusCode::PRECONDITION_FAILED => REFUSED,
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => UNAUTHORIZED,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => TIMEOUT,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => UNREACHABLE,
+        _ => FAILURE,
+    }
+}
+
+/// The exit code for an error of the client library, by what the broker answered or why the
+/// connection failed
+pub fn pulsar_code(error: &pulsar::Error) -> i32 {
+    let connection = match error {
+        pulsar::Error::Connection(e)
+        | pulsar::Error::Consumer(ConsumerError::Connection(e))
+        | pulsar::Error::Producer(ProducerError::Connection(e))
+        | pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::Connection(e)) => e,
+        pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::Query(Some(server), _)) => {
+            return server_code(*server)
+        }
+        pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::NotFound) => return NOT_FOUND,
+        pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::DnsLookupError) => {
+            return UNREACHABLE
+        }
+        pulsar::Error::Authentication(_) => return UNAUTHORIZED,
+        _ => return FAILURE,
+    };
+    match connection {
+        ConnectionError::PulsarError(Some(server), _) => server_code(*server),
+        ConnectionError::Io(e) if e.kind() == io::ErrorKind::TimedOut => TIMEOUT,
+        ConnectionError::Io(_)
+        | ConnectionError::Disconnected
+        | ConnectionError::SocketAddr(_)
+        | ConnectionError::Tls(_) => UNREACHABLE,
+        ConnectionError::Authentication(_) => UNAUTHORIZED,
+        ConnectionError::Decoding(_) | ConnectionError::Encoding(_) => INVALID_DATA,
+        ConnectionError::NotFound => NOT_FOUND,
+        _ => FAILURE,
+    }
+}
+
+fn server_code(error: ServerError) -> i32 {
+    match error {
+        ServerError::AuthenticationError | ServerError::AuthorizationError => UNAUTHORIZED,
+        ServerError::TopicNotFound
+        | ServerError::SubscriptionNotFound
+        | ServerError::ConsumerNotFound => NOT_FOUND,
+        ServerError::ServiceNotReady => UNREACHABLE,
+        ServerError::InvalidTopicName => USAGE,
+        ServerError::ChecksumError | ServerError::IncompatibleSchema => INVALID_DATA,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_codes() {
+        for (status, code) in &[
+            (StatusCode::NOT_FOUND, NOT_FOUND),
+            (StatusCode::CONFLICT, ALREADY_EXISTS),
+            (StatusCode::PRECONDITION_FAILED, REFUSED),
+            (StatusCode::UNAUTHORIZED, UNAUTHORIZED),
+            (StatusCode::FORBIDDEN, UNAUTHORIZED),
+            (StatusCode::REQUEST_TIMEOUT, TIMEOUT),
+            (StatusCode::GATEWAY_TIMEOUT, TIMEOUT),
+            (StatusCode::BAD_GATEWAY, UNREACHABLE),
+            (StatusCode::SERVICE_UNAVAILABLE, UNREACHABLE),
+            (StatusCode::INTERNAL_SERVER_ERROR, FAILURE),
+            (StatusCode::BAD_REQUEST, FAILURE),
+        ] {
+            assert_eq!(status_code(*status), *code, "{}", status);
+        }
+    }
+
+    #[test]
+    fn pulsar_codes() {
+        let server = |error: ServerError| {
+            pulsar::Error::Connection(ConnectionError::PulsarError(Some(error), None))
+        };
+        let io_error = |kind: io::ErrorKind| {
+            pulsar::Error::Connection(ConnectionError::Io(io::Error::from(kind)))
+        };
+        for (error, code) in vec![
+            (server(ServerError::TopicNotFound), NOT_FOUND),
+            (server(ServerError::SubscriptionNotFound), NOT_FOUND),
+            (server(ServerError::ConsumerNotFound), NOT_FOUND),
+            (server(ServerError::AuthenticationError), UNAUTHORIZED),
+            (server(ServerError::AuthorizationError), UNAUTHORIZED),
+            (server(ServerError::ServiceNotReady), UNREACHABLE),
+            (server(ServerError::InvalidTopicName), USAGE),
+            (server(ServerError::ChecksumError), INVALID_DATA),
+            (server(ServerError::IncompatibleSchema), INVALID_DATA),
+            (server(ServerError::UnknownError), FAILURE),
+            (
+                pulsar::Error::Connection(ConnectionError::PulsarError(None, None)),
+                FAILURE,
+            ),
+            (io_error(io::ErrorKind::TimedOut), TIMEOUT),
+            (io_error(io::ErrorKind::ConnectionRefused), UNREACHABLE),
+            (
+                pulsar::Error::Connection(ConnectionError::Disconnected),
+                UNREACHABLE,
+            ),
+            (
+                pulsar::Error::Connection(ConnectionError::NotFound),
+                NOT_FOUND,
+            ),
+            (
+                pulsar::Error::Consumer(ConsumerError::Connection(ConnectionError::Disconnected)),
+                UNREACHABLE,
+            ),
+            (
+                pulsar::Error::Producer(ProducerError::Connection(ConnectionError::PulsarError(
+                    Some(ServerError::TopicNotFound),
+                    None,
+                ))),
+                NOT_FOUND,
+            ),
+            (
+                pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::NotFound),
+                NOT_FOUND,
+            ),
+            (
+                pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::DnsLookupError),
+                UNREACHABLE,
+            ),
+            (
+                pulsar::Error::ServiceDiscovery(ServiceDiscoveryError::Query(
+                    Some(ServerError::AuthorizationError),
+                    None,
+                )),
+                UNAUTHORIZED,
+            ),
+        ] {
+            assert_eq!(pulsar_code(&error), code, "{}", error);
+        }
+    }
+
+    #[test]
+    fn code_for_takes_the_first_error_in_the_chain_that_tells() {
+        let exit = anyhow::Error::new(ExitError::new(REFUSED, "Refused"));
+        assert_eq!(code_for(&exit), REFUSED);
+        assert_eq!(code_for(&exit.context("Running the command")), REFUSED);
+
+        let admin = || AdminError {
+            status: StatusCode::NOT_FOUND,
+            url: "http://127.0.0.1:8080/admin/v2/persistent/t/ns/topic"
+                .parse()
+                .unwrap(),
+            reason: String::new(),
+        };
+        assert_eq!(code_for(&anyhow::Error::new(admin())), NOT_FOUND);
+        assert_eq!(
+            code_for(&anyhow::Error::new(admin()).context("Deleting the topic")),
+            NOT_FOUND
+        );
+
+        let pulsar = pulsar::Error::Connection(ConnectionError::Disconnected);
+        assert_eq!(
+            code_for(&anyhow::Error::new(pulsar).context("Connecting")),
+            UNREACHABLE
+        );
+
+        let json = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(code_for(&anyhow::Error::new(json)), INVALID_DATA);
+
+        assert_eq!(code_for(&anyhow::format_err!("Something else")), FAILURE);
+        let io = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(code_for(&anyhow::Error::new(io)), FAILURE);
+    }
+}
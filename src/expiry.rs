@@ -0,0 +1,78 @@
+//! Warnings for consumed messages that are close to being expired by the namespace's message
+//! TTL or retention time, so that messages vanishing in the middle of a replay aren't mistaken
+//! for lost ones
+
+use crate::{
+    admin::AdminClient,
+    commands::namespace::{self, RetentionPolicies},
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::Result;
+use std::time::Duration;
+
+/// Share of the expiry limit past which a message is warned about
+const NEAR_EXPIRY: f64 = 0.9;
+
+pub struct ExpiryPolicies {
+    namespace: NamespaceName,
+    ttl: Option<u64>,
+    retention: RetentionPolicies,
+}
+
+impl ExpiryPolicies {
+    /// The policies of the namespace `topic` is in, fetched once
+    pub async fn fetch(admin: &AdminClient, topic: &TopicName) -> Result<Self> {
+        Self::fetch_namespace(admin, topic.namespace_name()).await
+    }
+
+    /// The policies of `namespace`, fetched once
+    pub async fn fetch_namespace(admin: &AdminClient, namespace: NamespaceName) -> Result<Self> {
+        Ok(Self {
+            ttl: namespace::message_ttl(admin, &namespace).await?,
+            retention: namespace::retention(admin, &namespace).await?,
+            namespace,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "Namespace {}: message TTL {}, retention {}",
+            self.namespace,
+            namespace::describe_ttl(self.ttl),
+            self.retention
+        )
+    }
+
+    /// The age after which messages may be expired, the shortest of the TTL and the retention
+    /// time, if either is limited
+    fn limit(&self) -> Option<(&'static str, Duration)> {
+        let ttl = self
+            .ttl
+            .filter(|ttl| *ttl > 0)
+            .map(|ttl| ("message TTL", Duration::from_secs(ttl)));
+        let retention = Some(self.retention.retention_time_in_minutes)
+            .filter(|minutes| *minutes > 0)
+            .map(|minutes| ("retention time", Duration::from_secs(minutes as u64 * 60)));
+        ttl.into_iter()
+            .chain(retention)
+            .min_by_key(|(_, limit)| *limit)
+    }
+
+    /// A warning if the message published at `publish_time` (milliseconds since the epoch) is
+    /// close to expiring
+    pub fn check(&self, publish_time: u64) -> Option<String> {
+        let (name, limit) = self.limit()?;
+        let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        let age = Duration::from_millis(now.saturating_sub(publish_time));
+        if age.as_secs_f64() < limit.as_secs_f64() * NEAR_EXPIRY {
+            return None;
+        }
+        Some(format!(
+            "{} old, near the {} of {} ({}): it and older ones may expire during the replay",
+            humantime::format_duration(Duration::from_secs(age.as_secs())),
+            name,
+            self.namespace,
+            humantime::format_duration(limit)
+        ))
+    }
+}
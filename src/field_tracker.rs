@@ -0,0 +1,236 @@
+//! Counting the values of a field of JSON payloads over a sliding window, for `consume
+//! --track-field`, e.g. to see whether the share of errors goes up during a deploy. The window
+//! is kept as slices of a sixtieth of it, the oldest slice being dropped once entirely out of
+//! the window, so that memory doesn't grow with the message rate
+
+use crate::{output, transform};
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    time::{Duration, Instant},
+};
+
+/// How many values the table shows, the fewest seen being left out
+const SHOWN_VALUES: usize = 20;
+
+/// How many distinct values are counted on their own within the window, the others being
+/// counted as OTHER, e.g. when tracking an ID by mistake
+const MAX_VALUES: usize = 10_000;
+
+const MISSING: &str = "(missing)";
+const NOT_JSON: &str = "(not JSON)";
+const OTHER: &str = "(other)";
+
+pub struct FieldTracker {
+    /// As given, e.g. `payload.status`
+    field: String,
+    pointer: String,
+    window: Duration,
+    slice: Duration,
+    /// The counts by value of each slice of the window, oldest first
+    slices: VecDeque<(Instant, HashMap<String, u64>)>,
+    /// The counts by value of the whole window
+    totals: HashMap<String, u64>,
+    /// The counts of the window as last rendered, for the deltas
+    rendered: HashMap<String, u64>,
+}
+
+struct Row {
+    value: String,
+    count: u64,
+    delta: i64,
+}
+
+impl FieldTracker {
+    /// Tracking `field`, as `payload.<path>`
+    pub fn new(field: &str, window: Duration) -> Result<Self> {
+        let path = transform::parse_path(field)
+            .ok_or_else(|| format_err!("Invalid field {:?}, expected payload.<path>", field))?;
+        let pointer = path
+            .iter()
+            .map(|segment| format!("/{}", segment.replace('~', "~0").replace('/', "~1")))
+            .collect();
+        Ok(Self {
+            field: field.to_owned(),
+            pointer,
+            window,
+            slice: (window / 60).max(Duration::from_millis(1)),
+            slices: VecDeque::new(),
+            totals: HashMap::new(),
+            rendered: HashMap::new(),
+        })
+    }
+
+    /// Counts the value of the field in `payload`
+    pub fn observe(&mut self, payload: &[u8]) {
+        let value = match serde_json::from_slice::<Value>(payload) {
+            Ok(document) => match document.pointer(&self.pointer) {
+                Some(Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+                None => MISSING.to_owned(),
+            },
+            Err(_) => NOT_JSON.to_owned(),
+        };
+        let value = if self.totals.len() >= MAX_VALUES && !self.totals.contains_key(&value) {
+            OTHER.to_owned()
+        } else {
+            value
+        };
+        let now = Instant::now();
+        match self.slices.back_mut() {
+            Some((start, counts)) if now < *start + self.slice => {
+                *counts.entry(value.clone()).or_default() += 1;
+            }
+            _ => {
+                let mut counts = HashMap::new();
+                counts.insert(value.clone(), 1);
+                self.slices.push_back((now, counts));
+            }
+        }
+        *self.totals.entry(value).or_default() += 1;
+    }
+
+    /// Drops the counts of the slices entirely out of the window
+    fn evict(&mut self) {
+        let now = Instant::now();
+        while let Some((start, _)) = self.slices.front() {
+            if *start + self.slice + self.window > now {
+                break;
+            }
+            let (_, counts) = self.slices.pop_front().expect("looked at above");
+            for (value, count) in counts {
+                if let Some(total) = self.totals.get_mut(&value) {
+                    *total -= count;
+                    if *total == 0 {
+                        self.totals.remove(&value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The values of the window, most frequent first, with how their counts changed since last
+    /// rendered
+    fn rows(&mut self) -> Vec<Row> {
+        self.evict();
+        let mut rows = self
+            .totals
+            .iter()
+            .map(|(value, count)| Row {
+                value: value.clone(),
+                count: *count,
+                delta: *count as i64 - self.rendered.get(value).copied().unwrap_or(0) as i64,
+            })
+            .chain(
+                // Gone from the window since
+                self.rendered
+                    .iter()
+                    .filter(|(value, _)| !self.totals.contains_key(*value))
+                    .map(|(value, count)| Row {
+                        value: value.clone(),
+                        count: 0,
+                        delta: -(*count as i64),
+                    }),
+            )
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        self.rendered = self.totals.clone();
+        rows
+    }
+
+    /// Prints the counts of the window: a table redrawn in place on a terminal, a JSON line
+    /// otherwise, e.g. to pipe the snapshots elsewhere
+    pub fn render(&mut self, redraw: &mut output::Redraw) -> Result<()> {
+        let rows = self.rows();
+        if redraw.is_tty() {
+            redraw.draw(&self.table(&rows))
+        } else {
+            redraw.draw(&format!("{}\n", self.snapshot(&rows)))
+        }
+    }
+
+    /// The last counts of the window, as text and as JSON
+    pub fn summary(&mut self) -> (String, Value) {
+        let rows = self.rows();
+        (self.table(&rows), self.snapshot(&rows))
+    }
+
+    fn total(&self) -> u64 {
+        self.totals.values().sum()
+    }
+
+    fn table(&self, rows: &[Row]) -> String {
+        let total = self.total();
+        let width = rows
+            .iter()
+            .take(SHOWN_VALUES)
+            .map(|row| row.value.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("VALUE".len());
+        let mut out = String::new();
+        // Writing to a String never fails
+        let _ = writeln!(
+            out,
+            "{} over the last {} ({} messages):",
+            self.field,
+            humantime::format_duration(self.window),
+            total
+        );
+        let _ = writeln!(
+            out,
+            "  {:<width$}  {:>10}  {:>6}  {:>8}",
+            "VALUE",
+            "COUNT",
+            "SHARE",
+            "DELTA",
+            width = width
+        );
+        for row in rows.iter().take(SHOWN_VALUES) {
+            let _ = writeln!(
+                out,
+                "  {:<width$}  {:>10}  {:>5.1}%  {:>+8}",
+                row.value,
+                row.count,
+                share(row.count, total),
+                row.delta,
+                width = width
+            );
+        }
+        if rows.len() > SHOWN_VALUES {
+            let _ = writeln!(out, "  ... {} more values", rows.len() - SHOWN_VALUES);
+        }
+        out
+    }
+
+    fn snapshot(&self, rows: &[Row]) -> Value {
+        let total = self.total();
+        json!({
+            "time": Utc::now().to_rfc3339(),
+            "field": self.field,
+            "windowSeconds": self.window.as_secs_f64(),
+            "total": total,
+            "values": rows
+                .iter()
+                .map(|row| json!({
+                    "value": row.value,
+                    "count": row.count,
+                    "share": share(row.count, total) / 100.0,
+                    "delta": row.delta,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// `count` as a percentage of `total`
+fn share(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
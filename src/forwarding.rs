@@ -0,0 +1,44 @@
+//! Helpers for commands that consume a topic up to a known point, mostly to move its messages
+//! to another topic
+
+use crate::{display, message_id::MessageId};
+use pulsar::consumer::Message;
+
+/// Position of a message within its partition: ledger, entry and batch index
+pub type Position = (i64, i64, i32);
+
+pub fn position_of(message: &Message<Vec<u8>>) -> Position {
+    let id = &message.message_id.id;
+    (
+        id.ledger_id as i64,
+        id.entry_id as i64,
+        id.batch_index.unwrap_or(-1),
+    )
+}
+
+/// Whether `message` is the last one of the entry identified by `last`, or later
+pub fn reached(message: &Message<Vec<u8>>, position: Position, last: &MessageId) -> bool {
+    let (ledger_id, entry_id, batch_index) = position;
+    if (ledger_id, entry_id) != (last.ledger_id, last.entry_id) {
+        return (ledger_id, entry_id) > (last.ledger_id, last.entry_id);
+    }
+    match message.message_id.batch_size {
+        Some(batch_size) if batch_index >= 0 => batch_index + 1 >= batch_size,
+        _ => true,
+    }
+}
+
+/// The message to send to the destination topic, preserving the original's key, properties
+/// and event time
+pub fn copy_of(message: &Message<Vec<u8>>) -> pulsar::producer::Message {
+    pulsar::producer::Message {
+        payload: message.payload.data.clone(),
+        properties: display::properties(message)
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect(),
+        partition_key: message.metadata().partition_key.clone(),
+        event_time: message.metadata().event_time,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,82 @@
+//! Payloads fetched over HTTP, for `produce --from-url`
+
+use crate::{
+    connection::TimeoutOpts,
+    exit::{self, ExitError},
+};
+use anyhow::{Context, Result};
+use reqwest::{header::CONTENT_TYPE, redirect};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+use url::Url;
+
+/// Property set to the `Content-Type` of the fetched body, when it has one
+pub const CONTENT_TYPE_PROPERTY: &str = "content-type";
+
+pub struct HttpSource {
+    http: reqwest::Client,
+    url: Url,
+}
+
+/// A body fetched from the source
+pub struct Fetched {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+impl HttpSource {
+    /// A source of `url`, giving up after `max_redirects` redirects
+    pub fn new(url: Url, timeouts: &TimeoutOpts, max_redirects: usize) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(timeouts.connect_timeout.into())
+            .timeout(timeouts.operation_timeout.into())
+            .redirect(redirect::Policy::limited(max_redirects))
+            .build()?;
+        Ok(Self { http, url })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Fetches the body from the source, failing unless it answered with a 2xx status
+    pub async fn fetch(&self) -> Result<Fetched> {
+        let response = self
+            .http
+            .get(self.url.clone())
+            .send()
+            .await
+            .with_context(|| format!("Failed fetching {}", self.url))?;
+        let status = response.status();
+        if !status.is_success() {
+            let code = match status.as_u16() {
+                401 | 403 => exit::UNAUTHORIZED,
+                404 => exit::NOT_FOUND,
+                _ => exit::FAILURE,
+            };
+            return Err(ExitError::new(code, format!("{} answered {}", self.url, status)).into());
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = response
+            .bytes()
+            .await
+            .with_context(|| format!("Failed reading the body of {}", self.url))?;
+        Ok(Fetched {
+            body: body.to_vec(),
+            content_type,
+        })
+    }
+}
+
+/// Hash of a body, to tell whether it changed since the last fetch
+pub fn hash(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
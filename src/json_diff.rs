@@ -0,0 +1,214 @@
+//! Structural differences between two JSON documents, for `diff`: where they differ as JSON
+//! pointers, rather than which lines of their text do
+
+use serde_json::Value;
+use termion::color;
+
+#[derive(Debug, PartialEq)]
+pub enum Difference {
+    /// Only in the first document
+    Removed(String, Value),
+    /// Only in the second document
+    Added(String, Value),
+    Changed(String, Value, Value),
+}
+
+/// How `b` differs from `a`, objects being compared key by key and arrays index by index
+pub fn diff(a: &Value, b: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_at(String::new(), a, b, &mut differences);
+    differences
+}
+
+fn diff_at(path: String, a: &Value, b: &Value, differences: &mut Vec<Difference>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, a_value) in a {
+                let key_path = format!("{}/{}", path, escape(key));
+                match b.get(key) {
+                    Some(b_value) => diff_at(key_path, a_value, b_value, differences),
+                    None => differences.push(Difference::Removed(key_path, a_value.clone())),
+                }
+            }
+            for (key, b_value) in b {
+                if !a.contains_key(key) {
+                    differences.push(Difference::Added(
+                        format!("{}/{}", path, escape(key)),
+                        b_value.clone(),
+                    ));
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                let index_path = format!("{}/{}", path, i);
+                match (a.get(i), b.get(i)) {
+                    (Some(a_value), Some(b_value)) => {
+                        diff_at(index_path, a_value, b_value, differences)
+                    }
+                    (Some(a_value), None) => {
+                        differences.push(Difference::Removed(index_path, a_value.clone()))
+                    }
+                    (None, Some(b_value)) => {
+                        differences.push(Difference::Added(index_path, b_value.clone()))
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        (a, b) if a != b => differences.push(Difference::Changed(path, a.clone(), b.clone())),
+        _ => {}
+    }
+}
+
+/// `key` as a JSON pointer reference token
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+/// The differences one per line, removals in red and additions in green
+pub fn describe(differences: &[Difference]) -> String {
+    let pointer = |path: &str| if path.is_empty() { "/" } else { path }.to_owned();
+    let mut out = String::new();
+    for difference in differences {
+        match difference {
+            Difference::Removed(path, a) => out.push_str(&format!(
+                "{}  - {}: {}{}\n",
+                color::Fg(color::Red),
+                pointer(path),
+                a,
+                color::Fg(color::Reset)
+            )),
+            Difference::Added(path, b) => out.push_str(&format!(
+                "{}  + {}: {}{}\n",
+                color::Fg(color::Green),
+                pointer(path),
+                b,
+                color::Fg(color::Reset)
+            )),
+            Difference::Changed(path, a, b) => out.push_str(&format!(
+                "  ~ {}: {}{}{} -> {}{}{}\n",
+                pointer(path),
+                color::Fg(color::Red),
+                a,
+                color::Fg(color::Reset),
+                color::Fg(color::Green),
+                b,
+                color::Fg(color::Reset)
+            )),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The differences in a stable order, whichever order keys are iterated in
+    fn sorted_diff(a: Value, b: Value) -> Vec<Difference> {
+        let mut differences = diff(&a, &b);
+        differences.sort_by(|a, b| format!("{:?}", a).cmp(&format!("{:?}", b)));
+        differences
+    }
+
+    #[test]
+    fn equal() {
+        for value in &[
+            json!(null),
+            json!(1),
+            json!("a"),
+            json!([1, [2, 3]]),
+            json!({"a": {"b": [1, {"c": null}]}}),
+        ] {
+            assert!(diff(value, value).is_empty(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn changed_scalars() {
+        assert_eq!(
+            diff(&json!(1), &json!("1")),
+            vec![Difference::Changed(String::new(), json!(1), json!("1"))]
+        );
+        assert_eq!(
+            sorted_diff(
+                json!({"a": 1, "b": true, "c": "x"}),
+                json!({"a": 2, "b": true, "c": null})
+            ),
+            vec![
+                Difference::Changed("/a".to_owned(), json!(1), json!(2)),
+                Difference::Changed("/c".to_owned(), json!("x"), json!(null)),
+            ]
+        );
+        // An object replaced by something else is one change, not one per key
+        assert_eq!(
+            diff(&json!({"a": {"b": 1}}), &json!({"a": [1]})),
+            vec![Difference::Changed(
+                "/a".to_owned(),
+                json!({"b": 1}),
+                json!([1])
+            )]
+        );
+    }
+
+    #[test]
+    fn added_and_removed_keys() {
+        assert_eq!(
+            sorted_diff(json!({"a": 1, "b": 2}), json!({"b": 2, "c": 3})),
+            vec![
+                Difference::Added("/c".to_owned(), json!(3)),
+                Difference::Removed("/a".to_owned(), json!(1)),
+            ]
+        );
+        // Escaped as in JSON pointers
+        assert_eq!(
+            diff(&json!({}), &json!({"a/b~c": 1})),
+            vec![Difference::Added("/a~1b~0c".to_owned(), json!(1))]
+        );
+    }
+
+    #[test]
+    fn nested_objects() {
+        assert_eq!(
+            sorted_diff(
+                json!({"user": {"name": "a", "address": {"city": "X", "zip": "1"}}}),
+                json!({"user": {"name": "a", "address": {"city": "Y"}, "email": "e"}})
+            ),
+            vec![
+                Difference::Added("/user/email".to_owned(), json!("e")),
+                Difference::Changed("/user/address/city".to_owned(), json!("X"), json!("Y")),
+                Difference::Removed("/user/address/zip".to_owned(), json!("1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn arrays_of_different_lengths() {
+        assert_eq!(
+            diff(&json!([1, 2, 3]), &json!([1, 4])),
+            vec![
+                Difference::Changed("/1".to_owned(), json!(2), json!(4)),
+                Difference::Removed("/2".to_owned(), json!(3)),
+            ]
+        );
+        assert_eq!(
+            diff(&json!({"tags": []}), &json!({"tags": ["a", "b"]})),
+            vec![
+                Difference::Added("/tags/0".to_owned(), json!("a")),
+                Difference::Added("/tags/1".to_owned(), json!("b")),
+            ]
+        );
+    }
+
+    #[test]
+    fn describe_one_per_line() {
+        let described = describe(&diff(&json!({"a": [1]}), &json!({"a": [2, 3]})));
+        let lines = described.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("~ /a/0: "), "{:?}", lines[0]);
+        assert!(lines[1].contains("+ /a/1: 3"), "{:?}", lines[1]);
+        assert!(describe(&diff(&json!(1), &json!(2))).contains("~ /: "));
+    }
+}
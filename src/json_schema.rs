@@ -0,0 +1,202 @@
+//! Validating consumed JSON payloads against a JSON Schema document, for
+//! `consume --validate-schema`
+
+use crate::exit::{self, ExitError};
+use anyhow::Result;
+use jsonschema::JSONSchema;
+use serde_json::{json, Value};
+use std::{fs, path::Path};
+
+pub struct SchemaValidator {
+    schema: JSONSchema,
+    checked: u64,
+    invalid: u64,
+}
+
+impl SchemaValidator {
+    /// Loads and compiles the schema at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let usage = |message: String| ExitError::new(exit::USAGE, message);
+        let text = fs::read_to_string(path)
+            .map_err(|e| usage(format!("Failed reading {}: {}", path.display(), e)))?;
+        let document = serde_json::from_str::<Value>(&text)
+            .map_err(|e| usage(format!("{} is not JSON: {}", path.display(), e)))?;
+        Ok(Self::compile(&document).map_err(|e| {
+            usage(format!(
+                "{} is not a valid JSON Schema: {}",
+                path.display(),
+                e
+            ))
+        })?)
+    }
+
+    /// Compiles `document`, giving why it isn't a valid schema otherwise
+    fn compile(document: &Value) -> Result<Self, String> {
+        let schema = JSONSchema::compile(document).map_err(|e| e.to_string())?;
+        Ok(Self {
+            schema,
+            checked: 0,
+            invalid: 0,
+        })
+    }
+
+    /// Why `payload` doesn't match the schema, as `<path>: <error>` for each error, the path
+    /// being a JSON pointer into the payload. Empty if it matches
+    pub fn validate(&mut self, payload: &[u8]) -> Vec<String> {
+        self.checked += 1;
+        let errors = match serde_json::from_slice::<Value>(payload) {
+            Ok(instance) => match self.schema.validate(&instance) {
+                Ok(()) => Vec::new(),
+                Err(errors) => errors
+                    .map(|error| {
+                        format!("{}: {}", pointer(&error.instance_path.to_string()), error)
+                    })
+                    .collect(),
+            },
+            Err(e) => vec![format!("payload is not JSON: {}", e)],
+        };
+        if !errors.is_empty() {
+            self.invalid += 1;
+        }
+        errors
+    }
+
+    pub fn invalid(&self) -> u64 {
+        self.invalid
+    }
+
+    /// How many messages were checked and not valid, as text and as JSON
+    pub fn summary(&self) -> (String, Value) {
+        (
+            format!(
+                "Schema validation: {} of {} messages invalid",
+                self.invalid, self.checked
+            ),
+            json!({ "checked": self.checked, "invalid": self.invalid }),
+        )
+    }
+}
+
+/// The root of the payload shown as `/` rather than as nothing
+fn pointer(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn validator(schema: Value) -> SchemaValidator {
+        SchemaValidator::compile(&schema).expect("A valid schema")
+    }
+
+    /// The paths each error is about, in order
+    fn error_paths(errors: &[String]) -> Vec<&str> {
+        let mut paths = errors
+            .iter()
+            .map(|error| error.split_once(": ").expect("<path>: <error>").0)
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths
+    }
+
+    fn user_schema() -> Value {
+        json!({
+            "type": "object",
+            "required": ["id"],
+            "properties": {
+                "id": {"type": "string"},
+                "user": {
+                    "type": "object",
+                    "properties": {
+                        "age": {"type": "integer", "minimum": 0},
+                        "tags": {"type": "array", "items": {"type": "string"}}
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn valid() {
+        let mut validator = validator(user_schema());
+        assert!(validator
+            .validate(br#"{"id": "a", "user": {"age": 30, "tags": ["x"]}}"#)
+            .is_empty());
+        assert!(validator.validate(br#"{"id": "b", "extra": 1}"#).is_empty());
+        assert_eq!(validator.invalid(), 0);
+    }
+
+    #[test]
+    fn error_paths_are_json_pointers() {
+        let mut validator = validator(user_schema());
+        let errors = validator.validate(br#"{"user": {"age": -1, "tags": ["x", 2]}}"#);
+        assert_eq!(error_paths(&errors), vec!["/", "/user/age", "/user/tags/1"]);
+        assert!(errors
+            .iter()
+            .any(|error| error.starts_with("/: ") && error.contains("id")));
+        assert_eq!(error_paths(&validator.validate(b"[1]")), vec!["/"]);
+    }
+
+    #[test]
+    fn not_json() {
+        let mut validator = validator(json!({"type": "object"}));
+        let errors = validator.validate(b"{not json");
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("payload is not JSON: "),
+            "{}",
+            errors[0]
+        );
+    }
+
+    #[test]
+    fn counters() {
+        let mut validator = validator(user_schema());
+        for payload in &[
+            &br#"{"id": "a"}"#[..],
+            &b"{}"[..],
+            &b"not json"[..],
+            &br#"{"id": 1}"#[..],
+        ] {
+            validator.validate(payload);
+        }
+        assert_eq!(validator.invalid(), 3);
+        assert_eq!(
+            validator.summary(),
+            (
+                "Schema validation: 3 of 4 messages invalid".to_owned(),
+                json!({"checked": 4, "invalid": 3})
+            )
+        );
+    }
+
+    #[test]
+    fn load() {
+        let path = |name: &str| {
+            std::env::temp_dir().join(format!("pulsar-cli-{}-{}", std::process::id(), name))
+        };
+        let load = |path: &PathBuf, text: &str| {
+            fs::write(path, text).unwrap();
+            let loaded = SchemaValidator::load(path);
+            fs::remove_file(path).unwrap();
+            loaded
+        };
+
+        let mut loaded = load(&path("valid.json"), r#"{"type": "integer"}"#).unwrap();
+        assert!(loaded.validate(b"1").is_empty());
+        for (name, text) in &[("not-json.json", "{"), ("invalid.json", r#"{"type": 12}"#)] {
+            let error = load(&path(*name), *text).err().expect("An invalid schema");
+            assert_eq!(exit::code_for(&error), exit::USAGE, "{}", error);
+        }
+        let error = SchemaValidator::load(&path("missing.json"))
+            .err()
+            .expect("A missing schema");
+        assert_eq!(exit::code_for(&error), exit::USAGE, "{}", error);
+    }
+}
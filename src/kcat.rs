@@ -0,0 +1,263 @@
+//! Printing consumed messages the way kcat does with `-f`, for `consume --output kcat`, so that
+//! scripts reading kcat output can read Pulsar topics unchanged. Like kcat, keys, payloads and
+//! header values are printed as they are, tabs and newlines included: only the format string
+//! is unescaped
+//!
+//! Supported: `%t` topic, `%p` partition (-1 if not partitioned), `%o` message ID, `%k` key,
+//! `%K` key length (-1 without a key), `%s` payload, `%S` payload length, `%h` headers (the
+//! properties, as `name=value` separated by commas), `%T` timestamp in milliseconds, `%%`,
+//! and `\n`, `\r`, `\t` and `\\`
+
+use crate::{message_id::MessageId, topic_name::TopicName};
+use anyhow::{format_err, Result};
+use pulsar::{consumer::Message, proto::MessageIdData, Payload};
+use std::{fmt::Write, str::FromStr};
+
+#[derive(Clone, Copy)]
+enum Field {
+    Topic,
+    Partition,
+    Offset,
+    Key,
+    KeyLength,
+    Payload,
+    PayloadLength,
+    Headers,
+    Timestamp,
+}
+
+#[derive(Clone)]
+enum Token {
+    Literal(String),
+    Field(Field),
+}
+
+#[derive(Clone)]
+pub struct KcatFormat {
+    tokens: Vec<Token>,
+}
+
+impl FromStr for KcatFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '%' => {
+                    let field = match chars.next() {
+                        Some('t') => Field::Topic,
+                        Some('p') => Field::Partition,
+                        Some('o') => Field::Offset,
+                        Some('k') => Field::Key,
+                        Some('K') => Field::KeyLength,
+                        Some('s') => Field::Payload,
+                        Some('S') => Field::PayloadLength,
+                        Some('h') => Field::Headers,
+                        Some('T') => Field::Timestamp,
+                        Some('%') => {
+                            literal.push('%');
+                            continue;
+                        }
+                        other => {
+                            return Err(format_err!(
+                                "Invalid kcat format {:?}: unsupported %{}",
+                                s,
+                                other.map(String::from).unwrap_or_default()
+                            ))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                    }
+                    tokens.push(Token::Field(field));
+                }
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('r') => literal.push('\r'),
+                    Some('t') => literal.push('\t'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(literal));
+        }
+        Ok(Self { tokens })
+    }
+}
+
+impl KcatFormat {
+    /// `message` as the format has it, payloads that aren't UTF-8 printed lossily
+    pub fn format(&self, message: &Message<Vec<u8>>) -> String {
+        self.format_parts(&message.topic, &message.message_id.id, &message.payload)
+    }
+
+    /// The message of `topic` with `id` and `payload`, which is all `format` needs of it
+    fn format_parts(&self, topic: &str, id: &MessageIdData, payload: &Payload) -> String {
+        let metadata = &payload.metadata;
+        let key = metadata.partition_key.as_deref();
+        let mut out = String::new();
+        for token in self.tokens.iter() {
+            let field = match token {
+                Token::Literal(literal) => {
+                    out.push_str(literal);
+                    continue;
+                }
+                Token::Field(field) => *field,
+            };
+            let _ = match field {
+                Field::Topic => write!(out, "{}", topic),
+                Field::Partition => write!(out, "{}", partition(topic)),
+                Field::Offset => write!(out, "{}", MessageId::from(id)),
+                Field::Key => write!(out, "{}", key.unwrap_or_default()),
+                Field::KeyLength => write!(out, "{}", key.map_or(-1, |key| key.len() as i64)),
+                Field::Payload => write!(out, "{}", String::from_utf8_lossy(&payload.data)),
+                Field::PayloadLength => write!(out, "{}", payload.data.len()),
+                // The properties of the message itself, as display::properties has them
+                Field::Headers => write!(
+                    out,
+                    "{}",
+                    metadata
+                        .properties
+                        .iter()
+                        .map(|item| format!("{}={}", item.key, item.value))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                ),
+                Field::Timestamp => write!(
+                    out,
+                    "{}",
+                    metadata.event_time.unwrap_or(metadata.publish_time)
+                ),
+            };
+        }
+        out
+    }
+}
+
+/// The partition index of `topic`, -1 if it isn't a partition
+fn partition(topic: &str) -> i64 {
+    topic
+        .parse::<TopicName>()
+        .ok()
+        .and_then(|topic| topic.partitioned_parent())
+        .map_or(-1, |(_, index)| i64::from(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulsar::proto::{KeyValue, MessageMetadata};
+
+    const TOPIC: &str = "persistent://t/ns/orders-partition-2";
+
+    fn id() -> MessageIdData {
+        MessageIdData {
+            ledger_id: 12,
+            entry_id: 34,
+            partition: Some(2),
+            ..Default::default()
+        }
+    }
+
+    fn payload(key: Option<&str>, data: &[u8], properties: &[(&str, &str)]) -> Payload {
+        Payload {
+            metadata: MessageMetadata {
+                partition_key: key.map(str::to_owned),
+                publish_time: 1_600_000_000_000,
+                properties: properties
+                    .iter()
+                    .map(|(key, value)| KeyValue {
+                        key: (*key).to_owned(),
+                        value: (*value).to_owned(),
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            data: data.to_vec(),
+        }
+    }
+
+    fn formatted(format: &str, payload: &Payload) -> String {
+        format
+            .parse::<KcatFormat>()
+            .unwrap()
+            .format_parts(TOPIC, &id(), payload)
+    }
+
+    #[test]
+    fn escapes_in_the_format() {
+        let payload = payload(Some("k"), b"v", &[]);
+        assert_eq!(formatted("%k\\t%s\\n", &payload), "k\tv\n");
+        assert_eq!(formatted("%k\\r\\n%s\\\\", &payload), "k\r\nv\\");
+        // Unknown escapes and a trailing backslash are taken as they are
+        assert_eq!(formatted("%k\\x%s\\", &payload), "k\\xv\\");
+        assert_eq!(formatted("100%% %s", &payload), "100% v");
+    }
+
+    #[test]
+    fn keys_and_payloads_printed_as_they_are() {
+        let payload = payload(Some("a\tkey"), b"line 1\nline 2\t\\n", &[]);
+        assert_eq!(
+            formatted("%k\\t%s\\n", &payload),
+            "a\tkey\tline 1\nline 2\t\\n\n"
+        );
+        assert_eq!(formatted("%K %S", &payload), "5 16");
+    }
+
+    #[test]
+    fn custom_separator() {
+        let payload = payload(Some("k"), b"v", &[]);
+        assert_eq!(
+            formatted("%t|%p|%o|%k|%s|%T", &payload),
+            format!("{}|2|12:34:2|k|v|1600000000000", TOPIC)
+        );
+        assert_eq!(formatted("%k ::: %s", &payload), "k ::: v");
+    }
+
+    #[test]
+    fn without_a_key() {
+        assert_eq!(formatted("[%k] %K", &payload(None, b"v", &[])), "[] -1");
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(formatted("%h", &payload(None, b"v", &[])), "");
+        assert_eq!(
+            formatted(
+                "%h",
+                &payload(None, b"v", &[("env", "prod"), ("trace", "a=b")])
+            ),
+            "env=prod,trace=a=b"
+        );
+    }
+
+    #[test]
+    fn timestamp_prefers_the_event_time() {
+        let mut payload = payload(None, b"v", &[]);
+        payload.metadata.event_time = Some(1_500_000_000_000);
+        assert_eq!(formatted("%T", &payload), "1500000000000");
+    }
+
+    #[test]
+    fn partition_of_topics_that_are_not_partitions() {
+        assert_eq!(partition("persistent://t/ns/orders"), -1);
+        assert_eq!(partition(TOPIC), 2);
+    }
+
+    #[test]
+    fn unsupported_fields() {
+        for format in &["%x", "%s %", "%R"] {
+            assert!(format.parse::<KcatFormat>().is_err(), "{}", format);
+        }
+    }
+}
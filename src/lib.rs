@@ -0,0 +1,845 @@
+//! The commands of pulsar-cli, for the binary and for embedding them, e.g. in test harnesses:
+//! parse a command line into `Opts` (with `StructOpt`) and `run` it, or call the commands in
+//! `commands` with a `connection::Connector` of their own
+//!
+//! The options of the commands, e.g. `commands::consume::ConsumeOpts`, are only built by
+//! parsing arguments, with `StructOpt::from_iter` or `from_iter_safe`, which also applies
+//! their defaults and environment variables
+
+use admin::{AdminClient, AdminOpts};
+use anyhow::Result;
+use auth::Auth;
+use connection::{Connector, ServiceUrls};
+use exit::ExitError;
+use log::info;
+use output::Report;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use structopt::{
+    clap::{AppSettings, Shell},
+    StructOpt,
+};
+use tls::TlsConfig;
+
+mod ack_hold;
+/// The Pulsar admin REST API, for the commands that inspect or manage a cluster
+pub mod admin;
+mod annotate;
+pub mod auth;
+mod checksum;
+pub mod commands;
+mod config;
+pub mod confirm;
+pub mod connection;
+mod connection_events;
+mod csv_source;
+mod discovery;
+pub mod display;
+pub mod exit;
+mod expiry;
+mod field_tracker;
+mod forwarding;
+mod http_source;
+mod json_diff;
+mod json_schema;
+mod kcat;
+mod logging;
+mod message_id;
+mod metrics;
+mod ordering;
+pub mod output;
+mod picker;
+mod preflight;
+mod printer;
+mod producers;
+mod proxy;
+mod rate_limit;
+mod redelivery;
+mod replay_source;
+mod routing;
+mod silence;
+mod sink;
+mod template;
+pub mod tls;
+mod topic_files;
+mod topic_name;
+mod transform;
+mod units;
+mod xml;
+
+/// Delay between consecutive polls of the admin API while waiting for a condition
+const ADMIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+const DEFAULT_URL: &str = "pulsar://127.0.0.1";
+
+// structopt takes the doc comments of the structs it derives for as the about of their
+// command, replacing the description of the crate here and that of the command each one is
+// flattened into, hence `doc` attributes only seen by rustdoc
+#[cfg_attr(
+    doc,
+    doc = "The command line: the global options, then the command with its own"
+)]
+#[derive(StructOpt)]
+pub struct Opts {
+    /// Profile of the configuration file to take the options not given from
+    #[structopt(long, env = "PULSAR_PROFILE")]
+    profile: Option<String>,
+    /// Pulsar service URL [default: pulsar://127.0.0.1]. Given more than once or as a
+    /// comma-separated list, connections fail over from one to the next
+    #[structopt(long, env = "PULSAR_URL", number_of_values = 1)]
+    url: Vec<ServiceUrls>,
+    #[structopt(flatten)]
+    topics: topic_name::TopicOpts,
+    #[structopt(flatten)]
+    tls: tls::TlsOpts,
+    #[structopt(flatten)]
+    auth: auth::AuthOpts,
+    #[structopt(flatten)]
+    timeouts: connection::TimeoutOpts,
+    #[structopt(flatten)]
+    retry: connection::RetryOpts,
+    #[structopt(flatten)]
+    log: logging::LogOpts,
+    #[structopt(flatten)]
+    admin: AdminOpts,
+    #[structopt(flatten)]
+    proxy: proxy::ProxyOpts,
+    /// Address to serve Prometheus metrics on, at /metrics, e.g. 0.0.0.0:9464
+    #[structopt(long, env = "PULSAR_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+    /// Applied as soon as the command line is parsed, see main
+    #[allow(dead_code)]
+    #[structopt(flatten)]
+    output: output::OutputOpts,
+    #[structopt(flatten)]
+    confirm: confirm::ConfirmOpts,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Consume the messages of topics, printing them or forwarding them to another topic
+    Consume(commands::consume::ConsumeOpts),
+
+    /// Publish messages to topics, generated at an interval or read from stdin or a URL
+    Produce(commands::produce::ProduceOpts),
+
+    /// Copy the messages of a topic to another topic, possibly on another cluster
+    Copy(commands::copy::CopyOpts),
+
+    /// Continuously mirror a topic to another cluster, resuming from a checkpoint file
+    Mirror(commands::mirror::MirrorOpts),
+
+    /// Consume and ack the backlog of a subscription, optionally only the messages matching a
+    /// filter
+    Drain(commands::drain::DrainOpts),
+
+    /// Publish messages and consume them back, checking that each arrives exactly once and in
+    /// order
+    Verify(commands::verify::VerifyOpts),
+
+    /// Compare the messages published on two topics for a while, e.g. during a dual-write
+    /// migration: matched by a field of their JSON payload, reporting the ones only on one
+    /// side and how the payloads of the others differ
+    Diff(commands::diff::DiffOpts),
+
+    /// Publish and consume at a fixed rate, reporting throughput, latencies and loss
+    Bench(commands::bench::BenchOpts),
+
+    /// Fetch and print a single message by its ID
+    GetMessage(commands::get_message::GetMessageOpts),
+
+    /// Print the ID of the last message published on a topic
+    LastMessageId(commands::last_message_id::LastMessageIdOpts),
+
+    /// Parse and compare message IDs, and estimate the number of entries between two
+    MessageId {
+        #[structopt(subcommand)]
+        command: commands::message_id::MessageIdCommand,
+    },
+
+    /// Print the broker serving a topic (or each of its partitions)
+    Lookup(commands::lookup::LookupOpts),
+
+    /// Print the number of partitions of a topic
+    Partitions(commands::lookup::PartitionsOpts),
+
+    /// Check that the broker is reachable and serving requests, exiting non-zero if it isn't
+    Ping(commands::ping::PingOpts),
+
+    /// List the namespaces of a tenant
+    Namespaces(commands::namespace::NamespacesOpts),
+
+    /// Inspect and configure a namespace
+    Namespace {
+        #[structopt(subcommand)]
+        command: commands::namespace::NamespaceCommand,
+    },
+
+    /// Show the next messages of a subscription's backlog without consuming them
+    Peek(commands::peek::PeekOpts),
+
+    /// Inspect and manage the schema of a topic
+    Schema {
+        #[structopt(subcommand)]
+        command: commands::schema::SchemaCommand,
+    },
+
+    /// Show statistics of a topic
+    Stats(commands::stats::StatsOpts),
+
+    /// Follow the rates and backlogs of a topic, optionally until a threshold is crossed
+    Watch(commands::watch::WatchOpts),
+
+    /// Periodically record the backlog of every subscription in a namespace, as CSV or JSON
+    Lag(commands::lag::LagOpts),
+
+    /// Trigger the compaction of a topic, optionally waiting for it to finish
+    Compact(commands::compact::CompactOpts),
+
+    /// List the tenants of the cluster
+    Tenants(commands::namespace::TenantsOpts),
+
+    /// List the clusters of the Pulsar instance
+    Clusters(commands::cluster::ClustersOpts),
+
+    /// List the active brokers with the number of bundles each one owns
+    Brokers(commands::cluster::BrokersOpts),
+
+    /// List the topics of a namespace
+    Topics(commands::topics::TopicsOpts),
+
+    /// List the subscriptions of a topic, or show the details of one
+    Subscriptions(commands::subscriptions::SubscriptionsOpts),
+
+    /// Estimate the size and age of the backlog of a subscription
+    Backlog(commands::backlog::BacklogOpts),
+
+    /// List the subscriptions of a namespace without consumers, with their backlog and the age
+    /// of their mark-delete position, flagging and optionally deleting the stale ones
+    AuditSubscriptions(commands::audit_subscriptions::AuditSubscriptionsOpts),
+
+    /// List the consumers connected to a subscription
+    Consumers(commands::consumers::ConsumersOpts),
+
+    /// Manage the subscriptions of a topic
+    Subscription {
+        #[structopt(subcommand)]
+        command: commands::subscription::SubscriptionCommand,
+    },
+
+    /// Run the steps of a YAML scenario file, each a pulsar-cli command line or a wait, and
+    /// report which passed, e.g. to rehearse a load test or a failover
+    RunScenario(commands::run_scenario::RunScenarioOpts),
+
+    /// Export the cursor positions of a subscription and reset it to them later
+    Cursor {
+        #[structopt(subcommand)]
+        command: commands::cursor::CursorCommand,
+    },
+
+    /// Create, delete and repartition topics
+    Topic {
+        #[structopt(subcommand)]
+        command: commands::topic::TopicCommand,
+    },
+
+    /// Inspect, grant and revoke the permissions of roles on a topic or namespace
+    Permissions {
+        #[structopt(subcommand)]
+        command: commands::permissions::PermissionsCommand,
+    },
+
+    /// Print a shell completion script
+    Completions {
+        #[structopt(possible_values = &Shell::variants())]
+        shell: Shell,
+    },
+
+    /// List, show and change the profiles of the configuration file
+    Config {
+        #[structopt(subcommand)]
+        command: commands::config::ConfigCommand,
+    },
+}
+
+/// Parses the command line, taking the global options it doesn't give from the profile in use
+pub fn load_opts(matches: &structopt::clap::ArgMatches) -> Result<Opts> {
+    let config = config::Config::load()?;
+    let profile = config.profile(matches.value_of("profile"))?;
+    // Before the topic names are parsed
+    let topics = topic_name::TopicOpts::from_clap(matches);
+    let prefix = topic_name::topic_prefix_with(
+        profile.and_then(config::Profile::topic_prefix),
+        topics
+            .tenant
+            .as_deref()
+            .or_else(|| profile.and_then(config::Profile::tenant)),
+        topics
+            .namespace
+            .as_deref()
+            .or_else(|| profile.and_then(config::Profile::namespace)),
+    )?;
+    if let Some(prefix) = prefix {
+        topic_name::set_topic_prefix(&prefix)?;
+    }
+    let mut opts = Opts::from_clap(matches);
+    opts.auth.ignore_environment_if_given(matches);
+    // Switches can't take their value from the environment the way options do
+    opts.tls.tls_insecure |= config::env_switch("PULSAR_TLS_INSECURE")?;
+    opts.admin.admin_tls_insecure |= config::env_switch("PULSAR_ADMIN_TLS_INSECURE")?;
+    opts.retry.retry_jitter |= config::env_switch("PULSAR_RETRY_JITTER")?;
+    if let Some(profile) = profile {
+        profile.apply(&mut opts);
+    }
+    check_command(&opts.command, &opts.confirm, matches)?;
+    Ok(opts)
+}
+
+/// Parses `args`, a command line without the program name nor global options, into the
+/// command of a step of `run-scenario`, which runs with the global options of the scenario
+fn parse_step(opts: &Opts, args: &[String]) -> Result<Command> {
+    let matches = Command::clap()
+        .setting(AppSettings::NoBinaryName)
+        .get_matches_from_safe(args)
+        .map_err(|e| ExitError::new(exit::USAGE, e.message))?;
+    let command = Command::from_clap(&matches);
+    if let Command::RunScenario(_) = command {
+        return Err(ExitError::new(exit::USAGE, "A step can't run another scenario").into());
+    }
+    check_command(&command, &opts.confirm, &matches)?;
+    Ok(command)
+}
+
+/// Checks the options of `command` given in `matches`, and that the global ones apply to it
+fn check_command(
+    command: &Command,
+    confirm: &confirm::ConfirmOpts,
+    matches: &structopt::clap::ArgMatches,
+) -> Result<()> {
+    if output::is_kcat() && !matches!(command, Command::Consume(_)) {
+        return Err(ExitError::new(exit::USAGE, "--output kcat only applies to consume").into());
+    }
+    if confirm.dry_run && !command.is_destructive() {
+        return Err(ExitError::new(
+            exit::USAGE,
+            "--dry-run only applies to the commands that ask for confirmation, e.g. topic delete or subscription skip",
+        )
+        .into());
+    }
+    validate(command, matches)
+}
+
+impl Command {
+    /// Whether the command deletes or discards something, after confirmation
+    fn is_destructive(&self) -> bool {
+        match self {
+            Command::Topic { command } => command.is_destructive(),
+            Command::Subscription { .. } => true,
+            Command::Cursor { command } => command.is_destructive(),
+            Command::Schema { command } => command.is_destructive(),
+            Command::AuditSubscriptions(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Rejects the combinations of options that clap can't tell are wrong, which would otherwise
+/// make the command do something else than what was meant
+fn validate(command: &Command, matches: &structopt::clap::ArgMatches) -> Result<()> {
+    match command {
+        Command::Consume(consume_opts) => consume_opts.validate(matches),
+        Command::Produce(produce_opts) => produce_opts.validate(matches),
+        _ => Ok(()),
+    }
+}
+
+/// Sets up logging and confirmations as `opts` say, once before running commands
+pub fn init(opts: &Opts) {
+    logging::init(&opts.log);
+    confirm::set_opts(&opts.confirm);
+}
+
+/// What the commands connect with, set up once from the global options
+pub struct Session<'a> {
+    opts: &'a Opts,
+    url: ServiceUrls,
+    tls: TlsConfig,
+    auth: Auth,
+    proxy: Option<url::Url>,
+    connector: Arc<Connector>,
+}
+
+impl<'a> Session<'a> {
+    fn new(opts: &'a Opts) -> Result<Self> {
+        let url = match ServiceUrls::concat(&opts.url) {
+            Some(url) => url,
+            None => DEFAULT_URL.parse()?,
+        };
+        let tls = TlsConfig::load(&opts.tls)?;
+        let auth = Auth::load(&opts.auth)?;
+        let proxy = opts.proxy.url()?;
+        if let Some(proxy) = &proxy {
+            info!("Reaching the admin API through {}", proxy);
+        }
+        let connector = Arc::new(Connector::new(
+            &opts.retry,
+            tls.clone(),
+            auth.clone(),
+            proxy.clone(),
+            &opts.timeouts,
+        ));
+        Ok(Self {
+            opts,
+            url,
+            tls,
+            auth,
+            proxy,
+            connector,
+        })
+    }
+
+    fn admin_client(&self) -> Result<AdminClient> {
+        AdminClient::new(
+            &self.opts.admin,
+            self.url.first(),
+            &self.tls,
+            &self.auth,
+            &self.opts.timeouts,
+            self.proxy.as_ref(),
+        )
+    }
+}
+
+/// Runs the command of `opts`, returning what to print
+pub async fn run(opts: Opts) -> Result<Report> {
+    let mut result = run_with_session(&opts).await;
+    if let Some((line, json)) = connection_events::summary() {
+        match result.as_mut() {
+            Ok(report) if !report.is_empty() => report.append("connectionEvents", line, json),
+            // Commands that printed as they went, and failures, whose stdout is left alone
+            _ => eprintln!("{}", line),
+        }
+    }
+    result
+}
+
+/// Sets up the session of `opts` and runs its command in it
+async fn run_with_session(opts: &Opts) -> Result<Report> {
+    if let Command::Config { command } = &opts.command {
+        // Without loading what the profile refers to, so that a broken one can be fixed
+        return commands::config::run(command, opts.profile.as_deref());
+    }
+    let session = Session::new(opts)?;
+    // Stopped on returning
+    let _metrics_server = match opts.metrics_listen {
+        Some(address) => Some(metrics::serve(address).await?),
+        None => None,
+    };
+    match &opts.command {
+        Command::RunScenario(scenario_opts) => {
+            commands::run_scenario::run(&session, scenario_opts).await
+        }
+        command => run_command(&session, command).await,
+    }
+}
+
+/// Runs `command` with what `session` set up, for `run` or a step of `run-scenario`
+async fn run_command(session: &Session<'_>, command: &Command) -> Result<Report> {
+    let Session {
+        opts,
+        url,
+        connector,
+        ..
+    } = session;
+    let admin_client = || session.admin_client();
+
+    match command {
+        Command::Consume(consume_opts) => {
+            commands::consume::run(&url, &connector, &admin_client, consume_opts).await
+        }
+
+        Command::Produce(produce_opts) => {
+            commands::produce::run(
+                &url,
+                &connector,
+                &admin_client,
+                &opts.timeouts,
+                produce_opts,
+            )
+            .await
+        }
+
+        Command::Copy(copy_opts) => {
+            let admin = admin_client()?;
+            commands::copy::run(&url, &admin, &connector, copy_opts).await
+        }
+
+        Command::Mirror(mirror_opts) => commands::mirror::run(&url, &connector, mirror_opts).await,
+
+        Command::Drain(drain_opts) => {
+            let admin = admin_client()?;
+            commands::drain::run(&url, &admin, &connector, drain_opts).await
+        }
+
+        Command::Verify(verify_opts) => commands::verify::run(&url, &connector, verify_opts).await,
+
+        Command::Diff(diff_opts) => commands::diff::run(&url, &connector, diff_opts).await,
+
+        Command::Bench(bench_opts) => commands::bench::run(&url, &connector, bench_opts).await,
+
+        Command::GetMessage(get_message_opts) => {
+            commands::get_message::run(&admin_client()?, get_message_opts).await
+        }
+
+        Command::LastMessageId(last_message_id_opts) => {
+            commands::last_message_id::run(&admin_client()?, last_message_id_opts).await
+        }
+
+        Command::MessageId { command } => commands::message_id::run(&admin_client, command).await,
+
+        Command::Lookup(lookup_opts) => {
+            commands::lookup::run_lookup(&url, &connector, lookup_opts).await
+        }
+
+        Command::Partitions(partitions_opts) => {
+            commands::lookup::run_partitions(&url, &connector, partitions_opts).await
+        }
+
+        Command::Ping(ping_opts) => {
+            commands::ping::run(&url, &admin_client()?, &connector, ping_opts).await
+        }
+
+        Command::Namespaces(namespaces_opts) => {
+            commands::namespace::run_namespaces(
+                &admin_client()?,
+                opts.topics.tenant.as_deref(),
+                namespaces_opts,
+            )
+            .await
+        }
+
+        Command::Namespace { command } => commands::namespace::run(&admin_client()?, command).await,
+
+        Command::Peek(peek_opts) => commands::peek::run(&admin_client()?, peek_opts).await,
+
+        Command::Schema { command } => commands::schema::run(&admin_client()?, command).await,
+
+        Command::Stats(stats_opts) => commands::stats::run(&admin_client()?, stats_opts).await,
+
+        Command::Watch(watch_opts) => commands::watch::run(&admin_client()?, watch_opts).await,
+
+        Command::Lag(lag_opts) => commands::lag::run(&admin_client()?, lag_opts).await,
+
+        Command::Compact(compact_opts) => {
+            commands::compact::run(&admin_client()?, compact_opts).await
+        }
+
+        Command::Tenants(tenants_opts) => {
+            commands::namespace::run_tenants(&admin_client()?, tenants_opts).await
+        }
+
+        Command::Clusters(clusters_opts) => {
+            commands::cluster::run_clusters(&admin_client()?, clusters_opts).await
+        }
+
+        Command::Brokers(brokers_opts) => {
+            commands::cluster::run_brokers(&admin_client()?, brokers_opts).await
+        }
+
+        Command::Topics(topics_opts) => commands::topics::run(&admin_client()?, topics_opts).await,
+
+        Command::Subscriptions(subscriptions_opts) => {
+            commands::subscriptions::run(&admin_client()?, subscriptions_opts).await
+        }
+
+        Command::Subscription { command } => {
+            commands::subscription::run(&admin_client()?, command).await
+        }
+
+        Command::Cursor { command } => commands::cursor::run(&admin_client()?, command).await,
+
+        Command::Topic { command } => {
+            commands::topic::run(&url, &admin_client()?, &connector, command).await
+        }
+
+        Command::Backlog(backlog_opts) => {
+            commands::backlog::run(&admin_client()?, backlog_opts).await
+        }
+
+        Command::AuditSubscriptions(audit_opts) => {
+            commands::audit_subscriptions::run(&admin_client()?, audit_opts).await
+        }
+
+        Command::Consumers(consumers_opts) => {
+            commands::consumers::run(&admin_client()?, consumers_opts).await
+        }
+
+        Command::Permissions { command } => {
+            commands::permissions::run(&admin_client()?, command).await
+        }
+
+        Command::Completions { shell } => {
+            // Generated from the same definition the arguments are parsed with, so completions
+            // never miss a subcommand or flag. Into a buffer first, as clap panics when it fails
+            // writing, e.g. to a closed pipe
+            let mut completions = Vec::new();
+            Opts::clap().gen_completions_to("pulsar-cli", *shell, &mut completions);
+            output::write(&String::from_utf8_lossy(&completions))?;
+            Ok(Report::default())
+        }
+
+        Command::Config { command } => commands::config::run(command, opts.profile.as_deref()),
+
+        // Refused when parsing the steps
+        Command::RunScenario(_) => {
+            Err(ExitError::new(exit::USAGE, "A step can't run another scenario").into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPIC: &str = "persistent://t/ns/orders";
+
+    /// Parses `args` as the binary does and validates the options of their command
+    fn validated(args: &[&str]) -> Result<()> {
+        let matches = Opts::clap()
+            .get_matches_from_safe(std::iter::once("pulsar-cli").chain(args.iter().copied()))
+            .expect("The arguments should parse");
+        let opts = Opts::from_clap(&matches);
+        validate(&opts.command, &matches)
+    }
+
+    fn assert_rejected(args: &[&str]) {
+        match validated(args) {
+            Ok(()) => panic!("{:?} should be rejected", args),
+            Err(e) => assert_eq!(exit::code_for(&e), exit::USAGE, "{:?}: {}", args, e),
+        }
+    }
+
+    fn assert_accepted(args: &[&str]) {
+        if let Err(e) = validated(args) {
+            panic!("{:?} should be accepted: {}", args, e);
+        }
+    }
+
+    #[test]
+    fn consume_rejects_what_non_persistent_topics_lack() {
+        for flag in &["--durable", "--earliest", "--ack", "--warn-near-expiry"] {
+            assert_rejected(&["consume", "--topic", "non-persistent://t/ns/live", *flag]);
+            assert_accepted(&["consume", "--topic", TOPIC, *flag]);
+        }
+    }
+
+    #[test]
+    fn consume_rejects_forwarding_back_into_the_topic() {
+        assert_rejected(&["consume", "--topic", TOPIC, "--forward-to-topic", TOPIC]);
+        // The same topic, named differently
+        assert_rejected(&[
+            "consume",
+            "--topic",
+            "orders",
+            "--forward-to-topic",
+            "persistent://public/default/orders",
+        ]);
+        assert_rejected(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--forward-route",
+            &format!("env=prod:{}", TOPIC),
+        ]);
+        assert_rejected(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--forward-route",
+            "env=prod:persistent://t/ns/prod",
+            "--forward-default",
+            TOPIC,
+        ]);
+        assert_rejected(&[
+            "consume",
+            "--all-topics",
+            "--namespace",
+            "t/ns",
+            "--forward-to-topic",
+            "persistent://t/ns/copy",
+        ]);
+    }
+
+    #[test]
+    fn consume_accepts_forwarding_elsewhere() {
+        assert_accepted(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--forward-to-topic",
+            TOPIC,
+            "--forward-to-url",
+            "pulsar://other:6650",
+        ]);
+        assert_accepted(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--forward-route",
+            "env=prod:persistent://t/ns/prod",
+            "--forward-default",
+            "persistent://t/ns/other",
+        ]);
+        assert_accepted(&[
+            "consume",
+            "--all-topics",
+            "--namespace",
+            "t/ns",
+            "--forward-to-topic",
+            "persistent://t/other/copy",
+        ]);
+    }
+
+    #[test]
+    fn consume_rejects_forward_to_url_alone() {
+        assert_rejected(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--forward-to-url",
+            "pulsar://other:6650",
+        ]);
+    }
+
+    #[test]
+    fn consume_rejects_options_without_the_one_they_apply_with() {
+        for (flag, value) in &[
+            ("--window", "5m"),
+            ("--track-interval", "1s"),
+            ("--topic-refresh", "10s"),
+            ("--max-topics", "10"),
+            ("--storm-threshold", "50"),
+            ("--storm-window", "100"),
+            ("--checksum-algo", "sha256"),
+            ("--max-open-files", "8"),
+            ("--notes-file", "notes.ndjson"),
+            ("--sink-buffer", "10"),
+        ] {
+            assert_rejected(&["consume", "--topic", TOPIC, *flag, *value]);
+        }
+        assert_rejected(&[
+            "consume",
+            "--topic",
+            TOPIC,
+            "--output-dir-per-topic",
+            "out",
+            "--rotate-compress",
+        ]);
+        // Their defaults don't count as given
+        assert_accepted(&["consume", "--topic", TOPIC]);
+    }
+
+    #[test]
+    fn consume_accepts_options_with_the_one_they_apply_with() {
+        for args in &[
+            &["--track-field", "payload.status", "--window", "5m"][..],
+            &["--all-topics", "--namespace", "t/ns", "--max-topics", "10"][..],
+            &["--detect-redelivery-storms", "--storm-threshold", "50"][..],
+            &["--show-checksum", "--checksum-algo", "sha256"][..],
+            &["--output-dir-per-topic", "out", "--max-open-files", "8"][..],
+            &["--output-dir-per-topic", "out", "--rotate-interval", "1h"][..],
+            &["--annotate", "--notes-file", "notes.ndjson"][..],
+            &["--sink", "tcp://127.0.0.1:9000", "--sink-buffer", "10"][..],
+        ] {
+            let mut argv = vec!["consume"];
+            if !args.contains(&"--all-topics") {
+                argv.extend(&["--topic", TOPIC]);
+            }
+            argv.extend(args.iter());
+            assert_accepted(&argv);
+        }
+    }
+
+    #[test]
+    fn consume_rejects_display_options_when_not_printing() {
+        for (flag, value) in &[("--overflow", "drop-display"), ("--display-buffer", "10")] {
+            assert_rejected(&[
+                "consume",
+                "--topic",
+                TOPIC,
+                "--sink",
+                "tcp://127.0.0.1:9000",
+                *flag,
+                *value,
+            ]);
+            assert_rejected(&[
+                "consume",
+                "--topic",
+                TOPIC,
+                "--output-dir-per-topic",
+                "out",
+                *flag,
+                *value,
+            ]);
+            assert_accepted(&["consume", "--topic", TOPIC, *flag, *value]);
+        }
+    }
+
+    #[test]
+    fn consume_rejects_zero_rates() {
+        assert_rejected(&["consume", "--topic", TOPIC, "--max-rate", "0"]);
+        assert_rejected(&["consume", "--topic", TOPIC, "--max-msg-rate", "0"]);
+        assert_accepted(&["consume", "--topic", TOPIC, "--max-rate", "1MB/s"]);
+    }
+
+    #[test]
+    fn produce_rejects_options_without_the_one_they_apply_with() {
+        assert_rejected(&["produce", "--topic", TOPIC, "--wait-timeout", "10s"]);
+        assert_rejected(&["produce", "--topic", TOPIC, "--transform-failure", "skip"]);
+        assert_rejected(&["produce", "--topic", TOPIC, "--max-redirects", "2"]);
+        assert_accepted(&["produce", "--topic", TOPIC]);
+        assert_accepted(&[
+            "produce",
+            "--topic",
+            TOPIC,
+            "--wait-for-subscription",
+            "sub",
+            "--wait-timeout",
+            "10s",
+        ]);
+        assert_accepted(&[
+            "produce",
+            "--topic",
+            TOPIC,
+            "--from-url",
+            "http://127.0.0.1/feed",
+            "--max-redirects",
+            "2",
+        ]);
+    }
+
+    #[test]
+    fn steps_are_commands_without_global_options() {
+        let opts =
+            Opts::from_iter_safe(&["pulsar-cli", "--url", "pulsar://broker:6650", "ping"]).unwrap();
+        let step = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            parse_step(&opts, &args)
+        };
+        assert!(matches!(
+            step(&["produce", "--topic", TOPIC, "--count", "10"]),
+            Ok(Command::Produce(_))
+        ));
+        for args in &[
+            &["--url", "pulsar://other:6650", "ping"][..],
+            &["produce", "--topic", TOPIC, "--wait-timeout", "10s"][..],
+            &["run-scenario", "nested.yaml"][..],
+        ] {
+            match step(args) {
+                Ok(_) => panic!("{:?} should be rejected", args),
+                Err(e) => assert_eq!(exit::code_for(&e), exit::USAGE, "{:?}: {}", args, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Log output, always to stderr so that stdout only has what commands print
+
+use crate::connection_events;
+use anyhow::{format_err, Result};
+use chrono::Utc;
+use serde_json::json;
+use std::{io::Write, str::FromStr};
+use structopt::StructOpt;
+
+/// Filters used with no -v, then with each additional one
+const VERBOSITY_FILTERS: &[&str] = &[
+    "warn,pulsar_cli=info",
+    "info,pulsar_cli=debug",
+    "debug,pulsar_cli=trace",
+    "trace",
+];
+
+#[derive(StructOpt)]
+pub struct LogOpts {
+    /// Log more, repeat for even more (-vv, -vvv)
+    #[structopt(long, short = "v", parse(from_occurrences))]
+    pub verbose: usize,
+
+    /// Log filter such as debug or warn,pulsar_cli=trace, taking precedence over -v and
+    /// RUST_LOG
+    #[structopt(long)]
+    pub log_level: Option<String>,
+
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    pub log_format: LogFormat,
+
+    /// Print each connection to Pulsar, failover, subscription, producer creation, retry and
+    /// reconnect to stderr as it happens, with how long it took, counting them in the summary,
+    /// e.g. to attach the timeline of a flaky network to a ticket
+    #[structopt(long)]
+    pub show_connection_events: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format_err!("Invalid log format: {:?}", other)),
+        }
+    }
+}
+
+pub fn init(opts: &LogOpts) {
+    let filters = match (&opts.log_level, opts.verbose, std::env::var("RUST_LOG")) {
+        (Some(filters), _, _) => filters.clone(),
+        (None, 0, Ok(filters)) if !filters.is_empty() => filters,
+        (None, verbose, _) => {
+            VERBOSITY_FILTERS[verbose.min(VERBOSITY_FILTERS.len() - 1)].to_owned()
+        }
+    };
+    let mut builder = env_logger::Builder::new();
+    builder
+        .parse_filters(&filters)
+        .target(env_logger::Target::Stderr);
+    if opts.log_format == LogFormat::Json {
+        builder.format(|buf, record| {
+            writeln!(
+                buf,
+                "{}",
+                json!({
+                    "timestamp": Utc::now().to_rfc3339(),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                })
+            )
+        });
+    }
+    builder.init();
+    if opts.show_connection_events {
+        connection_events::enable(opts.log_format == LogFormat::Json);
+    }
+}
@@ -0,0 +1,251 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// A message ID as written by humans and in logs: `ledger:entry[:partition[:batchIndex]]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MessageId {
+    pub ledger_id: i64,
+    pub entry_id: i64,
+    pub partition: Option<i32>,
+    pub batch_index: Option<i32>,
+}
+
+impl MessageId {
+    pub const EARLIEST: MessageId = MessageId {
+        ledger_id: -1,
+        entry_id: -1,
+        partition: None,
+        batch_index: None,
+    };
+
+    pub const LATEST: MessageId = MessageId {
+        ledger_id: i64::MAX,
+        entry_id: i64::MAX,
+        partition: None,
+        batch_index: None,
+    };
+
+    /// The JSON representation the admin API expects in request bodies
+    pub fn to_admin_json(&self) -> Value {
+        json!({
+            "ledgerId": self.ledger_id,
+            "entryId": self.entry_id,
+            "partitionIndex": self.partition.unwrap_or(-1),
+            "batchIndex": self.batch_index.unwrap_or(-1),
+        })
+    }
+}
+
+impl MessageId {
+    /// In the order of the messages on a topic: by ledger, entry and then batch index, an ID
+    /// without a batch index coming before those of the messages of its batch. IDs on two
+    /// different partitions aren't ordered, an ID without a partition being taken to be on
+    /// the other's
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        // -1 being how IDs with a batch index but no partition are written
+        let partitions = (
+            self.partition.filter(|p| *p >= 0),
+            other.partition.filter(|p| *p >= 0),
+        );
+        if let (Some(partition), Some(other_partition)) = partitions {
+            if partition != other_partition {
+                return None;
+            }
+        }
+        Some(
+            (
+                self.ledger_id,
+                self.entry_id,
+                self.batch_index.unwrap_or(-1),
+            )
+                .cmp(&(
+                    other.ledger_id,
+                    other.entry_id,
+                    other.batch_index.unwrap_or(-1),
+                )),
+        )
+    }
+}
+
+/// A message ID as represented in admin API responses
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AdminMessageId {
+    ledger_id: i64,
+    entry_id: i64,
+    #[serde(default = "minus_one")]
+    partition_index: i32,
+    #[serde(default = "minus_one")]
+    batch_index: i32,
+}
+
+fn minus_one() -> i32 {
+    -1
+}
+
+impl MessageId {
+    pub fn from_admin_json(value: Value) -> Result<Self> {
+        let id = serde_json::from_value::<AdminMessageId>(value)?;
+        Ok(Self {
+            ledger_id: id.ledger_id,
+            entry_id: id.entry_id,
+            partition: Some(id.partition_index).filter(|p| *p >= 0),
+            batch_index: Some(id.batch_index).filter(|b| *b >= 0),
+        })
+    }
+}
+
+impl From<&pulsar::proto::MessageIdData> for MessageId {
+    /// The ID of a message the broker acknowledged receiving
+    fn from(id: &pulsar::proto::MessageIdData) -> Self {
+        Self {
+            ledger_id: id.ledger_id as i64,
+            entry_id: id.entry_id as i64,
+            partition: id.partition.filter(|p| *p >= 0),
+            batch_index: id.batch_index.filter(|b| *b >= 0),
+        }
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts = s.split(':').collect::<Vec<_>>();
+        if parts.len() < 2 || parts.len() > 4 {
+            bail!(
+                "Invalid message ID {:?}, expected ledger:entry[:partition[:batchIndex]]",
+                s
+            );
+        }
+        let component = |index: usize, name: &str| -> Result<Option<i64>> {
+            match parts.get(index) {
+                None => Ok(None),
+                Some(part) => match part.parse() {
+                    Ok(value) => Ok(Some(value)),
+                    Err(_) => bail!("Invalid {} {:?} in message ID {:?}", name, part, s),
+                },
+            }
+        };
+
+        Ok(Self {
+            ledger_id: component(0, "ledger ID")?.unwrap_or_default(),
+            entry_id: component(1, "entry ID")?.unwrap_or_default(),
+            partition: component(2, "partition")?.map(|p| p as i32),
+            batch_index: component(3, "batch index")?.map(|b| b as i32),
+        })
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.ledger_id, self.entry_id)?;
+        if self.partition.is_some() || self.batch_index.is_some() {
+            write!(f, ":{}", self.partition.unwrap_or(-1))?;
+        }
+        if let Some(batch_index) = self.batch_index {
+            write!(f, ":{}", batch_index)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(
+        ledger_id: i64,
+        entry_id: i64,
+        partition: Option<i32>,
+        batch_index: Option<i32>,
+    ) -> MessageId {
+        MessageId {
+            ledger_id,
+            entry_id,
+            partition,
+            batch_index,
+        }
+    }
+
+    #[test]
+    fn valid_ids() {
+        assert_eq!(
+            "12:34".parse::<MessageId>().unwrap(),
+            id(12, 34, None, None)
+        );
+        assert_eq!(
+            "12:34:2".parse::<MessageId>().unwrap(),
+            id(12, 34, Some(2), None)
+        );
+        assert_eq!("-1:-1".parse::<MessageId>().unwrap(), MessageId::EARLIEST);
+        for text in &["12:34", "12:34:2", "12:34:2:5", "12:34:-1:5"] {
+            assert_eq!(text.parse::<MessageId>().unwrap().to_string(), *text);
+        }
+    }
+
+    #[test]
+    fn batch_indexes() {
+        assert_eq!(
+            "12:34:2:5".parse::<MessageId>().unwrap(),
+            id(12, 34, Some(2), Some(5))
+        );
+        // Without a partition, as the client library writes batched IDs of unpartitioned topics
+        assert_eq!(
+            "12:34:-1:5".parse::<MessageId>().unwrap(),
+            id(12, 34, Some(-1), Some(5))
+        );
+        assert_eq!(id(12, 34, None, Some(5)).to_string(), "12:34:-1:5");
+    }
+
+    #[test]
+    fn malformed_ids() {
+        for text in &[
+            "",
+            "12",
+            "12:",
+            ":34",
+            "12:34:2:5:6",
+            "a:34",
+            "12:b",
+            "12:34:x",
+            "12:34:2:y",
+            "12.5:34",
+        ] {
+            assert!(text.parse::<MessageId>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn ordering_by_ledger_entry_and_batch_index() {
+        let ordered = [
+            id(11, 99, None, None),
+            id(12, 33, None, None),
+            id(12, 34, None, None),
+            id(12, 34, None, Some(0)),
+            id(12, 34, None, Some(5)),
+            id(12, 35, None, Some(0)),
+            id(13, 0, None, None),
+        ];
+        for (i, a) in ordered.iter().enumerate() {
+            for (j, b) in ordered.iter().enumerate() {
+                assert_eq!(a.compare(b), Some(i.cmp(&j)), "{} and {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn ordering_across_partitions() {
+        let on_2 = id(12, 34, Some(2), None);
+        assert_eq!(on_2.compare(&id(12, 35, Some(3), None)), None);
+        // Without a partition, taken to be on the other's
+        assert_eq!(on_2.compare(&id(12, 35, None, None)), Some(Ordering::Less));
+        assert_eq!(
+            id(12, 33, Some(-1), Some(0)).compare(&on_2),
+            Some(Ordering::Less)
+        );
+        assert_eq!(on_2.compare(&on_2), Some(Ordering::Equal));
+    }
+}
@@ -0,0 +1,281 @@
+//! Counters of long-running sessions, printed in their summaries and, with `--metrics-listen`,
+//! exposed to Prometheus
+
+use anyhow::{format_err, Result};
+use log::{info, warn};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Upper bounds of the end-to-end latency histogram buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 60.0];
+
+/// The counters of this process, shared by everything that reports them
+pub static STATS: Lazy<Stats> = Lazy::new(Stats::default);
+
+#[derive(Default)]
+pub struct Stats {
+    pub consumed: AtomicU64,
+    pub consumed_bytes: AtomicU64,
+    pub produced: AtomicU64,
+    pub produced_bytes: AtomicU64,
+    /// Messages consumed and sent on to another topic, e.g. with --forward-to-topic or by mirror
+    pub forwarded: AtomicU64,
+    pub acked: AtomicU64,
+    pub ack_failures: AtomicU64,
+    pub consumer_reconnects: AtomicU64,
+    /// Consumers rebuilt by consume --liveness-timeout after receiving nothing for that long
+    pub idle_reconnects: AtomicU64,
+    pub producer_reconnects: AtomicU64,
+    pub send_failures: AtomicU64,
+    /// Times consuming waited for --max-rate or --max-msg-rate
+    pub throttled: AtomicU64,
+    /// Messages dropped by consume --sink because its buffer was full
+    pub sink_dropped: AtomicU64,
+    /// Messages consume didn't print, with --overflow drop-display, because printing couldn't
+    /// keep up
+    pub display_dropped: AtomicU64,
+    /// Messages consume received and holds unacked, until printed or for --ack-hold or
+    /// --ack-hold-time, a gauge
+    pub held_unacked: AtomicU64,
+    latency: Histogram,
+}
+
+/// Counts of observations under each of `LATENCY_BUCKETS`, plus those above all of them
+#[derive(Default)]
+struct Histogram {
+    buckets: [AtomicU64; 10],
+    sum_micros: AtomicU64,
+}
+
+/// Increments `counter`, e.g. `metrics::add(&metrics::STATS.acked, 1)`
+pub fn add(counter: &AtomicU64, n: u64) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+/// Sets a gauge, e.g. `metrics::set(&metrics::STATS.held_unacked, 10)`
+pub fn set(gauge: &AtomicU64, n: u64) {
+    gauge.store(n, Ordering::Relaxed);
+}
+
+pub fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+impl Stats {
+    pub fn consumed(&self, payload: &[u8]) {
+        add(&self.consumed, 1);
+        add(&self.consumed_bytes, payload.len() as u64);
+    }
+
+    pub fn produced(&self, payload: &[u8]) {
+        add(&self.produced, 1);
+        add(&self.produced_bytes, payload.len() as u64);
+    }
+
+    /// Records how long after `publish_time` (milliseconds since the epoch) a message was
+    /// received
+    pub fn observe_latency(&self, publish_time: u64) {
+        let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        // Clocks of the brokers and this host may disagree
+        let latency = Duration::from_millis(now.saturating_sub(publish_time));
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        add(&self.latency.buckets[bucket], 1);
+        add(&self.latency.sum_micros, latency.as_micros() as u64);
+    }
+
+    /// The counters in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            ("messages_consumed", "Messages received", &self.consumed),
+            (
+                "bytes_consumed",
+                "Payload bytes received",
+                &self.consumed_bytes,
+            ),
+            ("messages_produced", "Messages sent", &self.produced),
+            ("bytes_produced", "Payload bytes sent", &self.produced_bytes),
+            (
+                "messages_forwarded",
+                "Messages received and sent on to another topic",
+                &self.forwarded,
+            ),
+            ("acks", "Messages acknowledged", &self.acked),
+            (
+                "ack_failures",
+                "Acks that failed, whether tried again or not",
+                &self.ack_failures,
+            ),
+            (
+                "consumer_reconnects",
+                "Consumers rebuilt after failing",
+                &self.consumer_reconnects,
+            ),
+            (
+                "idle_reconnects",
+                "Consumers rebuilt after receiving nothing for --liveness-timeout",
+                &self.idle_reconnects,
+            ),
+            (
+                "producer_reconnects",
+                "Producers rebuilt after failing",
+                &self.producer_reconnects,
+            ),
+            (
+                "send_failures",
+                "Messages that failed to send, whether sent again or dropped",
+                &self.send_failures,
+            ),
+            (
+                "throttled",
+                "Times consuming waited to stay under its rate limit",
+                &self.throttled,
+            ),
+            (
+                "sink_dropped",
+                "Messages not streamed to the sink because its buffer was full",
+                &self.sink_dropped,
+            ),
+            (
+                "display_dropped",
+                "Messages not printed because printing couldn't keep up",
+                &self.display_dropped,
+            ),
+        ];
+        for (name, help, counter) in counters.iter() {
+            let _ = writeln!(out, "# HELP pulsar_cli_{}_total {}", name, help);
+            let _ = writeln!(out, "# TYPE pulsar_cli_{}_total counter", name);
+            let _ = writeln!(out, "pulsar_cli_{}_total {}", name, get(counter));
+        }
+        let name = "pulsar_cli_held_unacked_messages";
+        let _ = writeln!(
+            out,
+            "# HELP {} Messages received and not acked yet, until printed or for --ack-hold",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        let _ = writeln!(out, "{} {}", name, get(&self.held_unacked));
+
+        let name = "pulsar_cli_end_to_end_latency_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time from publishing to receiving messages",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        let mut count = 0;
+        for (i, bucket) in self.latency.buckets.iter().enumerate() {
+            count += get(bucket);
+            let bound = match LATENCY_BUCKETS.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        let sum = get(&self.latency.sum_micros) as f64 / 1e6;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        let _ = writeln!(out, "{}_count {}", name, count);
+        out
+    }
+}
+
+/// The percentiles of `latencies`, as text and as JSON, if there are any
+pub fn describe_latencies(label: &str, latencies: &mut [Duration]) -> Option<(String, Value)> {
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    let percentile = |p: f64| {
+        let index = ((latencies.len() - 1) as f64 * p).round() as usize;
+        latencies[index].as_secs_f64() * 1000.0
+    };
+    let (p50, p90, p99, max) = (
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0),
+    );
+    Some((
+        format!(
+            "{}: p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms, max {:.1}ms",
+            label, p50, p90, p99, max
+        ),
+        json!({ "p50": p50, "p90": p90, "p99": p99, "max": max }),
+    ))
+}
+
+/// The HTTP server of `--metrics-listen`, stopped when dropped
+pub struct MetricsServer {
+    task: JoinHandle<()>,
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Starts serving the metrics on `address`, failing right away if it can't be listened on
+pub async fn serve(address: SocketAddr) -> Result<MetricsServer> {
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|e| format_err!("Failed listening on {} for metrics: {}", address, e))?;
+    info!("Serving metrics on http://{}/metrics", address);
+    let task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            warn!("Failed serving metrics: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed accepting a metrics connection: {}", e),
+            }
+        }
+    });
+    Ok(MetricsServer { task })
+}
+
+/// Answers a single request, with the metrics for `GET /metrics`
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    // Only the request line matters, the rest of the request is ignored
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let response = match request
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["GET", "/metrics"] | ["GET", "/"] => {
+            let body = STATS.render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Checking that the messages of each partition key arrive in order, for consume, copy and
+//! mirror with `--verify-key-order`. Messages they forward are tagged with their position on
+//! the source topic, so that another pulsar-cli consuming the destination with
+//! `--verify-forwarded` can tell whether forwarding kept them in order.
+
+use crate::{
+    display,
+    forwarding::{position_of, Position},
+    message_id::MessageId,
+};
+use log::warn;
+use pulsar::consumer::Message;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Source partition and position of a forwarded message, as `<partition>@ledger:entry:batch`
+pub const SOURCE_POSITION_PROPERTY: &str = "pulsar-cli-source-position";
+
+/// What was last seen of a key
+#[derive(Default)]
+struct KeyState {
+    event_time: Option<u64>,
+    /// Last sequence ID of each producer
+    sequences: HashMap<String, u64>,
+    /// Last source partition and position, with `--verify-forwarded`
+    source: Option<(String, Position)>,
+}
+
+#[derive(Default)]
+pub struct KeyOrder {
+    /// Whether to check the source positions tagged by the forwarding pulsar-cli rather than
+    /// event times and sequence IDs
+    forwarded: bool,
+    keys: HashMap<String, KeyState>,
+    violations: BTreeMap<String, u64>,
+    checked: u64,
+}
+
+impl KeyOrder {
+    pub fn new(forwarded: bool) -> Self {
+        Self {
+            forwarded,
+            ..Default::default()
+        }
+    }
+
+    /// Checks `message` against the previous messages of its key, warning if it is out of order
+    pub fn check(&mut self, message: &Message<Vec<u8>>) {
+        let metadata = message.metadata();
+        let key = metadata.partition_key.clone().unwrap_or_default();
+        let state = self.keys.entry(key.clone()).or_default();
+        self.checked += 1;
+
+        let violation = if self.forwarded {
+            match source_position(message) {
+                Some(source) => {
+                    let previous = state.source.replace(source.clone());
+                    match previous {
+                        Some((partition, position))
+                            if partition == source.0 && position >= source.1 =>
+                        {
+                            Some(format!(
+                                "source position {:?} after {:?}",
+                                source.1, position
+                            ))
+                        }
+                        _ => None,
+                    }
+                }
+                None => {
+                    warn!(
+                        "Message {} has no {} property, was it forwarded with --verify-key-order?",
+                        MessageId::from(&message.message_id.id),
+                        SOURCE_POSITION_PROPERTY
+                    );
+                    None
+                }
+            }
+        } else {
+            let event_time = metadata.event_time;
+            let earlier_event = match (state.event_time, event_time) {
+                (Some(last), Some(current)) if current < last => Some(format!(
+                    "event time {} after {}",
+                    display::Timestamp::from_millis(current),
+                    display::Timestamp::from_millis(last)
+                )),
+                _ => None,
+            };
+            if event_time.is_some() {
+                state.event_time = event_time;
+            }
+            // Messages of the same batch share a sequence ID
+            let last_sequence = state
+                .sequences
+                .insert(metadata.producer_name.clone(), metadata.sequence_id);
+            let earlier_sequence = match last_sequence {
+                Some(last) if metadata.sequence_id < last => Some(format!(
+                    "sequence ID {} of producer {:?} after {}",
+                    metadata.sequence_id, metadata.producer_name, last
+                )),
+                _ => None,
+            };
+            earlier_event.or(earlier_sequence)
+        };
+
+        if let Some(violation) = violation {
+            warn!(
+                "Message {} of key {:?} out of order: {}",
+                MessageId::from(&message.message_id.id),
+                key,
+                violation
+            );
+            *self.violations.entry(key).or_default() += 1;
+        }
+    }
+
+    /// The violations of each key, as text and as JSON
+    pub fn summary(&self) -> (Vec<String>, Value) {
+        let total = self.violations.values().sum::<u64>();
+        let mut lines = vec![format!(
+            "Key order: {} messages of {} keys checked, {} out of order",
+            self.checked,
+            self.keys.len(),
+            total
+        )];
+        lines.extend(
+            self.violations
+                .iter()
+                .map(|(key, count)| format!("  {:?}: {} out of order", key, count)),
+        );
+        let json = json!({
+            "checked": self.checked,
+            "keys": self.keys.len(),
+            "outOfOrder": total,
+            "violations": self.violations,
+        });
+        (lines, json)
+    }
+}
+
+/// Tags `copy`, about to be forwarded, with the position of `source`, the message it is a copy
+/// of
+pub fn tag_source(copy: &mut pulsar::producer::Message, source: &Message<Vec<u8>>) {
+    let (ledger_id, entry_id, batch_index) = position_of(source);
+    copy.properties.insert(
+        SOURCE_POSITION_PROPERTY.to_owned(),
+        format!(
+            "{}@{}:{}:{}",
+            source.topic, ledger_id, entry_id, batch_index
+        ),
+    );
+}
+
+fn source_position(message: &Message<Vec<u8>>) -> Option<(String, Position)> {
+    let (_, value) = display::properties(message)
+        .into_iter()
+        .find(|(name, _)| *name == SOURCE_POSITION_PROPERTY)?;
+    let (partition, position) = value.rsplit_once('@')?;
+    let mut components = position.split(':');
+    let position = (
+        components.next()?.parse().ok()?,
+        components.next()?.parse().ok()?,
+        components.next()?.parse().ok()?,
+    );
+    Some((partition.to_owned(), position))
+}
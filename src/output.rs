@@ -0,0 +1,271 @@
+//! What commands print once they are done, as text for people or, with `--output json`, as a
+//! single JSON value for scripts. With `--output json`, errors are printed to stderr as
+//! `{"error": "...", "code": N}` too, `code` being the exit code (see `exit`)
+//!
+//! Everything printed to stdout goes through `write`, so that a closed stdout (e.g.
+//! `pulsar-cli consume | head -5`) stops the command as if it were done rather than panicking.
+
+use anyhow::{format_err, Result};
+use colored_json::to_colored_json_auto;
+use once_cell::sync::OnceCell;
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+use structopt::StructOpt;
+use termion::{clear, cursor};
+
+static FORMAT: OnceCell<OutputFormat> = OnceCell::new();
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "How results are printed")]
+#[derive(StructOpt)]
+pub struct OutputOpts {
+    /// Print results as text or as JSON, errors included. consume can also print messages the
+    /// way kcat does, see its --kcat-format
+    #[structopt(
+        long,
+        env = "PULSAR_OUTPUT",
+        default_value = "text",
+        possible_values = &["text", "json", "kcat"]
+    )]
+    pub output: OutputFormat,
+}
+
+/// The value of `--output`
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    /// Messages as kcat prints them, only for consume; text otherwise
+    Kcat,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "kcat" => Ok(OutputFormat::Kcat),
+            other => Err(format_err!("Invalid output format: {:?}", other)),
+        }
+    }
+}
+
+/// Makes results print as `format` from then on
+pub fn set_format(format: OutputFormat) {
+    // Only ever set once, before any command runs
+    let _ = FORMAT.set(format);
+}
+
+/// Whether `--output json` was given
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Json)
+}
+
+/// Whether `--output kcat` was given
+pub fn is_kcat() -> bool {
+    FORMAT.get() == Some(&OutputFormat::Kcat)
+}
+
+/// Whether a command that has a `--json` option (given if `flag`) prints JSON, which
+/// `--output json` implies
+pub fn json(flag: bool) -> bool {
+    flag || is_json()
+}
+
+/// The result of a command
+#[derive(Default)]
+pub struct Report {
+    text: Option<String>,
+    json: Option<Value>,
+}
+
+impl Report {
+    /// A result told by `text` or, with `--output json`, `json`
+    pub fn new(text: impl Into<String>, json: Value) -> Self {
+        Self {
+            text: Some(text.into()),
+            json: Some(json),
+        }
+    }
+
+    /// A result that is the same JSON value whatever the output format, e.g. for commands
+    /// given `--json`
+    pub fn json(json: Value) -> Self {
+        Self {
+            text: None,
+            json: Some(json),
+        }
+    }
+
+    /// A result that is only a message, `{"message": ...}` with `--output json`
+    pub fn message(text: impl Into<String>) -> Self {
+        let text = text.into();
+        Self::new(text.clone(), json!({ "message": text }))
+    }
+
+    /// Replaces what the result is with `--output json`
+    pub fn set_json(&mut self, json: Value) {
+        self.json = Some(json);
+    }
+
+    /// What the result is with `--output json`, e.g. for a scenario to check it
+    pub fn json_value(&self) -> Option<&Value> {
+        self.json.as_ref()
+    }
+
+    /// Whether there is nothing to print, e.g. for commands that printed as they went
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.json.is_none()
+    }
+
+    /// Adds `line` to the text of the result and `json` to its JSON as `key`, when that is an
+    /// object, e.g. to add to a summary
+    pub fn append(&mut self, key: &str, line: impl AsRef<str>, json: Value) {
+        self.line(line);
+        if let Some(Value::Object(object)) = self.json.as_mut() {
+            object.insert(key.to_owned(), json);
+        }
+    }
+
+    /// Adds a line to the text of the result
+    pub fn line(&mut self, line: impl AsRef<str>) {
+        let text = self.text.get_or_insert_with(String::new);
+        text.push_str(line.as_ref());
+        text.push('\n');
+    }
+
+    /// Prints the result to stdout in the format asked for
+    pub fn print(&self) -> Result<()> {
+        match (&self.text, &self.json) {
+            (Some(text), _) if !is_json() => write(&with_newline(text)),
+            (_, Some(json)) => write_line(to_colored_json_auto(json)?),
+            // Commands that printed as they went, e.g. consume
+            (_, None) => Ok(()),
+        }
+    }
+}
+
+impl From<()> for Report {
+    fn from(_: ()) -> Self {
+        Self::default()
+    }
+}
+
+fn with_newline(text: &str) -> String {
+    if text.is_empty() || text.ends_with('\n') {
+        text.to_owned()
+    } else {
+        format!("{}\n", text)
+    }
+}
+
+/// Stdout was closed before everything was printed. Like other Unix tools, pulsar-cli then
+/// exits quietly and successfully
+#[derive(Debug)]
+pub struct StdoutClosed;
+
+impl fmt::Display for StdoutClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Stdout closed")
+    }
+}
+
+impl std::error::Error for StdoutClosed {}
+
+/// Whether `error` is only that stdout was closed
+pub fn is_stdout_closed(error: &anyhow::Error) -> bool {
+    error.chain().any(|e| e.is::<StdoutClosed>())
+}
+
+/// Prints `text` to stdout as it is, failing with `StdoutClosed` once nothing reads it anymore
+pub fn write(text: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(text.as_bytes())
+        .and_then(|_| stdout.flush())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::BrokenPipe => StdoutClosed.into(),
+            _ => format_err!("Failed writing to stdout: {}", e),
+        })
+}
+
+/// Prints `line` and a newline, as `write` does
+pub fn write_line(line: impl fmt::Display) -> Result<()> {
+    write(&format!("{}\n", line))
+}
+
+/// Prints a block of lines refreshed periodically, e.g. a table of stats: on a terminal in
+/// place of the block printed before, otherwise (e.g. when logging to a file, or with
+/// `--output json`) after it
+pub struct Redraw {
+    tty: bool,
+    drawn_lines: u16,
+}
+
+impl Redraw {
+    /// Drawing to stdout, in place if it is a terminal
+    pub fn stdout() -> Self {
+        Self {
+            tty: !is_json() && termion::is_tty(&io::stdout()),
+            drawn_lines: 0,
+        }
+    }
+
+    /// Whether blocks are drawn in place
+    pub fn is_tty(&self) -> bool {
+        self.tty
+    }
+
+    /// Prints `block`, replacing the one drawn before on a terminal
+    pub fn draw(&mut self, block: &str) -> Result<()> {
+        let mut out = block.to_owned();
+        if self.tty && self.drawn_lines > 0 {
+            out.insert_str(
+                0,
+                &format!("{}\r{}", cursor::Up(self.drawn_lines), clear::AfterCursor),
+            );
+        }
+        write(&out)?;
+        self.drawn_lines = block.lines().count() as u16;
+        Ok(())
+    }
+}
+
+/// Prints how a command is getting on, to stdout, or to stderr with `--output json` to keep
+/// stdout for the result
+pub fn progress(line: impl AsRef<str>) -> Result<()> {
+    if is_json() {
+        eprintln_lossy(line.as_ref());
+        Ok(())
+    } else {
+        write_line(line.as_ref())
+    }
+}
+
+/// Prints `line` to stderr, unless it was closed too
+fn eprintln_lossy(line: &str) {
+    let _ = writeln!(io::stderr(), "{}", line);
+}
+
+/// Prints why a command failed, to stderr: what failed and its root cause, or with `verbose`
+/// the whole chain of causes
+pub fn print_error(error: &anyhow::Error, code: i32, verbose: bool) {
+    if is_json() {
+        eprintln_lossy(&json!({ "error": format!("{:#}", error), "code": code }).to_string());
+    } else if verbose {
+        eprintln_lossy(&format!("Error: {:?}", error));
+    } else {
+        let (message, cause) = (error.to_string(), error.root_cause().to_string());
+        if message.contains(&cause) {
+            eprintln_lossy(&format!("Error: {}", message));
+        } else {
+            eprintln_lossy(&format!("Error: {}: {}", message, cause));
+        }
+    }
+}
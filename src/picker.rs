@@ -0,0 +1,166 @@
+//! Picking a topic or subscription on the terminal when its name isn't given, narrowing the
+//! choices down by typing part of it
+
+use crate::{
+    admin::AdminClient,
+    exit::{self, ExitError},
+    topic_name::{NamespaceName, TopicName},
+};
+use anyhow::Result;
+use std::io::{self, Write};
+use termion::{clear, cursor, event::Key, input::TermRead, raw::IntoRawMode};
+
+/// How many of the matches are listed above the query
+const VISIBLE_MATCHES: usize = 10;
+
+/// Whether there is a terminal to pick on
+fn is_interactive() -> bool {
+    termion::is_tty(&io::stdin()) && termion::is_tty(&io::stderr())
+}
+
+fn missing(option: &str) -> anyhow::Error {
+    ExitError::new(
+        exit::USAGE,
+        format!(
+            "Missing {}, required without a terminal to pick from",
+            option
+        ),
+    )
+    .into()
+}
+
+/// A topic picked among those of the namespace bare topic names live in, for when `--topic`
+/// isn't given
+pub async fn topic(admin: &AdminClient) -> Result<String> {
+    if !is_interactive() {
+        return Err(missing("--topic"));
+    }
+
+    // Parsed the way bare names are, for the namespace of --tenant/--namespace or the profile
+    let probe = "topic".parse::<TopicName>()?;
+    let namespace = NamespaceName {
+        tenant: probe.tenant,
+        namespace: probe.namespace,
+    };
+    // Partitions are picked through their partitioned topic
+    let topics = admin.top_level_topics(&namespace, probe.persistent).await?;
+    pick(
+        &format!("Topic in {}", namespace),
+        &topics.into_iter().collect::<Vec<_>>(),
+    )
+}
+
+/// The subscription given, or else one picked among those of `topic`
+pub async fn subscription(
+    admin: &AdminClient,
+    topic: &TopicName,
+    given: Option<&str>,
+) -> Result<String> {
+    if let Some(subscription) = given {
+        return Ok(subscription.to_owned());
+    }
+    if !is_interactive() {
+        return Err(missing("--subscription"));
+    }
+    let mut subscriptions = admin.subscriptions(topic).await?;
+    subscriptions.sort();
+    pick(&format!("Subscription of {}", topic), &subscriptions)
+}
+
+/// Lets the user choose one of `items` with the arrow keys and Enter, typing to filter them.
+/// Drawn on stderr so that stdout stays for what the command prints
+fn pick(prompt: &str, items: &[String]) -> Result<String> {
+    if items.is_empty() {
+        return Err(
+            ExitError::new(exit::NOT_FOUND, format!("{}: none to pick from", prompt)).into(),
+        );
+    }
+
+    let mut screen = io::stderr().into_raw_mode()?;
+    let mut keys = io::stdin().keys();
+    let mut query = String::new();
+    let mut selected = 0;
+    let mut drawn_lines = 0;
+    loop {
+        let matches = matching(items, &query);
+        let visible = matches.len().min(VISIBLE_MATCHES);
+        selected = selected.min(visible.saturating_sub(1));
+
+        erase(&mut screen, drawn_lines)?;
+        for (i, item) in matches.iter().take(visible).enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            write!(screen, "{} {}\r\n", marker, item)?;
+        }
+        write!(
+            screen,
+            "{} ({}/{}): {}",
+            prompt,
+            matches.len(),
+            items.len(),
+            query
+        )?;
+        screen.flush()?;
+        drawn_lines = visible as u16;
+
+        match keys.next().transpose()? {
+            Some(Key::Char('\n')) => {
+                if let Some(item) = matches.get(selected) {
+                    erase(&mut screen, drawn_lines)?;
+                    return Ok((*item).to_owned());
+                }
+            }
+            Some(Key::Up) | Some(Key::Ctrl('p')) => selected = selected.saturating_sub(1),
+            Some(Key::Down) | Some(Key::Ctrl('n')) => selected += 1,
+            Some(Key::Backspace) => {
+                query.pop();
+            }
+            Some(Key::Char(c)) if !c.is_control() => {
+                query.push(c);
+                selected = 0;
+            }
+            Some(Key::Esc) | Some(Key::Ctrl('c')) | Some(Key::Ctrl('d')) | None => {
+                erase(&mut screen, drawn_lines)?;
+                return Err(ExitError::new(exit::REFUSED, "Aborted").into());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Clears what was drawn, `lines` of matches and the query below them
+fn erase(screen: &mut impl Write, lines: u16) -> io::Result<()> {
+    if lines > 0 {
+        write!(screen, "{}", cursor::Up(lines))?;
+    }
+    write!(screen, "\r{}", clear::AfterCursor)
+}
+
+/// The items with all the characters of `query` in order, ignoring case, the ones where they
+/// are closest together first
+fn matching<'a>(items: &'a [String], query: &str) -> Vec<&'a str> {
+    let query = query.to_lowercase().chars().collect::<Vec<_>>();
+    let mut scored = items
+        .iter()
+        .filter_map(|item| Some((score(&item.to_lowercase(), &query)?, item.as_str())))
+        .collect::<Vec<_>>();
+    scored.sort();
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+/// How far apart the characters of `query` are in `item`, then where they start, or None if
+/// they aren't all in it
+fn score(item: &str, query: &[char]) -> Option<(usize, usize)> {
+    let mut remaining = query.iter().peekable();
+    let (mut start, mut end) = (None, 0);
+    for (i, c) in item.chars().enumerate() {
+        if remaining.peek() == Some(&&c) {
+            start.get_or_insert(i);
+            end = i;
+            remaining.next();
+        }
+    }
+    if remaining.peek().is_some() {
+        return None;
+    }
+    Some(start.map_or((0, 0), |start| (end - start, start)))
+}
@@ -0,0 +1,95 @@
+//! Checks made with the admin API before consume subscribes, so that subscribing in a way the
+//! broker would refuse is explained, with what to do instead, rather than failing with the
+//! client's error. Skipped when the admin API can't tell, never holding up consuming
+
+use crate::{
+    admin::AdminClient,
+    exit::{self, ExitError},
+    topic_name::TopicName,
+};
+use anyhow::Result;
+use log::{debug, info};
+use pulsar::SubType;
+use std::time::Duration;
+
+/// How long the admin API has to answer before consuming goes ahead unchecked
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fails if subscribing to `subscription` on `topic` as `sub_type` would be refused because of
+/// the consumers already connected to it
+pub async fn check_subscription(
+    admin: &AdminClient,
+    topic: &TopicName,
+    subscription: &str,
+    sub_type: &SubType,
+) -> Result<()> {
+    let stats = match tokio::time::timeout(PREFLIGHT_TIMEOUT, admin.topic_stats(topic)).await {
+        Ok(Ok(stats)) => stats,
+        Ok(Err(e)) => {
+            debug!("Not checking subscription {:?}: {:#}", subscription, e);
+            return Ok(());
+        }
+        Err(_) => {
+            debug!(
+                "Not checking subscription {:?}: the admin API didn't answer within {:?}",
+                subscription, PREFLIGHT_TIMEOUT
+            );
+            return Ok(());
+        }
+    };
+    let existing = match stats.subscriptions.get(subscription) {
+        Some(existing) => existing,
+        None => return Ok(()),
+    };
+    let wanted = match sub_type {
+        SubType::Shared => "Shared",
+        _ => "Exclusive",
+    };
+    if existing.consumers.is_empty() {
+        if !existing.sub_type.is_empty() && existing.sub_type != wanted {
+            info!(
+                "Subscription {:?} on {} was {} and becomes {} with no consumers connected",
+                subscription, topic, existing.sub_type, wanted
+            );
+        }
+        return Ok(());
+    }
+
+    let consumers = existing
+        .consumers
+        .iter()
+        .map(|consumer| format!("{} ({})", consumer.consumer_name, consumer.address))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let explanation = match (existing.sub_type.as_str(), wanted) {
+        ("Shared", "Shared") => return Ok(()),
+        ("Exclusive", "Exclusive") => format!(
+            "Subscription {:?} on {} is Exclusive and already has a consumer: {}; pick another --subscription-name",
+            subscription, topic, consumers
+        ),
+        ("Exclusive", _) => format!(
+            "Subscription {:?} on {} is Exclusive and already has a consumer: {}, so the broker won't let a Shared one in; pick another --subscription-name",
+            subscription, topic, consumers
+        ),
+        ("Shared", _) => format!(
+            "Subscription {:?} on {} is Shared with {} consumer(s) connected ({}), so the broker won't let an Exclusive one in; pass --shared to share its messages with them, or pick another --subscription-name",
+            subscription,
+            topic,
+            existing.consumers.len(),
+            consumers
+        ),
+        (other, _) => format!(
+            "Subscription {:?} on {} is {} with {} consumer(s) connected ({}), which consume can't join; pick another --subscription-name",
+            subscription,
+            topic,
+            other,
+            existing.consumers.len(),
+            consumers
+        ),
+    };
+    Err(ExitError::new(
+        exit::REFUSED,
+        format!("{} (or pass --no-preflight to try anyway)", explanation),
+    )
+    .into())
+}
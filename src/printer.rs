@@ -0,0 +1,148 @@
+//! Printing consumed messages from a thread of its own, fed through a bounded queue, so that
+//! a slow terminal or pipe doesn't hold up receiving, acking and forwarding. When the queue is
+//! full, consume either waits for it (`--overflow block`) or doesn't print the message
+//! (`--overflow drop-display`), still acking and forwarding it
+
+use crate::{
+    display,
+    metrics::{self, STATS},
+    output,
+};
+use anyhow::{format_err, Result};
+use log::warn;
+use pulsar::consumer::Message;
+use std::{str::FromStr, thread};
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    watch,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Block,
+    DropDisplay,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop-display" => Ok(Overflow::DropDisplay),
+            other => Err(format_err!("Invalid overflow handling: {:?}", other)),
+        }
+    }
+}
+
+/// A message to print, with what is printed under it
+pub struct Printed {
+    pub message: Message<Vec<u8>>,
+    /// Printed as they are
+    pub lines: Vec<String>,
+    /// Printed in red
+    pub problems: Vec<String>,
+}
+
+pub struct Printer {
+    queue: mpsc::Sender<Printed>,
+    overflow: Overflow,
+    capacity: usize,
+    queued: u64,
+    /// How many messages were printed
+    printed: watch::Receiver<u64>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+}
+
+impl Printer {
+    /// Starts printing each message queued as `format` has it, up to `capacity` waiting
+    pub fn spawn<F>(capacity: usize, overflow: Overflow, format: F) -> Self
+    where
+        F: Fn(&Message<Vec<u8>>) -> Result<String> + Send + 'static,
+    {
+        let capacity = capacity.max(1);
+        let (queue, mut messages) = mpsc::channel::<Printed>(capacity);
+        let (printed, printed_receiver) = watch::channel(0);
+        // A thread rather than a task, so that stdout blocking (e.g. a paused terminal) can't
+        // hold up the runtime or its shutdown
+        let thread = thread::spawn(move || {
+            let mut count = 0;
+            while let Some(message) = messages.blocking_recv() {
+                let mut out = format(&message.message)?;
+                for line in message.lines {
+                    out.push_str(&line);
+                    out.push('\n');
+                }
+                out.push_str(&display::highlight(&message.problems));
+                output::write(&out)?;
+                count += 1;
+                let _ = printed.send(count);
+            }
+            Ok(())
+        });
+        Self {
+            queue,
+            overflow,
+            capacity,
+            queued: 0,
+            printed: printed_receiver,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queues `printed`, returning how many messages have to be printed before it is, unless
+    /// it was dropped. Fails with why printing stopped if it did, e.g. `output::StdoutClosed`
+    pub async fn print(&mut self, printed: Printed) -> Result<Option<u64>> {
+        let queued = match self.overflow {
+            Overflow::Block => self.queue.send(printed).await.is_ok(),
+            Overflow::DropDisplay => match self.queue.try_send(printed) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    if metrics::get(&STATS.display_dropped) == 0 {
+                        warn!(
+                            "Printing can't keep up, not printing messages while {} are waiting to be",
+                            self.capacity
+                        );
+                    }
+                    metrics::add(&STATS.display_dropped, 1);
+                    return Ok(None);
+                }
+                Err(TrySendError::Closed(_)) => false,
+            },
+        };
+        if queued {
+            self.queued += 1;
+            Ok(Some(self.queued))
+        } else {
+            // The thread only stops before the queue is closed if it failed
+            join(self.thread.take()).map(|()| None)
+        }
+    }
+
+    /// How many messages were printed
+    pub fn printed(&self) -> u64 {
+        *self.printed.borrow()
+    }
+
+    /// Resolves once more messages were printed, never once printing stopped
+    pub async fn printed_more(&mut self) {
+        if self.printed.changed().await.is_err() {
+            futures::future::pending().await
+        }
+    }
+
+    /// Prints the messages still queued, failing with why printing stopped if it did
+    pub fn finish(self) -> Result<()> {
+        let Self { queue, thread, .. } = self;
+        drop(queue);
+        join(thread)
+    }
+}
+
+fn join(thread: Option<thread::JoinHandle<Result<()>>>) -> Result<()> {
+    match thread {
+        Some(thread) => tokio::task::block_in_place(|| thread.join())
+            .map_err(|_| format_err!("The printing thread panicked"))?,
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,120 @@
+//! The producers of the consumed messages, for `consume --producers-summary`: how much each
+//! published and which property keys it sets, e.g. to find the one still on the old schema
+
+use crate::{display, units};
+use pulsar::consumer::Message;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// How many producers are told apart, the messages of any more being counted under `OTHER`
+const MAX_PRODUCERS: usize = 1000;
+const OTHER: &str = "<other>";
+
+#[derive(Default)]
+struct ProducerStats {
+    messages: u64,
+    bytes: u64,
+    first_publish_time: Option<u64>,
+    last_publish_time: Option<u64>,
+    property_keys: BTreeSet<String>,
+}
+
+#[derive(Default)]
+pub struct ProducersSummary {
+    producers: HashMap<String, ProducerStats>,
+}
+
+impl ProducersSummary {
+    pub fn record(&mut self, message: &Message<Vec<u8>>) {
+        let metadata = message.metadata();
+        let name = if self.producers.len() < MAX_PRODUCERS
+            || self.producers.contains_key(&metadata.producer_name)
+        {
+            metadata.producer_name.as_str()
+        } else {
+            OTHER
+        };
+        let stats = self.producers.entry(name.to_owned()).or_default();
+        stats.messages += 1;
+        stats.bytes += message.payload.data.len() as u64;
+        let publish_time = metadata.publish_time;
+        stats.first_publish_time = Some(
+            stats
+                .first_publish_time
+                .unwrap_or(publish_time)
+                .min(publish_time),
+        );
+        stats.last_publish_time = Some(
+            stats
+                .last_publish_time
+                .unwrap_or(publish_time)
+                .max(publish_time),
+        );
+        for (key, _) in display::properties(message) {
+            if !stats.property_keys.contains(key) {
+                stats.property_keys.insert(key.to_owned());
+            }
+        }
+    }
+
+    /// The producers, ordered by `sort` (`count` or `bytes`, most first) or else by name, as
+    /// a table and as JSON
+    pub fn summary(&self, sort: Option<&str>) -> (String, Value) {
+        let mut producers = self.producers.iter().collect::<Vec<_>>();
+        match sort {
+            Some("count") => producers.sort_by(|a, b| b.1.messages.cmp(&a.1.messages)),
+            Some("bytes") => producers.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes)),
+            _ => producers.sort_by(|a, b| a.0.cmp(b.0)),
+        }
+        let time = |millis: Option<u64>| {
+            millis.map(|millis| display::Timestamp::from_millis(millis).to_string())
+        };
+
+        let width = producers
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(Some("PRODUCER".len()))
+            .max()
+            .unwrap_or_default();
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:width$}  {:>10}  {:>10}  {:28}  {:28}  PROPERTY KEYS",
+            "PRODUCER",
+            "MESSAGES",
+            "BYTES",
+            "FIRST PUBLISHED",
+            "LAST PUBLISHED",
+            width = width
+        );
+        let mut json = Map::new();
+        for (name, stats) in producers {
+            let keys = stats.property_keys.iter().cloned().collect::<Vec<_>>();
+            let _ = writeln!(
+                out,
+                "{:width$}  {:>10}  {:>10}  {:28}  {:28}  {}",
+                name,
+                stats.messages,
+                units::format_bytes(stats.bytes as f64),
+                time(stats.first_publish_time).unwrap_or_default(),
+                time(stats.last_publish_time).unwrap_or_default(),
+                keys.join(","),
+                width = width
+            );
+            json.insert(
+                name.clone(),
+                json!({
+                    "messages": stats.messages,
+                    "bytes": stats.bytes,
+                    "firstPublishTime": time(stats.first_publish_time),
+                    "lastPublishTime": time(stats.last_publish_time),
+                    "propertyKeys": keys,
+                }),
+            );
+        }
+        (out, json.into())
+    }
+}
@@ -0,0 +1,79 @@
+//! Proxies the admin API is reached through, SOCKS5 or HTTP CONNECT. Connections to brokers over
+//! the binary protocol are made by the Pulsar client, which can't be given a proxy, so the
+//! commands making them refuse one
+
+use crate::exit::{self, ExitError};
+use anyhow::Result;
+use std::time::Duration;
+use structopt::StructOpt;
+use url::Url;
+
+/// Environment variables a proxy is taken from with --proxy-from-env, in order
+const PROXY_ENV_VARS: &[&str] = &["ALL_PROXY", "all_proxy", "HTTPS_PROXY", "https_proxy"];
+
+#[derive(StructOpt)]
+pub struct ProxyOpts {
+    /// Proxy to reach the admin API through: socks5://host:port or http://host:port (HTTP
+    /// CONNECT). Commands connecting to brokers, e.g. consume or produce, refuse it
+    #[structopt(long, env = "PULSAR_PROXY")]
+    pub proxy: Option<Url>,
+
+    /// Use the proxy of $ALL_PROXY or $HTTPS_PROXY
+    #[structopt(long, conflicts_with = "proxy")]
+    pub proxy_from_env: bool,
+}
+
+impl ProxyOpts {
+    /// The proxy to use, if any
+    pub fn url(&self) -> Result<Option<Url>> {
+        let url = match &self.proxy {
+            Some(url) => url.clone(),
+            None if self.proxy_from_env => {
+                let found = PROXY_ENV_VARS.iter().find_map(|name| {
+                    std::env::var(name)
+                        .ok()
+                        .filter(|value| !value.is_empty())
+                        .map(|value| (*name, value))
+                });
+                let (name, value) = match found {
+                    Some(found) => found,
+                    None => return Ok(None),
+                };
+                Url::parse(&value).map_err(|e| {
+                    ExitError::new(
+                        exit::USAGE,
+                        format!("Invalid proxy URL in ${}: {}", name, e),
+                    )
+                })?
+            }
+            None => return Ok(None),
+        };
+        match url.scheme() {
+            "socks5" | "socks5h" | "http" | "https" => Ok(Some(url)),
+            scheme => Err(ExitError::new(
+                exit::USAGE,
+                format!(
+                    "Unsupported proxy scheme {:?}, expected socks5, socks5h or http",
+                    scheme
+                ),
+            )
+            .into()),
+        }
+    }
+}
+
+/// Tells which leg of a connection through `proxy` failed, by trying to connect to the proxy
+/// itself
+pub async fn failed_leg(proxy: &Url, timeout: Duration) -> String {
+    let host = proxy.host_str().unwrap_or_default();
+    let port = proxy.port_or_known_default().unwrap_or(1080);
+    let connected = tokio::time::timeout(timeout, tokio::net::TcpStream::connect((host, port)));
+    match connected.await {
+        Ok(Ok(_)) => format!(
+            "the proxy {} is reachable, it failed to connect onwards",
+            proxy
+        ),
+        Ok(Err(e)) => format!("the proxy {} is unreachable: {}", proxy, e),
+        Err(_) => format!("the proxy {} is unreachable: timed out", proxy),
+    }
+}
@@ -0,0 +1,39 @@
+//! Token buckets capping how fast messages are handled, e.g. by consume with `--max-rate`
+
+use std::time::{Duration, Instant};
+
+pub struct RateLimiter {
+    /// Tokens added per second, up to one second's worth
+    rate: f64,
+    /// Negative when the last acquisition was larger than what was available
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes `cost` tokens, first waiting for the tokens taken before to have been made up for
+    /// if need be. Returns whether it waited
+    pub async fn acquire(&mut self, cost: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+
+        let waited = self.tokens < 0.0;
+        if waited {
+            tokio::time::sleep(Duration::from_secs_f64(-self.tokens / self.rate)).await;
+            self.tokens = 0.0;
+            self.refilled = Instant::now();
+        }
+        self.tokens -= cost;
+        waited
+    }
+}
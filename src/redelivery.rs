@@ -0,0 +1,263 @@
+//! Detecting redelivery storms for `consume --detect-redelivery-storms`: a sliding window of
+//! the latest messages, warned about when too many of them are messages received before, e.g.
+//! because a consumer of the subscription keeps failing to ack or nacking them
+
+use crate::message_id::MessageId;
+use log::{info, warn};
+use pulsar::consumer::Message;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How many of the latest message IDs are remembered to tell redeliveries apart, well beyond
+/// the window so that messages redelivered after an ack timeout are still recognized
+const REMEMBERED_IDS: usize = 100_000;
+
+/// How many of the most redelivered messages a storm warning names
+const TOP_OFFENDERS: usize = 5;
+
+/// A message received more than once, as named in storm warnings
+pub struct Offender {
+    pub topic: String,
+    pub id: MessageId,
+    pub producer: String,
+    pub deliveries: u32,
+}
+
+/// The window having more redeliveries than the threshold
+pub struct Storm {
+    pub redelivered: usize,
+    pub window: usize,
+    pub offenders: Vec<Offender>,
+}
+
+pub struct StormDetector {
+    window_size: usize,
+    /// Share of the window, in percent, above which it is a storm
+    threshold: f64,
+    /// The latest messages, by topic and ID, and whether each was a redelivery
+    window: VecDeque<((String, MessageId), bool)>,
+    redelivered_in_window: usize,
+    /// How many times each remembered message was received, and by which producer it was sent
+    deliveries: HashMap<(String, MessageId), (u32, String)>,
+    remembered: VecDeque<(String, MessageId)>,
+    in_storm: bool,
+    storms: u64,
+}
+
+impl StormDetector {
+    pub fn new(window_size: usize, threshold: f64) -> Self {
+        Self {
+            window_size: window_size.max(1),
+            threshold,
+            window: VecDeque::new(),
+            redelivered_in_window: 0,
+            deliveries: HashMap::new(),
+            remembered: VecDeque::new(),
+            in_storm: false,
+            storms: 0,
+        }
+    }
+
+    /// Records `message`, giving the storm it starts if it tips the window over the threshold.
+    /// A storm is only given once, until the window is back under the threshold
+    pub fn observe(&mut self, message: &Message<Vec<u8>>) -> Option<Storm> {
+        self.observe_id(
+            &message.topic,
+            MessageId::from(&message.message_id.id),
+            &message.metadata().producer_name,
+        )
+    }
+
+    /// Records the message `id` of `topic`, sent by `producer`, as `observe` does
+    fn observe_id(&mut self, topic: &str, id: MessageId, producer: &str) -> Option<Storm> {
+        let key = (topic.to_owned(), id);
+        let redelivered = match self.deliveries.get_mut(&key) {
+            Some((deliveries, _)) => {
+                *deliveries += 1;
+                true
+            }
+            None => {
+                self.deliveries
+                    .insert(key.clone(), (1, producer.to_owned()));
+                self.remembered.push_back(key.clone());
+                if self.remembered.len() > REMEMBERED_IDS {
+                    if let Some(forgotten) = self.remembered.pop_front() {
+                        self.deliveries.remove(&forgotten);
+                    }
+                }
+                false
+            }
+        };
+
+        self.window.push_back((key, redelivered));
+        if redelivered {
+            self.redelivered_in_window += 1;
+        }
+        if self.window.len() > self.window_size {
+            if let Some((_, true)) = self.window.pop_front() {
+                self.redelivered_in_window -= 1;
+            }
+        }
+
+        // Not before the window is full, so that a single early redelivery isn't a storm
+        let storming = self.window.len() >= self.window_size
+            && self.redelivered_in_window as f64 * 100.0 / self.window.len() as f64
+                > self.threshold;
+        if storming == self.in_storm {
+            return None;
+        }
+        self.in_storm = storming;
+        if !storming {
+            info!(
+                "Redelivery storm over: {} of the last {} messages were redeliveries",
+                self.redelivered_in_window,
+                self.window.len()
+            );
+            return None;
+        }
+        self.storms += 1;
+        Some(Storm {
+            redelivered: self.redelivered_in_window,
+            window: self.window.len(),
+            offenders: self.offenders(),
+        })
+    }
+
+    /// The most redelivered messages of the window
+    fn offenders(&self) -> Vec<Offender> {
+        let mut named = HashSet::new();
+        let mut offenders = self
+            .window
+            .iter()
+            .filter(|(key, redelivered)| *redelivered && named.insert(key))
+            .filter_map(|(key, _)| {
+                let (deliveries, producer) = self.deliveries.get(key)?;
+                Some(Offender {
+                    topic: key.0.clone(),
+                    id: key.1,
+                    producer: producer.clone(),
+                    deliveries: *deliveries,
+                })
+            })
+            .collect::<Vec<_>>();
+        offenders.sort_by(|a, b| b.deliveries.cmp(&a.deliveries));
+        offenders.truncate(TOP_OFFENDERS);
+        offenders
+    }
+
+    /// How many storms started so far
+    pub fn storms(&self) -> u64 {
+        self.storms
+    }
+}
+
+impl Storm {
+    /// Warns about the storm, naming the most redelivered messages
+    pub fn warn(&self) {
+        warn!(
+            "REDELIVERY STORM: {} of the last {} messages were redeliveries",
+            self.redelivered, self.window
+        );
+        for offender in self.offenders.iter() {
+            warn!(
+                "  {} on {}, from producer {:?}: received {} times",
+                offender.id, offender.topic, offender.producer, offender.deliveries
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(entry: i64) -> MessageId {
+        MessageId {
+            ledger_id: 1,
+            entry_id: entry,
+            partition: None,
+            batch_index: None,
+        }
+    }
+
+    /// Observes the messages of `entries` in order, with the storm each one started if any
+    fn observe(detector: &mut StormDetector, entries: &[i64]) -> Vec<Option<Storm>> {
+        entries
+            .iter()
+            .map(|entry| detector.observe_id("persistent://t/ns/orders", id(*entry), "producer"))
+            .collect()
+    }
+
+    fn storm_starts(storms: &[Option<Storm>]) -> Vec<usize> {
+        storms
+            .iter()
+            .enumerate()
+            .filter(|(_, storm)| storm.is_some())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn threshold() {
+        // 1 then 2 of 10 messages redelivered, 10% then exactly 20%
+        let mut detector = StormDetector::new(10, 20.0);
+        let storms = observe(&mut detector, &[0, 1, 2, 3, 4, 5, 6, 7, 0, 1]);
+        assert!(storm_starts(&storms).is_empty());
+        // 3 of 10, above it
+        let storms = observe(&mut detector, &[2]);
+        let storm = storms[0].as_ref().expect("A storm");
+        assert_eq!((storm.redelivered, storm.window), (3, 10));
+        assert_eq!(detector.storms(), 1);
+    }
+
+    #[test]
+    fn not_before_the_window_is_full() {
+        let mut detector = StormDetector::new(10, 20.0);
+        assert!(storm_starts(&observe(&mut detector, &[0, 0, 0, 0])).is_empty());
+        assert_eq!(detector.storms(), 0);
+    }
+
+    #[test]
+    fn window_eviction() {
+        let mut detector = StormDetector::new(4, 50.0);
+        // The storm starts with the third redelivery, then stays
+        let storms = observe(&mut detector, &[0, 1, 2, 3, 0, 1, 2, 10]);
+        assert_eq!(storm_starts(&storms), vec![6]);
+        // Over once the redeliveries leave the window, another one starting after
+        let storms = observe(&mut detector, &[11, 12, 13, 10, 11, 12]);
+        assert_eq!(storm_starts(&storms), vec![5]);
+        assert_eq!(detector.storms(), 2);
+    }
+
+    #[test]
+    fn top_offenders() {
+        let mut detector = StormDetector::new(10, 50.0);
+        let storms = observe(&mut detector, &[1, 2, 3, 4, 1, 1, 1, 2, 2, 3]);
+        assert_eq!(storm_starts(&storms), vec![9]);
+        let offenders = &storms[9].as_ref().unwrap().offenders;
+        assert_eq!(
+            offenders
+                .iter()
+                .map(|offender| (offender.id, offender.deliveries))
+                .collect::<Vec<_>>(),
+            vec![(id(1), 4), (id(2), 3), (id(3), 2)]
+        );
+        assert_eq!(offenders[0].producer, "producer");
+        assert_eq!(offenders[0].topic, "persistent://t/ns/orders");
+    }
+
+    #[test]
+    fn top_offenders_are_the_first_few() {
+        let mut detector = StormDetector::new(14, 40.0);
+        let storms = observe(&mut detector, &[0, 1, 2, 3, 4, 5, 6, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(storm_starts(&storms), vec![13]);
+        assert_eq!(storms[13].as_ref().unwrap().offenders.len(), TOP_OFFENDERS);
+    }
+
+    #[test]
+    fn same_id_on_other_topics() {
+        let mut detector = StormDetector::new(2, 0.0);
+        assert!(detector.observe_id("a", id(0), "p").is_none());
+        assert!(detector.observe_id("b", id(0), "p").is_none());
+        assert!(detector.observe_id("a", id(0), "p").is_some());
+    }
+}
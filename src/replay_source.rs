@@ -0,0 +1,472 @@
+//! Messages read back from the files `consume --output-dir-per-topic` wrote, for `produce
+//! --replay`: each line is a message, its payload, properties and key as they were consumed.
+//! The files a glob matches are read in the order of their names, so that the rotated files of
+//! a topic come in the order they were written, whether they were gzipped or not. With
+//! `--replay-timing` the messages are published at the pace they were recorded at rather than
+//! as fast as they are read
+
+use crate::exit::{self, ExitError};
+use anyhow::{format_err, Result};
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use flate2::read::MultiGzDecoder;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::{sync::mpsc, time::Instant};
+
+/// Messages read ahead of publishing
+const LINE_QUEUE_SIZE: usize = 100;
+
+/// How often the progress of a replay is logged
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The pace of `produce --replay-timing`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplayTiming {
+    /// The gaps between the recorded publish times
+    Original,
+    /// This many messages per second
+    Uniform(f64),
+    /// The recorded gaps, this many times shorter
+    Scaled(f64),
+}
+
+impl FromStr for ReplayTiming {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            format_err!(
+                "Invalid replay timing {:?}, expected original, uniform:<messages per second> or scaled:<factor>",
+                s
+            )
+        };
+        let positive = |number: &str| {
+            number
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite() && *number > 0.0)
+                .ok_or_else(invalid)
+        };
+        match s.split_once(':') {
+            None if s == "original" => Ok(ReplayTiming::Original),
+            Some(("uniform", rate)) => Ok(ReplayTiming::Uniform(positive(rate)?)),
+            Some(("scaled", factor)) => Ok(ReplayTiming::Scaled(positive(factor)?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A message of a capture, with when it was published if the capture tells
+struct Recorded {
+    message: pulsar::producer::Message,
+    /// Milliseconds since the epoch
+    publish_time: Option<u64>,
+    /// How much of the capture files was read up to it
+    read: u64,
+}
+
+/// The messages of a capture being replayed, each when it is due
+pub struct Replay {
+    receiver: mpsc::Receiver<Result<Recorded>>,
+    pacer: Option<Pacer>,
+    /// The size of the capture files, for the progress
+    size: u64,
+    started: Option<Instant>,
+    last_progress: Option<Instant>,
+}
+
+pub struct ReplaySource {
+    files: Vec<PathBuf>,
+}
+
+impl ReplaySource {
+    /// The capture files matching `pattern`, e.g. `captures/orders*`, failing if there are
+    /// none. Other files, e.g. those still being compressed, are left out
+    pub fn new(pattern: &str) -> Result<Self> {
+        let mut files = glob::glob(pattern)
+            .map_err(|e| ExitError::new(exit::USAGE, format!("Invalid --replay: {}", e)))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format_err!("Failed listing the files of {}: {}", pattern, e))?;
+        files.retain(|path| is_capture(path.to_string_lossy().as_ref()));
+        files.sort();
+        if files.is_empty() {
+            return Err(ExitError::new(
+                exit::NOT_FOUND,
+                format!("No .ndjson or .ndjson.gz file matches {}", pattern),
+            )
+            .into());
+        }
+        Ok(Self { files })
+    }
+
+    /// Reads the files from a thread of their own, like the rows of `--stdin-format`, for
+    /// the messages to be replayed by `timing` with waits of up to `max_gap`, or as fast as
+    /// they are read without `timing`. The messages end after the last line of the last file or
+    /// the first invalid line
+    pub fn spawn(
+        self,
+        properties: HashMap<String, String>,
+        timing: Option<ReplayTiming>,
+        max_gap: Option<Duration>,
+    ) -> Replay {
+        let (sender, receiver) = mpsc::channel(LINE_QUEUE_SIZE);
+        // Compressed files counting by their compressed size, as read
+        let size = self
+            .files
+            .iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        thread::spawn(move || {
+            let mut read_before = 0;
+            for path in self.files {
+                log::info!("Replaying {}", path.display());
+                let read = Arc::new(AtomicU64::new(0));
+                let lines = match open(&path, read.clone()) {
+                    Ok(reader) => reader.lines(),
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(format_err!(
+                            "Failed reading {}: {}",
+                            path.display(),
+                            e
+                        )));
+                        return;
+                    }
+                };
+                for (n, line) in lines.enumerate() {
+                    let replayed = match line {
+                        Ok(line) => recorded(&line, &properties).map_err(|e| {
+                            ExitError::new(
+                                exit::INVALID_DATA,
+                                format!("Invalid line {} of {}: {:#}", n + 1, path.display(), e),
+                            )
+                            .into()
+                        }),
+                        Err(e) => Err(format_err!("Failed reading {}: {}", path.display(), e)),
+                    };
+                    let replayed = replayed.map(|recorded| Recorded {
+                        read: read_before + read.load(Ordering::Relaxed),
+                        ..recorded
+                    });
+                    let invalid = replayed.is_err();
+                    if sender.blocking_send(replayed).is_err() || invalid {
+                        return;
+                    }
+                }
+                read_before += read.load(Ordering::Relaxed);
+            }
+        });
+        Replay {
+            receiver,
+            pacer: timing.map(|timing| Pacer::new(timing, max_gap)),
+            size,
+            started: None,
+            last_progress: None,
+        }
+    }
+}
+
+impl Replay {
+    /// The next message once it is due, or `None` at the end of the capture
+    pub async fn next(&mut self) -> Option<Result<pulsar::producer::Message>> {
+        let recorded = match self.receiver.recv().await? {
+            Ok(recorded) => recorded,
+            Err(e) => return Some(Err(e)),
+        };
+        let started = *self.started.get_or_insert_with(Instant::now);
+        if let Some(pacer) = self.pacer.as_mut() {
+            tokio::time::sleep_until(started + pacer.due(recorded.publish_time)).await;
+        }
+        self.log_progress(started, recorded.read);
+        Some(Ok(recorded.message))
+    }
+
+    /// Logs how much of the capture was replayed and how long the rest should take, every
+    /// `PROGRESS_INTERVAL`
+    fn log_progress(&mut self, started: Instant, read: u64) {
+        let now = Instant::now();
+        let last = *self.last_progress.get_or_insert(started);
+        if now - last < PROGRESS_INTERVAL || self.size == 0 {
+            return;
+        }
+        self.last_progress = Some(now);
+        let done = (read as f64 / self.size as f64).min(1.0);
+        let left = if done > 0.0 {
+            let seconds = (now - started).as_secs_f64() * (1.0 - done) / done;
+            humantime::format_duration(Duration::from_secs(seconds.round() as u64)).to_string()
+        } else {
+            "unknown".to_owned()
+        };
+        log::info!("Replayed {:.1}% of the capture, ETA {}", done * 100.0, left);
+    }
+}
+
+/// When each replayed message is due, as an offset from the start of the replay. Sleeping
+/// until the start plus the offset rather than for each gap, the time sleeps overshoot by
+/// doesn't add up over a long replay
+struct Pacer {
+    timing: ReplayTiming,
+    max_gap: Option<Duration>,
+    /// Messages paced so far
+    count: u64,
+    offset: Duration,
+    last_publish_time: Option<u64>,
+}
+
+impl Pacer {
+    fn new(timing: ReplayTiming, max_gap: Option<Duration>) -> Self {
+        Self {
+            timing,
+            max_gap,
+            count: 0,
+            offset: Duration::default(),
+            last_publish_time: None,
+        }
+    }
+
+    /// The offset of the next message, published at `publish_time`. Messages without a
+    /// publish time, or published before the one before them, follow right after it
+    fn due(&mut self, publish_time: Option<u64>) -> Duration {
+        let count = self.count;
+        self.count += 1;
+        let factor = match self.timing {
+            ReplayTiming::Uniform(rate) => return Duration::from_secs_f64(count as f64 / rate),
+            ReplayTiming::Original => 1.0,
+            ReplayTiming::Scaled(factor) => factor,
+        };
+        let gap = match (self.last_publish_time, publish_time) {
+            (Some(last), Some(time)) => time.saturating_sub(last),
+            _ => 0,
+        };
+        if let Some(time) = publish_time {
+            self.last_publish_time =
+                Some(self.last_publish_time.map_or(time, |last| last.max(time)));
+        }
+        let mut gap = Duration::from_millis(gap).div_f64(factor);
+        if let Some(max_gap) = self.max_gap {
+            gap = gap.min(max_gap);
+        }
+        self.offset += gap;
+        self.offset
+    }
+}
+
+/// Whether `path` is a capture file, compressed or not
+fn is_capture(path: &str) -> bool {
+    path.ends_with(".ndjson") || path.ends_with(".ndjson.gz")
+}
+
+/// A reader counting the bytes read from it into `read`
+struct Counting<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// The lines of `path`, decompressed if it is gzipped, counting the bytes read from it into
+/// `read`
+fn open(path: &Path, read: Arc<AtomicU64>) -> io::Result<Box<dyn BufRead + Send>> {
+    let file = Counting {
+        inner: File::open(path)?,
+        read,
+    };
+    if path.to_string_lossy().ends_with(".gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// The message of a captured `line`, with `properties` besides those it had. A payload that
+/// was captured as JSON is published as its JSON text
+fn recorded(line: &str, properties: &HashMap<String, String>) -> Result<Recorded> {
+    let captured = serde_json::from_str::<Value>(line)?;
+    let payload = match captured.get("payload") {
+        Some(Value::String(text)) => text.clone().into_bytes(),
+        Some(json) => serde_json::to_vec(json)?,
+        None => return Err(format_err!("No payload")),
+    };
+    let mut properties = properties.clone();
+    if let Some(captured) = captured["properties"].as_object() {
+        for (key, value) in captured {
+            let value = value
+                .as_str()
+                .ok_or_else(|| format_err!("Property {} isn't a string", key))?;
+            properties.insert(key.clone(), value.to_owned());
+        }
+    }
+    let message = pulsar::producer::Message {
+        payload,
+        properties,
+        partition_key: captured["entry"]["key"].as_str().map(str::to_owned),
+        ..Default::default()
+    };
+    Ok(Recorded {
+        message,
+        publish_time: captured["entry"]["publish_time"]
+            .as_str()
+            .and_then(parse_time),
+        read: 0,
+    })
+}
+
+/// A time as displayed for a message, e.g. `2021-03-04 05:06:07.890 UTC`, in milliseconds
+/// since the epoch
+fn parse_time(time: &str) -> Option<u64> {
+    let time =
+        NaiveDateTime::parse_from_str(time.strip_suffix(" UTC")?, "%Y-%m-%d %H:%M:%S%.f").ok()?;
+    u64::try_from(Utc.from_utc_datetime(&time).timestamp_millis()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_lines() {
+        let properties = vec![("source".to_owned(), "replay".to_owned())]
+            .into_iter()
+            .collect();
+        let line = r#"{"time": "2021-03-04 05:06:07.890 UTC", "topic": "persistent://t/ns/orders", "properties": {"source": "cli", "tenant": "a"}, "entry": {"id": "1:2", "key": "user-1"}, "payload": {"id": 42}}"#;
+        let replayed = recorded(line, &properties).unwrap().message;
+        assert_eq!(replayed.payload, br#"{"id":42}"#);
+        assert_eq!(replayed.properties.len(), 2);
+        assert_eq!(replayed.properties["source"], "cli");
+        assert_eq!(replayed.properties["tenant"], "a");
+        assert_eq!(replayed.partition_key.as_deref(), Some("user-1"));
+
+        let text = recorded(
+            r#"{"properties": {}, "entry": {}, "payload": "plain text"}"#,
+            &properties,
+        )
+        .unwrap()
+        .message;
+        assert_eq!(text.payload, b"plain text");
+        assert_eq!(text.properties["source"], "replay");
+        assert_eq!(text.partition_key, None);
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        let properties = HashMap::new();
+        for line in &[
+            "not JSON",
+            r#"{"properties": {}}"#,
+            r#"{"properties": {"n": 1}, "payload": ""}"#,
+        ] {
+            assert!(recorded(line, &properties).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn publish_times() {
+        let line = r#"{"properties": {}, "entry": {"publish_time": "2021-03-04 05:06:07.890 UTC"}, "payload": ""}"#;
+        assert_eq!(
+            recorded(line, &HashMap::new()).unwrap().publish_time,
+            Some(1614834367890)
+        );
+        assert_eq!(parse_time("2021-03-04 05:06:07 UTC"), Some(1614834367000));
+        assert_eq!(parse_time("1 (implausible timestamp)"), None);
+    }
+
+    #[test]
+    fn replay_timings() {
+        assert_eq!(
+            "original".parse::<ReplayTiming>().unwrap(),
+            ReplayTiming::Original
+        );
+        assert_eq!(
+            "uniform:50".parse::<ReplayTiming>().unwrap(),
+            ReplayTiming::Uniform(50.0)
+        );
+        assert_eq!(
+            "scaled:2.5".parse::<ReplayTiming>().unwrap(),
+            ReplayTiming::Scaled(2.5)
+        );
+        for invalid in &[
+            "",
+            "scaled",
+            "scaled:0",
+            "uniform:-1",
+            "uniform:fast",
+            "fast:2",
+        ] {
+            assert!(invalid.parse::<ReplayTiming>().is_err(), "{}", invalid);
+        }
+    }
+
+    fn offsets(pacer: &mut Pacer, publish_times: &[Option<u64>]) -> Vec<u64> {
+        publish_times
+            .iter()
+            .map(|time| pacer.due(*time).as_millis() as u64)
+            .collect()
+    }
+
+    #[test]
+    fn original_timing_follows_the_publish_times() {
+        let mut pacer = Pacer::new(ReplayTiming::Original, None);
+        assert_eq!(
+            offsets(
+                &mut pacer,
+                &[Some(1000), Some(1500), None, Some(1400), Some(4000)]
+            ),
+            vec![0, 500, 500, 500, 3000]
+        );
+    }
+
+    #[test]
+    fn max_gap_caps_the_waits() {
+        let mut pacer = Pacer::new(ReplayTiming::Original, Some(Duration::from_secs(1)));
+        assert_eq!(
+            offsets(
+                &mut pacer,
+                &[Some(0), Some(500), Some(3_600_500), Some(3_601_000)]
+            ),
+            vec![0, 500, 1500, 2000]
+        );
+    }
+
+    #[test]
+    fn scaled_and_uniform_timings() {
+        let mut scaled = Pacer::new(ReplayTiming::Scaled(10.0), None);
+        assert_eq!(
+            offsets(&mut scaled, &[Some(0), Some(1000), Some(5000)]),
+            vec![0, 100, 500]
+        );
+        let mut uniform = Pacer::new(ReplayTiming::Uniform(4.0), None);
+        assert_eq!(
+            offsets(&mut uniform, &[Some(0), Some(60_000), None]),
+            vec![0, 250, 500]
+        );
+    }
+
+    #[test]
+    fn only_capture_files_are_replayed() {
+        assert!(is_capture("captures/orders.ndjson"));
+        assert!(is_capture("captures/orders.20210304T050607.890Z.ndjson.gz"));
+        assert!(!is_capture(
+            "captures/orders.20210304T050607.890Z.ndjson.gz.partial"
+        ));
+        assert!(!is_capture("captures/notes.txt"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Content-based routing for `consume --forward-route`: each message is forwarded to the topic
+//! of the first route one of its properties matches, or to `--forward-default`, with a
+//! producer per topic created on the first message routed there
+
+use crate::connection::{Connector, ServiceUrls};
+use anyhow::{format_err, Result};
+use log::info;
+use pulsar::{Producer, TokioExecutor};
+use serde_json::{json, Value};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    str::FromStr,
+};
+
+/// Messages whose property `property` is `value` go to `topic`
+#[derive(Clone)]
+pub struct Route {
+    property: String,
+    value: String,
+    topic: String,
+}
+
+impl FromStr for Route {
+    type Err = anyhow::Error;
+
+    /// Parses `<property>=<value>:<topic>`, the value ending at the first `:` so that the topic
+    /// can be a full name such as `persistent://tenant/ns/topic`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format_err!("Invalid route {:?}, expected <property>=<value>:<topic>", s);
+        let (property, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (value, topic) = rest.split_once(':').ok_or_else(invalid)?;
+        if property.is_empty() || topic.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            property: property.to_owned(),
+            value: value.to_owned(),
+            topic: topic.to_owned(),
+        })
+    }
+}
+
+/// The topic a message with `properties` goes to: that of the first route matching one of
+/// them, or `default`
+pub fn destination<'a>(
+    routes: &'a [Route],
+    default: Option<&'a str>,
+    properties: &[(&str, &str)],
+) -> Option<&'a str> {
+    routes
+        .iter()
+        .find(|route| {
+            properties
+                .iter()
+                .any(|(key, value)| *key == route.property && *value == route.value)
+        })
+        .map(|route| route.topic.as_str())
+        .or(default)
+}
+
+/// Every topic messages can be routed to
+pub fn destinations<'a>(
+    routes: &'a [Route],
+    default: Option<&'a str>,
+) -> impl Iterator<Item = &'a str> {
+    routes
+        .iter()
+        .map(|route| route.topic.as_str())
+        .chain(default)
+}
+
+pub struct Router {
+    routes: Vec<Route>,
+    default: Option<String>,
+    producers: HashMap<String, Producer<TokioExecutor>>,
+    /// Messages forwarded by topic
+    routed: BTreeMap<String, u64>,
+    /// Messages no route matched, without a default
+    unrouted: u64,
+}
+
+impl Router {
+    pub fn new(routes: Vec<Route>, default: Option<String>) -> Self {
+        Self {
+            routes,
+            default,
+            producers: HashMap::new(),
+            routed: BTreeMap::new(),
+            unrouted: 0,
+        }
+    }
+
+    /// Forwards `copy`, a message with `properties`, to its destination on `url`, creating its
+    /// producer (retrying according to the retry policy) if it is the first one going there.
+    /// Whether it had a destination
+    pub async fn forward(
+        &mut self,
+        connector: &Connector,
+        url: &ServiceUrls,
+        properties: &[(&str, &str)],
+        copy: pulsar::producer::Message,
+    ) -> Result<bool> {
+        let topic = match destination(&self.routes, self.default.as_deref(), properties) {
+            Some(topic) => topic.to_owned(),
+            None => {
+                self.unrouted += 1;
+                return Ok(false);
+            }
+        };
+        let producer = match self.producers.entry(topic.clone()) {
+            Entry::Occupied(producer) => producer.into_mut(),
+            Entry::Vacant(entry) => {
+                info!("Forwarding the first message routed to {}", topic);
+                entry.insert(connector.build_producer(url, &topic, None).await?)
+            }
+        };
+        producer.send(copy).await?;
+        *self.routed.entry(topic).or_default() += 1;
+        Ok(true)
+    }
+
+    /// The line and JSON of the consume summary: how many messages went to each topic
+    pub fn summary(&self) -> (String, Value) {
+        let mut counts = self
+            .routed
+            .iter()
+            .map(|(topic, count)| format!("{} to {}", count, topic))
+            .collect::<Vec<_>>();
+        counts.push(format!("{} matching no route", self.unrouted));
+        (
+            format!("Routed: {}", counts.join(", ")),
+            json!({ "routed": self.routed, "unrouted": self.unrouted }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn routes() -> Vec<Route> {
+        vec![
+            "env=prod:persistent://t/ns/prod".parse().unwrap(),
+            "env=staging:staging".parse().unwrap(),
+            "region=eu:eu".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn matching_route() {
+        let routes = routes();
+        assert_eq!(
+            destination(&routes, None, &[("env", "prod")]),
+            Some("persistent://t/ns/prod")
+        );
+        assert_eq!(
+            destination(&routes, Some("other"), &[("env", "staging")]),
+            Some("staging")
+        );
+    }
+
+    #[test]
+    fn first_matching_route_applies() {
+        let routes = routes();
+        assert_eq!(
+            destination(&routes, None, &[("region", "eu"), ("env", "prod")]),
+            Some("persistent://t/ns/prod")
+        );
+    }
+
+    #[test]
+    fn no_match_with_default() {
+        assert_eq!(
+            destination(&routes(), Some("other"), &[("env", "dev")]),
+            Some("other")
+        );
+    }
+
+    #[test]
+    fn no_match_without_default() {
+        assert_eq!(destination(&routes(), None, &[("env", "dev")]), None);
+    }
+
+    #[test]
+    fn missing_property() {
+        let routes = routes();
+        assert_eq!(destination(&routes, None, &[("tier", "gold")]), None);
+        assert_eq!(destination(&routes, None, &[]), None);
+        assert_eq!(destination(&routes, Some("other"), &[]), Some("other"));
+    }
+
+    #[test]
+    fn parse_route() {
+        let route = "env=prod:persistent://t/ns/prod".parse::<Route>().unwrap();
+        assert_eq!(route.property, "env");
+        assert_eq!(route.value, "prod");
+        assert_eq!(route.topic, "persistent://t/ns/prod");
+        assert!("env:prod".parse::<Route>().is_err());
+        assert!("env=prod".parse::<Route>().is_err());
+        assert!("=prod:topic".parse::<Route>().is_err());
+        assert!("env=prod:".parse::<Route>().is_err());
+    }
+}
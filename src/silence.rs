@@ -0,0 +1,138 @@
+//! Alerting when a topic goes silent, for `consume --alert-after`: nothing received for that
+//! long means something upstream broke, which is announced, optionally with a command run
+//! (e.g. to page someone). Reconnecting doesn't count as receiving something
+
+use crate::{display, output};
+use anyhow::Result;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::{process::Command, time::Instant};
+
+pub struct SilenceAlarm {
+    after: Duration,
+    /// When the last message was received, or consuming started
+    last_message: Instant,
+    /// Whether the current silence was alerted about already, so it is only once
+    alerted: bool,
+}
+
+impl SilenceAlarm {
+    pub fn new(after: Duration) -> Self {
+        Self {
+            after,
+            last_message: Instant::now(),
+            alerted: false,
+        }
+    }
+
+    /// Restarts the timer, on every message received
+    pub fn reset(&mut self) {
+        if self.alerted {
+            info!(
+                "Messages received again after {} of silence",
+                humantime::format_duration(Duration::from_secs(
+                    self.last_message.elapsed().as_secs()
+                ))
+            );
+            self.alerted = false;
+        }
+        self.last_message = Instant::now();
+    }
+
+    /// Completes once nothing was received for the duration of the alarm, or never if that
+    /// silence was already alerted about
+    pub async fn silent(&self) {
+        if self.alerted {
+            futures::future::pending::<()>().await;
+        }
+        tokio::time::sleep_until(self.last_message + self.after).await;
+    }
+
+    /// Announces the silence of `topic`, prominently, and runs `exec` with `sh -c` if given,
+    /// with the topic and the silence in `PULSAR_CLI_TOPIC` and `PULSAR_CLI_SILENT_FOR`. The
+    /// command isn't waited for, so consuming goes on while it runs
+    pub fn alert(&mut self, topic: &str, exec: Option<&str>) -> Result<String> {
+        self.alerted = true;
+        let silent_for = humantime::format_duration(self.after).to_string();
+        let alert = format!(
+            "ALERT: no message received from {} for {}",
+            topic, silent_for
+        );
+        output::write(&display::highlight(&[alert.clone()]))?;
+        if let Some(exec) = exec {
+            let child = Command::new("sh")
+                .arg("-c")
+                .arg(exec)
+                .env("PULSAR_CLI_TOPIC", topic)
+                .env("PULSAR_CLI_SILENT_FOR", &silent_for)
+                .spawn();
+            match child {
+                Ok(mut child) => {
+                    let exec = exec.to_owned();
+                    tokio::spawn(async move {
+                        match child.wait().await {
+                            Ok(status) if status.success() => {}
+                            Ok(status) => warn!("--alert-exec {:?} failed: {}", exec, status),
+                            Err(e) => warn!("--alert-exec {:?} failed: {}", exec, e),
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed running --alert-exec {:?}: {}", exec, e),
+            }
+        }
+        Ok(alert)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    const AFTER: Duration = Duration::from_secs(10);
+
+    /// Whether the alarm goes off within `within`
+    async fn fires(alarm: &SilenceAlarm, within: Duration) -> bool {
+        timeout(within, alarm.silent()).await.is_ok()
+    }
+
+    #[tokio::test]
+    async fn fires_after_the_window() {
+        tokio::time::pause();
+        let alarm = SilenceAlarm::new(AFTER);
+        assert!(!fires(&alarm, Duration::from_secs(9)).await);
+        assert!(fires(&alarm, Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn is_reset_by_a_message() {
+        tokio::time::pause();
+        let mut alarm = SilenceAlarm::new(AFTER);
+        tokio::time::advance(Duration::from_secs(8)).await;
+        alarm.reset();
+        assert!(!fires(&alarm, Duration::from_secs(9)).await);
+        assert!(fires(&alarm, Duration::from_secs(2)).await);
+    }
+
+    #[tokio::test]
+    async fn never_fires_while_messages_keep_arriving() {
+        tokio::time::pause();
+        let mut alarm = SilenceAlarm::new(AFTER);
+        for _ in 0..100 {
+            assert!(!fires(&alarm, Duration::from_secs(9)).await);
+            alarm.reset();
+        }
+    }
+
+    #[tokio::test]
+    async fn fires_once_per_silence() {
+        tokio::time::pause();
+        let mut alarm = SilenceAlarm::new(AFTER);
+        assert!(fires(&alarm, Duration::from_secs(11)).await);
+        // As alert leaves it
+        alarm.alerted = true;
+        assert!(!fires(&alarm, AFTER * 10).await);
+        alarm.reset();
+        assert!(fires(&alarm, Duration::from_secs(11)).await);
+    }
+}
@@ -0,0 +1,188 @@
+//! Streaming consumed messages to a socket with `consume --sink`, e.g. the socket input of a
+//! log shipper, one JSON object per line. Messages are buffered while the sink is away, the
+//! oldest dropped once the buffer is full
+
+use crate::metrics::{self, STATS};
+use anyhow::{format_err, Result};
+use log::{info, warn};
+use std::{
+    collections::VecDeque,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UnixStream},
+    sync::Notify,
+    task::JoinHandle,
+};
+
+/// Delay before reconnecting the first time, doubling up to `MAX_RECONNECT_DELAY`
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// How long closing waits for the buffered messages to be written
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to stream to, `tcp://host:port` or `unix:///path/to/socket`
+#[derive(Clone, Debug)]
+pub enum SinkAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for SinkAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(address) = s.strip_prefix("tcp://") {
+            Ok(SinkAddress::Tcp(address.to_owned()))
+        } else if let Some(path) = s.strip_prefix("unix://") {
+            Ok(SinkAddress::Unix(PathBuf::from(path)))
+        } else {
+            Err(format_err!(
+                "Invalid sink: {:?}, expected tcp://<host>:<port> or unix://<path>",
+                s
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for SinkAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkAddress::Tcp(address) => write!(f, "tcp://{}", address),
+            SinkAddress::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+impl SinkAddress {
+    async fn connect(&self) -> Result<Box<dyn AsyncWrite + Send + Unpin>> {
+        Ok(match self {
+            SinkAddress::Tcp(address) => Box::new(TcpStream::connect(address).await?),
+            SinkAddress::Unix(path) => Box::new(UnixStream::connect(path).await?),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    buffer: Mutex<VecDeque<String>>,
+    /// Told when a line is buffered or the sink is closing
+    notify: Notify,
+    closing: AtomicBool,
+}
+
+/// The sink, written to from a task of its own so that consuming never waits for it
+pub struct Sink {
+    shared: Arc<Shared>,
+    capacity: usize,
+    task: JoinHandle<()>,
+}
+
+impl Sink {
+    /// Starts streaming to `address`, buffering up to `capacity` lines while it can't be
+    /// written to
+    pub fn spawn(address: SinkAddress, capacity: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        let task = tokio::spawn(stream(address, shared.clone()));
+        Self {
+            shared,
+            capacity: capacity.max(1),
+            task,
+        }
+    }
+
+    /// Queues `line` for the sink, dropping the oldest buffered line if the buffer is full
+    pub fn send(&self, line: String) {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        if buffer.len() >= self.capacity {
+            buffer.pop_front();
+            if metrics::get(&STATS.sink_dropped) == 0 {
+                warn!(
+                    "Sink buffer full ({} messages), dropping the oldest ones",
+                    self.capacity
+                );
+            }
+            metrics::add(&STATS.sink_dropped, 1);
+        }
+        buffer.push_back(line);
+        drop(buffer);
+        self.shared.notify.notify_one();
+    }
+
+    /// Writes what is still buffered, giving up after `FLUSH_TIMEOUT`, and closes the sink
+    pub async fn close(mut self) {
+        self.shared.closing.store(true, Ordering::Relaxed);
+        self.shared.notify.notify_one();
+        if tokio::time::timeout(FLUSH_TIMEOUT, &mut self.task)
+            .await
+            .is_err()
+        {
+            self.task.abort();
+            warn!(
+                "Sink not flushed within {}, {} messages not written",
+                humantime::format_duration(FLUSH_TIMEOUT),
+                self.shared.buffer.lock().unwrap().len()
+            );
+        }
+    }
+}
+
+/// Writes the buffered lines to `address`, connecting again whenever writing fails, until
+/// closing with the buffer empty
+async fn stream(address: SinkAddress, shared: Arc<Shared>) {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        let mut connection = match address.connect().await {
+            Ok(connection) => {
+                info!("Streaming messages to {}", address);
+                delay = RECONNECT_DELAY;
+                connection
+            }
+            Err(e) => {
+                warn!(
+                    "Failed connecting to sink {}: {}, trying again in {}",
+                    address,
+                    e,
+                    humantime::format_duration(delay)
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                continue;
+            }
+        };
+
+        loop {
+            let line = shared.buffer.lock().unwrap().pop_front();
+            let line = match line {
+                Some(line) => line,
+                None if shared.closing.load(Ordering::Relaxed) => {
+                    let _ = connection.shutdown().await;
+                    return;
+                }
+                None => {
+                    shared.notify.notified().await;
+                    continue;
+                }
+            };
+            let written = async {
+                connection.write_all(line.as_bytes()).await?;
+                connection.write_all(b"\n").await?;
+                connection.flush().await
+            };
+            if let Err(e) = written.await {
+                warn!("Failed writing to sink {}: {}, reconnecting", address, e);
+                // Written again once reconnected, possibly in part a second time
+                shared.buffer.lock().unwrap().push_front(line);
+                break;
+            }
+        }
+    }
+}
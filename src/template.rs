@@ -0,0 +1,167 @@
+//! Text rendered from a template: topic names once at startup for `--topic-template`, e.g.
+//! `orders-test-{{uuid}}` for a topic of its own per test run, consumer, producer and
+//! subscription names, e.g. `pulsar-cli-{{hostname}}-{{pid}}` to tell instances apart in the
+//! broker's stats, and the values of `produce --prop` for each message, e.g.
+//! `trace-id={{uuid}}`. `{{uuid}}` is a random UUID, `{{now:<format>}}` the current time as
+//! strftime's format has it (by default `%Y%m%d%H%M%S`), `{{hostname}}`, `{{user}}` and
+//! `{{pid}}` those of this process, `{{n}}` the number of the producer among those of the
+//! command, from 0, and `{{iteration}}` the number of the message produced, from 0
+
+use crate::output;
+use anyhow::{bail, format_err, Context, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Utc,
+};
+use std::{path::Path, str::FromStr};
+
+const DEFAULT_TIME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+enum Part {
+    Text(String),
+    Uuid,
+    Now(String),
+    Hostname,
+    User,
+    Pid,
+    Instance,
+    Iteration,
+}
+
+/// What a template is rendered with, besides the time, random UUIDs and this process
+#[derive(Default)]
+pub struct Variables {
+    /// The number of the producer or consumer being named, when naming one
+    pub instance: Option<u64>,
+    /// The number of the message being produced, when rendering one
+    pub iteration: Option<u64>,
+}
+
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format_err!("Unclosed {{{{ in template {:?}", s))?;
+            let placeholder = rest[start + 2..start + end].trim();
+            parts.push(match placeholder.split_once(':') {
+                None if placeholder == "uuid" => Part::Uuid,
+                None if placeholder == "now" => Part::Now(DEFAULT_TIME_FORMAT.to_owned()),
+                None if placeholder == "hostname" => Part::Hostname,
+                None if placeholder == "user" => Part::User,
+                None if placeholder == "pid" => Part::Pid,
+                None if placeholder == "n" => Part::Instance,
+                None if placeholder == "iteration" => Part::Iteration,
+                Some(("now", format)) => {
+                    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                        bail!("Invalid time format {:?} in template {:?}", format, s);
+                    }
+                    Part::Now(format.to_owned())
+                }
+                _ => bail!(
+                    "Unknown placeholder {{{{{}}}}} in template {:?}, expected {{{{uuid}}}}, {{{{now:<format>}}}}, {{{{hostname}}}}, {{{{user}}}}, {{{{pid}}}}, {{{{n}}}} or {{{{iteration}}}}",
+                    placeholder,
+                    s
+                ),
+            });
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl Template {
+    /// Whether the template has no placeholders, rendering to the same text every time
+    pub fn is_static(&self) -> bool {
+        self.parts.iter().all(|part| matches!(part, Part::Text(_)))
+    }
+
+    /// Fails on a placeholder `variables` have no value for
+    pub fn render(&self, variables: &Variables) -> Result<String> {
+        let now = Utc::now();
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => Ok(text.clone()),
+                Part::Uuid => Ok(uuid::Uuid::new_v4().to_string()),
+                Part::Now(format) => Ok(now.format(format).to_string()),
+                Part::Hostname => Ok(hostname()),
+                Part::User => Ok(user()),
+                Part::Pid => Ok(std::process::id().to_string()),
+                Part::Instance => variables
+                    .instance
+                    .map(|instance| instance.to_string())
+                    .ok_or_else(|| {
+                        format_err!("{{{{n}}}} only applies to consumer and producer names")
+                    }),
+                Part::Iteration => variables
+                    .iteration
+                    .map(|iteration| iteration.to_string())
+                    .ok_or_else(|| {
+                        format_err!("{{{{iteration}}}} only applies to the messages produced")
+                    }),
+            })
+            .collect()
+    }
+}
+
+/// The short host name, e.g. that of a shared jump host
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return "unknown-host".to_owned();
+    }
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    let name = String::from_utf8_lossy(&buffer[..end]);
+    name.split('.').next().unwrap_or_default().to_owned()
+}
+
+/// The user running pulsar-cli, by name if the environment has it, otherwise by UID
+fn user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .ok()
+        .filter(|user| !user.is_empty())
+        .unwrap_or_else(|| unsafe { libc::getuid() }.to_string())
+}
+
+/// Renders a consumer, producer or subscription name, instance `n` of those of the command,
+/// logging it if it was templated so that it can be found in the broker's stats
+pub fn resolve_name(template: &Template, what: &str, n: u64) -> Result<String> {
+    let name = template
+        .render(&Variables {
+            instance: Some(n),
+            ..Default::default()
+        })
+        .with_context(|| format!("Invalid {} name", what))?;
+    if !template.is_static() {
+        log::info!("The {} name is {}", what, name);
+    }
+    Ok(name)
+}
+
+/// Renders the topic name of `--topic-template`, printing it and writing it to `name_file` if
+/// given, for the other side of a test to pick it up
+pub fn resolve_topic(template: &Template, name_file: Option<&Path>) -> Result<String> {
+    let topic = template.render(&Variables::default())?;
+    if let Some(path) = name_file {
+        std::fs::write(path, format!("{}\n", topic))
+            .with_context(|| format!("Failed writing the topic name to {}", path.display()))?;
+    }
+    output::progress(format!("Topic: {}", topic))?;
+    Ok(topic)
+}
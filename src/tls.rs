@@ -0,0 +1,86 @@
+//! TLS settings, shared by the binary protocol connections (`pulsar+ssl://` URLs) and the admin
+//! API client
+
+use anyhow::{bail, format_err, Result};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+// Not a doc comment, see `Opts`
+#[cfg_attr(doc, doc = "The TLS options, as given on the command line")]
+#[derive(StructOpt)]
+pub struct TlsOpts {
+    /// PEM file with the CA certificate(s) to trust
+    #[structopt(long, env = "PULSAR_TLS_CA_CERT")]
+    pub tls_ca_cert: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS with the admin API, which brokers can't be given
+    #[structopt(long, env = "PULSAR_TLS_CERT", requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM (PKCS #8) private key of --tls-cert
+    #[structopt(long, env = "PULSAR_TLS_KEY", requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Skip verification of server certificates [env: PULSAR_TLS_INSECURE]
+    #[structopt(long)]
+    pub tls_insecure: bool,
+
+    /// Whether server certificates must match the hostname connected to [default: true]
+    #[structopt(long, env = "PULSAR_TLS_HOSTNAME_VERIFICATION", parse(try_from_str))]
+    pub tls_hostname_verification: Option<bool>,
+}
+
+/// The TLS settings with the files they refer to loaded and validated, so that a bad file is
+/// reported by name up front rather than as a failed connection
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    pub ca_chain: Option<Vec<u8>>,
+    /// Client certificate and key
+    pub identity: Option<(Vec<u8>, Vec<u8>)>,
+    pub insecure: bool,
+    pub hostname_verification: bool,
+}
+
+impl TlsConfig {
+    /// Reads the files `opts` refers to, failing on those that are missing or invalid
+    pub fn load(opts: &TlsOpts) -> Result<Self> {
+        let ca_chain = opts.tls_ca_cert.as_deref().map(load_ca_chain).transpose()?;
+        let identity = match (&opts.tls_cert, &opts.tls_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let cert = read(cert_path, "client certificate")?;
+                let key = read(key_path, "client key")?;
+                reqwest::Identity::from_pkcs8_pem(&cert, &key).map_err(|e| {
+                    format_err!(
+                        "Invalid client certificate {} or key {}: {}",
+                        cert_path.display(),
+                        key_path.display(),
+                        e
+                    )
+                })?;
+                Some((cert, key))
+            }
+            (None, None) => None,
+            // Only possible when one of them comes from the profile
+            _ => bail!("--tls-cert and --tls-key must be given together"),
+        };
+        Ok(Self {
+            ca_chain,
+            identity,
+            insecure: opts.tls_insecure,
+            hostname_verification: opts.tls_hostname_verification.unwrap_or(true),
+        })
+    }
+}
+
+/// Reads a PEM file of CA certificates, checking that it is one
+pub fn load_ca_chain(path: &Path) -> Result<Vec<u8>> {
+    let pem = read(path, "CA certificate")?;
+    reqwest::Certificate::from_pem(&pem)
+        .map_err(|e| format_err!("Invalid CA certificate {}: {}", path.display(), e))?;
+    Ok(pem)
+}
+
+fn read(path: &Path, what: &str) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| format_err!("Failed reading {} {}: {}", what, path.display(), e))
+}
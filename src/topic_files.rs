@@ -0,0 +1,440 @@
+//! Writing consumed messages to a file per topic with `consume --output-dir-per-topic`, one
+//! JSON object per line, e.g. to replay each topic of a capture on its own later. The capture
+//! stops, rather than failing, once `--max-capture-bytes` or `--max-capture-messages` would be
+//! exceeded or the disk is full. With `--rotate-size` or `--rotate-interval` the file of a topic
+//! is renamed with the time it was rotated at before the line that would go past either, so
+//! that `produce --replay` reads the files of a topic in the order they were written
+
+use crate::units;
+use anyhow::{format_err, Result};
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use log::warn;
+use serde_json::{json, Value};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How much a capture may write
+#[derive(Clone, Copy, Default)]
+pub struct CaptureBudget {
+    pub max_bytes: Option<u64>,
+    pub max_messages: Option<u64>,
+}
+
+/// When the file of a topic is rotated, if ever
+#[derive(Clone, Copy, Default)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub interval: Option<Duration>,
+    /// Gzip the rotated files, from a thread of their own
+    pub compress: bool,
+}
+
+/// The file of a topic since it was last rotated
+struct Segment {
+    size: u64,
+    started: Instant,
+}
+
+pub struct TopicFiles {
+    dir: PathBuf,
+    /// How many files are open at once, the least recently written to being closed first
+    max_open: usize,
+    /// The open files by topic, with when each was last written to
+    open: HashMap<String, (BufWriter<File>, u64)>,
+    /// Every topic written to, whether its file is still open or not
+    topics: HashSet<String>,
+    writes: u64,
+    budget: CaptureBudget,
+    rotation: Rotation,
+    /// The files written to, whether still open or not
+    segments: HashMap<String, Segment>,
+    rotated: u64,
+    /// The threads compressing rotated files
+    compressing: Vec<JoinHandle<()>>,
+    /// Lines written, and their size
+    messages: u64,
+    bytes: u64,
+    /// Why the capture stopped, if it did
+    stopped: Option<String>,
+}
+
+impl TopicFiles {
+    /// Writes under `dir`, creating it if it doesn't exist, until `budget` is spent
+    pub fn new(
+        dir: &Path,
+        max_open: usize,
+        budget: CaptureBudget,
+        rotation: Rotation,
+    ) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .map_err(|e| format_err!("Failed creating {}: {}", dir.display(), e))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            max_open: max_open.max(1),
+            open: HashMap::new(),
+            topics: HashSet::new(),
+            writes: 0,
+            budget,
+            rotation,
+            segments: HashMap::new(),
+            rotated: 0,
+            compressing: Vec::new(),
+            messages: 0,
+            bytes: 0,
+            stopped: None,
+        })
+    }
+
+    /// The file of `topic`, its name percent-encoded so that the slashes and colons of topic
+    /// names don't make directories and different topics never share a file
+    pub fn path(&self, topic: &str) -> PathBuf {
+        path(&self.dir, topic)
+    }
+
+    /// Appends `line` to the file of `topic`, opening it on the first line or again after it
+    /// was closed to make room for others. Whether it was written: not once the capture
+    /// stopped
+    pub fn write(&mut self, topic: &str, line: &str) -> Result<bool> {
+        if self.stopped.is_some() {
+            return Ok(false);
+        }
+        let size = line.len() as u64 + 1;
+        if let Some(max) = self.budget.max_messages.filter(|max| self.messages >= *max) {
+            self.stop(format!("--max-capture-messages {} reached", max));
+            return Ok(false);
+        }
+        if let Some(max) = self.budget.max_bytes.filter(|max| self.bytes + size > *max) {
+            self.stop(format!(
+                "--max-capture-bytes {} reached",
+                units::format_bytes(max as f64)
+            ));
+            return Ok(false);
+        }
+        match self.try_write(topic, line) {
+            Ok(()) => {
+                self.messages += 1;
+                self.bytes += size;
+                Ok(true)
+            }
+            Err((path, e)) => {
+                self.failed(&path, e)?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn try_write(&mut self, topic: &str, line: &str) -> Result<(), (PathBuf, io::Error)> {
+        self.writes += 1;
+        let size = line.len() as u64 + 1;
+        if self.rotation_due(topic, size) {
+            self.rotate(topic)?;
+        }
+        if !self.open.contains_key(topic) {
+            if self.open.len() >= self.max_open {
+                self.close_least_recent()?;
+            }
+            let path = self.path(topic);
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .map_err(|e| (path.clone(), e))?;
+            if !self.segments.contains_key(topic) {
+                // Appending to the file of a previous capture
+                let size = file.metadata().map_err(|e| (path, e))?.len();
+                self.segments.insert(
+                    topic.to_owned(),
+                    Segment {
+                        size,
+                        started: Instant::now(),
+                    },
+                );
+            }
+            self.open
+                .insert(topic.to_owned(), (BufWriter::new(file), self.writes));
+            self.topics.insert(topic.to_owned());
+        }
+        let (file, last_write) = self.open.get_mut(topic).expect("opened above");
+        *last_write = self.writes;
+        writeln!(file, "{}", line).map_err(|e| (self.path(topic), e))?;
+        if let Some(segment) = self.segments.get_mut(topic) {
+            segment.size += size;
+        }
+        Ok(())
+    }
+
+    /// Whether the file of `topic` is to be rotated before writing `size` more bytes to it. A
+    /// file is never rotated empty, so a line longer than --rotate-size gets one of its own
+    fn rotation_due(&self, topic: &str, size: u64) -> bool {
+        let segment = match self.segments.get(topic) {
+            Some(segment) if segment.size > 0 => segment,
+            _ => return false,
+        };
+        let too_big = self
+            .rotation
+            .max_size
+            .map_or(false, |max| segment.size + size > max);
+        let too_old = self
+            .rotation
+            .interval
+            .map_or(false, |interval| segment.started.elapsed() >= interval);
+        too_big || too_old
+    }
+
+    /// Closes the file of `topic` and renames it with the current time, for the next line to
+    /// start a new one
+    fn rotate(&mut self, topic: &str) -> Result<(), (PathBuf, io::Error)> {
+        let path = self.path(topic);
+        if let Some((mut file, _)) = self.open.remove(topic) {
+            file.flush().map_err(|e| (path.clone(), e))?;
+        }
+        // Later than the last file rotated, even within the same millisecond
+        let mut time = Utc::now();
+        while rotated_path(&self.dir, topic, time).exists() {
+            time = time + chrono::Duration::milliseconds(1);
+        }
+        let rotated = rotated_path(&self.dir, topic, time);
+        fs::rename(&path, &rotated).map_err(|e| (path, e))?;
+        self.segments.remove(topic);
+        self.rotated += 1;
+        if self.rotation.compress {
+            self.compressing
+                .push(thread::spawn(move || compress(&rotated)));
+        }
+        Ok(())
+    }
+
+    fn close_least_recent(&mut self) -> Result<(), (PathBuf, io::Error)> {
+        let least_recent = self
+            .open
+            .iter()
+            .min_by_key(|(_, (_, last_write))| *last_write)
+            .map(|(topic, _)| topic.clone());
+        if let Some(topic) = least_recent {
+            if let Some((mut file, _)) = self.open.remove(&topic) {
+                file.flush().map_err(|e| (self.path(&topic), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out what is buffered for every open file, and waits for the rotated files to be
+    /// compressed
+    pub fn flush(&mut self) -> Result<()> {
+        for compressing in self.compressing.drain(..) {
+            // Only fails if the thread panicked, having left the file uncompressed
+            let _ = compressing.join();
+        }
+        let mut failure = None;
+        for (topic, (file, _)) in self.open.iter_mut() {
+            if let Err(e) = file.flush() {
+                failure = Some((path(&self.dir, topic), e));
+                break;
+            }
+        }
+        match failure {
+            Some((path, e)) => self.failed(&path, e),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops the capture if writing to `path` failed because the disk is full, failing
+    /// otherwise
+    fn failed(&mut self, path: &Path, e: io::Error) -> Result<()> {
+        if !is_disk_full(&e) {
+            return Err(format_err!("Failed writing to {}: {}", path.display(), e));
+        }
+        // What is still buffered can't be written either
+        self.open.clear();
+        self.stop(format!(
+            "the disk is full, writing to {}: {}",
+            path.display(),
+            e
+        ));
+        Ok(())
+    }
+
+    fn stop(&mut self, reason: String) {
+        warn!(
+            "Stopped capturing to {} after {} messages ({}): {}",
+            self.dir.display(),
+            self.messages,
+            units::format_bytes(self.bytes as f64),
+            reason
+        );
+        for (topic, (file, _)) in self.open.iter_mut() {
+            if let Err(e) = file.flush() {
+                warn!(
+                    "Failed writing to {}: {}",
+                    path(&self.dir, topic).display(),
+                    e
+                );
+            }
+        }
+        self.open.clear();
+        self.stopped = Some(reason);
+    }
+
+    /// How many messages were written to how many topics, and why the capture stopped if it
+    /// did, as text and as JSON
+    pub fn summary(&self) -> (String, Value) {
+        let mut line = format!(
+            "Wrote {} messages ({}) to {} topic files in {}",
+            self.messages,
+            units::format_bytes(self.bytes as f64),
+            self.topics.len(),
+            self.dir.display()
+        );
+        if self.rotated > 0 {
+            line.push_str(&format!(", rotated {} times", self.rotated));
+        }
+        if let Some(reason) = &self.stopped {
+            line.push_str(&format!(", capture stopped: {}", reason));
+        }
+        (
+            line,
+            json!({
+                "dir": self.dir.display().to_string(),
+                "topics": self.topics.len(),
+                "messages": self.messages,
+                "bytes": self.bytes,
+                "rotated": self.rotated,
+                "stoppedBecause": self.stopped,
+            }),
+        )
+    }
+}
+
+/// Whether writing failed for want of space, on the disk or in the user's quota
+fn is_disk_full(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(code) if code == libc::ENOSPC || code == libc::EDQUOT)
+}
+
+fn path(dir: &Path, topic: &str) -> PathBuf {
+    dir.join(format!("{}.ndjson", file_name(topic)))
+}
+
+/// The file of `topic` rotated at `time`, which sorts before the current one and after those
+/// rotated earlier
+fn rotated_path(dir: &Path, topic: &str, time: DateTime<Utc>) -> PathBuf {
+    dir.join(format!(
+        "{}.{}.ndjson",
+        file_name(topic),
+        time.format("%Y%m%dT%H%M%S%.3fZ")
+    ))
+}
+
+fn file_name(topic: &str) -> String {
+    url::form_urlencoded::byte_serialize(topic.as_bytes()).collect()
+}
+
+/// Gzips `path` into the same name with .gz appended, only removing it once the compressed
+/// file is complete
+fn compress(path: &Path) {
+    let compressed = path.with_extension("ndjson.gz");
+    let partial = path.with_extension("ndjson.gz.partial");
+    let result = (|| -> io::Result<()> {
+        let mut encoder = GzEncoder::new(
+            BufWriter::new(File::create(&partial)?),
+            Compression::default(),
+        );
+        io::copy(&mut File::open(path)?, &mut encoder)?;
+        encoder.finish()?.flush()?;
+        fs::rename(&partial, &compressed)?;
+        fs::remove_file(path)
+    })();
+    if let Err(e) = result {
+        warn!("Failed compressing {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    /// A directory of its own under the temporary one
+    fn temp_dir() -> PathBuf {
+        env::temp_dir().join(format!("pulsar-cli-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn names_in(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotated_files_sort_before_the_current_one() {
+        let dir = Path::new("captures");
+        let earlier = rotated_path(dir, "orders", "2021-03-04T05:06:07.890Z".parse().unwrap());
+        let later = rotated_path(dir, "orders", "2021-03-04T15:06:07.890Z".parse().unwrap());
+        assert_eq!(earlier, dir.join("orders.20210304T050607.890Z.ndjson"));
+        let mut paths = vec![path(dir, "orders"), later.clone(), earlier.clone()];
+        paths.sort();
+        assert_eq!(paths, vec![earlier, later, path(dir, "orders")]);
+    }
+
+    #[test]
+    fn rotates_by_size_between_lines() {
+        let dir = temp_dir();
+        let rotation = Rotation {
+            max_size: Some(10),
+            ..Default::default()
+        };
+        let mut files = TopicFiles::new(&dir, 4, CaptureBudget::default(), rotation).unwrap();
+        for line in &["1234", "5678", "9", "a-line-longer-than-the-size"] {
+            assert!(files.write("orders", line).unwrap());
+        }
+        files.flush().unwrap();
+        let names = names_in(&dir);
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[2], "orders.ndjson");
+        let contents = names
+            .iter()
+            .map(|name| fs::read_to_string(dir.join(name)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents,
+            vec!["1234\n5678\n", "9\n", "a-line-longer-than-the-size\n"]
+        );
+        assert_eq!(files.summary().1["rotated"], 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compresses_the_rotated_files() {
+        let dir = temp_dir();
+        let rotation = Rotation {
+            max_size: Some(1),
+            compress: true,
+            ..Default::default()
+        };
+        let mut files = TopicFiles::new(&dir, 4, CaptureBudget::default(), rotation).unwrap();
+        for line in &["first", "second"] {
+            assert!(files.write("orders", line).unwrap());
+        }
+        files.flush().unwrap();
+        let names = names_in(&dir);
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with(".ndjson.gz"));
+        let mut contents = String::new();
+        io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(File::open(dir.join(&names[0])).unwrap()),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, "first\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,418 @@
+use anyhow::{bail, format_err, Result};
+use once_cell::sync::OnceCell;
+use std::{fmt, str::FromStr};
+use structopt::StructOpt;
+
+const DEFAULT_TENANT: &str = "public";
+const DEFAULT_NAMESPACE: &str = "default";
+
+/// What bare topic names are prefixed with instead of `public/default`, set from
+/// `--tenant`/`--namespace` and the profile before the topic names on the command line are
+/// parsed
+static TOPIC_PREFIX: OnceCell<String> = OnceCell::new();
+
+/// Infix of the names of the individual partitions of a partitioned topic
+pub const PARTITION_INFIX: &str = "-partition-";
+
+#[derive(StructOpt)]
+pub struct TopicOpts {
+    /// Tenant bare topic names such as `orders` live in [default: public]
+    #[structopt(long, env = "PULSAR_TENANT")]
+    pub tenant: Option<String>,
+
+    /// Namespace bare topic names such as `orders` live in [default: default]
+    #[structopt(long, env = "PULSAR_NAMESPACE")]
+    pub namespace: Option<String>,
+}
+
+/// Checks that `name` is a valid tenant or namespace name, which Pulsar restricts to letters,
+/// digits and `-=:._`
+pub fn check_name(what: &str, name: &str) -> Result<()> {
+    if name.is_empty() {
+        bail!("Empty {} name", what);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !"-=:._".contains(*c))
+    {
+        bail!(
+            "Invalid character {:?} in {} name {:?}, only letters, digits and -=:._ are allowed",
+            c,
+            what,
+            name
+        );
+    }
+    Ok(())
+}
+
+/// A fully-qualified topic name, e.g. `persistent://tenant/namespace/topic`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TopicName {
+    pub persistent: bool,
+    pub tenant: String,
+    pub namespace: String,
+    pub local_name: String,
+}
+
+impl TopicName {
+    pub fn domain(&self) -> &'static str {
+        if self.persistent {
+            "persistent"
+        } else {
+            "non-persistent"
+        }
+    }
+
+    /// The namespace this topic is in
+    pub fn namespace_name(&self) -> NamespaceName {
+        NamespaceName {
+            tenant: self.tenant.clone(),
+            namespace: self.namespace.clone(),
+        }
+    }
+
+    /// The path of this topic under the admin REST API, e.g. `persistent/tenant/ns/topic`
+    pub fn admin_path(&self) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            self.domain(),
+            self.tenant,
+            self.namespace,
+            url::form_urlencoded::byte_serialize(self.local_name.as_bytes()).collect::<String>()
+        )
+    }
+
+    /// The path of this topic's schema resources under the admin REST API
+    pub fn schemas_admin_path(&self) -> String {
+        format!(
+            "schemas/{}/{}/{}",
+            self.tenant,
+            self.namespace,
+            url::form_urlencoded::byte_serialize(self.local_name.as_bytes()).collect::<String>()
+        )
+    }
+
+    /// The name of partition `index` of this (partitioned) topic
+    pub fn partition(&self, index: u32) -> TopicName {
+        TopicName {
+            local_name: format!("{}{}{}", self.local_name, PARTITION_INFIX, index),
+            ..self.clone()
+        }
+    }
+
+    /// If this is one partition of a partitioned topic, the partitioned topic and the index
+    pub fn partitioned_parent(&self) -> Option<(TopicName, u32)> {
+        let (base, index) = self.local_name.rsplit_once(PARTITION_INFIX)?;
+        let index = index.parse().ok()?;
+        Some((
+            TopicName {
+                local_name: base.to_owned(),
+                ..self.clone()
+            },
+            index,
+        ))
+    }
+}
+
+/// Checks a topic prefix, a namespace optionally preceded by a domain (e.g.
+/// `non-persistent://tenant/ns`), returning it normalized to end with a slash
+pub fn check_topic_prefix(prefix: &str) -> Result<String> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    if format!("{}topic", prefix).parse::<TopicName>().is_err() {
+        bail!(
+            "Invalid topic prefix {:?}, expected e.g. <tenant>/<namespace> or persistent://<tenant>/<namespace>",
+            prefix
+        );
+    }
+    Ok(prefix)
+}
+
+/// The prefix of bare topic names with `tenant` and `namespace`, where given, replacing those
+/// of `prefix` (`public/default` if there is none)
+pub fn topic_prefix_with(
+    prefix: Option<&str>,
+    tenant: Option<&str>,
+    namespace: Option<&str>,
+) -> Result<Option<String>> {
+    if tenant.is_none() && namespace.is_none() {
+        return prefix.map(check_topic_prefix).transpose();
+    }
+    let base = match prefix {
+        Some(prefix) => check_topic_prefix(prefix)?,
+        None => format!("{}/{}/", DEFAULT_TENANT, DEFAULT_NAMESPACE),
+    };
+    let base = format!("{}topic", base).parse::<TopicName>()?;
+    if let Some(tenant) = tenant {
+        check_name("tenant", tenant)?;
+    }
+    if let Some(namespace) = namespace {
+        check_name("namespace", namespace)?;
+    }
+    Ok(Some(format!(
+        "{}://{}/{}/",
+        base.domain(),
+        tenant.unwrap_or(&base.tenant),
+        namespace.unwrap_or(&base.namespace)
+    )))
+}
+
+/// Makes bare topic names resolve under `prefix` rather than `public/default`
+pub fn set_topic_prefix(prefix: &str) -> Result<()> {
+    let prefix = check_topic_prefix(prefix)?;
+    // Only ever set once, before anything is parsed
+    let _ = TOPIC_PREFIX.set(prefix);
+    Ok(())
+}
+
+impl FromStr for TopicName {
+    type Err = anyhow::Error;
+
+    /// Parses a topic name the same way Pulsar clients do: a bare `topic` lives in
+    /// `public/default` (or under `--tenant`/`--namespace` or the profile's topic prefix),
+    /// `tenant/ns/topic` is persistent, and fully-qualified names are taken as they are
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(prefix) = TOPIC_PREFIX.get() {
+            if !s.contains('/') {
+                return format!("{}{}", prefix, s).parse();
+            }
+        }
+
+        let (persistent, rest) = if let Some(rest) = s.strip_prefix("persistent://") {
+            (true, rest)
+        } else if let Some(rest) = s.strip_prefix("non-persistent://") {
+            (false, rest)
+        } else if s.contains("://") {
+            bail!("Invalid topic domain in {:?}", s);
+        } else {
+            (true, s)
+        };
+
+        let parts = rest.splitn(3, '/').collect::<Vec<_>>();
+        let (tenant, namespace, local_name) = match parts.as_slice() {
+            [local_name] if s == rest => (DEFAULT_TENANT, DEFAULT_NAMESPACE, *local_name),
+            [tenant, namespace, local_name] => (*tenant, *namespace, *local_name),
+            _ => bail!("Invalid topic name: {:?}", s),
+        };
+        if local_name.is_empty() {
+            bail!("Invalid topic name: {:?}", s);
+        }
+        check_name("tenant", tenant)
+            .and_then(|_| check_name("namespace", namespace))
+            .map_err(|e| format_err!("Invalid topic name {:?}: {}", s, e))?;
+
+        Ok(Self {
+            persistent,
+            tenant: tenant.to_owned(),
+            namespace: namespace.to_owned(),
+            local_name: local_name.to_owned(),
+        })
+    }
+}
+
+/// Whether `name` is that of a non-persistent topic once expanded as `TopicName` parses it, e.g.
+/// a bare name under a `non-persistent://` topic prefix; false if it isn't a valid name
+pub fn is_non_persistent(name: &str) -> bool {
+    name.parse::<TopicName>()
+        .map_or(false, |topic| !topic.persistent)
+}
+
+impl fmt::Display for TopicName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://{}/{}/{}",
+            self.domain(),
+            self.tenant,
+            self.namespace,
+            self.local_name
+        )
+    }
+}
+
+/// A namespace name, e.g. `tenant/namespace`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NamespaceName {
+    pub tenant: String,
+    pub namespace: String,
+}
+
+impl NamespaceName {
+    /// The admin API path listing the topics of this namespace in the given domain
+    pub fn topics_admin_path(&self, persistent: bool) -> String {
+        format!(
+            "{}/{}",
+            if persistent {
+                "persistent"
+            } else {
+                "non-persistent"
+            },
+            self
+        )
+    }
+}
+
+impl FromStr for NamespaceName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split('/').collect::<Vec<_>>().as_slice() {
+            [tenant, namespace] => {
+                check_name("tenant", tenant)
+                    .and_then(|_| check_name("namespace", namespace))
+                    .map_err(|e| format_err!("Invalid namespace {:?}: {}", s, e))?;
+                Ok(Self {
+                    tenant: (*tenant).to_owned(),
+                    namespace: (*namespace).to_owned(),
+                })
+            }
+            _ => bail!("Invalid namespace {:?}, expected <tenant>/<namespace>", s),
+        }
+    }
+}
+
+impl fmt::Display for NamespaceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.tenant, self.namespace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The topic prefix is never set here, as it is global to the tests
+
+    fn topic(persistent: bool, tenant: &str, namespace: &str, local_name: &str) -> TopicName {
+        TopicName {
+            persistent,
+            tenant: tenant.to_owned(),
+            namespace: namespace.to_owned(),
+            local_name: local_name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        for (name, parsed) in &[
+            ("persistent://t/ns/orders", topic(true, "t", "ns", "orders")),
+            (
+                "non-persistent://t/ns/live",
+                topic(false, "t", "ns", "live"),
+            ),
+            ("t/ns/orders", topic(true, "t", "ns", "orders")),
+            ("orders", topic(true, "public", "default", "orders")),
+            // The local name may contain slashes
+            ("persistent://t/ns/a/b", topic(true, "t", "ns", "a/b")),
+        ] {
+            assert_eq!(name.parse::<TopicName>().unwrap(), *parsed, "{}", name);
+        }
+        assert_eq!(
+            "non-persistent://t/ns/live"
+                .parse::<TopicName>()
+                .unwrap()
+                .to_string(),
+            "non-persistent://t/ns/live"
+        );
+        assert!(is_non_persistent("non-persistent://t/ns/live"));
+        assert!(!is_non_persistent("orders"));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for name in &[
+            "",
+            "kafka://t/ns/orders",
+            "http://t/ns/orders",
+            "persistent://orders",
+            "persistent://t/ns/",
+            "t/orders",
+            "t/n s/orders",
+            "persistent:///ns/orders",
+        ] {
+            assert!(name.parse::<TopicName>().is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn prefix_with_tenant_and_namespace() {
+        assert_eq!(topic_prefix_with(None, None, None).unwrap(), None);
+        for (prefix, tenant, namespace, expected) in &[
+            (None, Some("t"), None, "persistent://t/default/"),
+            (None, None, Some("ns"), "persistent://public/ns/"),
+            (None, Some("t"), Some("ns"), "persistent://t/ns/"),
+            (Some("a/b"), Some("t"), None, "persistent://t/b/"),
+            (
+                Some("non-persistent://a/b"),
+                None,
+                Some("ns"),
+                "non-persistent://a/ns/",
+            ),
+            (Some("a/b/"), None, None, "a/b/"),
+            (
+                Some("non-persistent://a/b"),
+                None,
+                None,
+                "non-persistent://a/b/",
+            ),
+        ] {
+            assert_eq!(
+                topic_prefix_with(*prefix, *tenant, *namespace)
+                    .unwrap()
+                    .as_deref(),
+                Some(*expected)
+            );
+        }
+    }
+
+    #[test]
+    fn bare_names_under_a_prefix() {
+        for (prefix, parsed) in &[
+            (
+                topic_prefix_with(None, Some("t"), Some("ns")),
+                topic(true, "t", "ns", "orders"),
+            ),
+            (
+                topic_prefix_with(Some("non-persistent://a/b"), None, Some("ns")),
+                topic(false, "a", "ns", "orders"),
+            ),
+        ] {
+            let prefix = prefix.as_ref().unwrap().as_ref().unwrap();
+            // As TopicName parses bare names once the prefix is set
+            assert_eq!(
+                format!("{}orders", prefix).parse::<TopicName>().unwrap(),
+                *parsed
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_prefixes() {
+        assert!(topic_prefix_with(Some("kafka://a/b"), None, None).is_err());
+        assert!(topic_prefix_with(Some("a"), None, None).is_err());
+        assert!(topic_prefix_with(Some("kafka://a/b"), Some("t"), None).is_err());
+        assert!(topic_prefix_with(None, Some("t/x"), None).is_err());
+        assert!(topic_prefix_with(None, None, Some("")).is_err());
+    }
+
+    #[test]
+    fn tenant_and_namespace_names() {
+        check_name("tenant", "acme-1.a=b:c_d").unwrap();
+        for name in &["", "a/b", "a b", "caf\u{e9}", "a*"] {
+            assert!(check_name("tenant", name).is_err(), "{:?}", name);
+        }
+        assert!(topic_prefix_with(None, Some("a b"), None).is_err());
+        assert!("t/n:s/orders".parse::<TopicName>().is_ok());
+    }
+
+    #[test]
+    fn partitions() {
+        let parent = topic(true, "t", "ns", "orders");
+        let partition = parent.partition(3);
+        assert_eq!(
+            partition.to_string(),
+            "persistent://t/ns/orders-partition-3"
+        );
+        assert_eq!(partition.partitioned_parent(), Some((parent.clone(), 3)));
+        assert_eq!(parent.partitioned_parent(), None);
+    }
+}
@@ -0,0 +1,380 @@
+//! Changes made to JSON payloads before producing them, for `produce --transform`, e.g. to bump
+//! a schema version or anonymize a field of replayed data without piping it through jq:
+//!
+//! - `set payload.<path> = <value>`, the value being JSON or else taken as a string
+//! - `del payload.<path>`
+//! - `rename payload.<path> to <name>`
+//!
+//! Paths are dot-separated object keys, numbers indexing arrays
+
+use crate::exit::{self, ExitError};
+use anyhow::{format_err, Result};
+use serde_json::{Map, Value};
+use std::{fmt, str::FromStr};
+
+const PAYLOAD_PREFIX: &str = "payload.";
+
+pub enum Transform {
+    Set(Vec<String>, Value),
+    Del(Vec<String>),
+    Rename(Vec<String>, String),
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            format_err!(
+                "Invalid transform: {:?}, expected `set payload.<path> = <value>`, `del payload.<path>` or `rename payload.<path> to <name>`",
+                s
+            )
+        };
+        let (operation, rest) = s.trim().split_once(' ').ok_or_else(invalid)?;
+        let rest = rest.trim();
+        match operation {
+            "set" => {
+                let (path, value) = rest.split_once('=').ok_or_else(invalid)?;
+                let value = value.trim();
+                let value =
+                    serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+                Ok(Transform::Set(
+                    parse_path(path.trim()).ok_or_else(invalid)?,
+                    value,
+                ))
+            }
+            "del" => Ok(Transform::Del(parse_path(rest).ok_or_else(invalid)?)),
+            "rename" => {
+                let (path, name) = rest.split_once(" to ").ok_or_else(invalid)?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(invalid());
+                }
+                Ok(Transform::Rename(
+                    parse_path(path.trim()).ok_or_else(invalid)?,
+                    name.to_owned(),
+                ))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Set(path, value) => write!(f, "set payload.{} = {}", path.join("."), value),
+            Transform::Del(path) => write!(f, "del payload.{}", path.join(".")),
+            Transform::Rename(path, name) => {
+                write!(f, "rename payload.{} to {}", path.join("."), name)
+            }
+        }
+    }
+}
+
+/// The segments of `payload.<path>`, e.g. `payload.user.email`
+pub fn parse_path(path: &str) -> Option<Vec<String>> {
+    let path = path.strip_prefix(PAYLOAD_PREFIX)?;
+    let segments = path.split('.').map(str::to_owned).collect::<Vec<_>>();
+    if segments.iter().any(String::is_empty) {
+        None
+    } else {
+        Some(segments)
+    }
+}
+
+impl Transform {
+    /// Applies the transform to `payload`. Deleting or renaming what isn't there does nothing,
+    /// setting creates the missing objects on the way
+    pub fn apply(&self, payload: &mut Value) -> Result<()> {
+        match self {
+            Transform::Set(path, value) => {
+                let (last, parents) = path.split_last().expect("paths aren't empty");
+                let mut current = payload;
+                for segment in parents {
+                    current = child(current, segment, true)?
+                        .ok_or_else(|| format_err!("payload.{} not found", path.join(".")))?;
+                }
+                match current {
+                    Value::Object(object) => {
+                        object.insert(last.clone(), value.clone());
+                    }
+                    Value::Array(array) => {
+                        let slot = last
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|i| array.get_mut(i))
+                            .ok_or_else(|| {
+                                format_err!("payload.{} is out of its array", path.join("."))
+                            })?;
+                        *slot = value.clone();
+                    }
+                    _ => return Err(not_a_container(path)),
+                }
+            }
+            Transform::Del(path) | Transform::Rename(path, _) => {
+                let (last, parents) = path.split_last().expect("paths aren't empty");
+                let mut current = payload;
+                for segment in parents {
+                    current = match child(current, segment, false)? {
+                        Some(child) => child,
+                        None => return Ok(()),
+                    };
+                }
+                match (current, self) {
+                    (Value::Object(object), _) => {
+                        if let (Some(value), Transform::Rename(_, name)) =
+                            (object.remove(last), self)
+                        {
+                            object.insert(name.clone(), value);
+                        }
+                    }
+                    (Value::Array(array), Transform::Del(_)) => {
+                        if let Some(i) = last.parse::<usize>().ok().filter(|i| *i < array.len()) {
+                            array.remove(i);
+                        }
+                    }
+                    (Value::Array(_), _) => {
+                        return Err(format_err!(
+                            "payload.{} is an array element, which has no name",
+                            path.join(".")
+                        ))
+                    }
+                    _ => return Err(not_a_container(path)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value at `segment` of `value`, an object created there if missing and `create`
+fn child<'a>(value: &'a mut Value, segment: &str, create: bool) -> Result<Option<&'a mut Value>> {
+    match value {
+        Value::Object(object) => {
+            if create && !object.contains_key(segment) {
+                object.insert(segment.to_owned(), Value::Object(Map::new()));
+            }
+            Ok(object.get_mut(segment))
+        }
+        Value::Array(array) => Ok(segment.parse::<usize>().ok().and_then(|i| array.get_mut(i))),
+        _ => Err(format_err!(
+            "{} inside a value that is neither an object nor an array",
+            segment
+        )),
+    }
+}
+
+fn not_a_container(path: &[String]) -> anyhow::Error {
+    format_err!(
+        "payload.{}: its parent is neither an object nor an array",
+        path.join(".")
+    )
+}
+
+/// `payload` with every transform applied in order, failing if it isn't JSON
+pub fn apply_all(transforms: &[Transform], payload: &[u8]) -> Result<Vec<u8>> {
+    let mut document = serde_json::from_slice::<Value>(payload)
+        .map_err(|e| ExitError::new(exit::INVALID_DATA, format!("Payload is not JSON: {}", e)))?;
+    for transform in transforms {
+        transform.apply(&mut document).map_err(|e| {
+            ExitError::new(exit::INVALID_DATA, format!("`{}` failed: {}", transform, e))
+        })?;
+    }
+    Ok(serde_json::to_vec(&document)?)
+}
+
+/// `payload` of message `i` with every transform applied, or `None` if one failed on it and
+/// `failure` says to skip it, which is logged
+pub fn apply_or_skip(
+    transforms: &[Transform],
+    failure: TransformFailure,
+    i: u64,
+    payload: &[u8],
+) -> Result<Option<Vec<u8>>> {
+    match apply_all(transforms, payload) {
+        Ok(payload) => Ok(Some(payload)),
+        Err(e) if failure == TransformFailure::Skip => {
+            log::warn!("Skipping message {}: {:#}", i, e);
+            Ok(None)
+        }
+        Err(e) => Err(e.context(format!("Message {}", i))),
+    }
+}
+
+/// What to do with the records a transform fails on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransformFailure {
+    Skip,
+    Abort,
+}
+
+impl FromStr for TransformFailure {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(TransformFailure::Skip),
+            "abort" => Ok(TransformFailure::Abort),
+            other => Err(format_err!(
+                "Invalid transform failure handling: {:?}",
+                other
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transformed(transforms: &[&str], payload: Value) -> Result<Value> {
+        let transforms = transforms
+            .iter()
+            .map(|transform| transform.parse())
+            .collect::<Result<Vec<Transform>>>()?;
+        let payload = apply_all(&transforms, &serde_json::to_vec(&payload)?)?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+
+    #[test]
+    fn parse() {
+        for (transform, parsed) in &[
+            ("set payload.version = 2", "set payload.version = 2"),
+            (
+                "  set payload.user.name=\"anonymous\" ",
+                "set payload.user.name = \"anonymous\"",
+            ),
+            // Not JSON, so a string
+            (
+                "set payload.user.name = anonymous",
+                "set payload.user.name = \"anonymous\"",
+            ),
+            (
+                "set payload.tags = [\"a\", 1]",
+                "set payload.tags = [\"a\",1]",
+            ),
+            ("del payload.user.email", "del payload.user.email"),
+            (
+                "rename payload.user.mail to email",
+                "rename payload.user.mail to email",
+            ),
+        ] {
+            assert_eq!(transform.parse::<Transform>().unwrap().to_string(), *parsed);
+        }
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for transform in &[
+            "",
+            "set",
+            "set payload.version",
+            "set version = 2",
+            "set payload. = 2",
+            "set payload.user..name = 2",
+            "del payload.",
+            "del user.email",
+            "rename payload.user.mail",
+            "rename payload.user.mail to ",
+            "rename user.mail to email",
+            "move payload.a to b",
+        ] {
+            assert!(transform.parse::<Transform>().is_err(), "{:?}", transform);
+        }
+    }
+
+    #[test]
+    fn apply_to_nested_paths() {
+        let payload = json!({"user": {"mail": "a@example.com", "tags": ["a", "b", "c"]}, "v": 1});
+        assert_eq!(
+            transformed(
+                &[
+                    "set payload.v = 2",
+                    "set payload.user.tags.1 = \"x\"",
+                    "del payload.user.tags.0",
+                    "rename payload.user.mail to email",
+                ],
+                payload
+            )
+            .unwrap(),
+            json!({"user": {"email": "a@example.com", "tags": ["x", "c"]}, "v": 2})
+        );
+    }
+
+    #[test]
+    fn apply_to_missing_paths() {
+        let payload = json!({"user": {"tags": ["a"]}});
+        // Setting creates the objects on the way
+        assert_eq!(
+            transformed(&["set payload.meta.source = replay"], payload.clone()).unwrap(),
+            json!({"user": {"tags": ["a"]}, "meta": {"source": "replay"}})
+        );
+        // Deleting or renaming what isn't there does nothing
+        for transform in &[
+            "del payload.user.email",
+            "del payload.account.id",
+            "del payload.user.tags.3",
+            "rename payload.user.mail to email",
+            "rename payload.account.id to key",
+        ] {
+            assert_eq!(
+                transformed(&[*transform], payload.clone()).unwrap(),
+                payload,
+                "{}",
+                transform
+            );
+        }
+        for transform in &[
+            // Out of the array
+            "set payload.user.tags.3 = \"x\"",
+            // Inside a string
+            "set payload.user.tags.0.name = \"x\"",
+            "del payload.user.tags.0.name",
+            // Array elements have no name
+            "rename payload.user.tags.0 to first",
+        ] {
+            assert!(
+                transformed(&[*transform], payload.clone()).is_err(),
+                "{}",
+                transform
+            );
+        }
+    }
+
+    #[test]
+    fn non_json_payloads() {
+        let transforms = vec!["set payload.v = 2".parse::<Transform>().unwrap()];
+        let error = apply_all(&transforms, b"not json").unwrap_err();
+        assert_eq!(exit::code_for(&error), exit::INVALID_DATA);
+
+        assert_eq!(
+            apply_or_skip(&transforms, TransformFailure::Skip, 3, b"not json").unwrap(),
+            None
+        );
+        let error =
+            apply_or_skip(&transforms, TransformFailure::Abort, 3, b"not json").unwrap_err();
+        assert_eq!(exit::code_for(&error), exit::INVALID_DATA);
+        assert!(error.to_string().starts_with("Message 3"), "{}", error);
+        for failure in &[TransformFailure::Skip, TransformFailure::Abort] {
+            assert_eq!(
+                apply_or_skip(&transforms, *failure, 3, br#"{"v":1}"#).unwrap(),
+                Some(br#"{"v":2}"#.to_vec())
+            );
+        }
+    }
+
+    #[test]
+    fn parse_transform_failure() {
+        assert_eq!(
+            "skip".parse::<TransformFailure>().unwrap(),
+            TransformFailure::Skip
+        );
+        assert_eq!(
+            "abort".parse::<TransformFailure>().unwrap(),
+            TransformFailure::Abort
+        );
+        assert!("ignore".parse::<TransformFailure>().is_err());
+    }
+}
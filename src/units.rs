@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+
+const BYTE_UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
+
+/// Formats a byte count with a binary-scaled unit, e.g. `1.5 MB`
+pub fn format_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", value, BYTE_UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, BYTE_UNITS[unit])
+    }
+}
+
+/// Parses a byte count with an optional binary-scaled unit, e.g. `512M` or `10GB`
+pub fn parse_bytes(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or_else(|| s.len());
+    let (number, unit) = s.split_at(split);
+    // The B suffix is optional, so that 10G, 10GB and 10gb are the same
+    let unit = unit.trim().to_uppercase();
+    let unit = unit.strip_suffix('B').unwrap_or(&unit);
+    let scale = if unit.is_empty() {
+        1.0
+    } else {
+        match BYTE_UNITS.iter().position(|known| known[..1] == *unit) {
+            Some(index) => 1024f64.powi(index as i32),
+            None => anyhow::bail!("Invalid size {:?}, expected e.g. 512M or 10GB", s),
+        }
+    };
+    match number.parse::<f64>() {
+        Ok(number) => Ok((number * scale).round() as u64),
+        Err(_) => anyhow::bail!("Invalid size {:?}, expected e.g. 512M or 10GB", s),
+    }
+}
+
+/// Parses a byte rate, a size per second with an optional `/s`, e.g. `5MB/s`
+pub fn parse_byte_rate(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    parse_bytes(s.strip_suffix("/s").unwrap_or(s))
+}
+
+/// Parses a point in time given either as an RFC 3339 timestamp or as a duration before now
+/// (e.g. `2h` for two hours ago)
+pub fn parse_time(s: &str) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    match humantime::parse_duration(s) {
+        Ok(ago) => Ok(Utc::now() - chrono::Duration::from_std(ago)?),
+        Err(_) => anyhow::bail!(
+            "Invalid time {:?}, expected an RFC 3339 timestamp or a duration like 2h",
+            s
+        ),
+    }
+}
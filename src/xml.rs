@@ -0,0 +1,155 @@
+//! Pretty-printing of XML payloads, for `consume --xml`: one element per line, indented by
+//! depth, elements holding only text on a single line. Text and attribute values are printed
+//! as they are in the payload, escapes included
+
+use anyhow::{bail, format_err, Result};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use termion::color;
+
+const INDENT: &str = "  ";
+
+/// `payload` indented, colored if `colored`, failing with where if it isn't a well-formed
+/// document
+pub fn pretty(payload: &[u8], colored: bool) -> Result<String> {
+    let mut reader = Reader::from_reader(payload);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut out = String::new();
+    let mut depth = 0;
+    let mut roots = 0;
+    // Whether an element was opened and only had text since, its end tag then going on the
+    // same line
+    let mut inline = false;
+    loop {
+        let event = reader
+            .read_event(&mut buf)
+            .map_err(|e| format_err!("{} at byte {}", e, reader.buffer_position()))?;
+        match event {
+            Event::Start(start) => {
+                if depth == 0 {
+                    roots += 1;
+                }
+                new_line(&mut out, depth);
+                out.push_str(&tag(&start, false, colored)?);
+                depth += 1;
+                inline = true;
+            }
+            Event::Empty(start) => {
+                if depth == 0 {
+                    roots += 1;
+                }
+                new_line(&mut out, depth);
+                out.push_str(&tag(&start, true, colored)?);
+                inline = false;
+            }
+            Event::End(end) => {
+                if depth == 0 {
+                    bail!("Unexpected end tag at byte {}", reader.buffer_position());
+                }
+                depth -= 1;
+                if !inline {
+                    new_line(&mut out, depth);
+                }
+                let name = format!("</{}>", String::from_utf8_lossy(end.name()));
+                out.push_str(&paint(colored, color::Blue, &name));
+                inline = false;
+            }
+            Event::Text(text) => {
+                if depth == 0 {
+                    bail!(
+                        "Text outside of the root element at byte {}",
+                        reader.buffer_position()
+                    );
+                }
+                if !inline {
+                    new_line(&mut out, depth);
+                }
+                out.push_str(&String::from_utf8_lossy(&text));
+            }
+            Event::CData(text) => {
+                if !inline {
+                    new_line(&mut out, depth);
+                }
+                out.push_str(&format!("<![CDATA[{}]]>", String::from_utf8_lossy(&text)));
+            }
+            Event::Comment(text) => {
+                new_line(&mut out, depth);
+                let comment = format!("<!--{}-->", String::from_utf8_lossy(&text));
+                out.push_str(&paint(colored, color::LightBlack, &comment));
+                inline = false;
+            }
+            Event::Decl(decl) => {
+                new_line(&mut out, depth);
+                let decl = format!("<?{}?>", String::from_utf8_lossy(&decl));
+                out.push_str(&paint(colored, color::LightBlack, &decl));
+            }
+            Event::PI(text) => {
+                new_line(&mut out, depth);
+                let instruction = format!("<?{}?>", String::from_utf8_lossy(&text));
+                out.push_str(&paint(colored, color::LightBlack, &instruction));
+                inline = false;
+            }
+            Event::DocType(text) => {
+                new_line(&mut out, depth);
+                out.push_str(&format!("<!DOCTYPE {}>", String::from_utf8_lossy(&text)));
+            }
+            Event::Eof => break,
+        }
+        buf.clear();
+    }
+    match roots {
+        _ if depth > 0 => bail!("{} element(s) not closed", depth),
+        0 => bail!("No root element"),
+        1 => Ok(out),
+        _ => bail!("{} root elements, a document has one", roots),
+    }
+}
+
+fn new_line(out: &mut String, depth: usize) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    for _ in 0..depth {
+        out.push_str(INDENT);
+    }
+}
+
+/// The start tag of an element, self-closing if `empty`
+fn tag(start: &BytesStart<'_>, empty: bool, colored: bool) -> Result<String> {
+    let mut out = paint(
+        colored,
+        color::Blue,
+        &format!("<{}", String::from_utf8_lossy(start.name())),
+    );
+    for attribute in start.attributes() {
+        let attribute = attribute?;
+        out.push(' ');
+        out.push_str(&paint(
+            colored,
+            color::Cyan,
+            &String::from_utf8_lossy(attribute.key),
+        ));
+        out.push('=');
+        let value = String::from_utf8_lossy(&attribute.value);
+        // So that quotes the value holds unescaped don't end it
+        let quote = if value.contains('"') { '\'' } else { '"' };
+        out.push_str(&paint(
+            colored,
+            color::Green,
+            &format!("{}{}{}", quote, value, quote),
+        ));
+    }
+    out.push_str(&paint(colored, color::Blue, if empty { "/>" } else { ">" }));
+    Ok(out)
+}
+
+fn paint<C: color::Color>(colored: bool, c: C, text: &str) -> String {
+    if colored {
+        format!("{}{}{}", color::Fg(c), text, color::Fg(color::Reset))
+    } else {
+        text.to_owned()
+    }
+}
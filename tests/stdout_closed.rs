@@ -0,0 +1,53 @@
+//! Running the binary with its stdout read by something that stops reading early, as with
+//! `pulsar-cli ... | head`: it has to exit quietly and successfully, not panic
+
+use std::{
+    io::Read,
+    os::unix::io::FromRawFd,
+    process::{Child, Command, Stdio},
+};
+
+/// Runs `pulsar-cli args`, without the configuration file or profile of whoever runs the tests
+fn spawn(args: &[&str], stdout: Stdio) -> Child {
+    let config_home = std::env::temp_dir().join(format!("pulsar-cli-test-{}", std::process::id()));
+    Command::new(env!("CARGO_BIN_EXE_pulsar-cli"))
+        .args(args)
+        .env("XDG_CONFIG_HOME", &config_home)
+        .env_remove("PULSAR_PROFILE")
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed running pulsar-cli")
+}
+
+fn assert_quiet_success(child: Child) {
+    let output = child.wait_with_output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "Exited with {}: {}",
+        output.status,
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+#[test]
+fn reader_exits_after_the_first_bytes() {
+    let mut child = spawn(&["completions", "bash"], Stdio::piped());
+    let mut stdout = child.stdout.take().unwrap();
+    let mut start = [0; 16];
+    stdout.read_exact(&mut start).unwrap();
+    drop(stdout);
+    assert_quiet_success(child);
+}
+
+#[test]
+fn reader_exited_before_anything_was_written() {
+    // A pipe whose reading end is already closed, so that the very first write fails
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    unsafe { libc::close(fds[0]) };
+    let stdout = unsafe { Stdio::from_raw_fd(fds[1]) };
+    assert_quiet_success(spawn(&["completions", "bash"], stdout));
+}